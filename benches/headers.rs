@@ -0,0 +1,36 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use beak::{headers, range::ByteRange};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn parsing(c: &mut Criterion) {
+    c.bench_function("quality values", |b| {
+        b.iter(|| {
+            headers::quality_values(black_box(
+                "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
+            ))
+        })
+    });
+    c.bench_function("cookie", |b| {
+        b.iter(|| {
+            headers::cookie(
+                black_box("theme=dark; session=8f14e45fceea167a5a36dedd4bea2543; csrf_token=abc"),
+                "csrf_token",
+            )
+        })
+    });
+    c.bench_function("byte range", |b| {
+        b.iter(|| ByteRange::parse(black_box(Some("bytes=1048576-2097151")), 1 << 30))
+    });
+
+    let date = UNIX_EPOCH + Duration::from_secs(1_660_000_000);
+    c.bench_function("http date format", |b| {
+        b.iter(|| headers::http_date(black_box(date)))
+    });
+    c.bench_function("http date parse", |b| {
+        b.iter(|| headers::parse_http_date(black_box("Mon, 08 Aug 2022 23:06:40 GMT")))
+    });
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
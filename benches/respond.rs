@@ -0,0 +1,143 @@
+// a load harness more than a microbenchmark: a real server on a loopback socket, driven over keep-alive
+// connections, so the whole respond path (routing, middleware, writing the response) is in the numbers.
+// it also counts the allocations a small response takes, with small responses on and off, and fails if turning them
+// on stops saving any
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use beak::{alloc_stats::CountingAllocator, *};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const LARGE: usize = 64 * 1024;
+const COUNTED: u64 = 1000;
+
+fn small(request: Request<'_, '_, '_>, _context: ()) -> BeakResult<()> {
+    request.respond_with_bytes(200, vec![], b"ok")?;
+    Ok(())
+}
+
+fn large(request: Request<'_, '_, '_>, _context: ()) -> BeakResult<()> {
+    request.respond_with_bytes(200, vec![], &[b'x'; LARGE])?;
+    Ok(())
+}
+
+fn_to_handler!(Small with context (); GET "/small" => small);
+fn_to_handler!(Large with context (); GET "/large" => large);
+
+struct Client {
+    stream: BufReader<TcpStream>,
+    request: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl Client {
+    fn new(addr: &str, path: &str) -> Client {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        Client {
+            stream: BufReader::new(stream),
+            request: format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).into_bytes(),
+            body: Vec::new(),
+        }
+    }
+
+    // sends the request and reads the whole response
+    fn round_trip(&mut self) -> usize {
+        self.stream.get_mut().write_all(&self.request).unwrap();
+
+        let mut length = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.stream.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+
+        self.body.resize(length, 0);
+        self.stream.read_exact(&mut self.body).unwrap();
+        length
+    }
+}
+
+fn serve(small_responses: bool) -> (String, ShutdownHandle, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let builder = ServerBuilder::new(addr.clone(), &[&Small, &Large])
+        .listener(listener)
+        .workers(4)
+        .small_responses(small_responses);
+    let shutdown = builder.shutdown_handle();
+    let server = thread::spawn(move || builder.run(()).unwrap());
+    (addr, shutdown, server)
+}
+
+// everything allocated while serving, client included - which allocates the same either way, since it reuses its
+// buffers
+fn allocations_per_request(client: &mut Client) -> f64 {
+    // warm up, so whatever's allocated once per connection isn't counted
+    client.round_trip();
+
+    let before = alloc_stats::stats().unwrap().allocations;
+    for _ in 0..COUNTED {
+        client.round_trip();
+    }
+    let after = alloc_stats::stats().unwrap().allocations;
+    (after - before) as f64 / COUNTED as f64
+}
+
+fn respond(c: &mut Criterion) {
+    let (addr, shutdown, server) = serve(false);
+    let mut client = Client::new(&addr, "/small");
+    c.bench_function("respond small, small responses off", |b| {
+        b.iter(|| client.round_trip())
+    });
+    let without = allocations_per_request(&mut client);
+    drop(client);
+    shutdown.shutdown();
+    server.join().unwrap();
+
+    let (addr, shutdown, server) = serve(true);
+    let mut client = Client::new(&addr, "/small");
+    c.bench_function("respond small", |b| b.iter(|| client.round_trip()));
+    let with = allocations_per_request(&mut client);
+
+    println!(
+        "allocations per small response: {:.2} with small responses, {:.2} without",
+        with, without
+    );
+    assert!(
+        with < without,
+        "small responses should save allocations, but took {:.2} per request against {:.2}",
+        with,
+        without
+    );
+
+    let mut client = Client::new(&addr, "/large");
+    let mut group = c.benchmark_group("respond large");
+    group.throughput(Throughput::Bytes(LARGE as u64));
+    group.bench_function("64KiB", |b| b.iter(|| client.round_trip()));
+    group.finish();
+
+    drop(client);
+    shutdown.shutdown();
+    server.join().unwrap();
+}
+
+criterion_group!(benches, respond);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+use beak::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn ok(_request: Request<'_, '_, '_>, _context: ()) -> BeakResult<()> {
+    Ok(())
+}
+
+fn_to_handler!(Index with context (); GET "/" => ok);
+fn_to_handler!(Posts with context (); GET "/posts" => ok);
+fn_to_handler!(NewPost with context (); POST "/posts" => ok);
+fn_to_handler!(Post with context (); GET "/posts/:id" => ok);
+fn_to_handler!(Comment with context (); GET "/posts/:id/comments/:comment" => ok);
+fn_to_handler!(Static with context (); GET "/static/*file" => ok);
+
+const ROUTES: Routes<()> = &[&Index, &Posts, &NewPost, &Post, &Comment, &Static];
+
+fn routing(c: &mut Criterion) {
+    let router = Router::new(ROUTES).unwrap();
+
+    let mut hosts = Router::new(ROUTES).unwrap();
+    hosts.add_host("example.com", ROUTES).unwrap();
+    hosts.add_host("*.example.com", ROUTES).unwrap();
+
+    c.bench_function("route static", |b| {
+        b.iter(|| router.at(None, "GET", black_box("/posts")).is_ok())
+    });
+    c.bench_function("route params", |b| {
+        b.iter(|| {
+            router
+                .at(None, "GET", black_box("/posts/1234/comments/5678"))
+                .is_ok()
+        })
+    });
+    c.bench_function("route catch-all", |b| {
+        b.iter(|| {
+            router
+                .at(None, "GET", black_box("/static/css/site/main.css"))
+                .is_ok()
+        })
+    });
+    c.bench_function("route method not allowed", |b| {
+        b.iter(|| router.at(None, "DELETE", black_box("/posts")).is_err())
+    });
+    c.bench_function("route not found", |b| {
+        b.iter(|| router.at(None, "GET", black_box("/nope/nothing")).is_err())
+    });
+    c.bench_function("route wildcard host", |b| {
+        b.iter(|| {
+            hosts
+                .at(Some(black_box("blog.example.com:8000")), "GET", "/posts/1")
+                .is_ok()
+        })
+    });
+}
+
+criterion_group!(benches, routing);
+criterion_main!(benches);
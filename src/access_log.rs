@@ -0,0 +1,360 @@
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
+use crate::{
+    headers::{civil_from_days, MONTHS},
+    random::roll,
+    CompletedRequest,
+};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LogFormatError {
+    #[error("unknown directive %{0} in log format")]
+    UnknownDirective(String),
+    #[error("log format ends in the middle of a directive")]
+    Unterminated,
+}
+
+/// The line formats an [`AccessLog`] can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`
+    Common,
+    /// Common, with the `Referer` and `User-Agent` on the end.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+const COMBINED: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    RemoteAddr,
+    Dash,
+    Time,
+    RequestLine,
+    Method,
+    Url,
+    Protocol,
+    Status,
+    // `-` for nothing, like apache's %b
+    BytesClf,
+    Bytes,
+    BytesRead,
+    Micros,
+    Seconds,
+    Route,
+    Referer,
+    UserAgent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Format {
+    Text(Vec<Piece>),
+    Json,
+}
+
+/// Writes a line for every request, for [`ServerBuilder::access_log`](crate::ServerBuilder::access_log) - to
+/// stderr unless told otherwise.
+pub struct AccessLog {
+    format: Format,
+    sample: f64,
+    skipped: HashSet<&'static str>,
+    output: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat) -> AccessLog {
+        let format = match format {
+            LogFormat::Common => Format::Text(parse(COMMON).unwrap()),
+            LogFormat::Combined => Format::Text(parse(COMBINED).unwrap()),
+            LogFormat::Json => Format::Json,
+        };
+
+        AccessLog {
+            format,
+            sample: 1.0,
+            skipped: HashSet::new(),
+            output: Box::new(|line| eprintln!("{}", line)),
+        }
+    }
+
+    /// Lines in a format of your own, with apache-style directives: `%h` client address, `%t` time, `%r` request
+    /// line, `%m` method, `%U` url, `%H` protocol, `%s` status, `%b` bytes sent (`-` for none), `%B` bytes sent,
+    /// `%I` bytes received, `%D` microseconds taken, `%T` seconds taken, `%R` the matched route,
+    /// `%{Referer}i` and `%{User-Agent}i`. `%l` and `%u` are always `-`, and `%%` is a `%`.
+    pub fn custom(format: &str) -> Result<AccessLog, LogFormatError> {
+        let mut log = AccessLog::new(LogFormat::Common);
+        log.format = Format::Text(parse(format)?);
+        Ok(log)
+    }
+
+    /// Writes lines to `writer` instead, one write per line.
+    pub fn writer(self, writer: impl Write + Send + 'static) -> Self {
+        let writer = Mutex::new(writer);
+        self.output(move |line| {
+            // there's nowhere to tell about failing to log
+            let _ = writeln!(writer.lock().unwrap(), "{}", line);
+        })
+    }
+
+    /// Hands lines to `output` instead, for loggers that want them one at a time.
+    pub fn output(mut self, output: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Only logs about `rate` (between 0 and 1) of requests, picked at random.
+    pub fn sample(mut self, rate: f64) -> Self {
+        self.sample = rate;
+        self
+    }
+
+    /// Leaves requests to `route` (a path pattern, exactly as its handler declares it) out of the log - health
+    /// checks, metrics scrapes.
+    pub fn skip_route(mut self, route: &'static str) -> Self {
+        self.skipped.insert(route);
+        self
+    }
+
+    /// Writes the line for `request`, unless it's skipped or sampled out.
+    pub fn log(&self, request: &CompletedRequest) {
+        if request
+            .route
+            .is_some_and(|route| self.skipped.contains(route))
+        {
+            return;
+        }
+        if self.sample < 1.0 && roll() >= self.sample {
+            return;
+        }
+
+        (self.output)(&self.line(request));
+    }
+
+    /// The line for `request`, without a newline.
+    pub fn line(&self, request: &CompletedRequest) -> String {
+        match &self.format {
+            Format::Text(pieces) => text_line(pieces, request),
+            Format::Json => json_line(request),
+        }
+    }
+}
+
+fn parse(format: &str) -> Result<Vec<Piece>, LogFormatError> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+
+        let mut directive = chars
+            .next()
+            .ok_or(LogFormatError::Unterminated)?
+            .to_string();
+        if directive == "{" {
+            for c in chars.by_ref() {
+                directive.push(c);
+                if c == '}' {
+                    break;
+                }
+            }
+            directive.push(chars.next().ok_or(LogFormatError::Unterminated)?);
+        } else if directive == ">" {
+            directive.push(chars.next().ok_or(LogFormatError::Unterminated)?);
+        }
+
+        let piece = match directive.as_str() {
+            "%" => {
+                literal.push('%');
+                continue;
+            }
+            "h" => Piece::RemoteAddr,
+            "l" | "u" => Piece::Dash,
+            "t" => Piece::Time,
+            "r" => Piece::RequestLine,
+            "m" => Piece::Method,
+            "U" => Piece::Url,
+            "H" => Piece::Protocol,
+            "s" | ">s" => Piece::Status,
+            "b" => Piece::BytesClf,
+            "B" => Piece::Bytes,
+            "I" => Piece::BytesRead,
+            "D" => Piece::Micros,
+            "T" => Piece::Seconds,
+            "R" => Piece::Route,
+            "{Referer}i" => Piece::Referer,
+            "{User-Agent}i" => Piece::UserAgent,
+            directive => return Err(LogFormatError::UnknownDirective(directive.to_owned())),
+        };
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        pieces.push(piece);
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+fn text_line(pieces: &[Piece], request: &CompletedRequest) -> String {
+    let mut line = String::new();
+    let or_dash = |value: Option<&str>| escape(value.unwrap_or("-"));
+
+    for piece in pieces {
+        let _ = match piece {
+            Piece::Literal(text) => write!(line, "{}", text),
+            Piece::RemoteAddr => match request.remote_addr {
+                Some(addr) => write!(line, "{}", addr.ip()),
+                None => write!(line, "-"),
+            },
+            Piece::Dash => write!(line, "-"),
+            Piece::Time => write!(line, "[{}]", clf_time(request.received)),
+            Piece::RequestLine => write!(
+                line,
+                "{} {} HTTP/{}",
+                escape(&request.method),
+                escape(&request.url),
+                request.http_version
+            ),
+            Piece::Method => write!(line, "{}", escape(&request.method)),
+            Piece::Url => write!(line, "{}", escape(&request.url)),
+            Piece::Protocol => write!(line, "HTTP/{}", request.http_version),
+            Piece::Status => match request.status {
+                Some(status) => write!(line, "{}", status),
+                None => write!(line, "-"),
+            },
+            Piece::BytesClf => match request.bytes_written {
+                0 => write!(line, "-"),
+                bytes => write!(line, "{}", bytes),
+            },
+            Piece::Bytes => write!(line, "{}", request.bytes_written),
+            Piece::BytesRead => write!(line, "{}", request.bytes_read),
+            Piece::Micros => write!(line, "{}", request.duration.as_micros()),
+            Piece::Seconds => write!(line, "{}", request.duration.as_secs()),
+            Piece::Route => write!(line, "{}", request.route.unwrap_or("-")),
+            Piece::Referer => write!(line, "{}", or_dash(request.referer.as_deref())),
+            Piece::UserAgent => write!(line, "{}", or_dash(request.user_agent.as_deref())),
+        };
+    }
+
+    line
+}
+
+fn json_line(request: &CompletedRequest) -> String {
+    let string = |value: Option<&str>| match value {
+        Some(value) => format!("\"{}\"", json_escape(value)),
+        None => "null".to_owned(),
+    };
+    let secs = unix_time(request.received);
+
+    format!(
+        concat!(
+            "{{\"time\":\"{}\",\"remote_addr\":{},\"method\":{},\"url\":{},\"http_version\":{},\"route\":{},",
+            "\"status\":{},\"bytes_read\":{},\"bytes_written\":{},\"duration_ms\":{},\"referer\":{},",
+            "\"user_agent\":{}}}"
+        ),
+        rfc3339(secs),
+        string(request.remote_addr.map(|addr| addr.ip().to_string()).as_deref()),
+        string(Some(&request.method)),
+        string(Some(&request.url)),
+        string(Some(&request.http_version)),
+        string(request.route),
+        request
+            .status
+            .map_or_else(|| "null".to_owned(), |status| status.to_string()),
+        request.bytes_read,
+        request.bytes_written,
+        request.duration.as_secs_f64() * 1000.0,
+        string(request.referer.as_deref()),
+        string(request.user_agent.as_deref()),
+    )
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+// `10/Oct/2000:13:55:36 +0000`, always in utc
+fn clf_time(time: SystemTime) -> String {
+    let secs = unix_time(time);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+// quotes and anything unprintable escaped the way apache does, so a client can't forge lines or fields
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            byte => {
+                let _ = write!(escaped, "\\x{:02x}", byte);
+            }
+        }
+    }
+    escaped
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
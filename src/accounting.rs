@@ -0,0 +1,183 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU16, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::response::SmallBuf;
+
+/// How many bytes a request has read off the wire and written back, counted as they go.
+///
+/// Clones share the same counters, so a handler can hold on to one from
+/// [`Request::accounting`](crate::Request::accounting) and read the totals after it's responded.
+#[derive(Debug, Clone, Default)]
+pub struct Accounting {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    read: AtomicU64,
+    written: AtomicU64,
+    // 0 until a status line has gone out
+    status: AtomicU16,
+    // when the request went to the middleware and handler, and when its status went out
+    handler_started: OnceLock<Instant>,
+    first_byte: OnceLock<Instant>,
+}
+
+impl Accounting {
+    /// Body bytes read, before any decompression.
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.read.load(Ordering::Relaxed)
+    }
+
+    /// Everything written: status line, headers and body.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+
+    /// The status that was sent, once it has been.
+    pub fn status(&self) -> Option<u16> {
+        match self.inner.status.load(Ordering::Relaxed) {
+            0 => None,
+            status => Some(status),
+        }
+    }
+
+    pub(crate) fn mark_handler_started(&self) {
+        let _ = self.inner.handler_started.set(Instant::now());
+    }
+
+    pub(crate) fn handler_started(&self) -> Option<Instant> {
+        self.inner.handler_started.get().copied()
+    }
+
+    pub(crate) fn first_byte(&self) -> Option<Instant> {
+        self.inner.first_byte.get().copied()
+    }
+}
+
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    accounting: Accounting,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R, accounting: Accounting) -> CountingReader<R> {
+        CountingReader { inner, accounting }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.accounting
+            .inner
+            .read
+            .fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    accounting: Accounting,
+    sniff: Sniff,
+}
+
+// what we've seen of the response, until we know its status
+enum Sniff {
+    // the start of a status line
+    Status(SmallBuf<64>),
+    // an interim (1xx) head like early hints, which doesn't count - with what's been seen of it, minus what can't be
+    // part of the blank line ending it
+    Interim(Vec<u8>),
+    Done,
+}
+
+impl<W> CountingWriter<W> {
+    pub(crate) fn new(inner: W, accounting: Accounting) -> CountingWriter<W> {
+        CountingWriter {
+            inner,
+            accounting,
+            sniff: Sniff::Status(SmallBuf::new()),
+        }
+    }
+
+    fn sniff_status(&mut self, written: &[u8]) {
+        let line = match &mut self.sniff {
+            Sniff::Status(line) => line,
+            Sniff::Interim(head) => {
+                head.extend_from_slice(written);
+                match head.windows(4).position(|window| window == b"\r\n\r\n") {
+                    Some(at) => {
+                        let rest = head.split_off(at + 4);
+                        self.sniff = Sniff::Status(SmallBuf::new());
+                        self.sniff_status(&rest);
+                    }
+                    None => {
+                        let keep = head.len().saturating_sub(3);
+                        head.drain(..keep);
+                    }
+                }
+                return;
+            }
+            Sniff::Done => return,
+        };
+
+        let room = 64 - line.len();
+        let taken = written.len().min(room);
+        line.extend_from_slice(&written[..taken]);
+
+        // "HTTP/1.1 200 OK\r\n" - the code is complete once there's something after it
+        let line = line.as_slice();
+        let mut parts = line.splitn(3, |b| *b == b' ');
+        let code = match (parts.next(), parts.next(), parts.next()) {
+            (Some(_), Some(code), Some(_)) => Some(code),
+            _ if line.len() >= 64 || line.contains(&b'\n') => None,
+            _ => return,
+        };
+
+        let status: Option<u16> = code
+            .and_then(|code| std::str::from_utf8(code).ok())
+            .and_then(|code| code.trim().parse().ok());
+
+        match status {
+            Some(status) if (100..200).contains(&status) => {
+                self.sniff = Sniff::Interim(line.to_vec());
+                self.sniff_status(&written[taken..]);
+                return;
+            }
+            Some(status) => {
+                self.accounting
+                    .inner
+                    .status
+                    .store(status, Ordering::Relaxed);
+                let _ = self.accounting.inner.first_byte.set(Instant::now());
+            }
+            None => {}
+        }
+
+        self.sniff = Sniff::Done;
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.accounting
+            .inner
+            .written
+            .fetch_add(written as u64, Ordering::Relaxed);
+        self.sniff_status(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
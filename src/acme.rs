@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use crate::{headers::header, BeakResult, Handler, Request};
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/:token";
+
+/// The answers to ACME HTTP-01 challenges, served at `/.well-known/acme-challenge/` once handed to
+/// [`ServerBuilder::acme_challenges`](crate::ServerBuilder::acme_challenges).
+///
+/// beak doesn't talk to the certificate authority itself - that takes an https client and account keys it hasn't
+/// got - so this is for running alongside certbot, lego or acme.sh. Point one at the [webroot](Self::webroot) it
+/// writes challenge files into, or [insert](Self::insert) the answers from a client of your own. Either way it's
+/// plain http on port 80 the authority checks, so it wants a listener there.
+#[derive(Clone, Default)]
+pub struct Challenges {
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+    webroot: Option<PathBuf>,
+}
+
+impl Challenges {
+    pub fn new() -> Challenges {
+        Challenges::default()
+    }
+
+    /// Look for challenges the files in `<dir>/.well-known/acme-challenge/` don't answer, the way certbot's
+    /// `--webroot` and lego's `--http.webroot` leave them.
+    pub fn webroot(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.webroot = Some(dir.into());
+        self
+    }
+
+    /// Answers the challenge for `token` with `key_authorization` until it's removed.
+    pub fn insert(&self, token: impl Into<String>, key_authorization: impl Into<String>) {
+        self.tokens
+            .write()
+            .unwrap()
+            .insert(token.into(), key_authorization.into());
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.tokens.write().unwrap().remove(token);
+    }
+
+    /// What to answer the challenge for `token` with - from what's been inserted, then from the webroot.
+    pub fn key_authorization(&self, token: &str) -> Option<String> {
+        // tokens are base64url, which is also what keeps them from naming anything outside the webroot
+        let valid = !token.is_empty()
+            && token
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return None;
+        }
+
+        if let Some(answer) = self.tokens.read().unwrap().get(token) {
+            return Some(answer.clone());
+        }
+
+        let path = self
+            .webroot
+            .as_ref()?
+            .join(".well-known/acme-challenge")
+            .join(token);
+        let answer = fs::read_to_string(path).ok()?;
+        Some(answer.trim().to_owned())
+    }
+}
+
+// serves the challenges for ServerBuilder::acme_challenges
+pub(crate) struct ChallengeEndpoint {
+    pub(crate) challenges: Challenges,
+}
+
+impl<C: Send + Sync> Handler<C> for ChallengeEndpoint {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let answer = request
+            .params
+            .get("token")
+            .and_then(|token| self.challenges.key_authorization(token));
+
+        match answer {
+            Some(answer) => request.respond_with_bytes(
+                200,
+                vec![header("Content-Type", "application/octet-stream")],
+                answer.as_bytes(),
+            )?,
+            None => request.respond_with_bytes(404, vec![], b"not found")?,
+        }
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        CHALLENGE_PATH
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET"]
+    }
+
+    // ahead of a catch-all the app might have, which matchit wouldn't take alongside it otherwise
+    fn priority(&self) -> i32 {
+        i32::MAX
+    }
+}
+
+// reloads the server once the certificate and key on disk have been renewed. renewal writes one file and then the
+// other, so they have to have settled for an interval - and look like a PEM certificate and key - first, or the new
+// process would start with a certificate that doesn't go with its key
+#[cfg(all(unix, feature = "reload"))]
+pub(crate) fn watch(
+    certificate: PathBuf,
+    key: PathBuf,
+    interval: std::time::Duration,
+    reload: crate::reload::ReloadHandle,
+    shutdown: crate::ShutdownHandle,
+) {
+    let modified = || {
+        let modified =
+            |path: &std::path::Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+        Some((modified(&certificate).ok()?, modified(&key).ok()?))
+    };
+
+    let started = modified();
+    let mut last = started;
+    while !shutdown.is_shutdown() {
+        std::thread::sleep(interval);
+
+        let now = modified();
+        let settled = now.is_some() && now == last;
+        if settled && now != started && looks_renewed(&certificate, &key) {
+            reload.reload();
+            return;
+        }
+        last = now;
+    }
+}
+
+#[cfg(all(unix, feature = "reload"))]
+fn looks_renewed(certificate: &std::path::Path, key: &std::path::Path) -> bool {
+    let read = |path: &std::path::Path| fs::read_to_string(path).unwrap_or_default();
+
+    read(certificate).contains("-----BEGIN CERTIFICATE-----")
+        && read(key).contains("PRIVATE KEY-----")
+}
@@ -0,0 +1,196 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use crate::{config::Reloader, maintenance::MaintenanceHandle, profiler::Profiler, ShutdownHandle};
+
+pub(crate) type LogLevelHook = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+const HELP: &str = "\
+routes               list every route
+maintenance          print whether maintenance mode is on
+maintenance on|off   switch maintenance mode
+log-level <level>    change the log level
+reload               reload the config
+profiles             show the requests the profiler captured
+memory               show heap usage, with the alloc-stats feature
+shutdown             drain and stop the server
+quit                 close this connection
+";
+
+// where the admin listener listens
+pub(crate) enum AdminAddr {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// what the commands act on
+pub(crate) struct Admin {
+    pub(crate) routes: String,
+    pub(crate) maintenance: MaintenanceHandle,
+    pub(crate) shutdown: ShutdownHandle,
+    pub(crate) log_level: Option<LogLevelHook>,
+    pub(crate) config: Option<Arc<Reloader>>,
+    pub(crate) profiler: Option<Profiler>,
+}
+
+// stops the listener once the server is done
+pub(crate) struct AdminGuard {
+    stopped: Arc<AtomicBool>,
+    addr: BoundAddr,
+}
+
+enum BoundAddr {
+    Tcp(std::net::SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl AdminGuard {
+    pub(crate) fn close(self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        // accept blocks until someone connects, so someone does
+        match self.addr {
+            BoundAddr::Tcp(addr) => drop(TcpStream::connect(addr)),
+            #[cfg(unix)]
+            BoundAddr::Unix(path) => {
+                drop(UnixStream::connect(&path));
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+pub(crate) fn listen(addr: &AdminAddr, admin: Admin) -> io::Result<AdminGuard> {
+    let admin = Arc::new(admin);
+    let stopped = Arc::new(AtomicBool::new(false));
+
+    let bound = match addr {
+        AdminAddr::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            let bound = BoundAddr::Tcp(listener.local_addr()?);
+            let (admin, stopped) = (admin.clone(), stopped.clone());
+            thread::spawn(move || accept(listener.incoming(), admin, stopped));
+            bound
+        }
+        #[cfg(unix)]
+        AdminAddr::Unix(path) => {
+            // left behind by a server that didn't get to clean up - binding fails while it's there
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+            let listener = UnixListener::bind(path)?;
+            let (admin, stopped) = (admin.clone(), stopped.clone());
+            thread::spawn(move || accept(listener.incoming(), admin, stopped));
+            BoundAddr::Unix(path.clone())
+        }
+    };
+
+    Ok(AdminGuard {
+        stopped,
+        addr: bound,
+    })
+}
+
+fn accept<S: Send + 'static>(
+    connections: impl Iterator<Item = io::Result<S>>,
+    admin: Arc<Admin>,
+    stopped: Arc<AtomicBool>,
+) where
+    for<'s> &'s S: Read + Write,
+{
+    for stream in connections {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        if let Ok(stream) = stream {
+            let admin = admin.clone();
+            thread::spawn(move || session(BufReader::new(&stream), &stream, &admin));
+        }
+    }
+}
+
+// one command per line, each answered before the next is read
+fn session(reader: impl BufRead, mut writer: impl Write, admin: &Admin) {
+    for line in reader.lines() {
+        let Ok(line) = line else { return };
+        let mut words = line.split_whitespace();
+
+        let answer = match (words.next(), words.next(), words.next()) {
+            (None, ..) => continue,
+            (Some("quit"), None, _) => return,
+            (Some("help"), None, _) => Ok(HELP.to_owned()),
+            (Some("routes"), None, _) => Ok(admin.routes.clone()),
+            (Some("maintenance"), None, _) => Ok(match admin.maintenance.is_enabled() {
+                true => "on\n".to_owned(),
+                false => "off\n".to_owned(),
+            }),
+            (Some("maintenance"), Some("on"), None) => {
+                admin.maintenance.enable();
+                Ok("ok\n".to_owned())
+            }
+            (Some("maintenance"), Some("off"), None) => {
+                admin.maintenance.disable();
+                Ok("ok\n".to_owned())
+            }
+            (Some("log-level"), Some(level), None) => match &admin.log_level {
+                Some(hook) => hook(level).map(|_| "ok\n".to_owned()),
+                None => Err("no log level hook is set".to_owned()),
+            },
+            (Some("reload"), None, _) => match &admin.config {
+                Some(config) => config.reload().map(|reload| format!("{}\n", reload)),
+                None => Err("there's no config to reload".to_owned()),
+            },
+            (Some("profiles"), None, _) => match &admin.profiler {
+                Some(profiler) => Ok(match profiler.captured() {
+                    captured if captured.is_empty() => "nothing captured yet\n".to_owned(),
+                    captured => captured.iter().map(|profile| profile.to_string()).collect(),
+                }),
+                None => Err("there's no profiler".to_owned()),
+            },
+            (Some("memory"), None, _) => memory(),
+            (Some("shutdown"), None, _) => {
+                admin.shutdown.shutdown();
+                Ok("ok, draining\n".to_owned())
+            }
+            _ => Err(format!("unknown command {:?}, try help", line.trim())),
+        };
+
+        let written = match answer {
+            Ok(text) => writer.write_all(text.as_bytes()),
+            Err(message) => writeln!(writer, "error: {}", message),
+        };
+        if written.and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(feature = "alloc-stats")]
+fn memory() -> Result<String, String> {
+    crate::alloc_stats::stats()
+        .map(|stats| stats.to_string())
+        .ok_or_else(|| "CountingAllocator isn't the global allocator".to_owned())
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+fn memory() -> Result<String, String> {
+    Err("beak was built without the alloc-stats feature".to_owned())
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{headers::header, path, store::ShardedMap, BeakResult, Middleware, Next, Request};
+
+/// How many requests a key can make in a window of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub requests: u64,
+    pub per: Duration,
+}
+
+/// Who a key belongs to, in the [`extensions`](Request::extensions) of every request made with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub id: String,
+    /// Unlimited if there isn't one.
+    pub quota: Option<Quota>,
+}
+
+impl Account {
+    pub fn new(id: impl Into<String>) -> Account {
+        Account {
+            id: id.into(),
+            quota: None,
+        }
+    }
+
+    pub fn quota(mut self, requests: u64, per: Duration) -> Self {
+        self.quota = Some(Quota { requests, per });
+        self
+    }
+}
+
+/// Finds the account a key belongs to - a closure looking it up in a database, or a [`ShardedMap`] of keys to
+/// accounts.
+pub trait KeyLookup: Send + Sync {
+    fn lookup(&self, key: &str) -> Option<Account>;
+}
+
+impl<F: Fn(&str) -> Option<Account> + Send + Sync> KeyLookup for F {
+    fn lookup(&self, key: &str) -> Option<Account> {
+        self(key)
+    }
+}
+
+impl KeyLookup for ShardedMap<String, Account> {
+    fn lookup(&self, key: &str) -> Option<Account> {
+        self.get(key)
+    }
+}
+
+/// How much an account's keys have been used since the server started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    /// Requests let through.
+    pub requests: u64,
+    /// Requests turned away for going over the quota.
+    pub rejected: u64,
+}
+
+struct Counter {
+    window_started: Instant,
+    in_window: u64,
+    usage: KeyUsage,
+}
+
+/// The usage counters of an [`ApiKeys`], by account. Clones share the counters, so keep one for a
+/// [metrics source](crate::ServerBuilder::metrics_source) or a dashboard of your own.
+#[derive(Clone, Default)]
+pub struct ApiKeyUsage {
+    accounts: Arc<Mutex<HashMap<String, Counter>>>,
+}
+
+impl ApiKeyUsage {
+    pub fn get(&self, account: &str) -> Option<KeyUsage> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts.get(account).map(|counter| counter.usage)
+    }
+
+    /// Every account's counters in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let accounts = self.accounts.lock().unwrap();
+        let mut accounts: Vec<_> = accounts.iter().collect();
+        accounts.sort_by_key(|(account, _)| *account);
+
+        let mut text = String::new();
+        for (name, help, count) in [
+            (
+                "beak_api_key_requests_total",
+                "Requests let through, by account.",
+                (|usage: &KeyUsage| usage.requests) as fn(&KeyUsage) -> u64,
+            ),
+            (
+                "beak_api_key_rejected_total",
+                "Requests over the account's quota, by account.",
+                |usage: &KeyUsage| usage.rejected,
+            ),
+        ] {
+            let _ = write!(text, "# HELP {} {}\n# TYPE {} counter\n", name, help, name);
+            for (account, counter) in &accounts {
+                let account = account.replace('\\', "\\\\").replace('"', "\\\"");
+                let _ = writeln!(
+                    text,
+                    "{}{{account=\"{}\"}} {}",
+                    name,
+                    account,
+                    count(&counter.usage)
+                );
+            }
+        }
+        text
+    }
+
+    // counts a request, and how long until there's room again if it's over the quota
+    fn count(&self, account: &Account) -> Result<(), Duration> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let now = Instant::now();
+        let counter = accounts
+            .entry(account.id.clone())
+            .or_insert_with(|| Counter {
+                window_started: now,
+                in_window: 0,
+                usage: KeyUsage::default(),
+            });
+
+        if let Some(quota) = account.quota {
+            if now.duration_since(counter.window_started) >= quota.per {
+                counter.window_started = now;
+                counter.in_window = 0;
+            }
+            if counter.in_window >= quota.requests {
+                counter.usage.rejected += 1;
+                return Err(quota
+                    .per
+                    .saturating_sub(now.duration_since(counter.window_started)));
+            }
+        }
+
+        counter.in_window += 1;
+        counter.usage.requests += 1;
+        Ok(())
+    }
+}
+
+/// Lets requests through only with a key [`KeyLookup`] knows, sent in an `X-Api-Key` header by default, and
+/// within its account's [`Quota`]. Requests without one get a 401, and ones over their quota a 429 with a
+/// `Retry-After` - quotas are fixed windows, starting from an account's first request.
+///
+/// Handlers find who's calling with [`Request::account`].
+pub struct ApiKeys<L> {
+    lookup: L,
+    header: &'static str,
+    query: Option<&'static str>,
+    usage: ApiKeyUsage,
+}
+
+impl<L: KeyLookup> ApiKeys<L> {
+    pub fn new(lookup: L) -> ApiKeys<L> {
+        ApiKeys {
+            lookup,
+            header: "X-Api-Key",
+            query: None,
+            usage: ApiKeyUsage::default(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Takes keys from this query parameter too, for clients that can't set headers. Urls end up in logs and
+    /// browser histories, so it's off by default.
+    pub fn query(mut self, parameter: &'static str) -> Self {
+        self.query = Some(parameter);
+        self
+    }
+
+    pub fn usage(&self) -> ApiKeyUsage {
+        self.usage.clone()
+    }
+
+    fn key(&self, request: &Request<'_, '_, '_>) -> Option<String> {
+        if let Some(key) = request.header(self.header) {
+            return Some(key.trim().to_owned());
+        }
+
+        let parameter = self.query?;
+        request.query()?.split('&').find_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            (name == parameter)
+                .then(|| path::percent_decode(value).ok())
+                .flatten()
+        })
+    }
+}
+
+impl<C: Send + Sync, L: KeyLookup> Middleware<C> for ApiKeys<L> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let account = match self.key(&request).filter(|key| !key.is_empty()) {
+            Some(key) => self.lookup.lookup(&key),
+            None => {
+                request.respond_with_bytes(401, vec![], b"missing api key")?;
+                return Ok(());
+            }
+        };
+        let account = match account {
+            Some(account) => account,
+            None => {
+                request.respond_with_bytes(401, vec![], b"invalid api key")?;
+                return Ok(());
+            }
+        };
+
+        if let Err(wait) = self.usage.count(&account) {
+            // rounded up, so a retry doesn't land just before the window's over
+            let wait = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+            request.respond_with_bytes(
+                429,
+                vec![header("Retry-After", wait.to_string())],
+                b"quota exceeded",
+            )?;
+            return Ok(());
+        }
+
+        request.extensions.insert(account);
+        next.run(request, context)
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Who the request's API key belongs to, with [`ApiKeys`] in front of the handler.
+    pub fn account(&self) -> Option<&Account> {
+        self.extensions.get::<Account>()
+    }
+}
@@ -0,0 +1,109 @@
+use std::{cell::RefCell, slice, str};
+
+use crate::path::{self, PathError};
+
+// the first chunk a worker's arena gets, and the most it keeps between requests
+const CHUNK: usize = 4 * 1024;
+const MAX_KEPT: usize = 64 * 1024;
+
+/// A bump allocator for whatever a request needs for as long as it's being handled, from
+/// [`Request::arena`](crate::Request::arena). Every worker has one, emptied between its requests but with its memory
+/// kept - so once a worker's warmed up, what a request allocates here doesn't touch the allocator at all.
+///
+/// Allocating is just copying onto the end of a chunk. Nothing's freed until the request is done with, so it's for
+/// lots of little short-lived things - decoded parameters, keys built to look things up - not for anything that
+/// grows.
+pub struct Arena {
+    // a chunk never grows past the capacity it started with, so nothing handed out of it ever moves
+    chunks: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Arena {
+    pub(crate) fn new() -> Arena {
+        Arena {
+            chunks: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn alloc_bytes(&self, bytes: &[u8]) -> &[u8] {
+        self.alloc_with(bytes.len(), |chunk| {
+            chunk.extend_from_slice(bytes);
+            Ok::<_, ()>(())
+        })
+        .unwrap()
+    }
+
+    pub fn alloc_str(&self, string: &str) -> &str {
+        // just copied from a str, so still utf-8
+        unsafe { str::from_utf8_unchecked(self.alloc_bytes(string.as_bytes())) }
+    }
+
+    /// `encoded` with its `%xx` escapes decoded, in the arena - or just `encoded` when there aren't any. Fails like
+    /// [`path::percent_decode`].
+    pub fn percent_decode<'a>(&'a self, encoded: &'a str) -> Result<&'a str, PathError> {
+        if !encoded.contains('%') {
+            return Ok(encoded);
+        }
+
+        // decoding only ever shrinks things
+        let decoded = self.alloc_with(encoded.len(), |chunk| {
+            path::percent_decode_into(encoded, chunk)
+        })?;
+        str::from_utf8(decoded).map_err(|_| PathError::InvalidEncoding)
+    }
+
+    /// How many bytes have been allocated since the request started.
+    pub fn used(&self) -> usize {
+        self.chunks.borrow().iter().map(Vec::len).sum()
+    }
+
+    // `fill` gets the chunk to push at most `room` bytes onto - if it fails, whatever it pushed is taken back off
+    fn alloc_with<E>(
+        &self,
+        room: usize,
+        fill: impl FnOnce(&mut Vec<u8>) -> Result<(), E>,
+    ) -> Result<&[u8], E> {
+        let mut chunks = self.chunks.borrow_mut();
+        let fits = chunks
+            .last()
+            .is_some_and(|chunk| chunk.capacity() - chunk.len() >= room);
+        if !fits {
+            let last = chunks.last().map_or(CHUNK / 2, Vec::capacity);
+            chunks.push(Vec::with_capacity(room.max(last * 2)));
+        }
+
+        let chunk = chunks.last_mut().unwrap();
+        let (start, capacity) = (chunk.len(), chunk.capacity());
+        if let Err(e) = fill(chunk) {
+            chunk.truncate(start);
+            return Err(e);
+        }
+        assert!(
+            chunk.capacity() == capacity,
+            "arena chunk grew past the room asked for"
+        );
+
+        // the chunk's buffer stays put until reset, which can't happen while anything's borrowed from the arena
+        let allocated = &chunk[start..];
+        Ok(unsafe { slice::from_raw_parts(allocated.as_ptr(), allocated.len()) })
+    }
+
+    // empties the arena for the worker's next request. a request that needed more than one chunk gets them all as
+    // one to start the next with, so a worker settles on a chunk big enough for what it serves
+    pub(crate) fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        let capacity: usize = chunks.iter().map(Vec::capacity).sum();
+        if capacity > MAX_KEPT {
+            return chunks.clear();
+        }
+
+        match chunks.len() {
+            0 => {}
+            1 => chunks[0].clear(),
+            _ => {
+                chunks.clear();
+                chunks.push(Vec::with_capacity(capacity));
+            }
+        }
+    }
+}
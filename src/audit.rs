@@ -0,0 +1,65 @@
+use crate::{
+    tee::{Captured, Tee},
+    BeakResult, Middleware, Next, Request,
+};
+
+/// What an [`Audit`] hook gets: the request line, and the response exactly as it went to the client.
+#[derive(Debug)]
+pub struct AuditRecord {
+    pub method: String,
+    pub url: String,
+    /// The status code from the response's status line, if there was a response.
+    pub status: Option<u16>,
+    /// Status line, headers and body as written - chunked if it was sent chunked.
+    pub response: Vec<u8>,
+    /// Whether the response was longer than the cap, leaving `response` cut short.
+    pub truncated: bool,
+}
+
+/// Tees every response into a hook alongside the write to the client, for compliance logging that has to capture
+/// what was actually sent. Handlers don't notice.
+pub struct Audit<F> {
+    cap: usize,
+    hook: F,
+}
+
+impl<F: Fn(&AuditRecord) + Send + Sync> Audit<F> {
+    /// Keeps at most `cap` bytes of each response.
+    pub fn new(cap: usize, hook: F) -> Audit<F> {
+        Audit { cap, hook }
+    }
+}
+
+impl<C: Send + Sync, F: Fn(&AuditRecord) + Send + Sync> Middleware<C> for Audit<F> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let method = request.method().to_owned();
+        let url = request.url.to_owned();
+
+        let mut captured = Captured::default();
+        let request =
+            request.wrap_output(|output| Box::new(Tee::new(output, &mut captured, self.cap)));
+        let result = next.run(request, context);
+
+        (self.hook)(&AuditRecord {
+            method,
+            url,
+            status: status_code(&captured.bytes),
+            response: captured.bytes,
+            truncated: captured.truncated,
+        });
+
+        result
+    }
+}
+
+// "HTTP/1.1 200 OK" -> 200
+pub(crate) fn status_code(response: &[u8]) -> Option<u16> {
+    let line = response.split(|b| *b == b'\n').next()?;
+    let code = line.split(|b| *b == b' ').nth(1)?;
+    std::str::from_utf8(code).ok()?.parse().ok()
+}
@@ -0,0 +1,63 @@
+#[cfg(any(feature = "grpc-web", feature = "tls"))]
+pub(crate) const ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// the url- and filename-safe one, which goes without padding
+#[cfg(any(feature = "blobs", feature = "metadata"))]
+const URL_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// grpc-web clients may send each frame base64'd on its own, padding and all, so padding can turn up in the middle.
+// line breaks are skipped, for pem
+#[cfg(any(feature = "grpc-web", feature = "tls"))]
+pub(crate) fn decode(text: &[u8]) -> Option<Vec<u8>> {
+    decode_with(text, ALPHABET)
+}
+
+#[cfg(any(feature = "blobs", feature = "metadata"))]
+pub(crate) fn decode_url(text: &[u8]) -> Option<Vec<u8>> {
+    decode_with(text, URL_ALPHABET)
+}
+
+fn decode_with(text: &[u8], alphabet: &[u8; 64]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group = 0u32;
+    let mut bits = 0;
+
+    for &byte in text {
+        let value = match byte {
+            b'=' => {
+                // whatever's left over is padding, not data
+                group = 0;
+                bits = 0;
+                continue;
+            }
+            b'\r' | b'\n' => continue,
+            byte => alphabet.iter().position(|&b| b == byte)? as u32,
+        };
+
+        group = (group << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(any(feature = "blobs", feature = "metadata"))]
+pub(crate) fn encode_url(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 4).div_ceil(3));
+    for chunk in data.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(URL_ALPHABET[((group >> (18 - 6 * i)) & 63) as usize] as char);
+        }
+    }
+    encoded
+}
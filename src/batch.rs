@@ -0,0 +1,252 @@
+use std::{io, time::Duration};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    headers::{self, header},
+    loopback::{Connector, RawResponse},
+    server::Batched,
+    BeakResult, Handler, Request,
+};
+
+// enough for a page's worth of api calls, without one request tying a worker up for long
+const MAX_REQUESTS: usize = 20;
+const MAX_BODY: usize = 1024 * 1024;
+// sub-requests wait on free workers like any other request, which is no reason to wait forever
+const SUB_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// passed on from the batch itself to every sub-request that doesn't set its own
+const INHERITED: &[&str] = &["Host", "Cookie", "Authorization"];
+// how each sub-request's body is framed is up to us
+const FRAMING: &[&str] = &["Content-Length", "Transfer-Encoding", "Connection"];
+
+// serves ServerBuilder::batch_endpoint, sending each sub-request back into the server over a loopback transport
+pub(crate) struct BatchEndpoint {
+    pub(crate) path: &'static str,
+    pub(crate) connector: Connector,
+}
+
+struct SubRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl<C: Send + Sync> Handler<C> for BatchEndpoint {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        // checked once it's routed here, whatever path it took
+        if request.extensions.get::<Batched>().is_some() {
+            request.respond_with_bytes(400, vec![], b"batches can't be nested")?;
+            return Ok(());
+        }
+
+        let body = match request.body.read_to_vec(MAX_BODY) {
+            Ok(body) => body,
+            Err(_) => {
+                request.respond_with_bytes(413, vec![], b"batch is too large")?;
+                return Ok(());
+            }
+        };
+
+        // every sub-request is checked before any of them runs, so a bad one doesn't leave a batch half done
+        let batch: Result<Value, _> = serde_json::from_slice(&body);
+        let parsed: Result<Vec<SubRequest>, String> = match batch {
+            Ok(Value::Array(requests)) if requests.len() > MAX_REQUESTS => Err(format!(
+                "a batch can have at most {} requests",
+                MAX_REQUESTS
+            )),
+            Ok(Value::Array(requests)) => requests
+                .iter()
+                .enumerate()
+                .map(|(i, sub)| {
+                    self.parse(sub, &request)
+                        .map_err(|e| format!("request {}: {}", i, e))
+                })
+                .collect(),
+            _ => Err("a batch has to be a json array of requests".to_owned()),
+        };
+        let requests = match parsed {
+            Ok(requests) => requests,
+            Err(e) => {
+                request.respond_with_bytes(400, vec![], e.as_bytes())?;
+                return Ok(());
+            }
+        };
+
+        let responses: Vec<Value> = requests
+            .iter()
+            .map(|sub| match self.send(sub) {
+                Ok(response) => response_json(response),
+                // the others still get their say, this one just didn't get an answer
+                Err(e) => object(vec![
+                    ("status", 502.into()),
+                    ("error", e.to_string().into()),
+                ]),
+            })
+            .collect();
+
+        let json = serde_json::to_vec(&Value::Array(responses)).map_err(io::Error::from)?;
+        request.respond_with_bytes(200, vec![header("Content-Type", "application/json")], &json)?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["POST"]
+    }
+}
+
+impl BatchEndpoint {
+    fn parse(&self, sub: &Value, batch: &Request<'_, '_, '_>) -> Result<SubRequest, String> {
+        let method = sub["method"].as_str().unwrap_or("GET");
+        if method.is_empty() || !method.bytes().all(is_token_byte) {
+            return Err("invalid method".to_owned());
+        }
+
+        let path = sub["path"].as_str().ok_or("no path")?;
+        if !path.starts_with('/')
+            || path
+                .bytes()
+                .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return Err("invalid path".to_owned());
+        }
+
+        let mut headers = Vec::new();
+        if let Some(fields) = sub["headers"].as_object() {
+            for (name, value) in fields {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| format!("header {} isn't a string", name))?;
+                if FRAMING
+                    .iter()
+                    .any(|framing| framing.eq_ignore_ascii_case(name))
+                {
+                    return Err(format!("{} is set by the batch", name));
+                }
+                headers::checked_header(name, value).map_err(|e| e.to_string())?;
+                if is_forwarded(name) {
+                    continue;
+                }
+                headers.push((name.clone(), value.to_owned()));
+            }
+        }
+        for name in INHERITED {
+            let set = headers
+                .iter()
+                .any(|(set, _)| set.eq_ignore_ascii_case(name));
+            if let (false, Some(value)) = (set, batch.header(name)) {
+                headers.push((name.to_string(), value.to_owned()));
+            }
+        }
+
+        // json that isn't a string is a json body
+        let body = match &sub["body"] {
+            Value::Null => String::new(),
+            Value::String(body) => body.clone(),
+            body => {
+                let typed = headers
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"));
+                if !typed {
+                    headers.push(("Content-Type".to_owned(), "application/json".to_owned()));
+                }
+                body.to_string()
+            }
+        };
+
+        Ok(SubRequest {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            headers,
+            body,
+        })
+    }
+
+    fn send(&self, sub: &SubRequest) -> io::Result<RawResponse> {
+        let mut raw = format!("{} {} HTTP/1.1\r\n", sub.method, sub.path);
+        for (name, value) in &sub.headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            sub.body.len()
+        ));
+        raw.push_str(&sub.body);
+
+        let mut connection = self.connector.connect()?;
+        connection.set_timeout(Some(SUB_REQUEST_TIMEOUT))?;
+        connection.send(raw.as_bytes())?;
+        // 1xx responses aren't what anyone's asking for
+        loop {
+            let response = match sub.method.eq_ignore_ascii_case("HEAD") {
+                true => connection.head_response()?,
+                false => connection.response()?,
+            };
+            if !(100..200).contains(&response.status) {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+// sub-requests come from us over the loopback transport, and shouldn't get to say they were forwarded from anywhere else -
+// least of all with a client certificate some proxy checked
+fn is_forwarded(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name == "forwarded" || name == "x-real-ip" || name.starts_with("x-forwarded-")
+}
+
+// anything but a tchar makes for a request line tiny_http reads differently from how it was meant
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// headers sent more than once are joined up, the way they could have been sent in the first place
+fn response_json(response: RawResponse) -> Value {
+    let mut headers = Map::new();
+    for (name, value) in response.headers {
+        if FRAMING
+            .iter()
+            .any(|framing| framing.eq_ignore_ascii_case(&name))
+        {
+            continue;
+        }
+        // a header sent more than once becomes an array of its values - joining them with commas would run
+        // Set-Cookies together, whose dates have commas of their own
+        match headers.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value.into()),
+            Some(before) => *before = Value::Array(vec![std::mem::take(before), value.into()]),
+            None => {
+                headers.insert(name, value.into());
+            }
+        }
+    }
+
+    object(vec![
+        ("status", response.status.into()),
+        ("headers", Value::Object(headers)),
+        ("body", String::from_utf8_lossy(&response.body).into()),
+    ])
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect::<Map<String, Value>>(),
+    )
+}
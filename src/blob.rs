@@ -0,0 +1,237 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{base64, files, headers::header, random, Request};
+
+/// The SHA-256 of a blob's contents, which is also its name. Written as unpadded base64url, 43 characters, the way
+/// it goes in urls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobHash(pub [u8; 32]);
+
+impl BlobHash {
+    pub fn of(data: &[u8]) -> BlobHash {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(data));
+        BlobHash(hash)
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl fmt::Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&base64::encode_url(&self.0))
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("not a base64url sha-256")]
+pub struct InvalidHash;
+
+impl FromStr for BlobHash {
+    type Err = InvalidHash;
+
+    fn from_str(text: &str) -> Result<BlobHash, InvalidHash> {
+        if text.len() != 43 {
+            return Err(InvalidHash);
+        }
+        let decoded = base64::decode_url(text.as_bytes()).ok_or(InvalidHash)?;
+        let hash = decoded.try_into().map_err(|_| InvalidHash)?;
+        Ok(BlobHash(hash))
+    }
+}
+
+/// What [`BlobStore::put`] made of an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stored {
+    pub hash: BlobHash,
+    pub size: u64,
+    /// Whether this is the first time the store's seen these contents - if not, the upload was thrown away, and
+    /// the blob that was there already is the one that's kept.
+    pub new: bool,
+}
+
+/// A content-addressed store on disk: every blob is kept under the hash of what's in it, so uploading the same file
+/// twice keeps one copy, and a blob can never change under a link to it.
+///
+/// Blobs are in `<dir>/<first two hex digits>/<rest of the hex hash>`, with `<dir>/tmp` for uploads on their way
+/// in. Anything can read them straight from there, and [`serve_request`](Self::serve_request) does for a route
+/// like `/blob/:hash`.
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    /// Creates `dir` if it isn't there yet.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<BlobStore> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("tmp"))?;
+        Ok(BlobStore { dir })
+    }
+
+    /// Where the blob with this hash is, if there is one.
+    pub fn path(&self, hash: &BlobHash) -> PathBuf {
+        let hex = hash.to_hex();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    pub fn contains(&self, hash: &BlobHash) -> bool {
+        self.path(hash).is_file()
+    }
+
+    pub fn open(&self, hash: &BlobHash) -> io::Result<Option<File>> {
+        match File::open(self.path(hash)) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores everything `data` has to read, hashing it on the way to disk.
+    pub fn put(&self, data: &mut dyn Read) -> io::Result<Stored> {
+        let (temp, mut file) = self.temp_file()?;
+        // gone unless it makes it into the store
+        let written = write_hashed(data, &mut file).and_then(|written| {
+            file.sync_all()?;
+            Ok(written)
+        });
+        drop(file);
+
+        match written {
+            Ok((hash, size)) => self.settle(&temp, hash, size),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    /// Moves the file at `path` into the store - one that a handler taking uploads
+    /// [to disk](crate::Handler::multipart_to_disk) was handed, say. It has to be on the same filesystem as the
+    /// store, or it's copied in instead.
+    pub fn put_file(&self, path: impl AsRef<Path>) -> io::Result<Stored> {
+        let path = path.as_ref();
+        let (hash, size) = write_hashed(&mut File::open(path)?, &mut io::sink())?;
+
+        let (temp, _) = self.temp_file()?;
+        if fs::rename(path, &temp).is_err() {
+            fs::copy(path, &temp)?;
+            let _ = fs::remove_file(path);
+        }
+        self.settle(&temp, hash, size)
+    }
+
+    /// Whether there was a blob to delete. Anything linking to it stops working, so this is for blobs nobody
+    /// refers to anymore.
+    pub fn delete(&self, hash: &BlobHash) -> io::Result<bool> {
+        match fs::remove_file(self.path(hash)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Serves the blob named by the route's `hash` parameter - 404 for a hash it doesn't have, or one that isn't a
+    /// hash at all. Blobs never change, so they're cached for good.
+    pub fn serve_request(&self, request: Request<'_, '_, '_>) -> io::Result<()> {
+        let hash = request
+            .params
+            .get("hash")
+            .and_then(|hash| hash.parse::<BlobHash>().ok());
+        match hash {
+            Some(hash) => self.serve(request, &hash),
+            None => request.respond_with_bytes(404, vec![], b"not found"),
+        }
+    }
+
+    /// Serves the blob with this hash, with ranges and conditional requests like any
+    /// [file](crate::files::serve_file).
+    pub fn serve(&self, request: Request<'_, '_, '_>, hash: &BlobHash) -> io::Result<()> {
+        self.serve_as(request, hash, "application/octet-stream")
+    }
+
+    /// [`serve`](Self::serve), with a `Content-Type` - the store doesn't keep track of what its blobs are.
+    pub fn serve_as(
+        &self,
+        mut request: Request<'_, '_, '_>,
+        hash: &BlobHash,
+        content_type: &str,
+    ) -> io::Result<()> {
+        let path = self.path(hash);
+        if !path.is_file() {
+            return request.respond_with_bytes(404, vec![], b"not found");
+        }
+
+        request.add_response_header(header(
+            "Cache-Control",
+            "public, max-age=31536000, immutable",
+        ));
+        files::serve_file_as(request, &path, content_type)
+    }
+
+    fn temp_file(&self) -> io::Result<(PathBuf, File)> {
+        loop {
+            let path = self
+                .dir
+                .join("tmp")
+                .join(format!("{:016x}", random::bits()));
+            match File::options().write(true).create_new(true).open(&path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                file => return file.map(|file| (path, file)),
+            }
+        }
+    }
+
+    // moves a finished upload from `temp` to where its hash says, unless it's already there
+    fn settle(&self, temp: &Path, hash: BlobHash, size: u64) -> io::Result<Stored> {
+        let path = self.path(&hash);
+        let new = !path.is_file();
+
+        let settled = match new {
+            // two of the same at once both land here, and whichever's renamed last has the same contents anyway
+            true => {
+                fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::rename(temp, &path))
+            }
+            false => fs::remove_file(temp),
+        };
+        if let Err(e) = settled {
+            let _ = fs::remove_file(temp);
+            return Err(e);
+        }
+
+        Ok(Stored { hash, size, new })
+    }
+}
+
+// copies `data` into `out`, and what it hashes to and how long it was
+fn write_hashed(data: &mut dyn Read, out: &mut dyn Write) -> io::Result<(BlobHash, u64)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = match data.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..read]);
+        out.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&hasher.finalize());
+    Ok((BlobHash(hash), size))
+}
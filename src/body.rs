@@ -0,0 +1,283 @@
+use std::io::{self, Read};
+
+use thiserror::Error;
+
+/// A request's body, already decoded if it came with a `Content-Encoding` beak understands.
+pub struct Body<'b> {
+    reader: Box<dyn Read + 'b>,
+}
+
+impl<'b> Body<'b> {
+    pub(crate) fn new(reader: impl Read + 'b) -> Body<'b> {
+        Body {
+            reader: Box::new(reader),
+        }
+    }
+
+    pub fn empty() -> Body<'b> {
+        Body::new(io::empty())
+    }
+
+    /// Wraps the raw body in whatever decoder `encoding` calls for, inflating at most `limit` bytes.
+    /// Encodings we don't know are passed through, check [`can_decode`] first.
+    #[cfg(feature = "decompression")]
+    pub(crate) fn decoded(
+        reader: impl Read + 'b,
+        encoding: Option<&str>,
+        limit: usize,
+    ) -> Body<'b> {
+        use flate2::read::{GzDecoder, ZlibDecoder};
+
+        match encoding.map(Encoding::parse) {
+            Some(Some(Encoding::Gzip)) => {
+                Body::new(InflationLimit::new(GzDecoder::new(reader), limit))
+            }
+            Some(Some(Encoding::Deflate)) => {
+                Body::new(InflationLimit::new(ZlibDecoder::new(reader), limit))
+            }
+            _ => Body::new(reader),
+        }
+    }
+
+    /// Reads the whole body, failing once it grows past `limit` bytes.
+    pub fn read_to_vec(&mut self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut data)?;
+
+        if data.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request body is too large",
+            ));
+        }
+
+        Ok(data)
+    }
+}
+
+impl<'b> Read for Body<'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(feature = "decompression")]
+enum Encoding {
+    Identity,
+    Gzip,
+    Deflate,
+}
+
+#[cfg(feature = "decompression")]
+impl Encoding {
+    fn parse(encoding: &str) -> Option<Encoding> {
+        let encoding = encoding.trim();
+
+        if encoding.eq_ignore_ascii_case("identity") {
+            Some(Encoding::Identity)
+        } else if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+            Some(Encoding::Gzip)
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            Some(Encoding::Deflate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a body with this `Content-Encoding` can be decoded - if not, the request gets a 415.
+#[cfg(feature = "decompression")]
+pub(crate) fn can_decode(encoding: Option<&str>) -> bool {
+    encoding.is_none_or(|encoding| Encoding::parse(encoding).is_some())
+}
+
+/// The boundary parameter of a `multipart/*` content type.
+pub(crate) fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let (essence, params) = content_type.split_once(';')?;
+    if !essence
+        .trim()
+        .to_ascii_lowercase()
+        .starts_with("multipart/")
+    {
+        return None;
+    }
+
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
+
+/// The body ended before the `Content-Length` it was declared with - most likely the client went away halfway
+/// through an upload. Reading the body fails with an `UnexpectedEof` error carrying one of these, so a cut-off upload
+/// can't pass for a complete one.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("request body ended after {received} of the {expected} bytes it was declared with")]
+pub struct TruncatedBody {
+    pub expected: u64,
+    pub received: u64,
+}
+
+impl TruncatedBody {
+    /// The truncation behind a body read failing, if that's why it did.
+    pub fn from_io(error: &io::Error) -> Option<TruncatedBody> {
+        error.get_ref()?.downcast_ref().copied()
+    }
+}
+
+// counts what comes off the wire, before any decoding - Content-Length is about the encoded body
+pub(crate) struct LengthCheck<R> {
+    inner: R,
+    expected: Option<u64>,
+    received: u64,
+}
+
+impl<R> LengthCheck<R> {
+    pub(crate) fn new(inner: R, content_length: Option<&str>) -> LengthCheck<R> {
+        LengthCheck {
+            inner,
+            expected: content_length.and_then(|length| length.trim().parse().ok()),
+            received: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LengthCheck<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.received += read as u64;
+
+        match self.expected {
+            Some(expected) if read == 0 && !buf.is_empty() && self.received < expected => {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    TruncatedBody {
+                        expected,
+                        received: self.received,
+                    },
+                ))
+            }
+            _ => Ok(read),
+        }
+    }
+}
+
+// calls `report` every `interval` bytes and once at the end of the body
+pub(crate) struct Progress<R, F> {
+    inner: R,
+    report: F,
+    interval: u64,
+    received: u64,
+    next_report: u64,
+    done: bool,
+}
+
+impl<R, F: FnMut(u64, bool)> Progress<R, F> {
+    pub(crate) fn new(inner: R, interval: u64, report: F) -> Progress<R, F> {
+        Progress {
+            inner,
+            report,
+            interval,
+            received: 0,
+            next_report: interval,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read, F: FnMut(u64, bool)> Read for Progress<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.received += read as u64;
+
+        if read == 0 && !buf.is_empty() && !self.done {
+            self.done = true;
+            (self.report)(self.received, true);
+        } else if self.received >= self.next_report {
+            self.next_report = self.received + self.interval;
+            (self.report)(self.received, false);
+        }
+
+        Ok(read)
+    }
+}
+
+// a few kilobytes of gzip can inflate into gigabytes, so decoders never get to produce more than the limit
+#[cfg(feature = "decompression")]
+struct InflationLimit<R> {
+    inner: R,
+    remaining: usize,
+}
+
+#[cfg(feature = "decompression")]
+impl<R> InflationLimit<R> {
+    fn new(inner: R, limit: usize) -> InflationLimit<R> {
+        InflationLimit {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+#[cfg(feature = "decompression")]
+impl<R: Read> Read for InflationLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            // only an error if there's actually more to inflate
+            let mut probe = [0u8; 1];
+            return match self.inner.read(&mut probe)? {
+                0 => Ok(0),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "decompressed request body is too large",
+                )),
+            };
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_multipart_boundaries() {
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=abc123"),
+            Some("abc123")
+        );
+        assert_eq!(
+            multipart_boundary("Multipart/Mixed;charset=utf-8; BOUNDARY=\"a b:c\""),
+            Some("a b:c")
+        );
+        assert_eq!(
+            multipart_boundary("multipart/form-data ; boundary = xyz ;"),
+            Some("xyz")
+        );
+    }
+
+    #[test]
+    fn no_boundary_without_multipart() {
+        assert_eq!(multipart_boundary("multipart/form-data"), None);
+        assert_eq!(
+            multipart_boundary("multipart/form-data; charset=utf-8"),
+            None
+        );
+        assert_eq!(multipart_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=\"\""),
+            None
+        );
+        assert_eq!(multipart_boundary("text/plain; boundary=abc"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::HashMap, thread, time::Duration};
+
+use crate::{random::roll, BeakResult, Middleware, Next, Request};
+
+/// What can go wrong with a request, and how often. Probabilities are between 0 and 1.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    delay: Option<(f64, Duration, Duration)>,
+    drop: f64,
+    error: Option<(f64, u16)>,
+}
+
+impl Faults {
+    pub fn new() -> Faults {
+        Faults::default()
+    }
+
+    /// With `probability`, sleep for somewhere between `min` and `max` before doing anything else.
+    pub fn delay(mut self, probability: f64, min: Duration, max: Duration) -> Self {
+        self.delay = Some((probability, min, max.max(min)));
+        self
+    }
+
+    /// With `probability`, never answer - the handler doesn't run and nothing is written back, which most clients
+    /// end up reporting as a timeout.
+    pub fn drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// With `probability`, answer `status` instead of running the handler.
+    pub fn error(mut self, probability: f64, status: u16) -> Self {
+        self.error = Some((probability, status));
+        self
+    }
+}
+
+/// Fault injection, for using beak as a misbehaving stand-in for some upstream in tests: something like
+/// `Chaos::new().all(Faults::new().error(0.1, 503)).route("/upload", Faults::new().drop(0.5))`.
+///
+/// Routes are the path patterns handlers declare, and a route's faults replace the ones set for all routes rather
+/// than adding to them. Never use this outside of tests.
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    all: Faults,
+    routes: HashMap<&'static str, Faults>,
+}
+
+impl Chaos {
+    pub fn new() -> Chaos {
+        Chaos::default()
+    }
+
+    pub fn all(mut self, faults: Faults) -> Self {
+        self.all = faults;
+        self
+    }
+
+    pub fn route(mut self, route: &'static str, faults: Faults) -> Self {
+        self.routes.insert(route, faults);
+        self
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for Chaos {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let faults = self.routes.get(request.route()).unwrap_or(&self.all);
+
+        if let Some((probability, min, max)) = faults.delay {
+            if chance(probability) {
+                thread::sleep(min + (max - min).mul_f64(roll()));
+            }
+        }
+
+        if chance(faults.drop) {
+            return Ok(());
+        }
+
+        if let Some((probability, status)) = faults.error {
+            if chance(probability) {
+                request.respond_with_bytes(status, vec![], b"injected fault")?;
+                return Ok(());
+            }
+        }
+
+        next.run(request, context)
+    }
+}
+
+fn chance(probability: f64) -> bool {
+    probability > 0.0 && roll() < probability
+}
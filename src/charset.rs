@@ -0,0 +1,157 @@
+use thiserror::Error;
+
+use crate::MultipartEntry;
+
+/// What to do with bytes that aren't valid in the charset they're decoded as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoding {
+    /// Fail with [`CharsetError::Invalid`].
+    Strict,
+    /// Swap them for U+FFFD.
+    Lossy,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CharsetError {
+    #[error("unsupported charset {0}")]
+    Unsupported(String),
+    #[error("text is not valid {0}")]
+    Invalid(&'static str),
+}
+
+// what browsers actually send: utf-8, and windows-1252 or utf-16 from old pages. like browsers, the latin-1 and ascii
+// labels mean windows-1252
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Charset {
+    Utf8,
+    Windows1252,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Charset {
+    fn from_label(label: &str) -> Option<Charset> {
+        match label.trim().trim_matches('"').to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "unicode-1-1-utf-8" => Some(Charset::Utf8),
+            "windows-1252" | "cp1252" | "x-cp1252" | "iso-8859-1" | "iso8859-1" | "latin1"
+            | "l1" | "us-ascii" | "ascii" => Some(Charset::Windows1252),
+            "utf-16le" | "utf-16" => Some(Charset::Utf16Le),
+            "utf-16be" => Some(Charset::Utf16Be),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Windows1252 => "windows-1252",
+            Charset::Utf16Le => "utf-16le",
+            Charset::Utf16Be => "utf-16be",
+        }
+    }
+}
+
+// 0x80..0xa0 in windows-1252, where it differs from latin-1. the five holes decode to the control characters, which
+// is what browsers do too
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Decodes `bytes` as `charset` (UTF-8 if it's `None`). The UTF-8, UTF-16 and windows-1252 labels are understood,
+/// with `iso-8859-1` and `us-ascii` read as windows-1252 like browsers do. A byte order mark takes precedence over
+/// the label, and is dropped.
+pub fn decode(
+    bytes: &[u8],
+    charset: Option<&str>,
+    decoding: Decoding,
+) -> Result<String, CharsetError> {
+    let mut charset = match charset {
+        Some(label) => {
+            Charset::from_label(label).ok_or_else(|| CharsetError::Unsupported(label.to_owned()))?
+        }
+        None => Charset::Utf8,
+    };
+
+    let mut bytes = bytes;
+    for (bom, detected) in [
+        (&b"\xef\xbb\xbf"[..], Charset::Utf8),
+        (&b"\xff\xfe"[..], Charset::Utf16Le),
+        (&b"\xfe\xff"[..], Charset::Utf16Be),
+    ] {
+        if let Some(rest) = bytes.strip_prefix(bom) {
+            charset = detected;
+            bytes = rest;
+            break;
+        }
+    }
+
+    let invalid = || CharsetError::Invalid(charset.name());
+    match charset {
+        Charset::Utf8 => match decoding {
+            Decoding::Strict => String::from_utf8(bytes.to_vec()).map_err(|_| invalid()),
+            Decoding::Lossy => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        },
+        Charset::Windows1252 => Ok(bytes
+            .iter()
+            .map(|&byte| match byte {
+                0x80..=0x9f => WINDOWS_1252_HIGH[byte as usize - 0x80],
+                byte => byte as char,
+            })
+            .collect()),
+        Charset::Utf16Le | Charset::Utf16Be => {
+            let units = bytes.chunks_exact(2).map(|pair| match charset {
+                Charset::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let odd = bytes.len() % 2 == 1;
+
+            match decoding {
+                Decoding::Strict if odd => Err(invalid()),
+                Decoding::Strict => char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .map_err(|_| invalid()),
+                Decoding::Lossy => {
+                    let mut text: String = char::decode_utf16(units)
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    if odd {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                    }
+                    Ok(text)
+                }
+            }
+        }
+    }
+}
+
+impl<'v> MultipartEntry<'v> {
+    /// The `charset` parameter of the part's own content type.
+    pub fn charset(&self) -> Option<&str> {
+        self.content_type
+            .as_ref()
+            .and_then(|content_type| content_type.get_param(mime::CHARSET))
+            .map(|charset| charset.as_str())
+    }
+
+    /// Whether this is the `_charset_` field browsers fill in with the charset the rest of the form was sent in.
+    /// Its value is what [`text_in`](Self::text_in) wants as the form's charset.
+    pub fn is_charset_field(&self) -> bool {
+        &*self.name == "_charset_"
+    }
+
+    /// The field's value as text, in the part's own charset or UTF-8.
+    pub fn text(&self, decoding: Decoding) -> Result<String, CharsetError> {
+        self.text_in(None, decoding)
+    }
+
+    /// The field's value as text. The part's own charset wins, then `form_charset` (from a `_charset_` field, or
+    /// the form's `accept-charset`), then UTF-8.
+    pub fn text_in(
+        &self,
+        form_charset: Option<&str>,
+        decoding: Decoding,
+    ) -> Result<String, CharsetError> {
+        decode(self.data, self.charset().or(form_charset), decoding)
+    }
+}
@@ -0,0 +1,468 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use thiserror::Error;
+use tiny_http::Header;
+
+use crate::find_header;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("invalid url {0}")]
+    InvalidUrl(String),
+    #[error("only plain http is supported, not {0}")]
+    UnsupportedScheme(String),
+    #[error("invalid header {0}")]
+    InvalidHeader(String),
+    #[error("invalid method {0:?}")]
+    InvalidMethod(String),
+    #[error("malformed response: {0}")]
+    Malformed(&'static str),
+    #[error("response body is larger than {0} bytes")]
+    TooLarge(usize),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A small blocking HTTP/1.1 client for calling other services from a handler - webhooks, internal APIs, a proxied
+/// backend. Plain `http://` only, one connection per request, redirects aren't followed, and the whole response is
+/// read into memory.
+#[derive(Debug, Clone)]
+pub struct Client {
+    timeout: Option<Duration>,
+    max_response_size: usize,
+    user_agent: String,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client {
+            timeout: Some(Duration::from_secs(30)),
+            max_response_size: 16 * 1024 * 1024,
+            user_agent: concat!("beak/", env!("CARGO_PKG_VERSION")).to_owned(),
+        }
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    /// For connecting, and for every read and write after that - 30 seconds unless set otherwise.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Responses with bigger bodies fail with [`ClientError::TooLarge`], 16MiB by default.
+    pub fn max_response_size(mut self, limit: usize) -> Self {
+        self.max_response_size = limit;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn get(&self, url: &str) -> ClientRequest<'_> {
+        self.request("GET", url)
+    }
+
+    pub fn post(&self, url: &str) -> ClientRequest<'_> {
+        self.request("POST", url)
+    }
+
+    pub fn request(&self, method: &str, url: &str) -> ClientRequest<'_> {
+        ClientRequest {
+            client: self,
+            method: method.to_owned(),
+            url: url.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// A request being put together, sent with [`send`](Self::send).
+pub struct ClientRequest<'c> {
+    client: &'c Client,
+    method: String,
+    url: String,
+    headers: Vec<Header>,
+    body: Vec<u8>,
+    // kept until send, so headers can be chained without a Result in between
+    error: Option<ClientError>,
+}
+
+impl<'c> ClientRequest<'c> {
+    pub fn header(mut self, name: &str, value: impl AsRef<[u8]>) -> Self {
+        match Header::from_bytes(name.as_bytes(), value.as_ref()) {
+            Ok(header) => self.headers.push(header),
+            Err(_) => self.error = Some(ClientError::InvalidHeader(name.to_owned())),
+        }
+        self
+    }
+
+    /// Sent as is. Set a `Content-Type` to go with it.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn send(self) -> Result<ClientResponse, ClientError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        // anything but a token would change what the request line says
+        let token = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+        if self.method.is_empty() || !self.method.bytes().all(token) {
+            return Err(ClientError::InvalidMethod(self.method));
+        }
+
+        let target = Target::parse(&self.url)?;
+        let client = self.client;
+
+        let mut stream = connect(&target.authority, client.timeout)?;
+        stream.set_read_timeout(client.timeout)?;
+        stream.set_write_timeout(client.timeout)?;
+
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            self.method, target.path, target.host
+        );
+        let has = |name: &str| find_header(&self.headers, name).is_some();
+        if !has("User-Agent") {
+            head.push_str(&format!("User-Agent: {}\r\n", client.user_agent));
+        }
+        if !self.body.is_empty() || !matches!(self.method.as_str(), "GET" | "HEAD" | "OPTIONS") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for header in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", header.field, header.value));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()?;
+
+        read_response(
+            BufReader::new(stream),
+            self.method == "HEAD",
+            client.max_response_size,
+        )
+    }
+}
+
+/// A response, read in full.
+#[derive(Debug, Clone)]
+pub struct ClientResponse {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<Header>,
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The body as UTF-8, with anything invalid replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+struct Target {
+    // what goes in the Host header
+    host: String,
+    // what gets connected to, always with a port
+    authority: String,
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Target, ClientError> {
+        let invalid = || ClientError::InvalidUrl(url.to_owned());
+
+        let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+        if !scheme.eq_ignore_ascii_case("http") {
+            return Err(ClientError::UnsupportedScheme(scheme.to_owned()));
+        }
+        // spaces and control characters would end the request line or the Host header early, and start something
+        // else of their own
+        if rest.bytes().any(|b| b <= b' ' || b == 0x7f) {
+            return Err(invalid());
+        }
+
+        // fragments stay on the client
+        let rest = rest.split('#').next().unwrap_or(rest);
+        let split = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (host, path) = rest.split_at(split);
+        if host.is_empty() || host.contains('@') {
+            return Err(invalid());
+        }
+
+        // [::1] and [::1]:8080 have colons of their own
+        let has_port = match host.rfind(']') {
+            Some(bracket) => host[bracket..].contains(':'),
+            None => host.contains(':'),
+        };
+        let authority = if has_port {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+
+        let path = match path {
+            "" => "/".to_owned(),
+            path if path.starts_with('?') => format!("/{}", path),
+            path => path.to_owned(),
+        };
+
+        Ok(Target {
+            host: host.to_owned(),
+            authority,
+            path,
+        })
+    }
+}
+
+fn connect(authority: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in authority.to_socket_addrs()? {
+        let connected = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match connected {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+// generous for a status line or header, but keeps a misbehaving server from feeding us one endless line
+const MAX_LINE: usize = 16 * 1024;
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, ClientError> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE as u64)
+        .read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(ClientError::Malformed("line too long or cut short"));
+    }
+
+    let line = String::from_utf8(line).map_err(|_| ClientError::Malformed("line is not utf-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn read_response(
+    mut reader: impl BufRead,
+    head_only: bool,
+    limit: usize,
+) -> Result<ClientResponse, ClientError> {
+    let (status, reason, headers) = loop {
+        let status_line = read_line(&mut reader)?;
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().unwrap_or("").starts_with("HTTP/1.") {
+            return Err(ClientError::Malformed("bad status line"));
+        }
+        let status: u16 = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .filter(|status| (100..=999).contains(status))
+            .ok_or(ClientError::Malformed("bad status code"))?;
+        let reason = parts.next().unwrap_or("").to_owned();
+
+        let headers = read_headers(&mut reader)?;
+        // 1xx responses come before the real one
+        if !(100..200).contains(&status) {
+            break (status, reason, headers);
+        }
+    };
+
+    let no_body = head_only || status == 204 || status == 304;
+    let chunked = find_header(&headers, "Transfer-Encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let content_length = find_header(&headers, "Content-Length")
+        .map(|length| length.trim().parse::<u64>())
+        .transpose()
+        .map_err(|_| ClientError::Malformed("bad content-length"))?;
+
+    let body = if no_body {
+        Vec::new()
+    } else if chunked {
+        read_chunked(&mut reader, limit)?
+    } else if let Some(length) = content_length {
+        if length > limit as u64 {
+            return Err(ClientError::TooLarge(limit));
+        }
+        let mut body = Vec::with_capacity(length as usize);
+        (&mut reader).take(length).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Err(ClientError::Malformed("body cut short"));
+        }
+        body
+    } else {
+        // no length at all means the body runs until the server closes the connection
+        let mut body = Vec::new();
+        (&mut reader)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > limit {
+            return Err(ClientError::TooLarge(limit));
+        }
+        body
+    };
+
+    Ok(ClientResponse {
+        status,
+        reason,
+        headers,
+        body,
+    })
+}
+
+fn read_headers(reader: &mut impl BufRead) -> Result<Vec<Header>, ClientError> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ClientError::Malformed("bad header"))?;
+        let header = Header::from_bytes(name.trim().as_bytes(), value.trim().as_bytes())
+            .map_err(|_| ClientError::Malformed("bad header"))?;
+        headers.push(header);
+    }
+}
+
+fn read_chunked(reader: &mut impl BufRead, limit: usize) -> Result<Vec<u8>, ClientError> {
+    let mut body = Vec::new();
+    loop {
+        let size_line = read_line(reader)?;
+        // chunk extensions after a ; are allowed, and meaningless to us
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let size =
+            u64::from_str_radix(size, 16).map_err(|_| ClientError::Malformed("bad chunk size"))?;
+
+        if size == 0 {
+            // trailers, which we drop
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        let total = (body.len() as u64).checked_add(size);
+        if total.is_none_or(|total| total > limit as u64) {
+            return Err(ClientError::TooLarge(limit));
+        }
+
+        let before = body.len();
+        reader.by_ref().take(size).read_to_end(&mut body)?;
+        if ((body.len() - before) as u64) < size {
+            return Err(ClientError::Malformed("chunk cut short"));
+        }
+        if !read_line(reader)?.is_empty() {
+            return Err(ClientError::Malformed("chunk is longer than its size"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        let target = Target::parse("http://example.com").unwrap();
+        assert_eq!(
+            (
+                target.host.as_str(),
+                target.authority.as_str(),
+                target.path.as_str()
+            ),
+            ("example.com", "example.com:80", "/")
+        );
+        let target = Target::parse("http://[::1]:8080?q=1#fragment").unwrap();
+        assert_eq!(
+            (
+                target.host.as_str(),
+                target.authority.as_str(),
+                target.path.as_str()
+            ),
+            ("[::1]:8080", "[::1]:8080", "/?q=1")
+        );
+        assert!(matches!(
+            Target::parse("https://example.com/"),
+            Err(ClientError::UnsupportedScheme(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_targets_that_would_break_the_request_line() {
+        for url in [
+            "http://example.com/a b",
+            "http://example.com/a\r\nX-Injected: 1",
+            "http://example.com/\t",
+            "http://exa\0mple.com/",
+            "http://example.com/\x7f",
+            "http://user@example.com/",
+            "http:///path",
+        ] {
+            assert!(
+                matches!(Target::parse(url), Err(ClientError::InvalidUrl(_))),
+                "{:?}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_methods() {
+        let client = Client::new();
+        for method in ["", "GET /evil HTTP/1.1\r\n", "GE T", "GET\0"] {
+            assert!(
+                matches!(
+                    client.request(method, "http://127.0.0.1:9/").send(),
+                    Err(ClientError::InvalidMethod(_))
+                ),
+                "{:?}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn huge_chunks_are_too_large() {
+        let body = b"5\r\nhello\r\nffffffffffffffff\r\n";
+        assert!(matches!(
+            read_chunked(&mut &body[..], 1024),
+            Err(ClientError::TooLarge(1024))
+        ));
+        let body = b"5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(read_chunked(&mut &body[..], 1024).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,412 @@
+use std::{env, fmt, path::PathBuf, sync::Mutex, time::Duration};
+
+use thiserror::Error;
+
+use crate::{admin::LogLevelHook, maintenance::MaintenanceHandle, ServerBuilder};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("invalid value for {key}: {value:?}")]
+    Invalid { key: String, value: String },
+    #[error("unknown config key {0}")]
+    UnknownKey(String),
+    #[error("could not read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[cfg(feature = "config")]
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error("tls_cert and tls_key have to be set together")]
+    IncompleteTls,
+    #[error("tls is configured, but beak was built without the tls feature")]
+    TlsUnsupported,
+}
+
+/// Server settings read from the environment or a config file, so a deployment can change them without a rebuild.
+///
+/// Every setting is optional, and [`ServerBuilder::config`] only overrides what's set. Keys are the same everywhere:
+/// `workers` in a TOML file is `BEAK_WORKERS` in the environment. Sizes are in bytes, and durations are either
+/// seconds or have a unit (`500ms`, `30s`, `5m`, `1h`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BeakConfig {
+    pub addr: Option<String>,
+    pub workers: Option<usize>,
+    pub multipart_upload_limit: Option<usize>,
+    /// Only used with the `decompression` feature.
+    pub inflate_limit: Option<usize>,
+    pub output_buffer: Option<usize>,
+    pub drain_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    /// PEM certificate chain, needs the `tls` feature.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key, needs the `tls` feature.
+    pub tls_key: Option<PathBuf>,
+    /// beak doesn't log by itself - this is here so the app's logger can be configured along with everything else.
+    pub log_level: Option<String>,
+    /// Whether to be in [maintenance mode](crate::MaintenanceHandle).
+    pub maintenance: Option<bool>,
+    /// In bytes per second, for the app's [`Throttle`](crate::throttle::Throttle) - like `log_level`, beak only
+    /// hands it over.
+    pub rate_limit: Option<u64>,
+    /// For the app's [`StaticDir`](crate::files::StaticDir), handed over the same way.
+    pub static_dir: Option<PathBuf>,
+}
+
+const KEYS: &[&str] = &[
+    "addr",
+    "workers",
+    "multipart_upload_limit",
+    "inflate_limit",
+    "output_buffer",
+    "drain_timeout",
+    "request_timeout",
+    "tls_cert",
+    "tls_key",
+    "log_level",
+    "maintenance",
+    "rate_limit",
+    "static_dir",
+];
+
+// what a running server can pick up from a reloaded config, everything else needs a restart
+const RELOADABLE: &[&str] = &["log_level", "maintenance", "rate_limit", "static_dir"];
+
+impl BeakConfig {
+    /// Reads `BEAK_ADDR`, `BEAK_WORKERS` and so on.
+    pub fn from_env() -> Result<BeakConfig, ConfigError> {
+        BeakConfig::from_env_prefixed("BEAK_")
+    }
+
+    /// Like [`from_env`](Self::from_env), for apps that want their own prefix (`MYAPP_WORKERS`).
+    pub fn from_env_prefixed(prefix: &str) -> Result<BeakConfig, ConfigError> {
+        let mut config = BeakConfig::default();
+        for key in KEYS {
+            let var = format!("{}{}", prefix, key.to_ascii_uppercase());
+            if let Ok(value) = env::var(&var) {
+                config.set(key, &value)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a TOML file with the settings as top-level keys. Keys beak doesn't know are an error, so typos don't go
+    /// unnoticed.
+    #[cfg(feature = "config")]
+    pub fn from_toml(path: impl Into<PathBuf>) -> Result<BeakConfig, ConfigError> {
+        let path = path.into();
+        let text = std::fs::read_to_string(&path).map_err(|source| ConfigError::Read {
+            path: path.clone(),
+            source,
+        })?;
+
+        let table = match text.parse::<toml::Value>()? {
+            toml::Value::Table(table) => table,
+            _ => return Ok(BeakConfig::default()),
+        };
+
+        let mut config = BeakConfig::default();
+        for (key, value) in &table {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                value => {
+                    return Err(ConfigError::Invalid {
+                        key: key.clone(),
+                        value: value.to_string(),
+                    })
+                }
+            };
+            config.set(key, &value)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Settings from `overrides` where it has them, from `self` otherwise - for a config file with environment
+    /// variables on top, say.
+    pub fn merge(self, overrides: BeakConfig) -> BeakConfig {
+        BeakConfig {
+            addr: overrides.addr.or(self.addr),
+            workers: overrides.workers.or(self.workers),
+            multipart_upload_limit: overrides
+                .multipart_upload_limit
+                .or(self.multipart_upload_limit),
+            inflate_limit: overrides.inflate_limit.or(self.inflate_limit),
+            output_buffer: overrides.output_buffer.or(self.output_buffer),
+            drain_timeout: overrides.drain_timeout.or(self.drain_timeout),
+            request_timeout: overrides.request_timeout.or(self.request_timeout),
+            tls_cert: overrides.tls_cert.or(self.tls_cert),
+            tls_key: overrides.tls_key.or(self.tls_key),
+            log_level: overrides.log_level.or(self.log_level),
+            maintenance: overrides.maintenance.or(self.maintenance),
+            rate_limit: overrides.rate_limit.or(self.rate_limit),
+            static_dir: overrides.static_dir.or(self.static_dir),
+        }
+    }
+
+    // every setting as debug output, to tell which ones changed
+    fn fields(&self) -> [(&'static str, String); 13] {
+        [
+            ("addr", format!("{:?}", self.addr)),
+            ("workers", format!("{:?}", self.workers)),
+            (
+                "multipart_upload_limit",
+                format!("{:?}", self.multipart_upload_limit),
+            ),
+            ("inflate_limit", format!("{:?}", self.inflate_limit)),
+            ("output_buffer", format!("{:?}", self.output_buffer)),
+            ("drain_timeout", format!("{:?}", self.drain_timeout)),
+            ("request_timeout", format!("{:?}", self.request_timeout)),
+            ("tls_cert", format!("{:?}", self.tls_cert)),
+            ("tls_key", format!("{:?}", self.tls_key)),
+            ("log_level", format!("{:?}", self.log_level)),
+            ("maintenance", format!("{:?}", self.maintenance)),
+            ("rate_limit", format!("{:?}", self.rate_limit)),
+            ("static_dir", format!("{:?}", self.static_dir)),
+        ]
+    }
+
+    /// What changes going from `self` to `new` while the server runs: `new`'s reloadable settings take effect,
+    /// changes to anything else are rejected and left as they were.
+    pub fn reload(&self, new: BeakConfig) -> ConfigReload {
+        let mut applied = Vec::new();
+        let mut rejected = Vec::new();
+        for ((key, old), (_, new)) in self.fields().into_iter().zip(new.fields()) {
+            match (old == new, RELOADABLE.contains(&key)) {
+                (true, _) => {}
+                (false, true) => applied.push(key),
+                (false, false) => rejected.push(key),
+            }
+        }
+
+        ConfigReload {
+            config: BeakConfig {
+                log_level: new.log_level,
+                maintenance: new.maintenance,
+                rate_limit: new.rate_limit,
+                static_dir: new.static_dir,
+                ..self.clone()
+            },
+            applied,
+            rejected,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::Invalid {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        let number = || value.trim().parse::<usize>().map_err(|_| invalid());
+
+        match key {
+            "addr" => self.addr = Some(value.trim().to_owned()),
+            "workers" => self.workers = Some(number()?),
+            "multipart_upload_limit" => self.multipart_upload_limit = Some(number()?),
+            "inflate_limit" => self.inflate_limit = Some(number()?),
+            "output_buffer" => self.output_buffer = Some(number()?),
+            "drain_timeout" => {
+                self.drain_timeout = Some(parse_duration(value).ok_or_else(invalid)?)
+            }
+            "request_timeout" => {
+                self.request_timeout = Some(parse_duration(value).ok_or_else(invalid)?)
+            }
+            "tls_cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" => self.tls_key = Some(PathBuf::from(value)),
+            "log_level" => self.log_level = Some(value.trim().to_ascii_lowercase()),
+            "maintenance" => {
+                self.maintenance = Some(match value.trim().to_ascii_lowercase().as_str() {
+                    "true" | "on" | "yes" | "1" => true,
+                    "false" | "off" | "no" | "0" => false,
+                    _ => return Err(invalid()),
+                })
+            }
+            "rate_limit" => self.rate_limit = Some(value.trim().parse().map_err(|_| invalid())?),
+            "static_dir" => self.static_dir = Some(PathBuf::from(value)),
+            key => return Err(ConfigError::UnknownKey(key.to_owned())),
+        }
+
+        Ok(())
+    }
+}
+
+/// What [`BeakConfig::reload`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigReload {
+    /// What's in effect now: the new config's reloadable settings, and the old one's everything else.
+    pub config: BeakConfig,
+    /// Reloadable settings that changed.
+    pub applied: Vec<&'static str>,
+    /// Settings that changed but only take effect on a restart.
+    pub rejected: Vec<&'static str>,
+}
+
+impl fmt::Display for ConfigReload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.applied.as_slice() {
+            [] => write!(f, "config reloaded, nothing changed")?,
+            applied => write!(f, "config reloaded, applied {}", applied.join(", "))?,
+        }
+        if !self.rejected.is_empty() {
+            write!(
+                f,
+                "; {} can't change without a restart and were left as they were",
+                self.rejected.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) type ConfigLoader = Box<dyn Fn() -> Result<BeakConfig, ConfigError> + Send + Sync>;
+pub(crate) type ReloadHook = Box<dyn Fn(Result<&ConfigReload, &ConfigError>) + Send + Sync>;
+
+// loads the config again whenever asked to, and applies what it can
+pub(crate) struct Reloader {
+    pub(crate) loader: ConfigLoader,
+    pub(crate) current: Mutex<BeakConfig>,
+    pub(crate) maintenance: MaintenanceHandle,
+    pub(crate) log_level: Option<LogLevelHook>,
+    pub(crate) hooks: Vec<ReloadHook>,
+}
+
+impl Reloader {
+    // the error comes back as a string, since hooks already had the error itself
+    pub(crate) fn reload(&self) -> Result<ConfigReload, String> {
+        let reloaded = self.apply();
+        if self.hooks.is_empty() {
+            match &reloaded {
+                Ok(reload) => eprintln!("{}", reload),
+                Err(e) => eprintln!("could not reload config: {}", e),
+            }
+        }
+        for hook in &self.hooks {
+            hook(reloaded.as_ref());
+        }
+        reloaded.map_err(|e| e.to_string())
+    }
+
+    fn apply(&self) -> Result<ConfigReload, ConfigError> {
+        let new = (self.loader)()?;
+        let mut current = self.current.lock().unwrap();
+        let reload = current.reload(new);
+
+        if reload.applied.contains(&"log_level") {
+            if let (Some(hook), Some(level)) = (&self.log_level, &reload.config.log_level) {
+                hook(level).map_err(|_| ConfigError::Invalid {
+                    key: "log_level".to_owned(),
+                    value: level.clone(),
+                })?;
+            }
+        }
+        // only when the file changed it, so it doesn't undo switching maintenance on some other way
+        if reload.applied.contains(&"maintenance") {
+            match reload.config.maintenance {
+                Some(true) => self.maintenance.enable(),
+                Some(false) => self.maintenance.disable(),
+                None => {}
+            }
+        }
+
+        *current = reload.config.clone();
+        Ok(reload)
+    }
+}
+
+// polls `path` for changes - there's no portable way to be told about them
+#[cfg(feature = "config")]
+pub(crate) fn watch(
+    path: PathBuf,
+    interval: Duration,
+    reloader: std::sync::Arc<Reloader>,
+    shutdown: crate::ShutdownHandle,
+) {
+    let modified = || {
+        std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    let mut last = modified();
+    while !shutdown.is_shutdown() {
+        std::thread::sleep(interval);
+
+        let now = modified();
+        // a file that's gone for now is probably being replaced, and gets picked up once it's back
+        if now.is_some() && now != last {
+            let _ = reloader.reload();
+        }
+        last = now;
+    }
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    Some(match unit.trim() {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        "h" => Duration::from_secs(number * 60 * 60),
+        _ => return None,
+    })
+}
+
+impl<C: Clone + Send + Sync + 'static> ServerBuilder<C> {
+    /// Applies every setting `config` has, reading the TLS certificate and key if it names them.
+    pub fn config(mut self, config: &BeakConfig) -> Result<Self, ConfigError> {
+        if let Some(addr) = &config.addr {
+            self = self.addr(addr.clone());
+        }
+        if let Some(workers) = config.workers {
+            self = self.workers(workers);
+        }
+        if let Some(limit) = config.multipart_upload_limit {
+            self = self.multipart_upload_limit(limit);
+        }
+        #[cfg(feature = "decompression")]
+        if let Some(limit) = config.inflate_limit {
+            self = self.inflate_limit(limit);
+        }
+        if let Some(capacity) = config.output_buffer {
+            self = self.output_buffer(capacity);
+        }
+        if let Some(timeout) = config.drain_timeout {
+            self = self.drain_timeout(timeout);
+        }
+        if let Some(timeout) = config.request_timeout {
+            self = self.request_timeout(timeout);
+        }
+        if let Some(enabled) = config.maintenance {
+            self = self.maintenance(enabled);
+        }
+
+        match (&config.tls_cert, &config.tls_key) {
+            (None, None) => {}
+            #[cfg(feature = "tls")]
+            (Some(cert), Some(key)) => {
+                let read = |path: &PathBuf| {
+                    std::fs::read(path).map_err(|source| ConfigError::Read {
+                        path: path.clone(),
+                        source,
+                    })
+                };
+                self = self.tls(read(cert)?, read(key)?);
+            }
+            #[cfg(not(feature = "tls"))]
+            (Some(_), Some(_)) => return Err(ConfigError::TlsUnsupported),
+            _ => return Err(ConfigError::IncompleteTls),
+        }
+
+        self.loaded_config = self.loaded_config.clone().merge(config.clone());
+        Ok(self)
+    }
+}
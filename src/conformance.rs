@@ -0,0 +1,286 @@
+use std::{fmt, io, time::Duration};
+
+use crate::loopback::{Connection, Connector, RawResponse};
+
+// long enough for a loaded CI box, short enough that a hang doesn't stall the run
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a server should do with a [`Case`]'s request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expect {
+    /// Take it as a request and answer it as such: anything but a 400 or a 5xx.
+    Answered,
+    /// Answer the `HEAD` as a request, with a head and no body.
+    AnsweredHead,
+    /// Refuse it: a 4xx or 5xx, or closing the connection without answering.
+    Rejected,
+    /// Answer with exactly this status.
+    Status(u16),
+    /// Answer with exactly this status, then close the connection without answering what was sent after it.
+    StatusThenClosed(u16),
+    /// Answer this many pipelined requests, in the order they were sent.
+    Pipelined(usize),
+    /// Anything at all, even closing the connection - just not hanging, or falling over.
+    Survives,
+}
+
+/// One request in the matrix, as raw bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct Case {
+    pub name: &'static str,
+    pub request: &'static [u8],
+    pub expect: Expect,
+}
+
+/// How the server did with a case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub case: &'static str,
+    pub passed: bool,
+    /// The statuses the server answered with, or what went wrong reading them.
+    pub got: String,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.passed { "ok  " } else { "FAIL" };
+        write!(f, "{} {} ({})", verdict, self.case, self.got)
+    }
+}
+
+/// Malformed and edge-case requests, and what an http/1.1 server should do with each. They're all for `/`, which
+/// the server doesn't need to have a route for.
+pub const CASES: &[Case] = &[
+    Case {
+        name: "plain get",
+        request: b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::Answered,
+    },
+    Case {
+        name: "head",
+        request: b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::AnsweredHead,
+    },
+    Case {
+        name: "http/1.0 without a host",
+        request: b"GET / HTTP/1.0\r\n\r\n",
+        expect: Expect::Answered,
+    },
+    Case {
+        name: "missing host",
+        request: b"GET / HTTP/1.1\r\n\r\n",
+        expect: Expect::Status(400),
+    },
+    Case {
+        name: "two hosts",
+        request: b"GET / HTTP/1.1\r\nHost: a.example\r\nHost: b.example\r\n\r\n",
+        expect: Expect::Status(400),
+    },
+    Case {
+        name: "absolute-form target",
+        request: b"GET http://localhost/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::Survives,
+    },
+    Case {
+        name: "folded header",
+        request: b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n b\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "bare lf line endings",
+        request: b"GET / HTTP/1.1\nHost: localhost\n\n",
+        expect: Expect::Survives,
+    },
+    Case {
+        name: "garbage request line",
+        request: b"HELLO\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "unsupported version",
+        request: b"GET / HTTP/3.0\r\nHost: localhost\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "bad chunk size",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "overflowing chunk size",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffffffff\r\nhello\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "negative content length",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: -5\r\n\r\nhello",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "content-length and transfer-encoding (cl.te)",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+        expect: Expect::Status(400),
+    },
+    Case {
+        name: "transfer-encoding and content-length (te.cl)",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n",
+        expect: Expect::Status(400),
+    },
+    Case {
+        name: "cl.te followed by a request",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::StatusThenClosed(400),
+    },
+    Case {
+        name: "te.cl followed by a request",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::StatusThenClosed(400),
+    },
+    Case {
+        name: "obfuscated transfer-encoding (te.te)",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: x\r\n\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "unknown transfer-encoding",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: xchunked\r\n\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "chunked twice",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, chunked\r\n\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "transfer-encoding on http/1.0",
+        request: b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "conflicting content lengths",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "content length list",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5, 6\r\n\r\nhello!",
+        expect: Expect::Rejected,
+    },
+    Case {
+        name: "repeated identical content length",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+        expect: Expect::Survives,
+    },
+    Case {
+        name: "pipelined gets",
+        request: b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::Pipelined(2),
+    },
+    Case {
+        name: "pipelined post and get",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        expect: Expect::Pipelined(2),
+    },
+    Case {
+        name: "expect 100-continue",
+        request: b"POST / HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello",
+        expect: Expect::Survives,
+    },
+];
+
+/// Runs every case in [`CASES`] against the server behind `connector`, each on a connection of its own.
+pub fn run(connector: &Connector) -> Vec<Outcome> {
+    CASES.iter().map(|case| run_case(connector, case)).collect()
+}
+
+/// Runs `case` against the server behind `connector`.
+pub fn run_case(connector: &Connector, case: &Case) -> Outcome {
+    let (passed, got) = match attempt(connector, case) {
+        Ok(responses) => {
+            let statuses: Vec<u16> = responses.iter().map(|response| response.status).collect();
+            let got = match statuses.is_empty() {
+                true => "closed without answering".to_owned(),
+                false => format!("{:?}", statuses),
+            };
+            (judge(case.expect, &statuses), got)
+        }
+        Err(e) => (false, e.to_string()),
+    };
+
+    Outcome {
+        case: case.name,
+        passed,
+        got,
+    }
+}
+
+// every final response the server sent before it closed the connection, or stopped sending them - up to how many
+// were asked for
+fn attempt(connector: &Connector, case: &Case) -> io::Result<Vec<RawResponse>> {
+    let mut connection = connector.connect()?;
+    connection.set_timeout(Some(TIMEOUT))?;
+    // a server can answer and hang up before it's read everything, which is no reason not to read the answer
+    if let Err(e) = connection.send(case.request) {
+        if !closed(&e) {
+            return Err(e);
+        }
+    }
+
+    let wanted = match case.expect {
+        Expect::Pipelined(count) => count,
+        // one more than it should answer, which it has to close the connection instead of
+        Expect::StatusThenClosed(_) => 2,
+        _ => 1,
+    };
+    let mut responses = Vec::new();
+    while responses.len() < wanted {
+        match next_final(&mut connection, case.expect == Expect::AnsweredHead) {
+            Ok(response) => responses.push(response),
+            // closed (or reset) before answering is a fine way to turn a request down
+            Err(e) if closed(&e) => break,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(io::Error::new(e.kind(), "timed out waiting for a response"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(responses)
+}
+
+fn next_final(connection: &mut Connection, head: bool) -> io::Result<RawResponse> {
+    loop {
+        let response = match head {
+            true => connection.head_response()?,
+            false => connection.response()?,
+        };
+        if !(100..200).contains(&response.status) {
+            return Ok(response);
+        }
+    }
+}
+
+fn closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+fn judge(expect: Expect, statuses: &[u16]) -> bool {
+    let answered = |status: &u16| *status != 400 && *status < 500;
+
+    match expect {
+        Expect::Answered | Expect::AnsweredHead => statuses.first().is_some_and(answered),
+        Expect::Rejected => statuses.first().is_none_or(|status| *status >= 400),
+        Expect::Status(expected) => statuses.first() == Some(&expected),
+        Expect::StatusThenClosed(expected) => statuses == [expected],
+        Expect::Pipelined(count) => statuses.len() == count && statuses.iter().all(answered),
+        Expect::Survives => true,
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// The connection a request came in on, from [`Request::connection`](crate::Request::connection) and handed to
+/// the `on_connection_open` and `on_connection_close` hooks. Every request on a kept-alive connection sees the
+/// same one, so whatever's stored in it - who a client certificate says the client is, a rate limiter's bucket - is
+/// there for all of them.
+///
+/// tiny_http doesn't say when a connection opens or closes, so beak goes by the client's address: a connection opens
+/// with the first request from an address it isn't already tracking, and closes once a request says it's the last
+/// (`Connection: close`, or http/1.0 without keep-alive), once nothing's come in on it for the server's
+/// [`connection_idle`](crate::ServerBuilder::connection_idle) time, once a new connection from its ip takes its place
+/// under [`max_busy_connections_per_ip`](crate::ServerBuilder::max_busy_connections_per_ip), or when the server shuts
+/// down.
+/// Transports without client addresses, like unix sockets, open and close a connection around every request.
+pub struct ClientConnection {
+    id: u64,
+    peer: Option<SocketAddr>,
+    secure: bool,
+    opened: Instant,
+    requests: AtomicU64,
+    // requests being handled right now, which keep the connection from counting as idle however long they take
+    active: AtomicUsize,
+    // since `opened`, so it can be updated without a lock
+    last_seen: AtomicU64,
+    values: Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl ClientConnection {
+    fn new(id: u64, peer: Option<SocketAddr>, secure: bool) -> ClientConnection {
+        ClientConnection {
+            id,
+            peer,
+            secure,
+            opened: Instant::now(),
+            requests: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+            last_seen: AtomicU64::new(0),
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts up from 0 for every connection the server sees.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn opened(&self) -> Instant {
+        self.opened
+    }
+
+    /// How many requests have come in on the connection so far, the current one included.
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Stores `value` for the rest of the connection, handing back whatever value of the same type was there
+    /// before. There's room for one value of each type, like [`Extensions`](crate::Extensions).
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<Arc<T>> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|old| old.downcast().ok())
+    }
+
+    /// The value of type `T`, shared - pipelined requests on a connection can be handled at the same time, so
+    /// anything that changes has to manage that itself, with atomics or a lock.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&TypeId::of::<T>())?
+            .clone()
+            .downcast()
+            .ok()
+    }
+
+    /// The value of type `T`, stored from `init` first if there isn't one yet.
+    pub fn get_or_insert_with<T: Send + Sync + 'static>(&self, init: impl FnOnce() -> T) -> Arc<T> {
+        let mut values = self.values.lock().unwrap();
+        let value = values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Arc::new(init()))
+            .clone();
+        drop(values);
+
+        // stored under its own type id, so it's always this type
+        value.downcast().ok().unwrap()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .lock()
+            .unwrap()
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok())
+    }
+
+    fn idle_for(&self, now: Instant) -> Duration {
+        let last_seen = self.opened + Duration::from_millis(self.last_seen.load(Ordering::Relaxed));
+        now.saturating_duration_since(last_seen)
+    }
+}
+
+// the connections the server's tracking, by client address
+pub(crate) struct Connections {
+    open: RwLock<Open>,
+    idle: Duration,
+    per_ip: Option<usize>,
+    next_id: AtomicU64,
+    last_sweep: Mutex<Instant>,
+    // closed to make room under the per-ip limit, for the next sweep to hand on
+    evicted: Mutex<Vec<Arc<ClientConnection>>>,
+}
+
+#[derive(Default)]
+struct Open {
+    by_peer: HashMap<SocketAddr, Arc<ClientConnection>>,
+    // how many of them each client ip has open
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+impl Open {
+    fn insert(&mut self, peer: SocketAddr, connection: Arc<ClientConnection>) {
+        self.by_peer.insert(peer, connection);
+        *self.per_ip.entry(peer.ip()).or_default() += 1;
+    }
+
+    fn remove(&mut self, peer: &SocketAddr) -> Option<Arc<ClientConnection>> {
+        let connection = self.by_peer.remove(peer)?;
+        if let Some(count) = self.per_ip.get_mut(&peer.ip()) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_ip.remove(&peer.ip());
+            }
+        }
+        Some(connection)
+    }
+}
+
+impl Connections {
+    pub(crate) fn new(idle: Duration, per_ip: Option<usize>) -> Connections {
+        Connections {
+            open: RwLock::new(Open::default()),
+            idle,
+            per_ip,
+            next_id: AtomicU64::new(0),
+            last_sweep: Mutex::new(Instant::now()),
+            evicted: Mutex::new(Vec::new()),
+        }
+    }
+
+    // the connection a request from `peer` came in on, and whether it's only just opened - or None if it would be a
+    // new one, and its ip already has as many open as it's allowed, all with requests being handled
+    pub(crate) fn started(
+        &self,
+        peer: Option<SocketAddr>,
+        secure: bool,
+    ) -> Option<(Arc<ClientConnection>, bool)> {
+        let (connection, opened) = match peer {
+            Some(peer) => self.find_or_open(peer, secure)?,
+            None => (Arc::new(self.open_connection(None, secure)), true),
+        };
+
+        connection.requests.fetch_add(1, Ordering::Relaxed);
+        connection.last_seen.store(
+            connection.opened.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+        Some((connection, opened))
+    }
+
+    fn find_or_open(
+        &self,
+        peer: SocketAddr,
+        secure: bool,
+    ) -> Option<(Arc<ClientConnection>, bool)> {
+        // counted as active under the lock, so a sweep can't take it for idle in between
+        if let Some(connection) = self.open.read().unwrap().by_peer.get(&peer) {
+            connection.active.fetch_add(1, Ordering::Relaxed);
+            return Some((connection.clone(), false));
+        }
+
+        let mut open = self.open.write().unwrap();
+        // someone else could have got here first, with a pipelined request on the same connection
+        if let Some(connection) = open.by_peer.get(&peer) {
+            connection.active.fetch_add(1, Ordering::Relaxed);
+            return Some((connection.clone(), false));
+        }
+
+        let count = open.per_ip.get(&peer.ip()).copied().unwrap_or(0);
+        if self.per_ip.is_some_and(|limit| count >= limit) {
+            // clients mostly just go away without saying it was their last request, so one of the ip's connections
+            // that nothing's happening on has most likely closed - it makes way, and only the ones with requests
+            // being handled hold the new one off
+            let now = Instant::now();
+            let quietest = open
+                .by_peer
+                .iter()
+                .filter(|(other, connection)| {
+                    other.ip() == peer.ip() && connection.active.load(Ordering::Relaxed) == 0
+                })
+                .max_by_key(|(_, connection)| connection.idle_for(now))
+                .map(|(other, _)| *other)?;
+            let evicted = open.remove(&quietest)?;
+            self.evicted.lock().unwrap().push(evicted);
+        }
+
+        let connection = Arc::new(self.open_connection(Some(peer), secure));
+        open.insert(peer, connection.clone());
+        Some((connection, true))
+    }
+
+    fn open_connection(&self, peer: Option<SocketAddr>, secure: bool) -> ClientConnection {
+        let connection =
+            ClientConnection::new(self.next_id.fetch_add(1, Ordering::Relaxed), peer, secure);
+        connection.active.store(1, Ordering::Relaxed);
+        connection
+    }
+
+    // once a request's been answered - hands the connection back if that closed it
+    pub(crate) fn finished(
+        &self,
+        connection: &Arc<ClientConnection>,
+        last: bool,
+    ) -> Option<Arc<ClientConnection>> {
+        let peer = match connection.peer {
+            Some(peer) => peer,
+            None => return Some(connection.clone()),
+        };
+
+        let still_active = connection.active.fetch_sub(1, Ordering::Relaxed) > 1;
+        if !last || still_active {
+            return None;
+        }
+
+        let mut open = self.open.write().unwrap();
+        // not if it's already been closed, shutting down
+        match open.by_peer.get(&peer) {
+            Some(tracked) if Arc::ptr_eq(tracked, connection) => open.remove(&peer),
+            _ => None,
+        }
+    }
+
+    // the connections that have gone quiet, no longer tracked - only looked for every so often, apart from the ones
+    // that made way for another under the per-ip limit
+    pub(crate) fn sweep(&self) -> Vec<Arc<ClientConnection>> {
+        let mut closed = std::mem::take(&mut *self.evicted.lock().unwrap());
+
+        let now = Instant::now();
+        {
+            // somebody else is already on it
+            let Ok(mut last_sweep) = self.last_sweep.try_lock() else {
+                return closed;
+            };
+            if now.saturating_duration_since(*last_sweep) < self.idle / 4 {
+                return closed;
+            }
+            *last_sweep = now;
+        }
+
+        let mut open = self.open.write().unwrap();
+        let idle: Vec<SocketAddr> = open
+            .by_peer
+            .iter()
+            .filter(|(_, connection)| {
+                connection.active.load(Ordering::Relaxed) == 0
+                    && connection.idle_for(now) >= self.idle
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        closed.extend(idle.iter().filter_map(|peer| open.remove(peer)));
+        closed
+    }
+
+    // every connection still being tracked, for shutting down
+    pub(crate) fn close_all(&self) -> Vec<Arc<ClientConnection>> {
+        let mut open = self.open.write().unwrap();
+        open.per_ip.clear();
+        open.by_peer
+            .drain()
+            .map(|(_, connection)| connection)
+            .collect()
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{headers::header, random, BeakResult, Middleware, Next, Request};
+
+/// A source in a CSP directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// `'self'`
+    SelfOrigin,
+    /// `'none'`
+    None,
+    UnsafeInline,
+    UnsafeEval,
+    StrictDynamic,
+    /// `'nonce-...'` with this request's nonce, see [`CspNonce`].
+    Nonce,
+    /// A scheme like `https:` or `data:`.
+    Scheme(&'static str),
+    /// A host expression like `cdn.example.com` or `https://*.example.com`.
+    Host(String),
+    /// A `sha256-`/`sha384-`/`sha512-` hash of an inline script or style, already base64-encoded.
+    Hash(String),
+}
+
+impl Source {
+    /// Panics if `host` contains whitespace, quotes, `;` or `,`, which would break out of the directive.
+    pub fn host(host: impl Into<String>) -> Source {
+        let host = host.into();
+        assert!(is_clean(&host), "invalid csp host source: {:?}", host);
+        Source::Host(host)
+    }
+
+    /// Panics if `hash` isn't an `algorithm-base64` pair CSP understands.
+    pub fn hash(hash: impl Into<String>) -> Source {
+        let hash = hash.into();
+        let valid = ["sha256-", "sha384-", "sha512-"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+            && is_clean(&hash);
+        assert!(valid, "invalid csp hash source: {:?}", hash);
+        Source::Hash(hash)
+    }
+
+    fn render(&self, nonce: Option<&str>) -> String {
+        match self {
+            Source::SelfOrigin => "'self'".to_owned(),
+            Source::None => "'none'".to_owned(),
+            Source::UnsafeInline => "'unsafe-inline'".to_owned(),
+            Source::UnsafeEval => "'unsafe-eval'".to_owned(),
+            Source::StrictDynamic => "'strict-dynamic'".to_owned(),
+            Source::Nonce => format!("'nonce-{}'", nonce.unwrap_or_default()),
+            Source::Scheme(scheme) => format!("{}:", scheme.trim_end_matches(':')),
+            Source::Host(host) => host.clone(),
+            Source::Hash(hash) => format!("'{}'", hash),
+        }
+    }
+}
+
+fn is_clean(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, ';' | ',' | '\'' | '"'))
+}
+
+/// A `Content-Security-Policy`, sent with every response when used as middleware.
+///
+/// Policies using [`Source::Nonce`] get a fresh nonce per request, which handlers find in
+/// [`Request::extensions`](crate::Request::extensions) as a [`CspNonce`] to put on their `<script>` and `<style>`
+/// tags.
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(&'static str, Vec<Source>)>,
+    flags: Vec<&'static str>,
+    report_uri: Option<String>,
+    report_only: bool,
+}
+
+macro_rules! directives {
+    ($($method:ident => $name:literal),+ $(,)?) => {
+        $(
+            pub fn $method(self, sources: impl IntoIterator<Item = Source>) -> Self {
+                self.directive($name, sources)
+            }
+        )+
+    };
+}
+
+impl ContentSecurityPolicy {
+    pub fn new() -> ContentSecurityPolicy {
+        ContentSecurityPolicy::default()
+    }
+
+    /// A strict starting point: only same-origin resources, scripts gated by nonce, no plugins or framing.
+    pub fn strict() -> ContentSecurityPolicy {
+        ContentSecurityPolicy::new()
+            .default_src([Source::SelfOrigin])
+            .script_src([Source::Nonce, Source::StrictDynamic])
+            .object_src([Source::None])
+            .base_uri([Source::None])
+            .frame_ancestors([Source::None])
+    }
+
+    directives! {
+        default_src => "default-src",
+        script_src => "script-src",
+        style_src => "style-src",
+        img_src => "img-src",
+        connect_src => "connect-src",
+        font_src => "font-src",
+        media_src => "media-src",
+        object_src => "object-src",
+        frame_src => "frame-src",
+        worker_src => "worker-src",
+        manifest_src => "manifest-src",
+        frame_ancestors => "frame-ancestors",
+        base_uri => "base-uri",
+        form_action => "form-action",
+    }
+
+    /// Sets a directive, replacing it if it was already set.
+    pub fn directive(
+        mut self,
+        name: &'static str,
+        sources: impl IntoIterator<Item = Source>,
+    ) -> Self {
+        let sources = sources.into_iter().collect();
+        match self
+            .directives
+            .iter_mut()
+            .find(|(existing, _)| *existing == name)
+        {
+            Some((_, existing)) => *existing = sources,
+            None => self.directives.push((name, sources)),
+        }
+        self
+    }
+
+    pub fn upgrade_insecure_requests(mut self) -> Self {
+        self.flags.push("upgrade-insecure-requests");
+        self
+    }
+
+    /// Panics if `uri` contains whitespace, quotes, `;` or `,`.
+    pub fn report_uri(mut self, uri: impl Into<String>) -> Self {
+        let uri = uri.into();
+        assert!(is_clean(&uri), "invalid csp report uri: {:?}", uri);
+        self.report_uri = Some(uri);
+        self
+    }
+
+    /// Send `Content-Security-Policy-Report-Only` instead, to try a policy out without breaking anything.
+    pub fn report_only(mut self) -> Self {
+        self.report_only = true;
+        self
+    }
+
+    pub fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    pub fn uses_nonce(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|(_, sources)| sources.contains(&Source::Nonce))
+    }
+
+    /// The header value, with `nonce` filled in wherever the policy asks for one.
+    pub fn render(&self, nonce: Option<&str>) -> String {
+        let mut parts: Vec<String> = self
+            .directives
+            .iter()
+            .map(|(name, sources)| {
+                let sources = sources.iter().map(|source| source.render(nonce));
+                [name.to_string()]
+                    .into_iter()
+                    .chain(sources)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+
+        parts.extend(self.flags.iter().map(|flag| flag.to_string()));
+        if let Some(uri) = &self.report_uri {
+            parts.push(format!("report-uri {}", uri));
+        }
+
+        parts.join("; ")
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for ContentSecurityPolicy {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let nonce = if self.uses_nonce() {
+            Some(random::hex_token(16)?)
+        } else {
+            None
+        };
+
+        request.add_response_header(header(self.header_name(), self.render(nonce.as_deref())));
+        if let Some(nonce) = nonce {
+            request.extensions.insert(CspNonce(nonce));
+        }
+
+        next.run(request, context)
+    }
+}
+
+/// This request's CSP nonce.
+#[derive(Debug, Clone)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// `nonce="..."`, ready to go inside a `<script>` or `<style>` tag.
+    pub fn attribute(&self) -> String {
+        format!(r#"nonce="{}""#, self.0)
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{Cursor, Read};
+
+use multipart::server::Multipart;
+
+use crate::{
+    body::{self, Body},
+    headers::{self, header},
+    random,
+    upload::UploadedFiles,
+    BeakResult, Middleware, Next, Request,
+};
+
+const TOKEN_BYTES: usize = 32;
+
+/// Double-submit CSRF protection.
+///
+/// Every client gets a random token in a cookie, and requests with unsafe methods (anything but GET, HEAD, OPTIONS
+/// and TRACE) have to echo it back - in a header for scripts, or in a form field for plain HTML forms. Those that
+/// don't get a 403 and never reach the handler.
+///
+/// Multipart forms can carry the field too, as long as it's in the first [`form_limit`](Self::form_limit) bytes -
+/// put the hidden input before the file inputs. Handlers that only take the
+/// [first part](crate::Handler::needs_multipart) need it to be that part, or the token in the header. Handlers find the token in
+/// [`Request::extensions`](crate::Request::extensions) as a [`CsrfToken`], ready to drop into the forms they
+/// render.
+pub struct Csrf {
+    cookie: &'static str,
+    header: &'static str,
+    field: &'static str,
+    secure: bool,
+    form_limit: usize,
+}
+
+impl Default for Csrf {
+    fn default() -> Csrf {
+        Csrf {
+            cookie: "csrf_token",
+            header: "X-CSRF-Token",
+            field: "csrf_token",
+            secure: false,
+            form_limit: 64 * 1024,
+        }
+    }
+}
+
+impl Csrf {
+    pub fn new() -> Csrf {
+        Csrf::default()
+    }
+
+    pub fn cookie_name(mut self, name: &'static str) -> Self {
+        self.cookie = name;
+        self
+    }
+
+    pub fn header_name(mut self, name: &'static str) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// The form field checked when the header is missing, `csrf_token` by default.
+    pub fn field_name(mut self, name: &'static str) -> Self {
+        self.field = name;
+        self
+    }
+
+    /// Only send the cookie over HTTPS. Off by default so plain-HTTP development works.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// How much of a urlencoded form - or the start of a multipart one - is read looking for the token (64KiB by
+    /// default).
+    pub fn form_limit(mut self, limit: usize) -> Self {
+        self.form_limit = limit;
+        self
+    }
+
+    // the token sent with the request, from the header or from the form body
+    fn submitted_token(&self, request: &mut Request<'_, '_, '_>) -> Result<Option<String>, u16> {
+        if let Some(token) = request.header(self.header) {
+            return Ok(Some(token.trim().to_owned()));
+        }
+
+        if let Some(uploaded) = request.extensions.get::<UploadedFiles>() {
+            return Ok(uploaded
+                .field(self.field)
+                .map(|token| token.trim().to_owned()));
+        }
+        if let Some(entry) = &request.multipart_entry {
+            return Ok((&*entry.name == self.field)
+                .then(|| String::from_utf8_lossy(entry.data).trim().to_owned()));
+        }
+
+        let content_type = request.header("Content-Type").unwrap_or("");
+        if let Some(boundary) = body::multipart_boundary(content_type) {
+            let boundary = boundary.to_owned();
+            // only the start, since the rest could be files of any size
+            let mut start = Vec::new();
+            (&mut request.body)
+                .take(self.form_limit as u64)
+                .read_to_end(&mut start)
+                .map_err(|_| 400u16)?;
+            let token = multipart_field(&start, &boundary, self.field);
+
+            let rest = std::mem::replace(&mut request.body, Body::new(Cursor::new(Vec::new())));
+            request.body = Body::new(Cursor::new(start).chain(rest));
+            return Ok(token);
+        }
+
+        let is_form = request.header("Content-Type").is_some_and(|content_type| {
+            content_type
+                .trim()
+                .to_ascii_lowercase()
+                .starts_with("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Ok(None);
+        }
+
+        let form = request
+            .body
+            .read_to_vec(self.form_limit)
+            .map_err(|_| 413u16)?;
+        let token = String::from_utf8_lossy(&form).split('&').find_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            // tokens are hex, so there's never anything to percent-decode
+            (name == self.field).then(|| value.to_owned())
+        });
+
+        // put back what we read, for the handler
+        request.body = Body::new(Cursor::new(form));
+        Ok(token)
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for Csrf {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let token = match request.cookie(self.cookie).filter(|token| is_token(token)) {
+            Some(token) => token.to_owned(),
+            None => {
+                let token = random::hex_token(TOKEN_BYTES)?;
+                let secure = if self.secure { "; Secure" } else { "" };
+                request.add_response_header(header(
+                    "Set-Cookie",
+                    format!("{}={}; Path=/; SameSite=Lax{}", self.cookie, token, secure),
+                ));
+                token
+            }
+        };
+
+        let safe = headers::is_safe_method(request.method());
+
+        if !safe {
+            let valid = match self.submitted_token(&mut request) {
+                Ok(submitted) => {
+                    submitted.is_some_and(|submitted| constant_time_eq(&submitted, &token))
+                }
+                Err(status) => {
+                    request.respond_with_bytes(status, vec![], &[])?;
+                    return Ok(());
+                }
+            };
+
+            if !valid {
+                request.respond_with_bytes(403, vec![], b"invalid csrf token")?;
+                return Ok(());
+            }
+        }
+
+        request.extensions.insert(CsrfToken {
+            token,
+            field: self.field,
+        });
+
+        next.run(request, context)
+    }
+}
+
+/// This request's CSRF token, for putting into forms.
+#[derive(Debug, Clone)]
+pub struct CsrfToken {
+    token: String,
+    field: &'static str,
+}
+
+impl CsrfToken {
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    /// A hidden `<input>` carrying the token, to paste into rendered forms.
+    pub fn hidden_input(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            self.field, self.token
+        )
+    }
+}
+
+// the first field called `name` that isn't a file, in however much of a multipart body there is in `data`
+fn multipart_field(data: &[u8], boundary: &str, name: &str) -> Option<String> {
+    let mut multipart = Multipart::with_body(data, boundary);
+    while let Ok(Some(mut entry)) = multipart.read_entry() {
+        if entry.headers.filename.is_none() && &*entry.headers.name == name {
+            let mut value = String::new();
+            entry.data.read_to_string(&mut value).ok()?;
+            return Some(value.trim().to_owned());
+        }
+    }
+    None
+}
+
+// anything else in the cookie is junk (or an attack), and gets replaced
+fn is_token(token: &str) -> bool {
+    token.len() == TOKEN_BYTES * 2 && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// so how long a comparison takes doesn't leak how much of the token was right
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_field_among_the_parts() {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\nabc\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\ncut sh";
+        assert_eq!(
+            multipart_field(body.as_bytes(), "b", "csrf_token").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(multipart_field(body.as_bytes(), "b", "other"), None);
+        assert_eq!(multipart_field(b"not multipart", "b", "csrf_token"), None);
+    }
+}
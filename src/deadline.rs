@@ -0,0 +1,114 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{BeakResult, Middleware, Next, Request};
+
+/// Gives requests a deadline - a fixed timeout, or whatever the client asks for in `X-Request-Timeout` (in seconds,
+/// fractions allowed). Handlers check [`Request::time_remaining`] to give up on slow work in time, and once the
+/// deadline passes, the response can't be written any more.
+#[derive(Debug, Clone, Default)]
+pub struct Deadline {
+    timeout: Option<Duration>,
+    header_max: Option<Duration>,
+}
+
+impl Deadline {
+    pub fn new() -> Deadline {
+        Deadline::default()
+    }
+
+    /// For every request, unless the client asks for less.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Honor `X-Request-Timeout` - up to `max`, so clients can't hold a worker for however long they like.
+    pub fn from_header(mut self, max: Duration) -> Self {
+        self.header_max = Some(max);
+        self
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for Deadline {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let requested = self.header_max.and_then(|max| {
+            let seconds: f64 = request.header("X-Request-Timeout")?.trim().parse().ok()?;
+            Some(Duration::try_from_secs_f64(seconds).ok()?.min(max))
+        });
+
+        let timeout = match (self.timeout, requested) {
+            (Some(timeout), Some(requested)) => Some(timeout.min(requested)),
+            (timeout, requested) => timeout.or(requested),
+        };
+
+        match timeout {
+            Some(timeout) => next.run(request.with_deadline(Instant::now() + timeout), context),
+            None => next.run(request, context),
+        }
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Sets when this request has to be answered by. Past it, writing the response fails with
+    /// [`io::ErrorKind::TimedOut`]. A deadline later than the one already set is ignored.
+    pub fn with_deadline(mut self, deadline: Instant) -> Request<'url, 'sender, 'mv> {
+        if self.deadline.is_some_and(|current| current <= deadline) {
+            return self;
+        }
+
+        self.deadline = Some(deadline);
+        self.wrap_output(|inner| Box::new(DeadlineWriter { inner, deadline }))
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// How long until the deadline, zero once it's passed - or `None` if there isn't one.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn past_deadline(&self) -> bool {
+        self.time_remaining()
+            .is_some_and(|remaining| remaining.is_zero())
+    }
+}
+
+struct DeadlineWriter<'w> {
+    inner: Box<dyn Write + Send + 'w>,
+    deadline: Instant,
+}
+
+impl<'w> DeadlineWriter<'w> {
+    fn check(&self) -> io::Result<()> {
+        if Instant::now() >= self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request deadline has passed",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<'w> Write for DeadlineWriter<'w> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check()?;
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use crate::{
+    blob::{BlobHash, BlobStore},
+    files::json_string,
+    headers::header,
+    Request,
+};
+
+/// Something made from a blob, to go in the store next to it.
+pub struct Derived {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Makes one kind of derivative - a thumbnail, a preview - from blobs it knows what to do with.
+pub trait Deriver: Send + Sync {
+    /// What the derivative's called, like `thumbnail`, which is how clients ask after it. It's part of the record
+    /// kept of it on disk, so it shouldn't change.
+    fn name(&self) -> &'static str;
+
+    /// `None` for a blob there's nothing to make this from - a thumbnail of something that isn't an image.
+    fn derive(&self, source: &Path) -> io::Result<Option<Derived>>;
+}
+
+/// Where a derivative of a blob has got to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Running,
+    /// In the store, under this hash.
+    Done(BlobHash),
+    /// The deriver had nothing to make from the blob.
+    Skipped,
+    /// Until the blob's [submitted](Derivatives::submit) again.
+    Failed(String),
+}
+
+/// Derivatives of the blobs in a [`BlobStore`], made in the background once they're
+/// [submitted](Self::submit) - by a pool of threads of its own, so a slow resize doesn't hold up a worker.
+///
+/// Finished derivatives go into the store like any other blob, and a record of which blob each one came from goes
+/// into a directory of the pipeline's, so they're still there after a restart. Ones that are pending, running or
+/// failed are only kept track of in memory.
+pub struct Derivatives {
+    store: Arc<BlobStore>,
+    dir: PathBuf,
+    derivers: Vec<Box<dyn Deriver>>,
+    status: Mutex<HashMap<(BlobHash, &'static str), Status>>,
+    queue: Mutex<Option<mpsc::Sender<BlobHash>>>,
+}
+
+impl Derivatives {
+    /// Keeps its records in `dir`, creating it if it isn't there yet.
+    pub fn new(store: Arc<BlobStore>, dir: impl Into<PathBuf>) -> io::Result<Derivatives> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Derivatives {
+            store,
+            dir,
+            derivers: Vec::new(),
+            status: Mutex::new(HashMap::new()),
+            queue: Mutex::new(None),
+        })
+    }
+
+    pub fn deriver(mut self, deriver: impl Deriver + 'static) -> Self {
+        self.derivers.push(Box::new(deriver));
+        self
+    }
+
+    /// Starts `workers` threads making derivatives, which go on until [`shutdown`](Self::shutdown).
+    pub fn start(self, workers: usize) -> Arc<Derivatives> {
+        let (sender, receiver) = mpsc::channel();
+        *self.queue.lock().unwrap() = Some(sender);
+
+        let derivatives = Arc::new(self);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let (derivatives, receiver) = (derivatives.clone(), receiver.clone());
+            thread::spawn(move || loop {
+                let next = receiver.lock().unwrap().recv();
+                match next {
+                    Ok(source) => derivatives.make(&source),
+                    Err(_) => return,
+                }
+            });
+        }
+
+        derivatives
+    }
+
+    /// Lets the workers finish what they're on and stop. Anything still queued is dropped, and anything submitted
+    /// after stays pending.
+    pub fn shutdown(&self) {
+        self.queue.lock().unwrap().take();
+    }
+
+    /// Queues up every derivative of `source` that isn't done or underway already.
+    pub fn submit(&self, source: &BlobHash) {
+        let mut queued = false;
+        {
+            let mut status = self.status.lock().unwrap();
+            for deriver in &self.derivers {
+                let key = (*source, deriver.name());
+                let underway = matches!(status.get(&key), Some(Status::Pending | Status::Running));
+                if !underway && self.record(source, deriver.name()).is_none() {
+                    status.insert(key, Status::Pending);
+                    queued = true;
+                }
+            }
+        }
+
+        if queued {
+            if let Some(queue) = &*self.queue.lock().unwrap() {
+                let _ = queue.send(*source);
+            }
+        }
+    }
+
+    /// Where the derivative `name` of `source` has got to, or `None` if it's never been submitted.
+    pub fn status(&self, source: &BlobHash, name: &str) -> Option<Status> {
+        let deriver = self.derivers.iter().find(|d| d.name() == name)?;
+        if let Some(status) = self.status.lock().unwrap().get(&(*source, deriver.name())) {
+            return Some(status.clone());
+        }
+        self.record(source, deriver.name())
+            .map(|(status, _)| status)
+    }
+
+    /// Every derivative of `source` there's a status for, by name.
+    pub fn statuses(&self, source: &BlobHash) -> Vec<(&'static str, Status)> {
+        self.derivers
+            .iter()
+            .filter_map(|deriver| Some((deriver.name(), self.status(source, deriver.name())?)))
+            .collect()
+    }
+
+    /// Answers with the status of every derivative of the blob named by the route's `hash` parameter, as JSON:
+    /// `{"thumbnail": {"status": "done", "blob": "<hash>"}, "preview": {"status": "running"}}`. Failures
+    /// have an `error` too. 404 for a blob the store doesn't have.
+    pub fn serve_status(&self, request: Request<'_, '_, '_>) -> io::Result<()> {
+        let source = match self.source(&request) {
+            Some(source) => source,
+            None => return request.respond_with_bytes(404, vec![], b"not found"),
+        };
+
+        let entries: Vec<String> = self
+            .statuses(&source)
+            .into_iter()
+            .map(|(name, status)| {
+                let fields = match status {
+                    Status::Pending => r#""status": "pending""#.to_owned(),
+                    Status::Running => r#""status": "running""#.to_owned(),
+                    Status::Done(blob) => format!(r#""status": "done", "blob": "{}""#, blob),
+                    Status::Skipped => r#""status": "skipped""#.to_owned(),
+                    Status::Failed(error) => {
+                        format!(r#""status": "failed", "error": {}"#, json_string(&error))
+                    }
+                };
+                format!("{}: {{{}}}", json_string(name), fields)
+            })
+            .collect();
+
+        request.respond_with_bytes(
+            200,
+            vec![
+                header("Content-Type", "application/json"),
+                header("Cache-Control", "no-store"),
+            ],
+            format!("{{{}}}", entries.join(", ")).as_bytes(),
+        )
+    }
+
+    /// Serves the derivative named by the route's `name` parameter of the blob named by its `hash` - or a 202 with a
+    /// `Retry-After` while it's still being made, and a 404 if it isn't going to be.
+    pub fn serve_derivative(&self, request: Request<'_, '_, '_>) -> io::Result<()> {
+        let found = self.source(&request).and_then(|source| {
+            let name = request.params.get("name")?;
+            Some((source, self.status(&source, name)?))
+        });
+
+        match found {
+            Some((source, Status::Done(blob))) => {
+                let name = request.params.get("name").unwrap();
+                let content_type = self
+                    .record(&source, name)
+                    .and_then(|(_, content_type)| content_type);
+                let content_type = content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream");
+                self.store.serve_as(request, &blob, content_type)
+            }
+            Some((_, Status::Pending | Status::Running)) => {
+                request.respond_with_bytes(202, vec![header("Retry-After", "1")], &[])
+            }
+            _ => request.respond_with_bytes(404, vec![], b"not found"),
+        }
+    }
+
+    fn source(&self, request: &Request<'_, '_, '_>) -> Option<BlobHash> {
+        let source = request.params.get("hash")?.parse().ok()?;
+        self.store.contains(&source).then_some(source)
+    }
+
+    // makes whatever's pending for `source`
+    fn make(&self, source: &BlobHash) {
+        for deriver in &self.derivers {
+            let key = (*source, deriver.name());
+            {
+                let mut status = self.status.lock().unwrap();
+                if status.get(&key) != Some(&Status::Pending) {
+                    continue;
+                }
+                status.insert(key, Status::Running);
+            }
+
+            let path = self.store.path(source);
+            // image decoders have been known to panic on files crafted to make them, and that shouldn't take a
+            // worker with it
+            let derived = panic::catch_unwind(AssertUnwindSafe(|| deriver.derive(&path)))
+                .unwrap_or_else(|_| Err(io::Error::other("deriver panicked")));
+
+            let finished = derived.and_then(|derived| match derived {
+                Some(derived) => {
+                    let stored = self.store.put(&mut derived.data.as_slice())?;
+                    let record = format!("{} {}", stored.hash, derived.content_type);
+                    self.write_record(source, deriver.name(), &record)?;
+                    Ok(Status::Done(stored.hash))
+                }
+                None => {
+                    self.write_record(source, deriver.name(), "skipped")?;
+                    Ok(Status::Skipped)
+                }
+            });
+
+            let mut status = self.status.lock().unwrap();
+            match finished {
+                // on disk now, so there's no need to keep it in memory too
+                Ok(_) => status.remove(&key),
+                Err(e) => status.insert(key, Status::Failed(e.to_string())),
+            };
+        }
+    }
+
+    fn record_path(&self, source: &BlobHash, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", source.to_hex(), name))
+    }
+
+    // a finished derivative's status, and its content type if it's done: records are `skipped`, or the
+    // derivative's hash and content type
+    fn record(&self, source: &BlobHash, name: &str) -> Option<(Status, Option<String>)> {
+        let record = fs::read_to_string(self.record_path(source, name)).ok()?;
+        match record.trim().split_once(' ') {
+            Some((blob, content_type)) => {
+                let blob = blob.parse().ok()?;
+                Some((Status::Done(blob), Some(content_type.to_owned())))
+            }
+            None if record.trim() == "skipped" => Some((Status::Skipped, None)),
+            None => None,
+        }
+    }
+
+    fn write_record(&self, source: &BlobHash, name: &str, record: &str) -> io::Result<()> {
+        // written aside and renamed, so a half-written record is never read
+        let path = self.record_path(source, name);
+        let temp = path.with_extension(format!("{}.tmp", name));
+        fs::write(&temp, record)?;
+        fs::rename(temp, path)
+    }
+}
+
+/// The start of a text file, up to `max_bytes` of it - cut at a character, so it's still valid UTF-8. Files that
+/// don't start out as UTF-8, or have NUL bytes in them, aren't text, and get skipped.
+pub struct TextPreview {
+    name: &'static str,
+    max_bytes: usize,
+}
+
+impl TextPreview {
+    /// Called `preview`, and 1KiB long.
+    pub fn new() -> TextPreview {
+        TextPreview {
+            name: "preview",
+            max_bytes: 1024,
+        }
+    }
+
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+}
+
+impl Default for TextPreview {
+    fn default() -> TextPreview {
+        TextPreview::new()
+    }
+}
+
+impl Deriver for TextPreview {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn derive(&self, source: &Path) -> io::Result<Option<Derived>> {
+        let mut start = Vec::new();
+        fs::File::open(source)?
+            .take(self.max_bytes as u64)
+            .read_to_end(&mut start)?;
+
+        let text = match std::str::from_utf8(&start) {
+            Ok(text) => text,
+            // cut off in the middle of a character, which is fine, as long as it's only the last one
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&start[..e.valid_up_to()]).unwrap()
+            }
+            Err(_) => return Ok(None),
+        };
+        if text.contains('\0') {
+            return Ok(None);
+        }
+
+        Ok(Some(Derived {
+            content_type: "text/plain; charset=utf-8".to_owned(),
+            data: text.as_bytes().to_vec(),
+        }))
+    }
+}
+
+/// A scaled down copy of an image, made with [`images::thumbnail`](crate::images::thumbnail). Anything that isn't a
+/// png, jpeg, gif or webp image gets skipped.
+#[cfg(feature = "images")]
+pub struct Thumbnail {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    max_pixels: u64,
+}
+
+#[cfg(feature = "images")]
+impl Thumbnail {
+    /// Called `thumbnail`, fitting in `width` by `height`, of images up to 40 megapixels.
+    pub fn new(width: u32, height: u32) -> Thumbnail {
+        Thumbnail {
+            name: "thumbnail",
+            width,
+            height,
+            max_pixels: 40_000_000,
+        }
+    }
+
+    pub fn name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Bigger images fail instead, before they're decoded.
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+}
+
+#[cfg(feature = "images")]
+impl Deriver for Thumbnail {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn derive(&self, source: &Path) -> io::Result<Option<Derived>> {
+        use crate::images::{self, ImageError};
+
+        let data = fs::read(source)?;
+        match images::thumbnail(&data, self.width, self.height, self.max_pixels) {
+            Ok((format, data)) => Ok(Some(Derived {
+                content_type: format.mime().to_owned(),
+                data,
+            })),
+            Err(ImageError::UnknownFormat) => Ok(None),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
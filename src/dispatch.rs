@@ -0,0 +1,95 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+use tiny_http::{Request as TinyHttpRequest, Response};
+
+use crate::{headers::header, ShutdownHandle};
+
+/// Which queue a handler's requests wait in when every worker is busy, with
+/// [`ServerBuilder::priority_queue`](crate::ServerBuilder::priority_queue) on. Workers always take the oldest request
+/// of the highest class there is, so under load health checks and small API calls go ahead of the giant uploads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QueueClass {
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// For health checks and the like, which should answer even when everything else is backed up.
+    Critical,
+}
+
+const CLASSES: usize = 4;
+
+// how often idle workers look up to check for shutdown
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+// accepted requests, one queue per class, waiting for a worker
+pub(crate) struct Dispatch {
+    queues: Mutex<[VecDeque<TinyHttpRequest>; CLASSES]>,
+    queued: Condvar,
+    limit: usize,
+}
+
+impl Dispatch {
+    pub(crate) fn new(limit: usize) -> Dispatch {
+        Dispatch {
+            queues: Mutex::new(Default::default()),
+            queued: Condvar::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    /// Queues `request`. When the queues are full, something gets turned away with a 503: the oldest request of
+    /// the lowest class waiting, if that's lower than this one, or this one otherwise.
+    pub(crate) fn push(&self, class: QueueClass, request: TinyHttpRequest) {
+        let mut queues = self.queues.lock().unwrap();
+        let queued: usize = queues.iter().map(VecDeque::len).sum();
+
+        if queued >= self.limit {
+            let evicted = queues[..class as usize]
+                .iter_mut()
+                .find_map(VecDeque::pop_front);
+            match evicted {
+                Some(evicted) => {
+                    queues[class as usize].push_back(request);
+                    drop(queues);
+                    self.queued.notify_one();
+                    reject(evicted);
+                }
+                None => {
+                    drop(queues);
+                    reject(request);
+                }
+            }
+            return;
+        }
+
+        queues[class as usize].push_back(request);
+        drop(queues);
+        self.queued.notify_one();
+    }
+
+    /// The next request to serve, highest class first. Once shutdown starts, whatever was already queued still gets
+    /// handed out, and then this returns `None`.
+    pub(crate) fn pop(&self, shutdown: &ShutdownHandle) -> Option<TinyHttpRequest> {
+        let mut queues = self.queues.lock().unwrap();
+        loop {
+            if let Some(request) = queues.iter_mut().rev().find_map(VecDeque::pop_front) {
+                return Some(request);
+            }
+            if shutdown.is_shutdown() {
+                return None;
+            }
+
+            queues = self.queued.wait_timeout(queues, SHUTDOWN_POLL).unwrap().0;
+        }
+    }
+}
+
+fn reject(request: TinyHttpRequest) {
+    let response = Response::empty(503).with_header(header("Retry-After", "1"));
+    let _ = TinyHttpRequest::ignore_client_closing_errors(request.respond(response));
+}
@@ -0,0 +1,183 @@
+use std::{collections::HashMap, io::Write, sync::OnceLock};
+
+use flate2::{write::GzEncoder, Compression};
+pub use include_dir::Dir;
+
+use crate::{
+    hash,
+    headers::{self, header},
+    path::{self, PathError},
+    range::{self, ByteRange},
+    Request,
+};
+
+struct Asset {
+    contents: &'static [u8],
+    gzipped: Option<Vec<u8>>,
+    content_type: String,
+    etag: String,
+    // the gzipped bytes are a representation of their own, with a tag of their own
+    gzipped_etag: String,
+}
+
+/// A directory baked into the binary with `include_dir!`, served with ETags, gzip and byte ranges.
+///
+/// Everything is indexed (and compressible files gzipped) the first time it's served. Usually built through
+/// [`embed_dir!`](crate::embed_dir) rather than by hand.
+pub struct EmbeddedDir {
+    dir: &'static Dir<'static>,
+    assets: OnceLock<HashMap<String, Asset>>,
+}
+
+impl EmbeddedDir {
+    pub const fn new(dir: &'static Dir<'static>) -> EmbeddedDir {
+        EmbeddedDir {
+            dir,
+            assets: OnceLock::new(),
+        }
+    }
+
+    fn assets(&self) -> &HashMap<String, Asset> {
+        self.assets.get_or_init(|| {
+            let mut assets = HashMap::new();
+            index(self.dir, &mut assets);
+            assets
+        })
+    }
+
+    /// Serves the file the request's catch-all parameter names (or its whole path, if the route doesn't have one).
+    pub fn serve_request(&self, request: Request<'_, '_, '_>) -> std::io::Result<()> {
+        let path = match request.wildcard() {
+            Err(PathError::NoWildcard) => {
+                path::percent_decode(request.path()).and_then(|decoded| path::normalize(&decoded))
+            }
+            path => path,
+        };
+
+        match path {
+            Ok(path) => self.serve(request, &path),
+            Err(_) => request.respond_with_bytes(400, vec![], b"bad path"),
+        }
+    }
+
+    /// Serves the file at `path` (relative to the embedded directory), falling back to `index.html` for
+    /// directories.
+    pub fn serve(&self, request: Request<'_, '_, '_>, path: &str) -> std::io::Result<()> {
+        let path = path.trim_matches('/');
+        let assets = self.assets();
+
+        let index = match path {
+            "" => "index.html".to_owned(),
+            path => format!("{}/index.html", path),
+        };
+        let asset = match assets.get(path).or_else(|| assets.get(&index)) {
+            Some(asset) => asset,
+            None => return request.respond_with_bytes(404, vec![], b"not found"),
+        };
+
+        // ranges are of the plain contents, so only whole responses get gzipped
+        let len = asset.contents.len() as u64;
+        let range = request
+            .header("Range")
+            .filter(|_| range::if_range(request.header("If-Range"), Some(&asset.etag), None));
+        let range = ByteRange::parse(range, len);
+        let gzipped = asset.gzipped.as_ref().filter(|_| {
+            matches!(range, ByteRange::Full)
+                && headers::accepts_encoding(request.header("Accept-Encoding"), "gzip")
+        });
+        let etag = match gzipped {
+            Some(_) => &asset.gzipped_etag,
+            None => &asset.etag,
+        };
+
+        let mut response_headers = vec![
+            header("ETag", etag),
+            header("Accept-Ranges", "bytes"),
+            header("Vary", "Accept-Encoding"),
+        ];
+
+        // the weak comparison, as If-None-Match does it
+        if request.header("If-None-Match").is_some_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        }) {
+            return request.respond_without_body(304, response_headers);
+        }
+
+        response_headers.push(header("Content-Type", &asset.content_type));
+
+        match range {
+            ByteRange::Partial(range) => {
+                response_headers.push(header(
+                    "Content-Range",
+                    range::content_range(Some(&range), len),
+                ));
+                let part = &asset.contents[range.start as usize..range.end as usize];
+                request.respond_with_bytes(206, response_headers, part)
+            }
+            ByteRange::Unsatisfiable => {
+                response_headers.push(header("Content-Range", range::content_range(None, len)));
+                request.respond_with_bytes(416, response_headers, &[])
+            }
+            ByteRange::Full => match gzipped {
+                Some(gzipped) => {
+                    response_headers.push(header("Content-Encoding", "gzip"));
+                    request.respond_with_bytes(200, response_headers, gzipped)
+                }
+                None => request.respond_with_bytes(200, response_headers, asset.contents),
+            },
+        }
+    }
+}
+
+fn index(dir: &'static Dir<'static>, assets: &mut HashMap<String, Asset>) {
+    for file in dir.files() {
+        let path = file.path().to_string_lossy().replace('\\', "/");
+        let content_type = mime_guess::from_path(file.path())
+            .first_or_octet_stream()
+            .to_string();
+
+        let contents = file.contents();
+        let gzipped = if is_compressible(&content_type) {
+            gzip(contents).filter(|gzipped| gzipped.len() < contents.len())
+        } else {
+            None
+        };
+
+        let hash = hash::fnv1a(contents);
+        assets.insert(
+            path,
+            Asset {
+                contents,
+                gzipped,
+                content_type,
+                etag: format!("\"{:016x}\"", hash),
+                gzipped_etag: format!("\"{:016x}-gzip\"", hash),
+            },
+        );
+    }
+
+    for dir in dir.dirs() {
+        index(dir, assets);
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.ends_with("+xml")
+        || content_type.ends_with("+json")
+        || [
+            "application/javascript",
+            "application/json",
+            "application/xml",
+            "image/svg+xml",
+        ]
+        .contains(&content_type)
+}
+
+fn gzip(contents: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(contents).ok()?;
+    encoder.finish().ok()
+}
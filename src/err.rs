@@ -1,9 +1,142 @@
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    error::Error,
+    fmt,
+};
+
 use thiserror::Error;
 
+use crate::{RouteError, TruncatedBody};
+
+type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
 #[derive(Error, Debug)]
 pub enum BeakError {
     #[error(transparent)]
     IOError(#[from] std::io::Error),
+    #[error("could not bind {addr}: {source}")]
+    Bind { addr: String, source: BoxError },
+    #[error("the tls certificate isn't for virtual host {0}, and there's no picking another by SNI")]
+    UncoveredHost(String),
+    #[error("{transports} listeners need a worker each, but there are only {workers} workers")]
+    TooFewWorkers { workers: usize, transports: usize },
+    #[error("invalid route {path}: {source}")]
+    Route {
+        path: &'static str,
+        source: matchit::InsertError,
+    },
+    #[error(transparent)]
+    Routing(#[from] RouteError),
+    #[error(transparent)]
+    Body(#[from] TruncatedBody),
+    #[error("could not parse {what}: {source}")]
+    Parse {
+        what: &'static str,
+        source: BoxError,
+    },
+    #[error("handler failed: {0}")]
+    Handler(#[source] BoxError),
+    #[error("handler panicked: {message}")]
+    Panic { message: String },
+    /// Another error, with what beak knew about the request it happened in.
+    #[error("{context}: {source}")]
+    InRequest {
+        context: Box<ErrorContext>,
+        source: Box<BeakError>,
+    },
+}
+
+impl BeakError {
+    /// Wraps whatever went wrong in a handler, keeping it as the source so the whole chain shows up.
+    pub fn handler(error: impl Into<BoxError>) -> BeakError {
+        BeakError::Handler(error.into())
+    }
+
+    /// For bodies, headers or params that didn't parse as `what`.
+    pub fn parse(what: &'static str, error: impl Into<BoxError>) -> BeakError {
+        BeakError::Parse {
+            what,
+            source: error.into(),
+        }
+    }
+
+    /// Attaches request context. An error that already has some keeps the context it had - that's the request it
+    /// happened in.
+    pub fn in_request(self, context: ErrorContext) -> BeakError {
+        match self {
+            BeakError::InRequest { .. } => self,
+            source => BeakError::InRequest {
+                context: Box::new(context),
+                source: Box::new(source),
+            },
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            BeakError::InRequest { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error underneath any request context.
+    pub fn root(&self) -> &BeakError {
+        match self {
+            BeakError::InRequest { source, .. } => source.root(),
+            error => error,
+        }
+    }
+}
+
+/// Where a [`BeakError`] happened.
+#[derive(Debug)]
+pub struct ErrorContext {
+    pub method: String,
+    /// As the client sent it, before any rewrites.
+    pub url: String,
+    /// The path pattern of the route that matched, if one did.
+    pub route: Option<&'static str>,
+    /// The request's `X-Request-Id`, when it came with one.
+    pub request_id: Option<String>,
+    backtrace: Backtrace,
+}
+
+impl ErrorContext {
+    /// Captures a backtrace along with the request, when `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` asks for one.
+    pub fn new(
+        method: impl Into<String>,
+        url: impl Into<String>,
+        route: Option<&'static str>,
+        request_id: Option<String>,
+    ) -> ErrorContext {
+        ErrorContext {
+            method: method.into(),
+            url: url.into(),
+            route,
+            request_id,
+            backtrace: Backtrace::capture(),
+        }
+    }
+
+    /// Where the context was attached, if a backtrace was captured.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self.backtrace.status() {
+            BacktraceStatus::Captured => Some(&self.backtrace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.url)?;
+        match (self.route, &self.request_id) {
+            (Some(route), Some(id)) => write!(f, " (route {}, request {})", route, id),
+            (Some(route), None) => write!(f, " (route {})", route),
+            (None, Some(id)) => write!(f, " (request {})", id),
+            (None, None) => Ok(()),
+        }
+    }
 }
 
 pub type BeakResult<T> = Result<T, BeakError>;
@@ -0,0 +1,125 @@
+use r2d2_postgres::{
+    postgres::{self, NoTls},
+    PostgresConnectionManager,
+};
+use thiserror::Error;
+
+use crate::{
+    transaction::{HasPool, TransactionPool},
+    BeakError, BeakResult, Middleware, Next, Request,
+};
+
+pub type Manager = PostgresConnectionManager<NoTls>;
+/// A connection checked out of the pool, going back in when it's dropped. Derefs to a [`postgres::Client`].
+pub type Connection = r2d2::PooledConnection<Manager>;
+
+#[derive(Error, Debug)]
+pub enum PostgresError {
+    #[error("couldn't get a connection from the pool: {0}")]
+    Pool(#[from] r2d2::Error),
+    #[error(transparent)]
+    Postgres(#[from] postgres::Error),
+}
+
+/// A pool of postgres connections, to go in the server's context. It's cheap to clone, and every clone shares the
+/// same pool.
+///
+/// Contexts with one in implement [`HasPool`] to hand it out, which is all [`Connections`] and
+/// [`WithTransaction`](crate::transaction::WithTransaction) need to find it - a context that's nothing but the pool
+/// can be the `Postgres` itself.
+#[derive(Clone)]
+pub struct Postgres {
+    pool: r2d2::Pool<Manager>,
+}
+
+impl Postgres {
+    /// Connects to the database `config` describes, like `host=localhost user=beak dbname=app` or a
+    /// `postgresql://` url, keeping up to `size` connections open. Fails if the first ones can't be made.
+    pub fn connect(config: &str, size: u32) -> Result<Postgres, PostgresError> {
+        let config: postgres::Config = config.parse()?;
+        let pool = r2d2::Pool::builder()
+            .max_size(size)
+            .build(PostgresConnectionManager::new(config, NoTls))?;
+        Ok(Postgres { pool })
+    }
+
+    /// For a pool that's set up some other way - with TLS, or a connection customizer.
+    pub fn from_pool(pool: r2d2::Pool<Manager>) -> Postgres {
+        Postgres { pool }
+    }
+
+    /// Checks a connection out, waiting for one to be free as long as the pool's connection timeout.
+    pub fn get(&self) -> Result<Connection, PostgresError> {
+        Ok(self.pool.get()?)
+    }
+}
+
+impl HasPool for Postgres {
+    type Pool = Postgres;
+
+    fn pool(&self) -> &Postgres {
+        self
+    }
+}
+
+/// Transactions are a connection of their own for the request, with `BEGIN` run on it.
+impl TransactionPool for Postgres {
+    type Transaction = Connection;
+    type Error = PostgresError;
+
+    fn begin(&self) -> Result<Connection, PostgresError> {
+        let mut connection = self.get()?;
+        connection.batch_execute("BEGIN")?;
+        Ok(connection)
+    }
+
+    fn commit(&self, mut connection: Connection) -> Result<(), PostgresError> {
+        Ok(connection.batch_execute("COMMIT")?)
+    }
+
+    fn rollback(&self, mut connection: Connection) -> Result<(), PostgresError> {
+        Ok(connection.batch_execute("ROLLBACK")?)
+    }
+}
+
+// what Connections leaves in the request's extensions: the pool, and a connection once one's asked for
+struct Lazy {
+    postgres: Postgres,
+    connection: Option<Connection>,
+}
+
+/// Lets handlers check a connection out of the context's pool with [`Request::postgres`] - only once they ask for
+/// one, so requests that don't use the database don't wait on it. It's back in the pool once the request's done.
+pub struct Connections;
+
+impl<C: HasPool<Pool = Postgres> + Send + Sync> Middleware<C> for Connections {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        request.extensions.insert(Lazy {
+            postgres: context.pool().clone(),
+            connection: None,
+        });
+        next.run(request, context)
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// A connection for this request, checked out of the pool the first time it's asked for. Needs
+    /// [`Connections`] in front of the handler. Requests in a
+    /// [`WithTransaction`](crate::transaction::WithTransaction) have theirs in a
+    /// [`Transaction`](crate::transaction::Transaction) instead.
+    pub fn postgres(&mut self) -> BeakResult<&mut postgres::Client> {
+        let lazy = self.extensions.get_mut::<Lazy>().ok_or_else(|| {
+            BeakError::handler("Request::postgres needs the ext::postgres::Connections middleware")
+        })?;
+
+        if lazy.connection.is_none() {
+            lazy.connection = Some(lazy.postgres.get().map_err(BeakError::handler)?);
+        }
+        Ok(lazy.connection.as_mut().unwrap())
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    headers::{self, header},
+    path::{self, PathError},
+    range::{self, ByteRange},
+    Request,
+};
+
+/// Serves a file from disk with `Last-Modified` and `ETag` validators, conditional requests and byte ranges -
+/// including `If-Range`, so resumed downloads pick up where they left off, or start over if the file changed in
+/// the meantime.
+///
+/// `path` is used as is, so anything built from the request should go through [`Request::wildcard`] or
+/// [`path::normalize`](crate::path::normalize) first.
+pub fn serve_file(request: Request<'_, '_, '_>, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    serve_file_as(request, path, content_type(path))
+}
+
+// serve_file, for files whose extension doesn't say what they are
+pub(crate) fn serve_file_as(
+    request: Request<'_, '_, '_>,
+    path: &Path,
+    content_type: &str,
+) -> io::Result<()> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return request.respond_with_bytes(404, vec![], b"not found")
+        }
+        Err(e) => return Err(e),
+    };
+
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return request.respond_with_bytes(404, vec![], b"not found");
+    }
+
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = etag(len, modified);
+
+    let mut response_headers = vec![header("ETag", &etag), header("Accept-Ranges", "bytes")];
+    if let Some(modified) = modified {
+        response_headers.push(header("Last-Modified", headers::http_date(modified)));
+    }
+
+    // If-None-Match wins when both are there
+    let not_modified = match request.header("If-None-Match") {
+        Some(tags) => tags.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        }),
+        None => {
+            let since = request
+                .header("If-Modified-Since")
+                .and_then(headers::parse_http_date);
+            match (since, modified) {
+                (Some(since), Some(modified)) => {
+                    headers::unix_secs(modified) <= headers::unix_secs(since)
+                }
+                _ => false,
+            }
+        }
+    };
+    if not_modified {
+        return request.respond_without_body(304, response_headers);
+    }
+
+    response_headers.push(header("Content-Type", content_type));
+
+    let range = request
+        .header("Range")
+        .filter(|_| range::if_range(request.header("If-Range"), Some(&etag), modified));
+
+    let (status, range) = match ByteRange::parse(range, len) {
+        ByteRange::Full => (200, 0..len),
+        ByteRange::Partial(range) => {
+            response_headers.push(header(
+                "Content-Range",
+                range::content_range(Some(&range), len),
+            ));
+            (206, range)
+        }
+        ByteRange::Unsatisfiable => {
+            response_headers.push(header("Content-Range", range::content_range(None, len)));
+            return request.respond_with_bytes(416, response_headers, &[]);
+        }
+    };
+
+    file.seek(SeekFrom::Start(range.start))?;
+    response_headers.push(header(
+        "Content-Length",
+        (range.end - range.start).to_string(),
+    ));
+
+    request.respond(status, response_headers, |writer, _| {
+        copy_file(file.take(range.end - range.start), writer)
+    })
+}
+
+// sendfile would need the socket, and what handlers get is a stack of writers (byte counting, middleware, tiny_http's
+// buffering) with no file descriptor to hand - so the next best thing is big reads, a fraction of the syscalls
+// io::copy's 8KiB buffer makes
+fn copy_file(mut file: impl Read, writer: &mut dyn Write) -> io::Result<()> {
+    let mut buffer = vec![0; 256 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => writer.write_all(&buffer[..read])?,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// strong enough for If-Range: a rewritten file hardly ever keeps both its length and its mtime to the nanosecond
+fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos());
+    format!("\"{:x}-{:x}\"", len, modified)
+}
+
+/// A directory on disk, served with [`serve_file`] - `EmbeddedDir`, but read from disk on every request instead of
+/// baked into the binary.
+pub struct StaticDir {
+    root: PathBuf,
+    index_files: Vec<String>,
+    listings: bool,
+    // kept longest prefix first, so the most specific one wins
+    fallbacks: Vec<(String, String)>,
+}
+
+impl StaticDir {
+    pub fn new(root: impl Into<PathBuf>) -> StaticDir {
+        StaticDir {
+            root: root.into(),
+            index_files: vec!["index.html".to_owned()],
+            listings: false,
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// What gets served for a directory, tried in order - `index.html` unless set otherwise. Nothing at all makes
+    /// directories a 404, or a listing if those are on.
+    pub fn index_files(mut self, names: &[&str]) -> Self {
+        self.index_files = names.iter().map(|name| (*name).to_owned()).collect();
+        self
+    }
+
+    /// Lists the contents of directories that have no index file, as HTML or - for clients that `Accept` it - as
+    /// JSON. Off by default, since a listing hands out every file name, including the ones nothing links to. Dotfiles
+    /// are left out either way.
+    pub fn listings(mut self, enabled: bool) -> Self {
+        self.listings = enabled;
+        self
+    }
+
+    /// For single-page apps doing their own routing: paths under `prefix` (relative to the directory, `""` for all
+    /// of it) that don't exist get `file` instead of a 404, so `/app/settings` loads `app/index.html`.
+    ///
+    /// Only requests that `Accept` HTML fall back, which is what browsers navigating send - a missing script or
+    /// image is still a 404, rather than a page of HTML it can't use.
+    pub fn spa_fallback(mut self, prefix: &str, file: &str) -> Self {
+        self.fallbacks.push((
+            prefix.trim_matches('/').to_owned(),
+            file.trim_matches('/').to_owned(),
+        ));
+        self.fallbacks
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Serves the file the request's catch-all parameter names (or its whole path, if the route doesn't have one).
+    pub fn serve_request(&self, request: Request<'_, '_, '_>) -> io::Result<()> {
+        let path = match request.wildcard() {
+            Err(PathError::NoWildcard) => {
+                path::percent_decode(request.path()).and_then(|decoded| path::normalize(&decoded))
+            }
+            path => path,
+        };
+
+        match path {
+            Ok(path) => self.serve(request, &path),
+            Err(_) => request.respond_with_bytes(400, vec![], b"bad path"),
+        }
+    }
+
+    /// Serves the file at `path`, relative to the root, going through the index files for directories. A path that
+    /// isn't [normalized](path::normalize) already gets a 400 if it can't be.
+    pub fn serve(&self, request: Request<'_, '_, '_>, path: &str) -> io::Result<()> {
+        // a no-op for paths from serve_request, but not every caller's path came through there
+        let path = match path::normalize(path) {
+            Ok(path) => path,
+            Err(_) => return request.respond_with_bytes(400, vec![], b"bad path"),
+        };
+        let path = path.as_str();
+        let full = self.root.join(path);
+        if full.is_file() {
+            return serve_file(request, full);
+        }
+
+        if full.is_dir() {
+            let index = self
+                .index_files
+                .iter()
+                .map(|name| full.join(name))
+                .find(|index| index.is_file());
+            if let Some(index) = index {
+                return serve_file(request, index);
+            }
+            if self.listings {
+                return list_dir(request, &full);
+            }
+        }
+
+        match self.fallback(path, request.header("Accept")) {
+            Some(fallback) => serve_file(request, self.root.join(fallback)),
+            None => request.respond_with_bytes(404, vec![], b"not found"),
+        }
+    }
+
+    fn fallback(&self, path: &str, accept: Option<&str>) -> Option<&str> {
+        let wants_html = accept.is_some_and(|accept| {
+            headers::quality_values(accept)
+                .iter()
+                .any(|(value, _)| *value == "text/html")
+        });
+        if !wants_html {
+            return None;
+        }
+
+        self.fallbacks
+            .iter()
+            .find(|(prefix, _)| {
+                prefix.is_empty()
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map(|(_, file)| file.as_str())
+    }
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+fn list_dir(request: Request<'_, '_, '_>, dir: &Path) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // follows symlinks, so a link to a directory gets listed as one
+        let metadata = match std::fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    // directories first
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let mut base = request.path().to_owned();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+
+    let wants_json = request.header("Accept").is_some_and(|accept| {
+        headers::quality_values(accept)
+            .iter()
+            .map(|(value, _)| *value)
+            .find(|value| matches!(*value, "application/json" | "text/html"))
+            == Some("application/json")
+    });
+
+    let (content_type, body) = if wants_json {
+        ("application/json", listing_json(&entries))
+    } else {
+        ("text/html; charset=utf-8", listing_html(&base, &entries))
+    };
+
+    request.respond_with_bytes(
+        200,
+        vec![
+            header("Content-Type", content_type),
+            header("Vary", "Accept"),
+        ],
+        body.as_bytes(),
+    )
+}
+
+fn listing_html(base: &str, entries: &[Entry]) -> String {
+    let title = html_escape(&path::percent_decode(base).unwrap_or_else(|_| base.to_owned()));
+    let mut html = format!(
+        "<!doctype html>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<h1>{title}</h1>\n<table>\n\
+         <tr><th>name</th><th>size</th><th>modified</th></tr>\n",
+        title = title
+    );
+    if base != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(base),
+            path::percent_encode(&entry.name),
+            slash,
+            html_escape(&entry.name),
+            slash,
+            if entry.is_dir {
+                String::new()
+            } else {
+                entry.len.to_string()
+            },
+            entry.modified.map(headers::http_date).unwrap_or_default(),
+        ));
+    }
+
+    html.push_str("</table>\n");
+    html
+}
+
+fn listing_json(entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{{\"name\":{},\"dir\":{},\"size\":{},\"modified\":{}}}",
+                json_string(&entry.name),
+                entry.is_dir,
+                if entry.is_dir {
+                    "null".to_owned()
+                } else {
+                    entry.len.to_string()
+                },
+                entry
+                    .modified
+                    .map_or("null".to_owned(), |modified| json_string(
+                        &headers::http_date(modified)
+                    )),
+            )
+        })
+        .collect();
+
+    format!("[{}]", entries.join(","))
+}
+
+pub(crate) fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// A content type for `path` from its extension, `application/octet-stream` for the ones we don't know.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("wasm") => "application/wasm",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
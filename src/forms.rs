@@ -0,0 +1,372 @@
+use std::io;
+
+use crate::{files::html_escape, path, Request};
+
+/// A submitted `application/x-www-form-urlencoded` form, with its fields in the order they came.
+#[derive(Debug, Clone, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Pairs that aren't valid percent-encoded utf-8 are dropped.
+    pub fn parse(body: &str) -> Form {
+        let fields = body
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                let decode = |text: &str| path::percent_decode(&text.replace('+', " ")).ok();
+                Some((decode(name)?, decode(value)?))
+            })
+            .collect();
+        Form { fields }
+    }
+
+    /// The first value of the field.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the field - checkboxes and multiple selects send one each.
+    pub fn get_all<'f>(&'f self, name: &'f str) -> impl Iterator<Item = &'f str> + 'f {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The field's value escaped for HTML, empty if there isn't one - for filling a form back in with what was sent
+    /// when it's rendered again with its errors.
+    pub fn html_value(&self, name: &str) -> String {
+        html_escape(self.get(name).unwrap_or(""))
+    }
+}
+
+enum Check {
+    Required,
+    MinLength(usize),
+    MaxLength(usize),
+    Email,
+    #[cfg(feature = "regex")]
+    Matches(regex::Regex),
+    Custom(Box<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+/// The rules for one field of a form, checked in the order they were added. Fields that are empty - or only
+/// whitespace - only have to pass [`required`](Self::required), the rest are for what's filled in.
+pub struct Field {
+    name: String,
+    checks: Vec<(Check, String)>,
+}
+
+impl Field {
+    pub fn new(name: impl Into<String>) -> Field {
+        Field {
+            name: name.into(),
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn required(self) -> Self {
+        self.check_with(Check::Required, "is required".to_owned())
+    }
+
+    /// In characters, not bytes.
+    pub fn min_length(self, min: usize) -> Self {
+        let message = format!("must be at least {} characters", min);
+        self.check_with(Check::MinLength(min), message)
+    }
+
+    /// In characters, not bytes.
+    pub fn max_length(self, max: usize) -> Self {
+        let message = format!("must be at most {} characters", max);
+        self.check_with(Check::MaxLength(max), message)
+    }
+
+    pub fn length(self, min: usize, max: usize) -> Self {
+        self.min_length(min).max_length(max)
+    }
+
+    /// Something with an `@` in it and a dot in the domain, which is about as far as checking an address goes
+    /// without sending it an email.
+    pub fn email(self) -> Self {
+        self.check_with(Check::Email, "must be an email address".to_owned())
+    }
+
+    /// Has to match `regex` somewhere - anchor it with `^` and `$` to match all of it.
+    #[cfg(feature = "regex")]
+    pub fn matches(self, regex: regex::Regex) -> Self {
+        self.check_with(Check::Matches(regex), "is invalid".to_owned())
+    }
+
+    /// A rule of your own, failing with `message`.
+    pub fn check(
+        self,
+        check: impl Fn(&str) -> bool + Send + Sync + 'static,
+        message: impl Into<String>,
+    ) -> Self {
+        self.check_with(Check::Custom(Box::new(check)), message.into())
+    }
+
+    /// Replaces the message of the rule added last.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        if let Some((_, last)) = self.checks.last_mut() {
+            *last = message.into();
+        }
+        self
+    }
+
+    fn check_with(mut self, check: Check, message: String) -> Self {
+        self.checks.push((check, message));
+        self
+    }
+
+    fn errors(&self, value: &str) -> Vec<String> {
+        let value = value.trim();
+        let length = value.chars().count();
+
+        let mut errors = Vec::new();
+        for (check, message) in &self.checks {
+            let valid = match check {
+                Check::Required => !value.is_empty(),
+                _ if value.is_empty() => true,
+                Check::MinLength(min) => length >= *min,
+                Check::MaxLength(max) => length <= *max,
+                Check::Email => is_email(value),
+                #[cfg(feature = "regex")]
+                Check::Matches(regex) => regex.is_match(value),
+                Check::Custom(check) => check(value),
+            };
+
+            if !valid {
+                errors.push(message.clone());
+                // nothing else says anything useful about a field that's missing
+                if matches!(check, Check::Required) {
+                    break;
+                }
+            }
+        }
+        errors
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    let (local, domain) = match value.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    !local.is_empty()
+        && !value.contains(char::is_whitespace)
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains("..")
+}
+
+/// Declarative rules for a form's fields, checked all at once so everything wrong with a submission can be shown
+/// together.
+#[derive(Default)]
+pub struct Validator {
+    fields: Vec<Field>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    pub fn field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// A field sent more than once is checked by its first value.
+    pub fn validate(&self, form: &Form) -> Result<(), FormErrors> {
+        let errors: Vec<_> = self
+            .fields
+            .iter()
+            .map(|field| {
+                (
+                    field.name.clone(),
+                    field.errors(form.get(&field.name).unwrap_or("")),
+                )
+            })
+            .filter(|(_, errors)| !errors.is_empty())
+            .collect();
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(FormErrors { errors }),
+        }
+    }
+}
+
+/// What's wrong with a submitted form, by field - in the order the [`Validator`]'s fields were added.
+#[derive(Debug, Clone, Default)]
+pub struct FormErrors {
+    errors: Vec<(String, Vec<String>)>,
+}
+
+impl FormErrors {
+    /// Every message for the field, empty if there's nothing wrong with it.
+    pub fn get(&self, field: &str) -> &[String] {
+        self.errors
+            .iter()
+            .find(|(name, _)| name == field)
+            .map_or(&[], |(_, errors)| errors.as_slice())
+    }
+
+    /// The field's first message, which is usually the one worth showing.
+    pub fn first(&self, field: &str) -> Option<&str> {
+        self.get(field).first().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.errors
+            .iter()
+            .map(|(field, errors)| (field.as_str(), errors.as_slice()))
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Reads a urlencoded form from the body, failing if it's something else or grows past `limit` bytes.
+    pub fn form(&mut self, limit: usize) -> io::Result<Form> {
+        let is_form = self.header("Content-Type").is_some_and(|content_type| {
+            content_type
+                .trim()
+                .to_ascii_lowercase()
+                .starts_with("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "request body isn't a urlencoded form",
+            ));
+        }
+
+        let body = self.body.read_to_vec(limit)?;
+        Ok(Form::parse(&String::from_utf8_lossy(&body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plus_and_percent_escapes() {
+        let form = Form::parse("name=Ada+Lovelace&sum=1%2B1&note=caf%C3%A9&flag&&empty=");
+        assert_eq!(form.get("name"), Some("Ada Lovelace"));
+        assert_eq!(form.get("sum"), Some("1+1"));
+        assert_eq!(form.get("note"), Some("café"));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.iter().count(), 5);
+    }
+
+    #[test]
+    fn drops_badly_encoded_pairs() {
+        let form = Form::parse("good=1&bad=%zz&worse=%ff&also=2");
+        assert_eq!(form.get("bad"), None);
+        assert_eq!(form.get("worse"), None);
+        assert_eq!(
+            form.get_all("good")
+                .chain(form.get_all("also"))
+                .collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+    }
+
+    #[test]
+    fn required_short_circuits() {
+        let field = Field::new("name").required().min_length(3).email();
+        assert_eq!(field.errors(""), ["is required"]);
+        assert_eq!(
+            field.errors("ab"),
+            ["must be at least 3 characters", "must be an email address"]
+        );
+    }
+
+    #[test]
+    fn whitespace_counts_as_empty() {
+        assert_eq!(
+            Field::new("name").required().errors("  \t "),
+            ["is required"]
+        );
+        // and only required has anything to say about an empty field
+        assert!(Field::new("name")
+            .min_length(3)
+            .email()
+            .errors("   ")
+            .is_empty());
+        // what's checked is trimmed
+        assert!(Field::new("name")
+            .max_length(3)
+            .errors("  abc  ")
+            .is_empty());
+    }
+
+    #[test]
+    fn lengths_are_in_characters() {
+        let field = Field::new("name").length(2, 3);
+        // four bytes, two characters
+        assert!(field.errors("éé").is_empty());
+        assert!(field.errors("日本語").is_empty());
+        assert_eq!(field.errors("日本語です"), ["must be at most 3 characters"]);
+        assert_eq!(field.errors("é"), ["must be at least 2 characters"]);
+    }
+
+    #[test]
+    fn emails() {
+        for email in ["a@b.c", "first.last@example.com", "a@b@example.com"] {
+            assert!(is_email(email), "{}", email);
+        }
+        for email in ["a@b", "a@.b", "a@b.", "a@b..c", "@b.c", "a b@c.d", "ab.c"] {
+            assert!(!is_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn messages_replace_the_last_rule() {
+        let field = Field::new("email")
+            .required()
+            .message("we need this")
+            .email();
+        assert_eq!(field.errors(""), ["we need this"]);
+        assert_eq!(field.errors("nope"), ["must be an email address"]);
+    }
+
+    #[test]
+    fn validates_every_field() {
+        let validator = Validator::new()
+            .field(Field::new("name").required())
+            .field(Field::new("email").email())
+            .field(Field::new("age").check(|age| age.parse::<u8>().is_ok(), "must be a number"));
+
+        let errors = validator
+            .validate(&Form::parse("email=nope&age=old"))
+            .unwrap_err();
+        assert_eq!(errors.first("name"), Some("is required"));
+        assert_eq!(errors.first("email"), Some("must be an email address"));
+        assert_eq!(errors.get("age"), ["must be a number"]);
+        assert_eq!(errors.iter().count(), 3);
+
+        assert!(validator
+            .validate(&Form::parse("name=Ada&email=ada%40example.com&age=36"))
+            .is_ok());
+    }
+}
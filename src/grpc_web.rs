@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use crate::{base64, headers::header, BeakResult, Handler, Request};
+
+/// gRPC status codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrpcCode {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Unauthenticated = 16,
+}
+
+/// What a failed call answers with, sent to the client in the trailers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcStatus {
+    pub code: GrpcCode,
+    pub message: String,
+}
+
+impl GrpcStatus {
+    pub fn new(code: GrpcCode, message: impl Into<String>) -> GrpcStatus {
+        GrpcStatus {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+type Method<C> =
+    Box<dyn Fn(&Request<'_, '_, '_>, C, &[u8]) -> Result<Vec<u8>, GrpcStatus> + Send + Sync>;
+
+/// Serves one gRPC service to gRPC-web clients, straight from the browser without a proxy translating for it.
+/// Messages come and go as encoded protobuf - decoding them is up to the methods, with whatever protobuf crate the
+/// app uses.
+///
+/// Only unary calls are supported, in both the binary (`application/grpc-web`) and base64
+/// (`application/grpc-web-text`) encodings, uncompressed. Browsers calling from another origin need CORS on top.
+pub struct GrpcWeb<C> {
+    path: &'static str,
+    methods: HashMap<&'static str, Method<C>>,
+    max_message_size: usize,
+}
+
+impl<C> GrpcWeb<C> {
+    /// For the service's full name, like `helloworld.Greeter` - which makes the route `/helloworld.Greeter/:method`.
+    pub fn new(service: &str) -> GrpcWeb<C> {
+        GrpcWeb {
+            path: Box::leak(format!("/{}/:method", service).into_boxed_str()),
+            methods: HashMap::new(),
+            max_message_size: 4 * 1024 * 1024,
+        }
+    }
+
+    /// Answers calls to `name` (like `SayHello`) with `method`, which gets the request for its metadata and the
+    /// encoded request message, and hands back the encoded response.
+    pub fn method(
+        mut self,
+        name: &'static str,
+        method: impl Fn(&Request<'_, '_, '_>, C, &[u8]) -> Result<Vec<u8>, GrpcStatus>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.methods.insert(name, Box::new(method));
+        self
+    }
+
+    /// Bigger requests fail with `RESOURCE_EXHAUSTED`, 4MiB by default like gRPC itself.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    // the message in a unary request body
+    fn message(&self, body: &[u8]) -> Result<Vec<u8>, GrpcStatus> {
+        let malformed = || GrpcStatus::new(GrpcCode::Internal, "malformed grpc-web frame");
+
+        let (flags, length) = match body {
+            [flags, a, b, c, d, ..] => (*flags, u32::from_be_bytes([*a, *b, *c, *d]) as usize),
+            _ => return Err(malformed()),
+        };
+        if flags & 1 != 0 {
+            return Err(GrpcStatus::new(
+                GrpcCode::Unimplemented,
+                "compressed messages are not supported",
+            ));
+        }
+        if flags != 0 || body.len() != 5 + length {
+            return Err(malformed());
+        }
+
+        Ok(body[5..].to_vec())
+    }
+}
+
+impl<C: Send + Sync> Handler<C> for GrpcWeb<C> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+    ) -> BeakResult<()> {
+        let content_type = request.header("Content-Type").unwrap_or("").trim();
+        let text = match content_type.split(['+', ';']).next().unwrap_or("") {
+            "application/grpc-web" => false,
+            "application/grpc-web-text" => true,
+            _ => {
+                request.respond_with_bytes(415, vec![], &[])?;
+                return Ok(());
+            }
+        };
+        let response_type = header(
+            "Content-Type",
+            if text {
+                "application/grpc-web-text+proto"
+            } else {
+                "application/grpc-web+proto"
+            },
+        );
+
+        // base64 takes a third more room than what it encodes
+        let limit = match text {
+            true => (self.max_message_size + 5) / 3 * 4 + 4,
+            false => self.max_message_size + 5,
+        };
+        let called = match request.body.read_to_vec(limit) {
+            Ok(body) if text => base64::decode(&body)
+                .ok_or_else(|| GrpcStatus::new(GrpcCode::Internal, "malformed grpc-web-text body")),
+            Ok(body) => Ok(body),
+            Err(_) => Err(GrpcStatus::new(
+                GrpcCode::ResourceExhausted,
+                "request message is too large",
+            )),
+        };
+
+        let name = request.params.get("method").unwrap_or_default();
+        let answered = called.and_then(|body| {
+            let message = self.message(&body)?;
+            let method = self.methods.get(name).ok_or_else(|| {
+                GrpcStatus::new(
+                    GrpcCode::Unimplemented,
+                    format!("no method called {}", name),
+                )
+            })?;
+            method(&request, context, &message)
+        });
+
+        let mut body = Vec::new();
+        let status = match answered {
+            Ok(message) => {
+                push_frame(&mut body, 0, &message);
+                GrpcStatus::new(GrpcCode::Ok, "")
+            }
+            Err(status) => status,
+        };
+
+        let mut trailers = format!("grpc-status: {}\r\n", status.code as u32);
+        if !status.message.is_empty() {
+            trailers.push_str(&format!(
+                "grpc-message: {}\r\n",
+                percent_encode(&status.message)
+            ));
+        }
+        push_frame(&mut body, 0x80, trailers.as_bytes());
+
+        if text {
+            body = base64_encode(&body).into_bytes();
+        }
+
+        // grpc errors are still http successes, the status is in the trailers
+        request.respond_with_bytes(200, vec![response_type], &body)?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["POST"]
+    }
+}
+
+fn push_frame(body: &mut Vec<u8>, flags: u8, data: &[u8]) {
+    body.push(flags);
+    body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    body.extend_from_slice(data);
+}
+
+// grpc-message is percent-encoded, so it can carry anything through a header
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(base64::ALPHABET[((group >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
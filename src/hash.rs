@@ -0,0 +1,7 @@
+// fnv-1a, stable across builds unlike std's hashers - for etags that survive restarts, and fingerprints kept in
+// stores other servers share. not for anything an attacker would want to collide
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
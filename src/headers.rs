@@ -0,0 +1,248 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+use tiny_http::Header;
+
+/// Why a response's head was refused - writing it as it was would let whatever's in it start headers (or a body) of
+/// its own. `respond` and the rest fail with an [`io::ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput)
+/// error carrying one of these.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvalidHeader {
+    #[error("invalid header name {0:?}")]
+    Name(String),
+    #[error("the value of header {0} has a line break or control character in it")]
+    Value(String),
+    #[error("the reason phrase has a line break or control character in it")]
+    Reason,
+}
+
+/// Splits a header like `Accept-Language` or `Accept-Encoding` into its values, highest `q` first.
+/// Values with `q=0` are dropped, and ties keep the order the client sent them in.
+pub fn quality_values(header: &str) -> Vec<(&str, f32)> {
+    let mut values: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|value| {
+            let mut parts = value.split(';');
+            let value = parts.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            (q > 0.0).then_some((value, q))
+        })
+        .collect();
+
+    // sort_by is stable, so equal q-values stay in header order
+    values.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    values
+}
+
+/// Whether the client's `Accept-Encoding` allows `encoding`.
+pub fn accepts_encoding(accept_encoding: Option<&str>, encoding: &str) -> bool {
+    accept_encoding.is_some_and(|header| {
+        quality_values(header)
+            .iter()
+            .any(|(value, _)| value.eq_ignore_ascii_case(encoding) || *value == "*")
+    })
+}
+
+/// The value of the cookie called `name` in a `Cookie` header.
+pub fn cookie<'h>(cookie_header: &'h str, name: &str) -> Option<&'h str> {
+    cookie_header.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key.trim() == name).then(|| value.trim().trim_matches('"'))
+    })
+}
+
+// methods that shouldn't change anything, so don't need CSRF or idempotency protection
+pub(crate) fn is_safe_method(method: &str) -> bool {
+    ["GET", "HEAD", "OPTIONS", "TRACE"]
+        .iter()
+        .any(|safe| method.eq_ignore_ascii_case(safe))
+}
+
+/// A header out of a name and value that might have come from a client, checked the way `respond` checks them -
+/// for finding out before it's time to respond.
+pub fn checked_header(name: &str, value: impl AsRef<[u8]>) -> Result<Header, InvalidHeader> {
+    let header = Header::from_bytes(name.as_bytes(), value.as_ref())
+        .map_err(|_| InvalidHeader::Name(name.to_owned()))?;
+    validate(&header)?;
+    Ok(header)
+}
+
+// names are tokens, and values can't have control characters besides tabs - so nothing in either ends the line
+pub(crate) fn validate(header: &Header) -> Result<(), InvalidHeader> {
+    let name = header.field.as_str().as_str();
+    let token = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    if name.is_empty() || !name.bytes().all(token) {
+        return Err(InvalidHeader::Name(name.to_owned()));
+    }
+
+    if !header.value.as_str().bytes().all(printable) {
+        return Err(InvalidHeader::Value(name.to_owned()));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_reason(reason: &str) -> Result<(), InvalidHeader> {
+    match reason.bytes().all(printable) {
+        true => Ok(()),
+        false => Err(InvalidHeader::Reason),
+    }
+}
+
+fn printable(b: u8) -> bool {
+    b == b'\t' || (b >= 0x20 && b != 0x7f)
+}
+
+// for headers we build ourselves out of values we know are fine
+pub(crate) fn header(name: &str, value: impl AsRef<[u8]>) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_ref()).expect("invalid header")
+}
+
+/// Formats a time the way HTTP headers want it, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// days since the epoch to a civil (year, month, day), from Howard Hinnant's date algorithms
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+/// Parses an HTTP date in the `Sun, 06 Nov 1994 08:49:37 GMT` form every client sends nowadays. The obsolete
+/// RFC 850 and asctime forms aren't supported.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let (_, date) = date.trim().split_once(", ")?;
+    let mut parts = date.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let time = parts.next()?;
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+
+    let mut time = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 || year < 1970 {
+        return None;
+    }
+
+    // the inverse of civil_from_days
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days as u64 * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// what http dates can tell apart
+pub(crate) fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        assert_eq!(http_date(at(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(at(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(at(784111777))
+        );
+        assert_eq!(
+            parse_http_date(" Tue, 29 Feb 2000 00:00:00 GMT "),
+            Some(at(951782400))
+        );
+        for secs in [0, 68169600, 951868799, 1700000000, 4102444800] {
+            assert_eq!(parse_http_date(&http_date(at(secs))), Some(at(secs)));
+        }
+    }
+
+    #[test]
+    fn rejects_other_dates() {
+        for date in [
+            "",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nvm 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn orders_quality_values() {
+        assert_eq!(
+            quality_values("gzip;q=0.5, br, identity;q=0, deflate;q=0.5"),
+            vec![("br", 1.0), ("gzip", 0.5), ("deflate", 0.5)]
+        );
+        assert!(accepts_encoding(Some("gzip, br"), "br"));
+        assert!(!accepts_encoding(Some("gzip;q=0"), "gzip"));
+        assert!(!accepts_encoding(None, "gzip"));
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{headers::header, Request};
+
+/// Fans messages out to every subscriber of a topic, across worker threads - the registry-and-channels part of a
+/// chat or notification server. Cheap to clone, and every clone is the same hub - keep one in the context.
+///
+/// Each subscriber has a queue of its own, holding up to [`capacity`](Hub::capacity) messages. A subscriber that
+/// falls further behind than that loses its oldest ones rather than holding up publishing or eating memory.
+pub struct Hub<T> {
+    inner: Arc<HubState<T>>,
+}
+
+// each queue with the id of the subscription it belongs to, so dropping one can find it
+type Subscribers<T> = Vec<(u64, Arc<Queue<T>>)>;
+
+struct HubState<T> {
+    topics: Mutex<HashMap<String, Subscribers<T>>>,
+    next_id: AtomicU64,
+    capacity: usize,
+}
+
+struct Queue<T> {
+    messages: Mutex<VecDeque<T>>,
+    arrived: Condvar,
+    lost: AtomicU64,
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Hub<T> {
+        Hub {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Default for Hub<T> {
+    fn default() -> Hub<T> {
+        Hub::new()
+    }
+}
+
+impl<T: Clone> Hub<T> {
+    pub fn new() -> Hub<T> {
+        Hub::with_capacity(256)
+    }
+
+    /// A hub whose subscribers each queue up to `capacity` messages.
+    pub fn with_capacity(capacity: usize) -> Hub<T> {
+        Hub {
+            inner: Arc::new(HubState {
+                topics: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+                capacity: capacity.max(1),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Starts receiving what's published to `topic` from now on. Dropping the subscription unsubscribes.
+    pub fn subscribe(&self, topic: &str) -> Subscription<T> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(Queue {
+            messages: Mutex::new(VecDeque::new()),
+            arrived: Condvar::new(),
+            lost: AtomicU64::new(0),
+        });
+
+        self.inner
+            .topics
+            .lock()
+            .unwrap()
+            .entry(topic.to_owned())
+            .or_default()
+            .push((id, queue.clone()));
+
+        Subscription {
+            hub: self.clone(),
+            topic: topic.to_owned(),
+            id,
+            queue,
+        }
+    }
+
+    /// Hands `message` to everyone subscribed to `topic`, returning how many that was.
+    pub fn publish(&self, topic: &str, message: T) -> usize {
+        // cloned out of the registry so subscribers can come and go while we deliver
+        let queues: Vec<Arc<Queue<T>>> = match self.inner.topics.lock().unwrap().get(topic) {
+            Some(subscribers) => subscribers.iter().map(|(_, queue)| queue.clone()).collect(),
+            None => return 0,
+        };
+
+        for queue in &queues {
+            let mut messages = queue.messages.lock().unwrap();
+            if messages.len() >= self.inner.capacity {
+                messages.pop_front();
+                queue.lost.fetch_add(1, Ordering::Relaxed);
+            }
+            messages.push_back(message.clone());
+            queue.arrived.notify_one();
+        }
+
+        queues.len()
+    }
+
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.inner
+            .topics
+            .lock()
+            .unwrap()
+            .get(topic)
+            .map_or(0, Vec::len)
+    }
+}
+
+/// One subscriber's end of a [`Hub`] topic.
+pub struct Subscription<T> {
+    hub: Hub<T>,
+    topic: String,
+    id: u64,
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Subscription<T> {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// The next message, if one's already waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.messages.lock().unwrap().pop_front()
+    }
+
+    /// The next message, waiting up to `timeout` for one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut messages = self.queue.messages.lock().unwrap();
+
+        loop {
+            if let Some(message) = messages.pop_front() {
+                return Some(message);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            messages = self
+                .queue
+                .arrived
+                .wait_timeout(messages, remaining)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// How many messages this subscriber missed by falling behind.
+    pub fn lost(&self) -> u64 {
+        self.queue.lost.load(Ordering::Relaxed)
+    }
+
+    /// Streams the subscription to the client as server-sent events, each message turned into an event's data by
+    /// `data`. When nothing's been published for `keepalive`, a comment goes out instead, which keeps proxies from
+    /// timing the connection out and notices clients that have gone away.
+    ///
+    /// This runs until the client disconnects, holding its worker the whole time - so there should be more workers
+    /// than clients listening at once, and a drain timeout for shutting down.
+    pub fn stream_events(
+        self,
+        request: Request<'_, '_, '_>,
+        keepalive: Duration,
+        mut data: impl FnMut(&T) -> String,
+    ) -> io::Result<()> {
+        let response_headers = vec![
+            header("Content-Type", "text/event-stream"),
+            header("Cache-Control", "no-cache"),
+        ];
+
+        request
+            .unbuffered()
+            .respond(200, response_headers, |writer, _| loop {
+                match self.recv_timeout(keepalive) {
+                    Some(message) => writer.write_all(event(&data(&message)).as_bytes())?,
+                    None => writer.write_all(b": keepalive\n\n")?,
+                }
+            })
+    }
+}
+
+// a line break in the data would end the field early, so each line gets one of its own - and a lone \r is as much
+// a line break to an event stream as \n is
+fn event(data: &str) -> String {
+    let mut event = String::new();
+    for line in data.replace("\r\n", "\n").split(['\r', '\n']) {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    event
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let mut topics = self.hub.inner.topics.lock().unwrap();
+        if let Some(subscribers) = topics.get_mut(&self.topic) {
+            subscribers.retain(|(id, _)| *id != self.id);
+            if subscribers.is_empty() {
+                topics.remove(&self.topic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_events_on_every_line_break() {
+        assert_eq!(event("hello"), "data: hello\n\n");
+        assert_eq!(
+            event("a\nb\r\nc\rd"),
+            "data: a\ndata: b\ndata: c\ndata: d\n\n"
+        );
+        assert_eq!(event("\r\n\r"), "data: \ndata: \ndata: \n\n");
+    }
+}
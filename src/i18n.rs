@@ -0,0 +1,135 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::headers;
+
+/// Language ranges from an `Accept-Language` header, most preferred first.
+pub fn parse_accept_language(header: &str) -> Vec<(&str, f32)> {
+    headers::quality_values(header)
+}
+
+/// Picks the supported language the client would like best, or `None` if it accepts none of them.
+///
+/// A range matches a language that is equal to it, more specific than it (`en` accepts `en-GB`) or less specific
+/// than it (`en-GB` falls back to `en`), all case-insensitively. `*` matches the first supported language.
+pub fn negotiate_language<'s, S: AsRef<str>>(header: &str, supported: &'s [S]) -> Option<&'s str> {
+    let supported = || supported.iter().map(AsRef::as_ref);
+
+    parse_accept_language(header)
+        .into_iter()
+        .find_map(|(range, _)| {
+            if range == "*" {
+                return supported().next();
+            }
+
+            supported()
+                .find(|language| language.eq_ignore_ascii_case(range))
+                .or_else(|| supported().find(|language| is_subtag_of(language, range)))
+                .or_else(|| supported().find(|language| is_subtag_of(range, language)))
+        })
+}
+
+// whether `tag` is `prefix` plus more subtags, like en-GB and en
+fn is_subtag_of(tag: &str, prefix: &str) -> bool {
+    tag.len() > prefix.len()
+        && tag.as_bytes()[prefix.len()] == b'-'
+        && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// Where localized messages (or template names, or whole templates) come from.
+///
+/// [`Request::localize`](crate::Request::localize) negotiates a language against [`languages`](Localizer::languages)
+/// and asks for the key in it.
+pub trait Localizer {
+    fn languages(&self) -> &[String];
+
+    fn lookup(&self, language: &str, key: &str) -> Option<Cow<'_, str>>;
+}
+
+/// A simple in-memory [`Localizer`]. Keys missing from a language fall back to the default language.
+pub struct Catalog {
+    default_language: String,
+    languages: Vec<String>,
+    messages: HashMap<(String, String), String>,
+}
+
+impl Catalog {
+    pub fn new(default_language: impl Into<String>) -> Catalog {
+        let default_language = default_language.into();
+
+        Catalog {
+            languages: vec![default_language.clone()],
+            default_language,
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, language: &str, key: impl Into<String>, message: impl Into<String>) {
+        if !self.languages.iter().any(|known| known == language) {
+            self.languages.push(language.to_owned());
+        }
+
+        self.messages
+            .insert((language.to_owned(), key.into()), message.into());
+    }
+
+    pub fn default_language(&self) -> &str {
+        &self.default_language
+    }
+}
+
+impl Localizer for Catalog {
+    fn languages(&self) -> &[String] {
+        &self.languages
+    }
+
+    fn lookup(&self, language: &str, key: &str) -> Option<Cow<'_, str>> {
+        let get = |language: &str| self.messages.get(&(language.to_owned(), key.to_owned()));
+
+        get(language)
+            .or_else(|| get(&self.default_language))
+            .map(|message| Cow::Borrowed(message.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec![
+                ("fr-CH", 1.0),
+                ("fr", 0.9),
+                ("en", 0.8),
+                ("de", 0.7),
+                ("*", 0.5)
+            ]
+        );
+        assert_eq!(
+            parse_accept_language("en;q=0.5, de, nl;q=0, ,"),
+            vec![("de", 1.0), ("en", 0.5)]
+        );
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn negotiates_languages() {
+        let supported = ["en", "de-AT", "pt-BR"];
+        assert_eq!(negotiate_language("de-AT", &supported), Some("de-AT"));
+        assert_eq!(negotiate_language("DE-at", &supported), Some("de-AT"));
+        // more specific than it, and less
+        assert_eq!(negotiate_language("de", &supported), Some("de-AT"));
+        assert_eq!(negotiate_language("en-GB", &supported), Some("en"));
+        assert_eq!(
+            negotiate_language("fr, pt;q=0.5", &supported),
+            Some("pt-BR")
+        );
+        assert_eq!(negotiate_language("*", &supported), Some("en"));
+        assert_eq!(negotiate_language("fr", &supported), None);
+        // a prefix that isn't a whole subtag doesn't count
+        assert_eq!(negotiate_language("p", &supported), None);
+        assert_eq!(negotiate_language("en;q=0", &supported), None);
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    body::Body,
+    hash, headers,
+    tee::{Captured, Tee},
+    BeakResult, Middleware, Next, Request,
+};
+
+/// Where an idempotency key stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// Nobody has used the key yet, and now it's ours.
+    Claimed,
+    /// Another request with the key is still being handled.
+    InProgress,
+    /// The key was already used - this is the response that got sent, head and all.
+    Completed(Vec<u8>),
+    /// The key was already used, for a request with a different body.
+    Mismatch,
+}
+
+/// Keeps track of idempotency keys and the responses they got. Keys are already scoped to a caller, method and path.
+pub trait IdempotencyStore: Send + Sync {
+    /// Atomically claims `key` if it's free (or expired) for a request whose body has `fingerprint`, otherwise reports
+    /// what it's doing - or that it was claimed with a different fingerprint.
+    fn claim(&self, key: &str, fingerprint: &str) -> Claim;
+
+    /// Stores the response sent for a claimed key, for replaying to retries.
+    fn complete(&self, key: &str, response: Vec<u8>);
+
+    /// Frees a claimed key without a response, so a retry runs the handler again.
+    fn release(&self, key: &str);
+}
+
+enum Entry {
+    InProgress,
+    Completed(Vec<u8>),
+}
+
+struct Claimed {
+    at: Instant,
+    fingerprint: String,
+    entry: Entry,
+}
+
+/// An [`IdempotencyStore`] in this process' memory, forgetting keys `ttl` after they were claimed.
+pub struct MemoryStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Claimed>>,
+}
+
+impl MemoryStore {
+    pub fn new(ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn claim(&self, key: &str, fingerprint: &str) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+
+        let now = Instant::now();
+        entries.retain(|_, claimed| now.duration_since(claimed.at) < self.ttl);
+
+        match entries.get(key) {
+            Some(claimed) if claimed.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Claimed {
+                entry: Entry::InProgress,
+                ..
+            }) => Claim::InProgress,
+            Some(Claimed {
+                entry: Entry::Completed(response),
+                ..
+            }) => Claim::Completed(response.clone()),
+            None => {
+                let claimed = Claimed {
+                    at: now,
+                    fingerprint: fingerprint.to_owned(),
+                    entry: Entry::InProgress,
+                };
+                entries.insert(key.to_owned(), claimed);
+                Claim::Claimed
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, response: Vec<u8>) {
+        if let Some(claimed) = self.entries.lock().unwrap().get_mut(key) {
+            claimed.entry = Entry::Completed(response);
+        }
+    }
+
+    fn release(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// An [`IdempotencyStore`] in a [`MetadataStore`](crate::metadata::MetadataStore), under `idempotency:<key>` - so
+/// keys are shared by every server using the same backend, and survive restarts if it does. Forgets keys `ttl`
+/// after they were claimed, or completed.
+///
+/// A backend that's failing can't say whether a key's been used, so requests with keys get a 409 until it's back.
+#[cfg(feature = "metadata")]
+pub struct MetadataBacked<M> {
+    metadata: M,
+    ttl: Duration,
+}
+
+#[cfg(feature = "metadata")]
+impl<M: crate::metadata::MetadataStore> MetadataBacked<M> {
+    pub fn new(metadata: M, ttl: Duration) -> MetadataBacked<M> {
+        MetadataBacked { metadata, ttl }
+    }
+
+    fn document(state: &str, fingerprint: &str, response: Option<&[u8]>) -> serde_json::Value {
+        let mut document = serde_json::Map::new();
+        document.insert("state".to_owned(), state.into());
+        document.insert("fingerprint".to_owned(), fingerprint.into());
+        if let Some(response) = response {
+            document.insert(
+                "response".to_owned(),
+                crate::base64::encode_url(response).into(),
+            );
+        }
+        document.into()
+    }
+}
+
+#[cfg(feature = "metadata")]
+impl<M: crate::metadata::MetadataStore> IdempotencyStore for MetadataBacked<M> {
+    fn claim(&self, key: &str, fingerprint: &str) -> Claim {
+        let key = format!("idempotency:{}", key);
+        let expires = Some(std::time::SystemTime::now() + self.ttl);
+        let document = Self::document("in_progress", fingerprint, None);
+        match self.metadata.insert(&key, &document, expires) {
+            Ok(true) => return Claim::Claimed,
+            Ok(false) => {}
+            Err(_) => return Claim::InProgress,
+        }
+
+        let document = match self.metadata.get(&key) {
+            Ok(Some(document)) => document,
+            _ => return Claim::InProgress,
+        };
+        if document["fingerprint"].as_str() != Some(fingerprint) {
+            return Claim::Mismatch;
+        }
+        let response = document["response"]
+            .as_str()
+            .and_then(|response| crate::base64::decode_url(response.as_bytes()));
+        match response {
+            Some(response) => Claim::Completed(response),
+            None => Claim::InProgress,
+        }
+    }
+
+    // the fingerprint is the claim's, which is still there to read back
+    fn complete(&self, key: &str, response: Vec<u8>) {
+        let key = format!("idempotency:{}", key);
+        let fingerprint = match self.metadata.get(&key) {
+            Ok(Some(document)) => document["fingerprint"].as_str().unwrap_or("").to_owned(),
+            _ => return,
+        };
+        let expires = Some(std::time::SystemTime::now() + self.ttl);
+        let _ = self.metadata.put(
+            &key,
+            &Self::document("completed", &fingerprint, Some(&response)),
+            expires,
+        );
+    }
+
+    fn release(&self, key: &str) {
+        let _ = self.metadata.delete(&format!("idempotency:{}", key));
+    }
+}
+
+/// Honors `Idempotency-Key` on unsafe methods: the first request with a key runs as usual and its response is
+/// stored, retries get that response replayed without the handler running again, and a retry that arrives while the
+/// first is still running gets a 409. Reusing a key for a request with a different body gets a 422.
+///
+/// Keys are the caller's own: they're scoped to the method, the path and whoever's calling - by default, which
+/// `Authorization` they sent, or none. Sessions are up to a [scope](Self::scope) of your own.
+///
+/// Bodies are read in full to fingerprint them, so ones bigger than the body limit (1MiB by default) get a 413.
+/// Responses bigger than the response limit (1MiB too) aren't stored, so retries of those run again.
+pub struct Idempotency<S> {
+    store: S,
+    max_body: usize,
+    max_response: usize,
+    scope: Box<dyn Fn(&Request<'_, '_, '_>) -> String + Send + Sync>,
+}
+
+impl<S: IdempotencyStore> Idempotency<S> {
+    pub fn new(store: S) -> Idempotency<S> {
+        Idempotency {
+            store,
+            max_body: 1024 * 1024,
+            max_response: 1024 * 1024,
+            // a hash, so the credentials themselves don't end up in the store
+            scope: Box::new(|request| match request.header("Authorization") {
+                Some(authorization) => format!("{:016x}", hash::fnv1a(authorization.as_bytes())),
+                None => String::new(),
+            }),
+        }
+    }
+
+    pub fn max_body(mut self, limit: usize) -> Self {
+        self.max_body = limit;
+        self
+    }
+
+    pub fn max_response(mut self, limit: usize) -> Self {
+        self.max_response = limit;
+        self
+    }
+
+    /// Who a request's keys belong to - the account or session it's for, say - so one caller's keys never replay
+    /// another's responses.
+    pub fn scope(
+        mut self,
+        scope: impl Fn(&Request<'_, '_, '_>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.scope = Box::new(scope);
+        self
+    }
+}
+
+// releases the key if the handler fails or panics before we get to store a response
+struct ClaimGuard<'g> {
+    store: &'g dyn IdempotencyStore,
+    key: &'g str,
+    response: Option<Vec<u8>>,
+}
+
+impl<'g> Drop for ClaimGuard<'g> {
+    fn drop(&mut self) {
+        match self.response.take() {
+            Some(response) => self.store.complete(self.key, response),
+            None => self.store.release(self.key),
+        }
+    }
+}
+
+impl<C: Send + Sync, S: IdempotencyStore> Middleware<C> for Idempotency<S> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let safe = headers::is_safe_method(request.method());
+
+        let key = match request.header("Idempotency-Key").map(str::trim) {
+            Some(key) if !safe && !key.is_empty() && key.len() <= 255 => format!(
+                "{} {} {} {}",
+                (self.scope)(&request),
+                request.method(),
+                request.path(),
+                key
+            ),
+            _ => return next.run(request, context),
+        };
+
+        let mut body = Vec::new();
+        (&mut request.body)
+            .take(self.max_body as u64 + 1)
+            .read_to_end(&mut body)?;
+        if body.len() > self.max_body {
+            request.respond_with_bytes(
+                413,
+                vec![],
+                b"request is too large for an idempotency key",
+            )?;
+            return Ok(());
+        }
+        let fingerprint = format!("{:016x}", hash::fnv1a(&body));
+        request.body = Body::new(Cursor::new(body));
+
+        match self.store.claim(&key, &fingerprint) {
+            Claim::Completed(response) => {
+                request.respond_raw(&response)?;
+                return Ok(());
+            }
+            Claim::InProgress => {
+                request.respond_with_bytes(
+                    409,
+                    vec![],
+                    b"a request with this idempotency key is in progress",
+                )?;
+                return Ok(());
+            }
+            Claim::Mismatch => {
+                request.respond_with_bytes(
+                    422,
+                    vec![],
+                    b"this idempotency key was used for a different request",
+                )?;
+                return Ok(());
+            }
+            Claim::Claimed => {}
+        }
+
+        let mut guard = ClaimGuard {
+            store: &self.store,
+            key: &key,
+            response: None,
+        };
+
+        let mut captured = Captured::default();
+        let request = request
+            .wrap_output(|output| Box::new(Tee::new(output, &mut captured, self.max_response)));
+        next.run(request, context)?;
+
+        if !captured.truncated && !captured.bytes.is_empty() {
+            guard.response = Some(captured.bytes);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageOutputFormat};
+use thiserror::Error;
+
+use crate::MultipartEntry;
+
+/// The image formats these helpers understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+}
+
+impl ImageFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::WebP => "webp",
+        }
+    }
+}
+
+/// What an upload turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("not a png, jpeg, gif or webp image")]
+    UnknownFormat,
+    #[error("image is cut short or malformed")]
+    Malformed,
+    #[error("image is {width}x{height}, more than the {max_pixels} pixels allowed")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    #[error(transparent)]
+    Codec(#[from] image::ImageError),
+}
+
+/// Works out an image's format and dimensions from its header, without decoding it.
+pub fn inspect(data: &[u8]) -> Result<ImageInfo, ImageError> {
+    let format = sniff(data).ok_or(ImageError::UnknownFormat)?;
+    let (width, height) = match format {
+        ImageFormat::Png => png_size(data),
+        ImageFormat::Jpeg => jpeg_size(data),
+        ImageFormat::Gif => gif_size(data),
+        ImageFormat::WebP => webp_size(data),
+    }
+    .ok_or(ImageError::Malformed)?;
+
+    Ok(ImageInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+/// Removes EXIF, XMP, text and comment metadata (camera details, GPS positions and the like) without touching the
+/// pixels. Colour profiles are kept. GIFs come back as they were.
+pub fn strip_metadata(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    match sniff(data).ok_or(ImageError::UnknownFormat)? {
+        ImageFormat::Png => strip_png(data),
+        ImageFormat::Jpeg => strip_jpeg(data),
+        ImageFormat::WebP => strip_webp(data),
+        ImageFormat::Gif => Some(data.to_vec()),
+    }
+    .ok_or(ImageError::Malformed)
+}
+
+/// Decodes the image and encodes it again, which throws away all metadata along with anything hiding in the file
+/// that isn't pixels. WebP comes back as PNG. Images over `max_pixels` are refused before decoding, so a tiny file
+/// claiming enormous dimensions can't eat all the memory.
+pub fn reencode(data: &[u8], max_pixels: u64) -> Result<(ImageFormat, Vec<u8>), ImageError> {
+    let (info, decoded) = decode(data, max_pixels)?;
+    encode(info.format, &decoded)
+}
+
+/// Scales the image down to fit in `width` by `height`, keeping its aspect ratio - or leaves it the size it is, if
+/// it fits already. It comes back re-encoded like [`reencode`] would, with the same limit on pixels.
+pub fn thumbnail(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    max_pixels: u64,
+) -> Result<(ImageFormat, Vec<u8>), ImageError> {
+    let (info, decoded) = decode(data, max_pixels)?;
+    match info.width <= width && info.height <= height {
+        true => encode(info.format, &decoded),
+        false => encode(info.format, &decoded.thumbnail(width, height)),
+    }
+}
+
+fn decode(data: &[u8], max_pixels: u64) -> Result<(ImageInfo, DynamicImage), ImageError> {
+    let info = inspect(data)?;
+    if info.width as u64 * info.height as u64 > max_pixels {
+        return Err(ImageError::TooLarge {
+            width: info.width,
+            height: info.height,
+            max_pixels,
+        });
+    }
+
+    let decoded = image::load_from_memory_with_format(
+        data,
+        match info.format {
+            ImageFormat::Png => image::ImageFormat::Png,
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            ImageFormat::Gif => image::ImageFormat::Gif,
+            ImageFormat::WebP => image::ImageFormat::WebP,
+        },
+    )?;
+    Ok((info, decoded))
+}
+
+fn encode(format: ImageFormat, image: &DynamicImage) -> Result<(ImageFormat, Vec<u8>), ImageError> {
+    let (format, output) = match format {
+        ImageFormat::Jpeg => (ImageFormat::Jpeg, ImageOutputFormat::Jpeg(90)),
+        ImageFormat::Gif => (ImageFormat::Gif, ImageOutputFormat::Gif),
+        ImageFormat::Png | ImageFormat::WebP => (ImageFormat::Png, ImageOutputFormat::Png),
+    };
+
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, output)?;
+    Ok((format, encoded.into_inner()))
+}
+
+impl<'v> MultipartEntry<'v> {
+    /// [`inspect`]s the uploaded file. What the client said its content type was doesn't come into it.
+    pub fn image_info(&self) -> Result<ImageInfo, ImageError> {
+        inspect(self.data)
+    }
+}
+
+fn sniff(data: &[u8]) -> Option<ImageFormat> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageFormat::Png)
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some(ImageFormat::Jpeg)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(ImageFormat::Gif)
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some(ImageFormat::WebP)
+    } else {
+        None
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn le24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    // the IHDR chunk always comes first
+    (data.get(12..16)? == b"IHDR").then_some(())?;
+    Some((be32(data, 16)?, be32(data, 20)?))
+}
+
+fn gif_size(data: &[u8]) -> Option<(u32, u32)> {
+    Some((le16(data, 6)?, le16(data, 8)?))
+}
+
+fn jpeg_size(data: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        // markers can be padded with any number of 0xff
+        while *data.get(at)? == 0xff && *data.get(at + 1)? == 0xff {
+            at += 1;
+        }
+        (*data.get(at)? == 0xff).then_some(())?;
+        let marker = *data.get(at + 1)?;
+        let length = be16(data, at + 2)? as usize;
+
+        // start-of-frame markers, minus the ones that reuse the range for other things
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            return Some((be16(data, at + 7)?, be16(data, at + 5)?));
+        }
+        if marker == 0xda {
+            return None;
+        }
+
+        at += 2 + length;
+    }
+}
+
+fn webp_size(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => Some((le16(data, 26)? & 0x3fff, le16(data, 28)? & 0x3fff)),
+        b"VP8L" => {
+            let bits = le32(data, 21)?;
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le24(data, 24)? + 1, le24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..8].to_vec();
+    let mut at = 8;
+
+    while at < data.len() {
+        let length = be32(data, at)? as usize;
+        let end = at.checked_add(12 + length)?;
+        let chunk = data.get(at..end)?;
+
+        if !matches!(
+            &chunk[4..8],
+            b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME"
+        ) {
+            stripped.extend_from_slice(chunk);
+        }
+        at = end;
+    }
+
+    Some(stripped)
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..2].to_vec();
+    let mut at = 2;
+
+    loop {
+        while *data.get(at)? == 0xff && *data.get(at + 1)? == 0xff {
+            at += 1;
+        }
+        (*data.get(at)? == 0xff).then_some(())?;
+        let marker = *data.get(at + 1)?;
+
+        // everything from the start of the scan on is image data
+        if marker == 0xda {
+            stripped.extend_from_slice(&data[at..]);
+            return Some(stripped);
+        }
+
+        let end = at + 2 + be16(data, at + 2)? as usize;
+        let segment = data.get(at..end)?;
+
+        // APP1 is EXIF and XMP, APP13 is IPTC, COM is free-form comments. APP2 carries the colour profile
+        if !matches!(marker, 0xe1 | 0xed | 0xfe) {
+            stripped.extend_from_slice(segment);
+        }
+        at = end;
+    }
+}
+
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..12].to_vec();
+    let mut at = 12;
+
+    while at < data.len() {
+        let length = le32(data, at + 4)? as usize;
+        // chunks are padded to an even length
+        let end = (at.checked_add(8 + length + (length & 1))?).min(data.len());
+        let chunk = data.get(at..end)?;
+
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let mut chunk = chunk.to_vec();
+                // and don't claim to have them any more
+                *chunk.get_mut(8)? &= !0b1100;
+                stripped.extend_from_slice(&chunk);
+            }
+            _ => stripped.extend_from_slice(chunk),
+        }
+        at = end;
+    }
+
+    let riff_size = (stripped.len() - 8) as u32;
+    stripped[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(stripped)
+}
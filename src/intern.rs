@@ -0,0 +1,189 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    sync::{OnceLock, RwLock},
+};
+
+// interned before anything asks, so the names every server uses are always there
+const COMMON_HEADERS: &[&str] = &[
+    "Accept",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Authorization",
+    "Cache-Control",
+    "Connection",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Type",
+    "Cookie",
+    "ETag",
+    "Host",
+    "If-Match",
+    "If-Modified-Since",
+    "If-None-Match",
+    "Last-Modified",
+    "Location",
+    "Origin",
+    "Range",
+    "Referer",
+    "Set-Cookie",
+    "Transfer-Encoding",
+    "User-Agent",
+    "Vary",
+    "X-Forwarded-For",
+    "X-Request-Id",
+];
+
+// header names longer than this are rare enough not to be worth interning
+const MAX_HEADER: usize = 64;
+
+/// A string interned for as long as the process runs. Every symbol for the same string points at the same bytes,
+/// so comparing two or hashing one only looks at where they point, however long the string.
+///
+/// Route patterns are interned as the routes are built, and the usual header names before anything's interned at
+/// all. Interning is a lookup in a global table, so do it up front -
+/// not per request with whatever a client sent.
+#[derive(Clone, Copy)]
+pub struct Symbol(&'static str);
+
+impl Symbol {
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        // a symbol's string is the only one of its kind, so the same address is the same string
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0.as_ptr() as usize).hash(state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[derive(Default)]
+struct Interner {
+    strings: HashSet<&'static str>,
+    // by lowercased name
+    headers: HashMap<&'static str, Symbol>,
+}
+
+impl Interner {
+    fn insert(&mut self, string: &str, leak: impl FnOnce() -> &'static str) -> Symbol {
+        if let Some(interned) = self.strings.get(string) {
+            return Symbol(interned);
+        }
+
+        let interned = leak();
+        self.strings.insert(interned);
+        Symbol(interned)
+    }
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+
+    INTERNER.get_or_init(|| {
+        let mut interner = Interner::default();
+        for name in COMMON_HEADERS {
+            let symbol = interner.insert(name, || *name);
+            let lowercase = Box::leak(name.to_ascii_lowercase().into_boxed_str());
+            interner.headers.insert(lowercase, symbol);
+        }
+        RwLock::new(interner)
+    })
+}
+
+/// The symbol for `string`, interning a copy of it if it's new.
+pub fn intern(string: &str) -> Symbol {
+    if let Some(symbol) = lookup(string) {
+        return symbol;
+    }
+
+    interner()
+        .write()
+        .unwrap()
+        .insert(string, || Box::leak(string.into()))
+}
+
+/// Like [`intern`], but a new string is interned as it is instead of copied.
+pub fn intern_static(string: &'static str) -> Symbol {
+    if let Some(symbol) = lookup(string) {
+        return symbol;
+    }
+
+    interner().write().unwrap().insert(string, || string)
+}
+
+/// The symbol for `string` if it's been interned, without interning it if it hasn't.
+pub fn lookup(string: &str) -> Option<Symbol> {
+    interner()
+        .read()
+        .unwrap()
+        .strings
+        .get(string)
+        .copied()
+        .map(Symbol)
+}
+
+/// The symbol for the header called `name`, whatever its case - the spelling the first time it was interned is
+/// the one that's kept. For headers of your own that you look up a lot, interned at startup like the usual ones.
+pub fn intern_header(name: &str) -> Symbol {
+    if let Some(symbol) = header(name) {
+        return symbol;
+    }
+
+    let mut interner = interner().write().unwrap();
+    let symbol = interner.insert(name, || Box::leak(name.into()));
+    if name.len() > MAX_HEADER {
+        return symbol;
+    }
+
+    let lowercase = Box::leak(name.to_ascii_lowercase().into_boxed_str());
+    *interner.headers.entry(lowercase).or_insert(symbol)
+}
+
+/// The symbol for the header called `name`, whatever its case, if it's been interned - safe to call with what a
+/// client sent, since it never interns anything.
+pub fn header(name: &str) -> Option<Symbol> {
+    if name.len() > MAX_HEADER {
+        return None;
+    }
+
+    // lowercased on the stack, so looking a name up doesn't allocate
+    let mut lowercase = [0; MAX_HEADER];
+    let lowercase = &mut lowercase[..name.len()];
+    lowercase.copy_from_slice(name.as_bytes());
+    lowercase.make_ascii_lowercase();
+    let lowercase = std::str::from_utf8(lowercase).ok()?;
+
+    interner().read().unwrap().headers.get(lowercase).copied()
+}
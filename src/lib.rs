@@ -1,18 +1,206 @@
 use std::{
+    borrow::Cow,
     io::{self, Read, Write},
-    mem,
     sync::Arc,
-    thread,
+    time::Instant,
 };
 
 use matchit::*;
 use mime::Mime;
-use multipart::server::Multipart;
 use tiny_http::{HTTPVersion, Header, Request as TinyHttpRequest, Response, StatusCode};
 
 mod err;
 pub use err::*;
 
+mod body;
+pub use body::{Body, TruncatedBody};
+
+mod accounting;
+pub use accounting::Accounting;
+
+mod response;
+use response::{AutoFlush, ReasonPhraseWriter};
+pub use response::Status;
+
+mod router;
+pub use router::{RouteError, RouteInfo, Router, Routes};
+
+mod extensions;
+pub use extensions::Extensions;
+
+mod arena;
+pub use arena::Arena;
+
+mod connection;
+pub use connection::ClientConnection;
+
+pub mod intern;
+
+mod middleware;
+pub use middleware::{Middleware, Next};
+
+mod tee;
+
+pub mod idempotency;
+pub mod api_keys;
+
+pub mod audit;
+pub mod transaction;
+pub mod trace;
+
+pub mod access_log;
+
+pub mod profiler;
+
+pub mod metrics;
+
+pub mod store;
+
+pub mod throttle;
+
+pub mod tarpit;
+
+pub mod deadline;
+
+pub mod mtls;
+
+pub mod files;
+pub mod headers;
+pub mod path;
+pub mod urls;
+pub mod redirect;
+pub mod upload;
+pub mod charset;
+pub mod notify;
+pub mod hub;
+pub mod maintenance;
+pub mod range;
+
+pub mod i18n;
+use i18n::Localizer;
+
+#[cfg(feature = "embed")]
+pub mod embed;
+
+#[cfg(feature = "openapi")]
+pub mod openapi;
+
+#[cfg(feature = "csrf")]
+pub mod csrf;
+
+pub mod forms;
+pub mod pagination;
+pub mod precondition;
+
+#[cfg(feature = "csp")]
+pub mod csp;
+
+#[cfg(feature = "images")]
+pub mod images;
+
+#[cfg(feature = "chaos")]
+pub mod chaos;
+
+#[cfg(feature = "client")]
+pub mod client;
+
+#[cfg(feature = "grpc-web")]
+pub mod grpc_web;
+
+#[cfg(feature = "alloc-stats")]
+pub mod alloc_stats;
+
+#[cfg(feature = "record")]
+pub mod record;
+
+#[cfg(feature = "otlp")]
+pub mod otlp;
+
+#[cfg(feature = "tus")]
+pub mod tus;
+
+#[cfg(feature = "s3")]
+pub mod s3;
+
+#[cfg(feature = "blobs")]
+pub mod blob;
+#[cfg(feature = "blobs")]
+pub mod derivatives;
+#[cfg(feature = "blobs")]
+pub mod retention;
+
+#[cfg(feature = "metadata")]
+pub mod metadata;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+
+/// Adapters for crates beak doesn't otherwise depend on.
+#[cfg(feature = "postgres")]
+pub mod ext {
+    #[cfg(feature = "postgres")]
+    pub mod postgres;
+}
+
+#[cfg(feature = "webhooks")]
+pub mod webhook;
+
+#[cfg(feature = "acme-challenges")]
+pub mod acme;
+
+mod random;
+
+mod hash;
+
+#[cfg(any(
+    feature = "grpc-web",
+    feature = "tls",
+    feature = "blobs",
+    feature = "metadata"
+))]
+mod base64;
+
+mod rewrite;
+
+mod well_known;
+pub use rewrite::Rewrite;
+
+mod server;
+pub use server::*;
+
+mod config;
+pub use config::{BeakConfig, ConfigError, ConfigReload};
+
+mod tcp;
+
+#[cfg(feature = "tls")]
+mod tls;
+
+mod transport;
+pub use transport::Transport;
+
+mod dispatch;
+pub use dispatch::QueueClass;
+
+mod admin;
+
+#[cfg(feature = "signals")]
+mod signals;
+
+#[cfg(unix)]
+pub mod systemd;
+
+pub mod loopback;
+
+#[cfg(feature = "batch")]
+mod batch;
+
+#[cfg(feature = "conformance")]
+pub mod conformance;
+
+#[cfg(all(unix, feature = "reload"))]
+pub mod reload;
+
+#[derive(Clone)]
 pub struct MultipartEntry<'v> {
     pub name: Arc<str>,
     pub file_name: Option<String>,
@@ -25,32 +213,267 @@ pub struct Request<'url, 'sender, 'mv> {
     pub params: Params<'url, 'url>,
     pub multipart_entry: Option<MultipartEntry<'mv>>,
     pub headers: &'url [Header],
+    pub body: Body<'sender>,
+    pub extensions: Extensions,
+    method: &'url str,
+    route: &'static str,
     http_version: HTTPVersion,
-    output: &'sender mut (dyn Write + Send + 'static),
+    // boxed rather than borrowed so middleware can wrap it in writers of their own
+    output: Box<dyn Write + Send + 'sender>,
+    response_headers: Vec<Header>,
+    accounting: Accounting,
+    deadline: Option<Instant>,
+    secure: bool,
+    urls: &'static urls::Urls,
+    arena: &'url Arena,
+    connection: Arc<ClientConnection>,
+}
+
+pub(crate) fn find_header<'h>(headers: &'h [Header], name: &str) -> Option<&'h str> {
+    headers
+        .iter()
+        .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|header| header.value.as_str())
 }
 
 impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
-    pub fn respond(
+    pub fn method(&self) -> &'url str {
+        self.method
+    }
+
+    /// The path pattern of the route this request matched, like `/posts/:id`.
+    pub fn route(&self) -> &'static str {
+        self.route
+    }
+
+    /// Scratch space for the request, freed all at once when it's done with - see [`Arena`].
+    pub fn arena(&self) -> &'url Arena {
+        self.arena
+    }
+
+    /// The connection the request came in on, and whatever's been stored in it for every request on it.
+    pub fn connection(&self) -> &ClientConnection {
+        &self.connection
+    }
+
+    /// The parameter called `name`, percent-decoded into the request's arena. `None` if the route hasn't got one.
+    pub fn decoded_param(&self, name: &str) -> Option<Result<&'url str, path::PathError>> {
+        let arena = self.arena;
+        self.params.get(name).map(|param| arena.percent_decode(param))
+    }
+
+    /// What the route's trailing catch-all (`/static/*file`) captured, percent-decoded and normalized into a relative
+    /// path with no `..` in it - safe to join onto a directory.
+    pub fn wildcard(&self) -> Result<String, path::PathError> {
+        let name = self
+            .route
+            .rsplit('/')
+            .next()
+            .and_then(|segment| segment.strip_prefix('*'))
+            .ok_or(path::PathError::NoWildcard)?;
+        let captured = self.params.get(name).ok_or(path::PathError::NoWildcard)?;
+
+        path::normalize(&path::percent_decode(captured)?)
+    }
+
+    pub fn path(&self) -> &'url str {
+        self.url.split_once('?').map_or(self.url, |(path, _)| path)
+    }
+
+    pub fn query(&self) -> Option<&'url str> {
+        self.url.split_once('?').map(|(_, query)| query)
+    }
+
+    /// The first header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'url str> {
+        find_header(self.headers, name)
+    }
+
+    /// Which of `supported` the client would rather have, going by `Accept-Language`.
+    pub fn preferred_language<'s, S: AsRef<str>>(&self, supported: &'s [S]) -> Option<&'s str> {
+        i18n::negotiate_language(self.header("Accept-Language")?, supported)
+    }
+
+    /// Looks `key` up in the client's preferred language, or the localizer's first language if it accepts none.
+    pub fn localize<'l>(&self, localizer: &'l impl Localizer, key: &str) -> Option<Cow<'l, str>> {
+        let languages = localizer.languages();
+        let language = self
+            .preferred_language(languages)
+            .or_else(|| languages.first().map(String::as_str))?;
+
+        localizer.lookup(language, key)
+    }
+
+    /// Running byte counts for this request. Hold on to it to see the totals after responding.
+    pub fn accounting(&self) -> Accounting {
+        self.accounting.clone()
+    }
+
+    /// The value of the cookie called `name`.
+    pub fn cookie(&self, name: &str) -> Option<&'url str> {
+        headers::cookie(self.header("Cookie")?, name)
+    }
+
+    /// Sends `header` with whatever response this request gets, for middleware that need to set cookies or
+    /// security headers without the handler knowing.
+    pub fn add_response_header(&mut self, header: Header) {
+        self.response_headers.push(header);
+    }
+
+    /// Sends a `103 Early Hints` interim response with these `Link` values (like `</app.css>; rel=preload; as=style`),
+    /// so the browser can start fetching while the real response is being put together. Only sent before
+    /// responding, as often as needed - HTTP/1.0 clients don't know interim responses, so they don't get any.
+    pub fn early_hints(&mut self, links: &[&str]) -> io::Result<()> {
+        if (self.http_version.0, self.http_version.1) < (1, 1) || links.is_empty() {
+            return Ok(());
+        }
+
+        let mut head = String::from("HTTP/1.1 103 Early Hints\r\n");
+        for link in links {
+            if link.contains(['\r', '\n']) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "link header value contains a line break",
+                ));
+            }
+            head.push_str("Link: ");
+            head.push_str(link);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+
+        // flushed right away, or it would sit in the buffer until the real response joins it
+        TinyHttpRequest::ignore_client_closing_errors(
+            self.output
+                .write_all(head.as_bytes())
+                .and_then(|_| self.output.flush()),
+        )
+    }
+
+    /// Swaps the output for a writer wrapping it, to watch or change the bytes that get sent.
+    pub fn wrap_output<'w>(
         self,
-        status: impl Into<StatusCode>,
-        headers: Vec<Header>,
+        wrap: impl FnOnce(Box<dyn Write + Send + 'sender>) -> Box<dyn Write + Send + 'w>,
+    ) -> Request<'url, 'w, 'mv>
+    where
+        'sender: 'w,
+    {
+        Request {
+            url: self.url,
+            params: self.params,
+            multipart_entry: self.multipart_entry,
+            headers: self.headers,
+            body: self.body,
+            extensions: self.extensions,
+            method: self.method,
+            route: self.route,
+            http_version: self.http_version,
+            output: wrap(self.output),
+            response_headers: self.response_headers,
+            accounting: self.accounting,
+            deadline: self.deadline,
+            secure: self.secure,
+            urls: self.urls,
+            arena: self.arena,
+            connection: self.connection,
+        }
+    }
+
+    /// Flushes the output after every write, for streaming handlers (server-sent events, long polls) whose writes
+    /// have to reach the client as soon as they're made, whatever the server's buffering.
+    pub fn unbuffered(self) -> Request<'url, 'sender, 'mv> {
+        self.wrap_output(|output| Box::new(AutoFlush::new(output)))
+    }
+
+    // for replaying a response that was already serialized, head and all
+    pub(crate) fn respond_raw(mut self, response: &[u8]) -> io::Result<()> {
+        TinyHttpRequest::ignore_client_closing_errors(self.output.write_all(response))
+    }
+
+    pub fn respond(
+        mut self,
+        status: impl Into<Status>,
+        mut headers: Vec<Header>,
         writer: impl FnOnce(&mut dyn Write, &mut io::Empty) -> io::Result<()>,
     ) -> io::Result<()> {
-        let response = Response::new(status.into(), headers, io::empty(), None, None);
+        let status = status.into();
+        headers.extend(self.response_headers);
+        check_head(&headers, status.custom_reason())?;
+        let response = Response::new(StatusCode(status.code()), headers, io::empty(), None, None);
+
+        let result = match status.custom_reason() {
+            Some(reason) => response.print_and_write(
+                ReasonPhraseWriter::new(&mut self.output, reason),
+                self.http_version,
+                self.headers,
+                false,
+                None,
+                None,
+                writer,
+            ),
+            None => response.print_and_write(
+                self.output,
+                self.http_version,
+                self.headers,
+                false,
+                None,
+                None,
+                writer,
+            ),
+        };
 
-        TinyHttpRequest::ignore_client_closing_errors(response.print_and_write(
-            self.output,
-            self.http_version,
-            self.headers,
-            false,
-            None,
-            None,
-            writer,
-        ))
+        TinyHttpRequest::ignore_client_closing_errors(result)
+    }
+
+    /// Responds with the whole of `data`, setting `Content-Length` for it.
+    pub fn respond_with_bytes(
+        self,
+        status: impl Into<Status>,
+        mut headers: Vec<Header>,
+        data: &[u8],
+    ) -> io::Result<()> {
+        headers.push(headers::header("Content-Length", data.len().to_string()));
+        self.respond(status, headers, |writer, _| writer.write_all(data))
+    }
+
+    /// Responds with only a head, for a 304 Not Modified or a 204 No Content - which never have a body, and
+    /// shouldn't get the `Content-Length: 0` [`respond_with_bytes`](Self::respond_with_bytes) would give them, since on
+    /// a 304 that says the resource itself is empty.
+    pub fn respond_without_body(
+        mut self,
+        status: impl Into<Status>,
+        mut headers: Vec<Header>,
+    ) -> io::Result<()> {
+        let status = status.into();
+        headers.extend(self.response_headers);
+        headers.push(headers::header(
+            "Date",
+            headers::http_date(std::time::SystemTime::now()),
+        ));
+        check_head(&headers, status.custom_reason())?;
+
+        let mut head = format!(
+            "HTTP/{}.{} {}\r\n",
+            self.http_version.0, self.http_version.1, status
+        );
+        for header in &headers {
+            head.push_str(header.field.as_str().as_str());
+            head.push_str(": ");
+            head.push_str(header.value.as_str());
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+
+        TinyHttpRequest::ignore_client_closing_errors(self.output.write_all(head.as_bytes()))
     }
 
     // i have such good naming
-    pub fn respond_with_tinyhttp(self, res: Response<impl Read>) -> io::Result<()> {
+    pub fn respond_with_tinyhttp(self, mut res: Response<impl Read>) -> io::Result<()> {
+        for header in self.response_headers {
+            res.add_header(header);
+        }
+        check_head(res.headers(), None)?;
+
         TinyHttpRequest::ignore_client_closing_errors(res.raw_print(
             self.output,
             self.http_version,
@@ -61,6 +484,15 @@ impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
     }
 }
 
+// refuses a head that would come out as something other than the headers it's made of
+fn check_head(headers: &[Header], reason: Option<&str>) -> io::Result<()> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+    for header in headers {
+        headers::validate(header).map_err(invalid)?;
+    }
+    reason.map_or(Ok(()), headers::validate_reason).map_err(invalid)
+}
+
 pub trait Handler<C: Send + Sync> {
     fn handle<'url, 'sender, 'mv>(
         &self,
@@ -70,9 +502,69 @@ pub trait Handler<C: Send + Sync> {
 
     fn needs_multipart(&self) -> bool;
 
+    /// Streams every part of a multipart body into a temporary directory, instead of only the first into memory -
+    /// the handler finds them as [`UploadedFiles`](upload::UploadedFiles) in its request's
+    /// [`extensions`](Request::extensions), and anything it doesn't persist is deleted once it's done. The directory
+    /// goes under the server's [`upload_dir`](ServerBuilder::upload_dir).
+    fn multipart_to_disk(&self) -> bool {
+        false
+    }
+
+    /// The route's path pattern. Segments are either literal, `:name` to capture one segment, or - at the very end
+    /// only - `*name` to capture everything that's left, which [`Request::wildcard`] hands back cleaned up.
     fn path(&self) -> &'static str;
+
+    /// The methods this handler answers, compared case-insensitively. Empty means every method.
+    /// Other methods on the same path get a 405, unless another handler takes them. Answering `GET` means answering
+    /// `HEAD` too, when no handler on the path takes it - the handler sees a `HEAD`, and whatever body it writes is
+    /// left out.
+    fn methods(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Routes with a higher priority are tried first, and a request only falls through to lower ones if nothing
+    /// higher matched its path and method. matchit refuses routes that overlap (`/files/*path` and `/files/special`),
+    /// so give one of them a different priority to have both.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Which queue this handler's requests wait in when the server is saturated, with
+    /// [`ServerBuilder::priority_queue`] on.
+    fn queue_class(&self) -> QueueClass {
+        QueueClass::Normal
+    }
+
+    /// Whether this handler takes `CONNECT` requests for `authority`, the `host:port` they ask to be connected to,
+    /// and [tunnels](Self::tunnel) them. Those don't go by the handler's path or methods: the highest priority handler
+    /// for the request's host that says yes gets it, and if none does it's routed like any other request. No
+    /// handler tunnels unless it says so.
+    fn tunnels(&self, _authority: &str) -> bool {
+        false
+    }
+
+    /// Takes over the connection of a `CONNECT` this handler said it [tunnels](Self::tunnels), after beak's answered
+    /// it with a 200. What's read from and written to `stream` is whatever goes through the tunnel, and the
+    /// connection closes once the handler drops it.
+    fn tunnel(&self, _authority: &str, _stream: Tunnel, _context: C) -> BeakResult<()> {
+        Ok(())
+    }
+
+    /// What route listings call this handler - its type name, unless it says otherwise.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// What the OpenAPI document says about this handler.
+    #[cfg(feature = "openapi")]
+    fn operation(&self) -> openapi::Operation {
+        openapi::Operation::new()
+    }
 }
 
+/// Both directions of a tunneled connection, for [`Handler::tunnel`].
+pub type Tunnel = Box<dyn tiny_http::ReadWrite + Send>;
+
 pub fn run<C: Clone + Send + Sync>(
     workers: usize,
     addr: &'static str,
@@ -80,134 +572,132 @@ pub fn run<C: Clone + Send + Sync>(
     routes: &'static [&'static (dyn Handler<C> + Send + Sync)],
     context: C,
 ) -> BeakResult<()> {
-    let server = Arc::new(tiny_http::Server::http(addr).expect("Could not bind address"));
+    ServerBuilder::new(addr, routes)
+        .workers(workers)
+        .multipart_upload_limit(multipart_upload_limit)
+        .run(context)
+}
 
-    let mut guards = Vec::with_capacity(workers);
+mod macros {
+    /// Turns a function into a [`Handler`]. Methods go before the path, like `GET | HEAD "/posts/:id"`;
+    /// without any, the handler answers every method. A name after the path (`"/posts/:id" as "post"`) is what
+    /// [`Request::url_for`] knows the route by - otherwise it goes by the handler's type name.
+    ///
+    /// The handler gets a `PATH` constant with its pattern, for [`url_for!`].
+    #[macro_export]
+    macro_rules! fn_to_handler {
+        (@impl $handler_name:ident, $ctx:ty, $path:literal, $fn_name:ident, $multipart:literal, $to_disk:literal, [$($method:ident)*], $name:expr) => {
+            pub struct $handler_name;
 
-    for _ in 0..workers {
-        let server = server.clone();
-        let context = context.clone();
+            impl $handler_name {
+                pub const PATH: &'static str = $path;
+            }
 
-        let mut router: Router<&(dyn Handler<C> + Send + Sync)> = Router::new();
-        for route in routes {
-            router.insert(route.path(), *route).unwrap();
-        }
+            impl $crate::Handler<$ctx> for $handler_name {
+                fn handle<'url, 'sender, 'mv>(
+                    &self,
+                    request: $crate::Request<'url, 'sender, 'mv>,
+                    context: $ctx,
+                ) -> $crate::BeakResult<()> {
+                    $fn_name(request, context)
+                }
 
-        let mut buffer = Vec::with_capacity(multipart_upload_limit);
-
-        let guard = thread::spawn(move || loop {
-            let mut mutable_req = server.recv().unwrap();
-
-            // we're going to have to borrow the request both mutably and immutably - we need it's data immutably, and it's output pipe mutably
-            // as these don't interact, this is safe to do, but violates borrow rules
-            let immutable_req_ptr: *const TinyHttpRequest = &mutable_req;
-            let immutable_req = unsafe { immutable_req_ptr.as_ref().unwrap_unchecked() };
-
-            let mut multipart_entry: Option<MultipartEntry<'_>> = None;
-
-            let url = immutable_req.url();
-            let matched = router.at(&url).unwrap();
-
-            if matched.value.needs_multipart() {
-                if let Some(mut multipart) = Multipart::from_request(&mut mutable_req)
-                    .ok()
-                    .and_then(|v| v.into_entry().into_result().ok())
-                    .flatten()
-                {
-                    multipart.data.read_to_end(&mut buffer).unwrap();
-                    multipart_entry = Some(MultipartEntry {
-                        name: multipart.headers.name.clone(),
-                        file_name: multipart.headers.filename,
-                        content_type: multipart.headers.content_type,
-                        data: &buffer,
-                    });
+                fn needs_multipart(&self) -> bool {
+                    $multipart
                 }
-            }
 
-            let mut resp_writer = mutable_req.extract_writer_impl();
-            let processed_req = Request {
-                url: &url,
-                params: matched.params,
-                multipart_entry,
-                headers: immutable_req.headers(),
-                http_version: immutable_req.http_version().clone(),
-                output: &mut resp_writer,
-            };
+                fn multipart_to_disk(&self) -> bool {
+                    $to_disk
+                }
 
-            matched
-                .value
-                .handle(processed_req, context.clone())
-                .unwrap();
+                fn path(&self) -> &'static str {
+                    $path
+                }
 
-            TinyHttpRequest::ignore_client_closing_errors(resp_writer.flush()).unwrap();
+                fn methods(&self) -> &'static [&'static str] {
+                    &[$(stringify!($method)),*]
+                }
 
-            // destroy our immutable reference *without* running the destructor
-            mem::forget(immutable_req);
+                fn name(&self) -> &'static str {
+                    $name
+                }
+            }
+        };
 
-            // drop our output pipe
-            drop(resp_writer);
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal as $name:literal => $fn_name:ident with multipart to disk) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, false, true, [$($method)*], $name);
+        };
 
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal as $name:literal => $fn_name:ident with multipart) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, true, false, [$($method)*], $name);
+        };
 
-            if let Some(sender) = mutable_req.notify_when_responded.take() {
-                sender.send(()).unwrap();
-            }
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal as $name:literal => $fn_name:ident) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, false, false, [$($method)*], $name);
+        };
 
-            // drop our request, running it's destructor
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal => $fn_name:ident with multipart to disk) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, false, true, [$($method)*], ::std::any::type_name::<$handler_name>());
+        };
 
-            drop(mutable_req);
-        });
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal => $fn_name:ident with multipart) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, true, false, [$($method)*], ::std::any::type_name::<$handler_name>());
+        };
 
-        guards.push(guard);
+        ($handler_name:ident with context $ctx:ty; $($method:ident)|* $path:literal => $fn_name:ident) => {
+            $crate::fn_to_handler!(@impl $handler_name, $ctx, $path, $fn_name, false, false, [$($method)*], ::std::any::type_name::<$handler_name>());
+        };
     }
 
-    for guard in guards {
-        guard.join().unwrap();
+    /// Builds the path to a handler made with [`fn_to_handler!`], like `url_for!(PostHandler, id = 42)`. Going by
+    /// the handler's type and checking the parameters against its pattern at compile time means a renamed route or
+    /// parameter breaks the build instead of the links. Values are anything `Display`, and get percent-encoded.
+    ///
+    /// This is just the path - [`Request::base_url`] has the rest of an absolute URL.
+    #[macro_export]
+    macro_rules! url_for {
+        ($handler:ty $(, $param:ident = $value:expr)* $(,)?) => {{
+            const _: () = assert!(
+                $crate::urls::params_match(<$handler>::PATH, &[$(stringify!($param)),*]),
+                "parameters don't match the route's path pattern"
+            );
+            $crate::urls::build_path(
+                <$handler>::PATH,
+                &[$((stringify!($param), &*$value.to_string())),*],
+            )
+            .expect("parameters were checked at compile time")
+        }};
     }
 
-    Ok(())
-}
-
-mod macros {
+    /// Serves an `include_dir!` directory from a route ending in a catch-all, like `/static/*file`.
+    /// The crate using this needs `include_dir` as a dependency of its own.
+    #[cfg(feature = "embed")]
     #[macro_export]
-    macro_rules! fn_to_handler {
-        ($handler_name:ident with context $ctx:ty; $path:literal => $fn_name:ident with multipart) => {
+    macro_rules! embed_dir {
+        ($handler_name:ident with context $ctx:ty; $path:literal => $dir:expr) => {
             pub struct $handler_name;
 
-            impl Handler<$ctx> for $handler_name {
-                fn handle<'url, 'sender, 'mv>(
-                    &self,
-                    request: Request<'url, 'sender, 'mv>,
-                    context: $ctx,
-                ) -> BeakResult<()> {
-                    $fn_name(request, context)
-                }
-            
-                fn needs_multipart(&self) -> bool {
-                    true
-                }
-            
-                fn path(&self) -> &'static str {
-                    $path
-                }
+            impl $handler_name {
+                pub const PATH: &'static str = $path;
             }
-        };
 
-        ($handler_name:ident with context $ctx:ty; $path:literal => $fn_name:ident) => {
-            pub struct $handler_name;
-
-            impl Handler<$ctx> for $handler_name {
+            impl $crate::Handler<$ctx> for $handler_name {
                 fn handle<'url, 'sender, 'mv>(
                     &self,
-                    request: Request<'url, 'sender, 'mv>,
-                    context: $ctx,
-                ) -> BeakResult<()> {
-                    $fn_name(request, context)
+                    request: $crate::Request<'url, 'sender, 'mv>,
+                    _context: $ctx,
+                ) -> $crate::BeakResult<()> {
+                    static EMBEDDED: $crate::embed::EmbeddedDir =
+                        $crate::embed::EmbeddedDir::new(&$dir);
+
+                    EMBEDDED.serve_request(request)?;
+                    Ok(())
                 }
-            
+
                 fn needs_multipart(&self) -> bool {
                     false
                 }
-            
+
                 fn path(&self) -> &'static str {
                     $path
                 }
@@ -0,0 +1,267 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+#[cfg(not(unix))]
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::{fs, os::unix::net::UnixStream, path::PathBuf};
+
+use tiny_http::Request as TinyHttpRequest;
+
+use crate::Transport;
+#[cfg(unix)]
+use crate::upload;
+
+#[cfg(unix)]
+type Stream = UnixStream;
+#[cfg(not(unix))]
+type Stream = TcpStream;
+
+// heads past this are a broken server, not something to keep reading
+const MAX_HEAD: usize = 64 * 1024;
+
+/// A transport for [`ServerBuilder::transport`](crate::ServerBuilder::transport) meant to be connected to by the
+/// process itself, through its [`Connector`] - for testing a whole server without binding a port.
+///
+/// It isn't an in-process pipe: tiny_http only reads from sockets, so the bytes go over a unix socket. That's in a
+/// directory of its own under the temp directory, with a random name and only the user the process runs as allowed
+/// in, so other users can't connect to it or put a socket of their own in its place - and tests can run side by
+/// side however many servers they start. Every request goes through tiny_http's parser and the rest of the pipeline
+/// exactly as one off the network would, keep-alive, chunked bodies and multipart included.
+///
+/// Where there are no unix sockets (Windows, say) it's a TCP socket on `127.0.0.1` instead, on a port the system
+/// picks. Any process on the machine can connect to that while it's up, so there it's only for machines whose other
+/// users can be trusted with the server - a CI runner, not a shared box.
+pub struct LoopbackTransport {
+    server: tiny_http::Server,
+    #[cfg(unix)]
+    dir: PathBuf,
+    #[cfg(unix)]
+    path: PathBuf,
+    #[cfg(not(unix))]
+    addr: SocketAddr,
+}
+
+/// Opens connections to a [`LoopbackTransport`] - keep one before handing the transport to the builder.
+#[derive(Debug, Clone)]
+pub struct Connector {
+    #[cfg(unix)]
+    path: PathBuf,
+    #[cfg(not(unix))]
+    addr: SocketAddr,
+}
+
+/// One connection, for sending requests over and reading their responses back, as many as it's kept alive for.
+pub struct Connection {
+    stream: BufReader<Stream>,
+}
+
+/// A response as it came over the connection, with a chunked body already put back together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawResponse {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl LoopbackTransport {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<LoopbackTransport> {
+        let dir = upload::private_dir(&std::env::temp_dir(), "beak-loopback")?;
+        let path = dir.join("beak.sock");
+        let server = match crate::transport::unix(&path) {
+            Ok(server) => server,
+            Err(e) => {
+                let _ = fs::remove_dir(&dir);
+                return Err(e);
+            }
+        };
+
+        Ok(LoopbackTransport { server, dir, path })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> io::Result<LoopbackTransport> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        let server = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+
+        Ok(LoopbackTransport { server, addr })
+    }
+
+    pub fn connector(&self) -> Connector {
+        Connector {
+            #[cfg(unix)]
+            path: self.path.clone(),
+            #[cfg(not(unix))]
+            addr: self.addr,
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn recv(&self) -> io::Result<TinyHttpRequest> {
+        self.server.recv()
+    }
+
+    fn unblock(&self) {
+        self.server.unblock()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+impl Connector {
+    pub fn connect(&self) -> io::Result<Connection> {
+        #[cfg(unix)]
+        let stream = UnixStream::connect(&self.path)?;
+        #[cfg(not(unix))]
+        let stream = TcpStream::connect(self.addr)?;
+
+        Ok(Connection {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    /// Sends `request` - a whole one, head and body, exactly as it'd go over the wire - on a connection of its own,
+    /// and reads the response.
+    pub fn send(&self, request: &[u8]) -> io::Result<RawResponse> {
+        let mut connection = self.connect()?;
+        connection.send(request)?;
+        connection.response()
+    }
+}
+
+impl Connection {
+    /// Writes raw bytes to the server. Requests can go a piece at a time, or several at once to pipeline them.
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(bytes)?;
+        stream.flush()
+    }
+
+    /// Fails reads that wait longer than `timeout` for the server, instead of hanging the test.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.get_ref().set_read_timeout(timeout)
+    }
+
+    /// Reads the next response. Interim (1xx) responses come back like any other, so read again for the final one.
+    pub fn response(&mut self) -> io::Result<RawResponse> {
+        self.read(true)
+    }
+
+    /// Reads the next response's head only, for the answer to a `HEAD` - which says how long a body it isn't
+    /// sending.
+    pub fn head_response(&mut self) -> io::Result<RawResponse> {
+        self.read(false)
+    }
+
+    fn read(&mut self, has_body: bool) -> io::Result<RawResponse> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+
+        let status_line = self.line()?;
+        let mut parts = status_line.splitn(3, ' ');
+        let status = match (parts.next(), parts.next()) {
+            (Some(version), Some(code)) if version.starts_with("HTTP/") => {
+                code.parse().map_err(|_| invalid("malformed status code"))?
+            }
+            _ => return Err(invalid("malformed status line")),
+        };
+        let reason = parts.next().unwrap_or("").to_owned();
+
+        let mut headers = Vec::new();
+        let mut head = status_line.len();
+        loop {
+            let line = self.line()?;
+            if line.is_empty() {
+                break;
+            }
+            head += line.len();
+            if head > MAX_HEAD {
+                return Err(invalid("response head too large"));
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed header"))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+
+        let chunked = find(&headers, "Transfer-Encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        let length = find(&headers, "Content-Length").and_then(|length| length.parse().ok());
+
+        let body = if !has_body || (100..200).contains(&status) || status == 204 || status == 304 {
+            Vec::new()
+        } else if chunked {
+            self.chunked()?
+        } else if let Some(length) = length {
+            let mut body = vec![0; length];
+            self.stream.read_exact(&mut body)?;
+            body
+        } else {
+            // no length, so the body runs until the server closes the connection
+            let mut body = Vec::new();
+            self.stream.read_to_end(&mut body)?;
+            body
+        };
+
+        Ok(RawResponse {
+            status,
+            reason,
+            headers,
+            body,
+        })
+    }
+
+    // a line without its line ending, failing if the connection closes first
+    fn line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+    }
+
+    fn chunked(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let size_line = self.line()?;
+            let size = size_line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed chunk size"))?;
+
+            if size == 0 {
+                // trailers, if any, up to the blank line
+                while !self.line()?.is_empty() {}
+                return Ok(body);
+            }
+
+            let start = body.len();
+            body.resize(start + size, 0);
+            self.stream.read_exact(&mut body[start..])?;
+            self.line()?;
+        }
+    }
+}
+
+impl RawResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find(&self.headers, name)
+    }
+}
+
+fn find<'h>(headers: &'h [(String, String)], name: &str) -> Option<&'h str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
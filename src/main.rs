@@ -1,22 +1,234 @@
-use beak::*;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    process, thread,
+};
 
-fn main() {
-    run(
-        4,
-        "localhost:8000",
-        200000,
-        &[&NyaHandler],
-        ()
-    ).unwrap();
+use beak::{files::StaticDir, *};
+use clap::{value_parser, Arg, ArgAction, Command};
+
+struct Serve {
+    files: StaticDir,
+    upload_limit: usize,
 }
 
+fn main() {
+    let command = Command::new("beak")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Serves a directory over http, optionally taking uploads into it")
+        .arg(
+            Arg::new("dir")
+                .help("The directory to serve")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("."),
+        )
+        .arg(
+            Arg::new("bind")
+                .short('b')
+                .long("bind")
+                .help("The address to listen on")
+                .value_name("ADDR")
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_parser(value_parser!(u16))
+                .default_value("8000"),
+        )
+        .arg(
+            Arg::new("workers")
+                .short('w')
+                .long("workers")
+                .help("How many requests are handled at once [default: one per cpu]")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("listings")
+                .short('l')
+                .long("listings")
+                .help("List the contents of directories without an index.html")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("spa")
+                .long("spa")
+                .help("Serve the top index.html for paths that don't exist, for single-page apps")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("uploads")
+                .short('u')
+                .long("uploads")
+                .help("Take multipart file uploads, POSTed to the directory they go in")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("upload-limit")
+                .long("upload-limit")
+                .help("The largest upload taken, in bytes")
+                .value_name("BYTES")
+                .value_parser(value_parser!(usize))
+                .default_value("104857600"),
+        );
+
+    #[cfg(feature = "tls")]
+    let command = command
+        .arg(
+            Arg::new("tls-cert")
+                .long("tls-cert")
+                .help("A PEM certificate chain, to serve https")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls-key"),
+        )
+        .arg(
+            Arg::new("tls-key")
+                .long("tls-key")
+                .help("The certificate's PEM private key")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls-cert"),
+        );
+
+    let matches = command.get_matches();
+
+    let dir = matches.get_one::<PathBuf>("dir").unwrap();
+    if !dir.is_dir() {
+        eprintln!("{} is not a directory", dir.display());
+        process::exit(1);
+    }
 
-fn handle<'a, 'b, 'c>(request: Request<'a, 'b, 'c>, context: ()) -> BeakResult<()> {
-    request.respond(200, vec![], |w, _| {
-        writeln!(w, "owo")
-    }).unwrap();
+    let addr = format!(
+        "{}:{}",
+        matches.get_one::<String>("bind").unwrap(),
+        matches.get_one::<u16>("port").unwrap()
+    );
+    let workers = matches
+        .get_one::<usize>("workers")
+        .copied()
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |workers| workers.get()));
+    let flag = |name: &str| matches.get_one::<bool>(name) == Some(&true);
+    let upload_limit = *matches.get_one::<usize>("upload-limit").unwrap();
+
+    let routes: Routes<&'static Serve> = if flag("uploads") {
+        &[
+            &FilesHandler,
+            &RootHandler,
+            &UploadHandler,
+            &RootUploadHandler,
+        ]
+    } else {
+        &[&FilesHandler, &RootHandler]
+    };
+
+    let mut files = StaticDir::new(dir).listings(flag("listings"));
+    if flag("spa") {
+        files = files.spa_fallback("", "index.html");
+    }
+    let serve: &'static Serve = Box::leak(Box::new(Serve {
+        files,
+        upload_limit,
+    }));
+
+    let builder = ServerBuilder::new(addr.clone(), routes)
+        .workers(workers)
+        // the buffer is allocated up front for every worker, so it doesn't grow with the limit
+        .multipart_upload_limit(upload_limit.min(1 << 20));
+
+    #[cfg(feature = "tls")]
+    let builder = match (
+        matches.get_one::<PathBuf>("tls-cert"),
+        matches.get_one::<PathBuf>("tls-key"),
+    ) {
+        (Some(cert), Some(key)) => {
+            let read = |path: &PathBuf| {
+                fs::read(path).unwrap_or_else(|e| {
+                    eprintln!("could not read {}: {}", path.display(), e);
+                    process::exit(1);
+                })
+            };
+            builder.tls(read(cert), read(key))
+        }
+        _ => builder,
+    };
+
+    eprintln!("serving {} on {}", dir.display(), addr);
+    if let Err(e) = builder.run(serve) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
 
+fn files(request: Request<'_, '_, '_>, serve: &'static Serve) -> BeakResult<()> {
+    serve.files.serve_request(request)?;
     Ok(())
 }
 
-fn_to_handler!(NyaHandler with context (); "/nya" => handle);
\ No newline at end of file
+fn upload(request: Request<'_, '_, '_>, serve: &'static Serve) -> BeakResult<()> {
+    save_upload(request, serve)?;
+    Ok(())
+}
+
+fn save_upload(request: Request<'_, '_, '_>, serve: &Serve) -> io::Result<()> {
+    let dir = match request.wildcard() {
+        Ok(dir) => dir,
+        Err(path::PathError::NoWildcard) => String::new(),
+        Err(_) => return request.respond_with_bytes(400, vec![], b"bad path"),
+    };
+
+    let entry = match &request.multipart_entry {
+        Some(entry) => entry,
+        None => {
+            return request.respond_with_bytes(400, vec![], b"expected a multipart file upload")
+        }
+    };
+    if entry.data.len() > serve.upload_limit {
+        return request.respond_with_bytes(413, vec![], b"upload too large");
+    }
+
+    // only the last segment of whatever the client called it, so it can't land anywhere but `dir`
+    let name = entry
+        .file_name
+        .as_deref()
+        .and_then(|name| path::normalize(name).ok())
+        .and_then(|name| name.rsplit('/').next().map(str::to_owned))
+        .filter(|name| !name.is_empty());
+    let name = match name {
+        Some(name) => name,
+        None => return request.respond_with_bytes(400, vec![], b"upload has no file name"),
+    };
+
+    let dir = serve.files.root().join(dir);
+    if !dir.is_dir() {
+        return request.respond_with_bytes(404, vec![], b"no such directory");
+    }
+
+    let target = dir.join(&name);
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target)
+        .and_then(|mut file| file.write_all(entry.data));
+
+    match written {
+        Ok(()) => request.respond_with_bytes(201, vec![], name.as_bytes()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            request.respond_with_bytes(409, vec![], b"a file with that name already exists")
+        }
+        Err(e) => {
+            // don't leave half a file behind
+            let _ = fs::remove_file(&target);
+            Err(e)
+        }
+    }
+}
+
+fn_to_handler!(FilesHandler with context &'static Serve; GET | HEAD "/*path" => files);
+fn_to_handler!(UploadHandler with context &'static Serve; POST "/*path" => upload with multipart);
+
+// a catch-all doesn't match the bare root, so it gets routes of its own
+fn_to_handler!(RootHandler with context &'static Serve; GET | HEAD "/" => files);
+fn_to_handler!(RootUploadHandler with context &'static Serve; POST "/" => upload with multipart);
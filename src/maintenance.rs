@@ -0,0 +1,105 @@
+use std::{
+    collections::HashSet,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use tiny_http::{Header, Response};
+
+use crate::headers::header;
+
+/// Switches a running server in and out of maintenance mode, where every route that isn't allowed through answers
+/// with a 503 and a `Retry-After` - for deploys and migrations. Everything here can be changed while the server runs.
+#[derive(Clone)]
+pub struct MaintenanceHandle {
+    inner: Arc<MaintenanceState>,
+}
+
+struct MaintenanceState {
+    enabled: AtomicBool,
+    settings: RwLock<Settings>,
+}
+
+struct Settings {
+    retry_after: Duration,
+    content_type: Option<Header>,
+    body: Vec<u8>,
+    allowed: HashSet<&'static str>,
+}
+
+impl MaintenanceHandle {
+    pub(crate) fn new() -> MaintenanceHandle {
+        MaintenanceHandle {
+            inner: Arc::new(MaintenanceState {
+                enabled: AtomicBool::new(false),
+                settings: RwLock::new(Settings {
+                    retry_after: Duration::from_secs(60),
+                    content_type: Some(header("Content-Type", "text/plain; charset=utf-8")),
+                    body: b"down for maintenance, back soon\n".to_vec(),
+                    allowed: HashSet::new(),
+                }),
+            }),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.inner.enabled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn disable(&self) {
+        self.inner.enabled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::SeqCst)
+    }
+
+    /// What clients are told to wait before trying again, a minute by default.
+    pub fn set_retry_after(&self, retry_after: Duration) {
+        self.inner.settings.write().unwrap().retry_after = retry_after;
+    }
+
+    /// What the 503s say. A `content_type` that isn't a valid header value is left out.
+    pub fn set_body(&self, content_type: &str, body: impl Into<Vec<u8>>) {
+        let mut settings = self.inner.settings.write().unwrap();
+        settings.content_type = Header::from_bytes(&b"Content-Type"[..], content_type).ok();
+        settings.body = body.into();
+    }
+
+    /// Keeps serving `route` (a path pattern, exactly as its handler declares it) during maintenance - health
+    /// checks, status pages, the admin API that turns maintenance off again.
+    pub fn allow(&self, route: &'static str) {
+        self.inner.settings.write().unwrap().allowed.insert(route);
+    }
+
+    // the 503 to send instead of routing to `route`, if there is one
+    pub(crate) fn response_for(
+        &self,
+        route: Option<&'static str>,
+    ) -> Option<Response<Cursor<Vec<u8>>>> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let settings = self.inner.settings.read().unwrap();
+        if route.is_some_and(|route| settings.allowed.contains(route)) {
+            return None;
+        }
+
+        let mut response = Response::from_data(settings.body.clone())
+            .with_status_code(503)
+            .with_header(header(
+                "Retry-After",
+                settings.retry_after.as_secs().to_string(),
+            ));
+        if let Some(content_type) = &settings.content_type {
+            response.add_header(content_type.clone());
+        }
+
+        Some(response)
+    }
+}
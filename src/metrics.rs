@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use crate::{
+    headers::header,
+    intern::{self, Symbol},
+    BeakResult, Handler, Request,
+};
+
+const DEFAULT_BUCKETS: &[u64] = &[1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// A latency histogram for every route, for [`ServerBuilder::route_timings`](crate::ServerBuilder::route_timings).
+/// Clones share the histograms, so keep one around to read percentiles off for a dashboard of your own.
+#[derive(Clone)]
+pub struct RouteTimings {
+    inner: Arc<Timings>,
+}
+
+struct Timings {
+    // upper bounds, ascending - there's an overflow bucket past the last one
+    bounds: Vec<Duration>,
+    // keyed by symbol, so recording a request doesn't hash the whole route
+    routes: RwLock<HashMap<Symbol, Histogram>>,
+}
+
+struct Histogram {
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+/// One route's latencies, as [`RouteTimings::summary`] sums them up. Percentiles are estimated from the buckets, so
+/// they're only as precise as the buckets are narrow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingSummary {
+    /// The route's path pattern.
+    pub route: &'static str,
+    pub count: u64,
+    /// All of their durations added up.
+    pub total: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Each bucket's upper bound with how many requests took at most that long, the overflow bucket last with no
+    /// bound.
+    pub buckets: Vec<(Option<Duration>, u64)>,
+}
+
+impl Default for RouteTimings {
+    fn default() -> RouteTimings {
+        RouteTimings::new()
+    }
+}
+
+impl RouteTimings {
+    /// With buckets from 1ms to 10s.
+    pub fn new() -> RouteTimings {
+        let bounds: Vec<_> = DEFAULT_BUCKETS
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect();
+        RouteTimings::with_buckets(&bounds)
+    }
+
+    /// With buckets up to each of `bounds`, and one more for anything slower.
+    pub fn with_buckets(bounds: &[Duration]) -> RouteTimings {
+        let mut bounds = bounds.to_vec();
+        bounds.sort();
+        bounds.dedup();
+
+        RouteTimings {
+            inner: Arc::new(Timings {
+                bounds,
+                routes: RwLock::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn record(&self, route: &'static str, duration: Duration) {
+        self.record_interned(intern::intern_static(route), duration);
+    }
+
+    // with the symbol the router already has for the route
+    pub(crate) fn record_interned(&self, route: Symbol, duration: Duration) {
+        let bucket = self.inner.bounds.partition_point(|bound| *bound < duration);
+
+        let record = |histogram: &Histogram| {
+            histogram.counts[bucket].fetch_add(1, Ordering::Relaxed);
+            histogram
+                .sum_micros
+                .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        };
+
+        if let Some(histogram) = self.inner.routes.read().unwrap().get(&route) {
+            return record(histogram);
+        }
+
+        let mut routes = self.inner.routes.write().unwrap();
+        let histogram = routes.entry(route).or_insert_with(|| Histogram {
+            counts: (0..=self.inner.bounds.len())
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            sum_micros: AtomicU64::new(0),
+        });
+        record(histogram);
+    }
+
+    /// `None` until the route has had a request.
+    pub fn summary(&self, route: &str) -> Option<TimingSummary> {
+        let route = intern::lookup(route)?;
+        let routes = self.inner.routes.read().unwrap();
+        Some(self.summarize(route.as_str(), routes.get(&route)?))
+    }
+
+    /// Every route that's had a request, in order.
+    pub fn summaries(&self) -> Vec<TimingSummary> {
+        let routes = self.inner.routes.read().unwrap();
+        let mut summaries: Vec<_> = routes
+            .iter()
+            .map(|(route, histogram)| self.summarize(route.as_str(), histogram))
+            .collect();
+        summaries.sort_by_key(|summary| summary.route);
+        summaries
+    }
+
+    fn summarize(&self, route: &'static str, histogram: &Histogram) -> TimingSummary {
+        let counts: Vec<u64> = histogram
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let total_micros = histogram.sum_micros.load(Ordering::Relaxed);
+
+        let mut cumulative = 0;
+        let buckets = counts
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| {
+                cumulative += bucket;
+                (self.inner.bounds.get(i).copied(), cumulative)
+            })
+            .collect();
+
+        TimingSummary {
+            route,
+            count,
+            total: Duration::from_micros(total_micros),
+            mean: Duration::from_micros(total_micros.checked_div(count).unwrap_or(0)),
+            p50: self.percentile(&counts, count, 0.5),
+            p90: self.percentile(&counts, count, 0.9),
+            p99: self.percentile(&counts, count, 0.99),
+            buckets,
+        }
+    }
+
+    // interpolated within the bucket the percentile lands in - the overflow bucket has no upper bound, so anything
+    // landing there is reported as its lower one
+    fn percentile(&self, counts: &[u64], count: u64, quantile: f64) -> Duration {
+        let bounds = &self.inner.bounds;
+        let rank = quantile * count as f64;
+
+        let mut seen = 0;
+        for (i, bucket) in counts.iter().enumerate() {
+            if *bucket == 0 || ((seen + bucket) as f64) < rank {
+                seen += bucket;
+                continue;
+            }
+
+            let lower = match i {
+                0 => Duration::ZERO,
+                i => bounds[i - 1],
+            };
+            let upper = match bounds.get(i) {
+                Some(upper) => *upper,
+                None => return lower,
+            };
+            let within = ((rank - seen as f64) / *bucket as f64).clamp(0.0, 1.0);
+            return lower + (upper - lower).mul_f64(within);
+        }
+
+        Duration::ZERO
+    }
+
+    /// Every histogram in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut text = String::from(
+            "# HELP beak_request_duration_seconds How long requests took, by route.\n\
+             # TYPE beak_request_duration_seconds histogram\n",
+        );
+
+        for summary in self.summaries() {
+            let route = summary.route.replace('\\', "\\\\").replace('"', "\\\"");
+            for (bound, count) in &summary.buckets {
+                let le = match bound {
+                    Some(bound) => bound.as_secs_f64().to_string(),
+                    None => "+Inf".to_owned(),
+                };
+                let _ = writeln!(
+                    text,
+                    "beak_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, le, count
+                );
+            }
+            let _ = writeln!(
+                text,
+                "beak_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route,
+                summary.total.as_secs_f64()
+            );
+            let _ = writeln!(
+                text,
+                "beak_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, summary.count
+            );
+        }
+
+        #[cfg(feature = "alloc-stats")]
+        if let Some(stats) = crate::alloc_stats::stats() {
+            let _ = write!(
+                text,
+                "# TYPE beak_heap_live_bytes gauge\nbeak_heap_live_bytes {}\n\
+                 # TYPE beak_heap_peak_bytes gauge\nbeak_heap_peak_bytes {}\n\
+                 # TYPE beak_heap_allocations_total counter\nbeak_heap_allocations_total {}\n",
+                stats.live, stats.peak, stats.allocations
+            );
+        }
+
+        text
+    }
+}
+
+pub(crate) type MetricsSource = Box<dyn Fn() -> String + Send + Sync>;
+
+// serves the timings for ServerBuilder::metrics_endpoint, and whatever its metrics sources have
+pub(crate) struct MetricsEndpoint {
+    pub(crate) path: &'static str,
+    pub(crate) timings: RouteTimings,
+    pub(crate) sources: Vec<MetricsSource>,
+}
+
+impl<C: Send + Sync> Handler<C> for MetricsEndpoint {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let mut text = self.timings.prometheus();
+        for source in &self.sources {
+            text.push_str(&source());
+        }
+
+        request.respond_with_bytes(
+            200,
+            vec![header("Content-Type", "text/plain; version=0.0.4")],
+            text.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET"]
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{router::HandlerRef, BeakResult, Handler, Request};
+
+/// Runs around every routed request. Middleware can look at or change the request, answer it themselves (without
+/// calling `next`), or pass it on with [`Next::run`] and do something after the rest of the chain is done.
+///
+/// Middleware run in the order they were added to the [`ServerBuilder`](crate::ServerBuilder), so the first one
+/// added sees the request first and finishes last.
+pub trait Middleware<C: Send + Sync>: Send + Sync {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()>;
+}
+
+pub(crate) type MiddlewareList<C> = Vec<Box<dyn Middleware<C>>>;
+
+/// The rest of the chain: the middleware after this one, then the route's handler.
+pub struct Next<'n, C: Send + Sync + 'static> {
+    middleware: &'n [Box<dyn Middleware<C>>],
+    handler: HandlerRef<C>,
+}
+
+impl<'n, C: Send + Sync + 'static> Next<'n, C> {
+    pub(crate) fn new(
+        middleware: &'n [Box<dyn Middleware<C>>],
+        handler: HandlerRef<C>,
+    ) -> Next<'n, C> {
+        Next {
+            middleware,
+            handler,
+        }
+    }
+
+    /// The handler the request was routed to.
+    pub fn handler(&self) -> &'static (dyn Handler<C> + Send + Sync) {
+        self.handler
+    }
+
+    pub fn run<'url, 'sender, 'mv>(
+        self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+    ) -> BeakResult<()> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                middleware.handle(request, context, Next::new(rest, self.handler))
+            }
+            None => self.handler.handle(request, context),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::net::IpAddr;
+
+use crate::{BeakResult, Middleware, Next, Request};
+
+/// Who a verified client certificate says the client is, found in
+/// [`Request::extensions`](crate::Request::extensions) once [`ClientCert`] has let the request through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The certificate's subject, like `CN=billing,O=internal`.
+    pub subject: Option<String>,
+    pub alt_names: Vec<AltName>,
+    /// The SHA-256 of the certificate, in lowercase hex.
+    pub fingerprint: Option<String>,
+}
+
+/// One of a certificate's subject alternative names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AltName {
+    Dns(String),
+    /// Like a SPIFFE ID, `spiffe://cluster.local/ns/billing/sa/api`.
+    Uri(String),
+}
+
+impl ClientIdentity {
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.alt_names.iter().filter_map(|name| match name {
+            AltName::Dns(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.alt_names.iter().filter_map(|name| match name {
+            AltName::Uri(uri) => Some(uri.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Client certificate authentication by way of a TLS-terminating proxy, for services that only other services talk
+/// to. This isn't mutual TLS on beak's own [TLS listener](crate::ServerBuilder::tls), which never asks clients for
+/// certificates.
+///
+/// tiny_http's listener has nowhere to plug in a client certificate verifier, so the handshake happens at the proxy
+/// in front - Envoy, or anything else that verifies client certificates and forwards what it found in
+/// `X-Forwarded-Client-Cert`. This reads that header from the proxies it's told to trust, and nobody else: coming
+/// from any other address it's a client claiming to be whoever it likes, so it's ignored. The proxy should replace
+/// the header rather than append to it, but if it appends, the last entry is the one it vouches for itself.
+///
+/// Requests without a verified certificate get a 403 unless certificates are [optional](Self::required), and the
+/// ones with one carry its [`ClientIdentity`] on to the handler.
+pub struct ClientCert {
+    trusted: Vec<IpAddr>,
+    trust_local: bool,
+    header: &'static str,
+    required: bool,
+}
+
+impl Default for ClientCert {
+    fn default() -> ClientCert {
+        ClientCert {
+            trusted: Vec::new(),
+            trust_local: false,
+            header: "X-Forwarded-Client-Cert",
+            required: true,
+        }
+    }
+}
+
+impl ClientCert {
+    pub fn new() -> ClientCert {
+        ClientCert::default()
+    }
+
+    /// Believe the header when it comes from `proxy`.
+    pub fn trust_proxy(mut self, proxy: IpAddr) -> Self {
+        self.trusted.push(proxy);
+        self
+    }
+
+    /// Believe the header on requests without a client address, which come over a unix socket - for a proxy on the
+    /// same machine that only it can write to.
+    pub fn trust_local_sockets(mut self, trust: bool) -> Self {
+        self.trust_local = trust;
+        self
+    }
+
+    /// Read the certificate from a header of this name instead.
+    pub fn header_name(mut self, name: &'static str) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Whether requests without a certificate are turned away, which they are by default. If not, they go on to the
+    /// handler without a [`ClientIdentity`].
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    fn trusts(&self, request: &Request<'_, '_, '_>) -> bool {
+        // a batch endpoint's sub-requests come over a unix socket too, with whatever headers the batch gave them
+        if request
+            .extensions
+            .get::<crate::server::Batched>()
+            .is_some()
+        {
+            return false;
+        }
+
+        match request.connection().peer_addr() {
+            Some(peer) => self.trusted.contains(&peer.ip()),
+            None => self.trust_local,
+        }
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for ClientCert {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let identity = match self.trusts(&request) {
+            true => request.header(self.header).and_then(parse_forwarded),
+            false => None,
+        };
+
+        match identity {
+            Some(identity) => {
+                request.extensions.insert(identity);
+            }
+            None if self.required => {
+                request.respond_with_bytes(403, vec![], &[])?;
+                return Ok(());
+            }
+            None => {}
+        }
+
+        next.run(request, context)
+    }
+}
+
+/// The identity in an `X-Forwarded-Client-Cert` value - from its last entry, if there's more than one - as long as
+/// it says something about the certificate.
+///
+/// Entries are `;`-separated `Key=value` pairs: `Hash` for the fingerprint, `Subject`, and any number of `DNS` and
+/// `URI` names, with other keys ignored. Values with `,`, `;` or `=` in them are quoted.
+pub fn parse_forwarded(value: &str) -> Option<ClientIdentity> {
+    let entry = split_unquoted(value, ',').pop()?;
+
+    let mut identity = ClientIdentity {
+        subject: None,
+        alt_names: Vec::new(),
+        fingerprint: None,
+    };
+    for pair in split_unquoted(&entry, ';') {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) => (key.trim(), unquote(value.trim())),
+            None => continue,
+        };
+
+        if key.eq_ignore_ascii_case("Hash") {
+            if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+                identity.fingerprint = Some(value.to_ascii_lowercase());
+            }
+        } else if key.eq_ignore_ascii_case("Subject") {
+            identity.subject = Some(value);
+        } else if key.eq_ignore_ascii_case("DNS") {
+            identity.alt_names.push(AltName::Dns(value));
+        } else if key.eq_ignore_ascii_case("URI") {
+            identity.alt_names.push(AltName::Uri(value));
+        }
+    }
+
+    let empty = identity.subject.is_none()
+        && identity.fingerprint.is_none()
+        && identity.alt_names.is_empty();
+    (!empty).then_some(identity)
+}
+
+// splits on `separator` where it isn't inside quotes, keeping the quotes for `unquote`
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let (mut quoted, mut escaped) = (false, false);
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if c == separator && !quoted => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    let inner = match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(inner) => inner,
+        None => return value.to_owned(),
+    };
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
@@ -0,0 +1,75 @@
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::Request;
+
+/// Something for requests to wait on until there's news: the code producing data calls [`notify`](Notify::notify)
+/// after every change, and waiters check again. Cheap to clone, and every clone is the same notifier - keep one in
+/// the context.
+#[derive(Clone, Default)]
+pub struct Notify {
+    // a counter rather than a flag, so a waiter can tell whether anything happened since it last looked
+    inner: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify::default()
+    }
+
+    /// Wakes everyone waiting, to check whether what they're after is there now.
+    pub fn notify(&self) {
+        let (version, changed) = &*self.inner;
+        *version.lock().unwrap() += 1;
+        changed.notify_all();
+    }
+
+    /// Calls `poll` until it comes up with something, once to start with and again after every
+    /// [`notify`](Self::notify), giving up after `timeout`. `poll` runs without any of our locks held, so it's free
+    /// to take the ones guarding the data.
+    pub fn wait_for<T>(&self, timeout: Duration, mut poll: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let (version, changed) = &*self.inner;
+
+        loop {
+            // taken before polling, so a notify that lands while `poll` runs still counts
+            let seen = *version.lock().unwrap();
+            if let Some(found) = poll() {
+                return Some(found);
+            }
+
+            let mut current = version.lock().unwrap();
+            while *current == seen {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                current = changed.wait_timeout(current, remaining).unwrap().0;
+            }
+        }
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Holds the request until `poll` has something for it - checked whenever `notify` fires - and answers it with
+    /// `respond`, or with a 204 once `timeout` runs out, so the client can ask again.
+    ///
+    /// A parked request keeps its worker for as long as it's waiting, so there should be more workers than clients
+    /// long-polling at once. Shutting down waits for them like any other request, so keep `timeout` under the
+    /// drain timeout.
+    pub fn long_poll<T>(
+        self,
+        notify: &Notify,
+        timeout: Duration,
+        poll: impl FnMut() -> Option<T>,
+        respond: impl FnOnce(Self, T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match notify.wait_for(timeout, poll) {
+            Some(found) => respond(self, found),
+            None => self.respond_with_bytes(204, vec![], &[]),
+        }
+    }
+}
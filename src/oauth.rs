@@ -0,0 +1,666 @@
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    base64,
+    client::{Client, ClientError, ClientResponse},
+    headers::{self, header},
+    metadata::MetadataStore,
+    path, random,
+    redirect::RedirectPolicy,
+    BeakError, BeakResult, Handler, Middleware, Next, Request,
+};
+
+// 32 random bytes, hex-encoded - for session ids, and a PKCE verifier that's within its 43 to 128 characters
+const TOKEN_BYTES: usize = 32;
+// how long someone has on the provider's login page before the state's forgotten
+const STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Error, Debug)]
+pub enum OAuthError {
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error("{endpoint} endpoint answered {status}: {body}")]
+    Endpoint {
+        endpoint: &'static str,
+        status: u16,
+        body: String,
+    },
+    #[error("unexpected response from the {0} endpoint")]
+    Malformed(&'static str),
+    /// The client only speaks plain http, so it won't send codes, secrets and tokens anywhere but this machine.
+    #[error("the {0} endpoint has to be a plain http url on this machine, like a tls proxy on 127.0.0.1")]
+    NotLocal(&'static str),
+}
+
+/// Where an OAuth2 provider's endpoints are, and who this app is to it.
+#[derive(Debug, Clone)]
+pub struct Provider {
+    authorize_url: String,
+    token_url: String,
+    userinfo_url: Option<String>,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: Vec<String>,
+}
+
+impl Provider {
+    /// `redirect_uri` is the full url of the callback route, as registered with the provider.
+    pub fn new(
+        authorize_url: impl Into<String>,
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+        redirect_uri: impl Into<String>,
+    ) -> Provider {
+        Provider {
+            authorize_url: authorize_url.into(),
+            token_url: token_url.into(),
+            userinfo_url: None,
+            client_id: client_id.into(),
+            client_secret: None,
+            redirect_uri: redirect_uri.into(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// The endpoints of an OpenID Connect provider, from its `/.well-known/openid-configuration`. Asks for the
+    /// `openid` scope, so the userinfo endpoint answers.
+    pub fn discover(
+        issuer: &str,
+        client_id: impl Into<String>,
+        redirect_uri: impl Into<String>,
+    ) -> Result<Provider, OAuthError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        local(&url, "discovery")?;
+        let configuration = json(Client::new().get(&url).send()?, "discovery")?;
+        let endpoint = |name: &str| {
+            configuration[name]
+                .as_str()
+                .map(str::to_owned)
+                .ok_or(OAuthError::Malformed("discovery"))
+        };
+
+        let mut provider = Provider::new(
+            endpoint("authorization_endpoint")?,
+            endpoint("token_endpoint")?,
+            client_id,
+            redirect_uri,
+        )
+        .scope("openid");
+        provider.userinfo_url = endpoint("userinfo_endpoint").ok();
+        Ok(provider)
+    }
+
+    /// Sent with the token request, for confidential clients. Public ones get by with PKCE alone.
+    pub fn client_secret(mut self, secret: impl Into<String>) -> Self {
+        self.client_secret = Some(secret.into());
+        self
+    }
+
+    /// Fetched with the access token once someone's logged in, to find out who they are.
+    pub fn userinfo_url(mut self, url: impl Into<String>) -> Self {
+        self.userinfo_url = Some(url.into());
+        self
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+}
+
+/// Who's logged in, in the [`extensions`](Request::extensions) of their requests with [`OAuth::sessions`] in front of
+/// the handler.
+#[derive(Debug, Clone)]
+pub struct Login {
+    /// The `sub` claim from the userinfo endpoint, which is what should identify people - emails and names change.
+    pub subject: Option<String>,
+    /// Everything the userinfo endpoint said, or `null` without one.
+    pub user: Value,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// When the access token stops working, if the provider said.
+    pub expires: Option<SystemTime>,
+}
+
+impl Login {
+    fn to_document(&self) -> Value {
+        let mut document = Map::new();
+        document.insert("subject".to_owned(), self.subject.clone().into());
+        document.insert("user".to_owned(), self.user.clone());
+        document.insert("access_token".to_owned(), self.access_token.clone().into());
+        document.insert(
+            "refresh_token".to_owned(),
+            self.refresh_token.clone().into(),
+        );
+        document.insert(
+            "expires".to_owned(),
+            self.expires
+                .and_then(|expires| expires.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_secs())
+                .into(),
+        );
+        document.into()
+    }
+
+    fn from_document(document: &Value) -> Option<Login> {
+        Some(Login {
+            subject: document["subject"].as_str().map(str::to_owned),
+            user: document["user"].clone(),
+            access_token: document["access_token"].as_str()?.to_owned(),
+            refresh_token: document["refresh_token"].as_str().map(str::to_owned),
+            expires: document["expires"]
+                .as_u64()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        })
+    }
+}
+
+/// "Login with" a provider, with the OAuth2 authorization-code flow and PKCE. Its [handlers](Self::handlers) are
+/// `<base>/login` to send someone off to the provider - with a `return_to` to come back to afterwards -
+/// `<base>/callback` for the provider to send them back to, and a `POST` to `<base>/logout`. Logins are kept as
+/// sessions in a [`MetadataStore`], under `oauth:session:`, with a cookie holding the session id, and
+/// [`sessions`](Self::sessions) finds them for the requests that follow.
+///
+/// The token and userinfo endpoints - and the issuer, for [`Provider::discover`] - are called with beak's own
+/// [`Client`], which only speaks plain http. So they have to be urls on this machine, to a proxy that adds TLS on the
+/// way out, and anything else fails with [`OAuthError::NotLocal`] rather than send codes and secrets over the
+/// network in the clear. ID tokens aren't verified, who someone is comes from the userinfo endpoint, over the same
+/// connection the tokens did.
+pub struct OAuth<M> {
+    provider: Provider,
+    store: M,
+    client: Client,
+    base: &'static str,
+    cookie: &'static str,
+    secure: bool,
+    session_ttl: Duration,
+    redirects: RedirectPolicy,
+    after_login: &'static str,
+    after_logout: &'static str,
+}
+
+impl<M: MetadataStore> OAuth<M> {
+    pub fn new(provider: Provider, store: M) -> OAuth<M> {
+        OAuth {
+            provider,
+            store,
+            client: Client::new(),
+            base: "/auth",
+            cookie: "session",
+            secure: false,
+            session_ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            redirects: RedirectPolicy::relative_only(),
+            after_login: "/",
+            after_logout: "/",
+        }
+    }
+
+    /// Where the handlers go, `/auth` by default.
+    pub fn base(mut self, base: &'static str) -> Self {
+        self.base = base;
+        self
+    }
+
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn cookie_name(mut self, name: &'static str) -> Self {
+        self.cookie = name;
+        self
+    }
+
+    /// Only send the cookies over HTTPS. Off by default so plain-HTTP development works.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// How long a login lasts, a week by default. It's not extended by using it.
+    pub fn session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    /// Where a `return_to` is allowed to go - only relative targets by default.
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirects = policy;
+        self
+    }
+
+    /// Where people end up after logging in without a `return_to`, `/` by default.
+    pub fn after_login(mut self, target: &'static str) -> Self {
+        self.after_login = target;
+        self
+    }
+
+    pub fn after_logout(mut self, target: &'static str) -> Self {
+        self.after_logout = target;
+        self
+    }
+
+    pub fn store(&self) -> &M {
+        &self.store
+    }
+
+    /// The login, callback and logout routes. Routes are `'static`, so the `OAuth` has to be too - `Box::leak` it,
+    /// and hand the same reference to [`sessions`](Self::sessions).
+    pub fn handlers<C: Send + Sync>(&'static self) -> [&'static (dyn Handler<C> + Send + Sync); 3]
+    where
+        M: 'static,
+    {
+        let path = |route: &str| -> &'static str {
+            Box::leak(format!("{}/{}", self.base, route).into_boxed_str())
+        };
+
+        [
+            Box::leak(Box::new(Route {
+                oauth: self,
+                path: path("login"),
+                kind: RouteKind::Login,
+            })),
+            Box::leak(Box::new(Route {
+                oauth: self,
+                path: path("callback"),
+                kind: RouteKind::Callback,
+            })),
+            Box::leak(Box::new(Route {
+                oauth: self,
+                path: path("logout"),
+                kind: RouteKind::Logout,
+            })),
+        ]
+    }
+
+    /// Middleware finding the login a request's session cookie belongs to, for [`Request::login`]. Requests without
+    /// one go through all the same.
+    pub fn sessions(&'static self) -> Sessions<M> {
+        Sessions(self)
+    }
+
+    /// Where to send someone to log in, coming back to `return_to` afterwards.
+    pub fn login_url(&self, return_to: &str) -> String {
+        format!(
+            "{}/login?return_to={}",
+            self.base,
+            path::percent_encode(return_to)
+        )
+    }
+
+    /// Looks up a session by the id in its cookie.
+    pub fn session(&self, id: &str) -> io::Result<Option<Login>> {
+        if !is_token(id) {
+            return Ok(None);
+        }
+        let document = self.store.get(&format!("oauth:session:{}", id))?;
+        Ok(document.as_ref().and_then(Login::from_document))
+    }
+
+    fn cookie_header(
+        &self,
+        name: &str,
+        value: &str,
+        path: &str,
+        max_age: u64,
+    ) -> tiny_http::Header {
+        let secure = if self.secure { "; Secure" } else { "" };
+        header(
+            "Set-Cookie",
+            format!(
+                "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax{}",
+                name, value, path, max_age, secure
+            ),
+        )
+    }
+
+    fn state_cookie(&self) -> String {
+        format!("{}_state", self.cookie)
+    }
+
+    fn login(&self, request: Request<'_, '_, '_>) -> BeakResult<()> {
+        let return_to = query_param(request.query(), "return_to")
+            .filter(|target| self.redirects.check(target).is_ok())
+            .unwrap_or_else(|| self.after_login.to_owned());
+
+        let state = random::hex_token(16)?;
+        let verifier = random::hex_token(TOKEN_BYTES)?;
+        let challenge = base64::encode_url(&Sha256::digest(verifier.as_bytes()));
+
+        let mut pending = Map::new();
+        pending.insert("verifier".to_owned(), verifier.into());
+        pending.insert("return_to".to_owned(), return_to.into());
+        self.store.put(
+            &format!("oauth:state:{}", state),
+            &pending.into(),
+            Some(SystemTime::now() + STATE_TTL),
+        )?;
+
+        let provider = &self.provider;
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &provider.client_id),
+            ("redirect_uri", &provider.redirect_uri),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        let scope = provider.scopes.join(" ");
+        if !scope.is_empty() {
+            params.push(("scope", &scope));
+        }
+        let separator = if provider.authorize_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let location = format!(
+            "{}{}{}",
+            provider.authorize_url,
+            separator,
+            form_encode(&params)
+        );
+
+        // the state cookie ties the callback to the browser that started the login, so nobody can finish theirs in
+        // someone else's
+        let cookie =
+            self.cookie_header(&self.state_cookie(), &state, self.base, STATE_TTL.as_secs());
+        request.respond_with_bytes(302, vec![cookie, header("Location", location)], &[])?;
+        Ok(())
+    }
+
+    fn callback(&self, request: Request<'_, '_, '_>) -> BeakResult<()> {
+        let query = request.query();
+        if let Some(error) = query_param(query, "error") {
+            request.respond_with_bytes(
+                403,
+                vec![],
+                format!("login failed: {}", error).as_bytes(),
+            )?;
+            return Ok(());
+        }
+
+        let state = query_param(query, "state")
+            .filter(|state| request.cookie(&self.state_cookie()) == Some(state.as_str()));
+        let (state, code) = match (state, query_param(query, "code")) {
+            (Some(state), Some(code)) => (state, code),
+            _ => {
+                request.respond_with_bytes(400, vec![], b"invalid login state")?;
+                return Ok(());
+            }
+        };
+
+        // each state is good for one callback
+        let key = format!("oauth:state:{}", state);
+        let pending = self.store.take(&key)?;
+        let (verifier, return_to) = match &pending {
+            Some(pending) => (
+                pending["verifier"].as_str(),
+                pending["return_to"].as_str().unwrap_or(self.after_login),
+            ),
+            None => (None, self.after_login),
+        };
+        let verifier = match verifier {
+            Some(verifier) => verifier,
+            None => {
+                request.respond_with_bytes(400, vec![], b"invalid login state")?;
+                return Ok(());
+            }
+        };
+
+        let login = self.exchange(&code, verifier).map_err(BeakError::handler)?;
+        let session = random::hex_token(TOKEN_BYTES)?;
+        self.store.put(
+            &format!("oauth:session:{}", session),
+            &login.to_document(),
+            Some(SystemTime::now() + self.session_ttl),
+        )?;
+
+        let headers = vec![
+            self.cookie_header(&self.state_cookie(), "", self.base, 0),
+            self.cookie_header(self.cookie, &session, "/", self.session_ttl.as_secs()),
+        ];
+        let location = headers::checked_header("Location", return_to)
+            .unwrap_or_else(|_| header("Location", self.after_login));
+        request.respond_with_bytes(303, [headers, vec![location]].concat(), &[])?;
+        Ok(())
+    }
+
+    fn logout(&self, request: Request<'_, '_, '_>) -> BeakResult<()> {
+        if let Some(session) = request.cookie(self.cookie).filter(|id| is_token(id)) {
+            self.store.delete(&format!("oauth:session:{}", session))?;
+        }
+
+        let cookie = self.cookie_header(self.cookie, "", "/", 0);
+        request.respond_with_bytes(
+            303,
+            vec![cookie, header("Location", self.after_logout)],
+            &[],
+        )?;
+        Ok(())
+    }
+
+    // trades the code for tokens, and finds out who they're for
+    fn exchange(&self, code: &str, verifier: &str) -> Result<Login, OAuthError> {
+        let provider = &self.provider;
+        local(&provider.token_url, "token")?;
+        if let Some(url) = &provider.userinfo_url {
+            local(url, "userinfo")?;
+        }
+
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &provider.redirect_uri),
+            ("code_verifier", verifier),
+            ("client_id", &provider.client_id),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            params.push(("client_secret", secret));
+        }
+
+        let response = self
+            .client
+            .post(&provider.token_url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "application/json")
+            .body(form_encode(&params))
+            .send()?;
+        let tokens = json(response, "token")?;
+
+        let access_token = tokens["access_token"]
+            .as_str()
+            .ok_or(OAuthError::Malformed("token"))?
+            .to_owned();
+        let expires = tokens["expires_in"]
+            .as_u64()
+            .map(|secs| SystemTime::now() + Duration::from_secs(secs));
+
+        let user = match &provider.userinfo_url {
+            Some(url) => json(
+                self.client
+                    .get(url)
+                    .header("Authorization", format!("Bearer {}", access_token))
+                    .header("Accept", "application/json")
+                    .send()?,
+                "userinfo",
+            )?,
+            None => Value::Null,
+        };
+
+        Ok(Login {
+            subject: user["sub"].as_str().map(str::to_owned),
+            user,
+            access_token,
+            refresh_token: tokens["refresh_token"].as_str().map(str::to_owned),
+            expires,
+        })
+    }
+}
+
+fn json(response: ClientResponse, endpoint: &'static str) -> Result<Value, OAuthError> {
+    if !response.is_success() {
+        return Err(OAuthError::Endpoint {
+            endpoint,
+            status: response.status,
+            body: response.text(),
+        });
+    }
+    serde_json::from_slice(&response.body).map_err(|_| OAuthError::Malformed(endpoint))
+}
+
+// plain http to a loopback address. the client turns away every other scheme anyway
+fn local(url: &str, endpoint: &'static str) -> Result<(), OAuthError> {
+    let rest = url
+        .split_once("://")
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("http"))
+        .map(|(_, rest)| rest);
+    // no userinfo, which would leave what's after the @ to decide where it goes
+    let authority = rest
+        .map(|rest| rest.split(['/', '?', '#']).next().unwrap_or(rest))
+        .filter(|authority| !authority.contains('@'));
+    let host = authority.map(|authority| match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or(v6),
+        None => authority.split(':').next().unwrap_or(authority),
+    });
+
+    let loopback = host.is_some_and(|host| {
+        host.eq_ignore_ascii_case("localhost")
+            || host
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    });
+    match loopback {
+        true => Ok(()),
+        false => Err(OAuthError::NotLocal(endpoint)),
+    }
+}
+
+fn form_encode(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, path::percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// query strings from the provider are form-encoded, so a + is a space
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name)
+            .then(|| path::percent_decode(&value.replace('+', " ")).ok())
+            .flatten()
+    })
+}
+
+// anything else in the cookie is junk, and never looked up
+fn is_token(id: &str) -> bool {
+    id.len() == TOKEN_BYTES * 2 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+enum RouteKind {
+    Login,
+    Callback,
+    Logout,
+}
+
+struct Route<M: 'static> {
+    oauth: &'static OAuth<M>,
+    path: &'static str,
+    kind: RouteKind,
+}
+
+impl<C: Send + Sync, M: MetadataStore + 'static> Handler<C> for Route<M> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        match self.kind {
+            RouteKind::Login => self.oauth.login(request),
+            RouteKind::Callback => self.oauth.callback(request),
+            RouteKind::Logout => self.oauth.logout(request),
+        }
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        match self.kind {
+            RouteKind::Logout => &["POST"],
+            _ => &["GET"],
+        }
+    }
+}
+
+/// Finds the [`Login`] behind a request's session cookie - see [`OAuth::sessions`].
+pub struct Sessions<M: 'static>(&'static OAuth<M>);
+
+impl<C: Send + Sync, M: MetadataStore + 'static> Middleware<C> for Sessions<M> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let login = match request.cookie(self.0.cookie) {
+            Some(id) => self.0.session(id)?,
+            None => None,
+        };
+        if let Some(login) = login {
+            request.extensions.insert(login);
+        }
+
+        next.run(request, context)
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Who's logged in, with [`OAuth::sessions`] in front of the handler.
+    pub fn login(&self) -> Option<&Login> {
+        self.extensions.get::<Login>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_plain_http() {
+        for url in [
+            "http://127.0.0.1:8080/token",
+            "http://localhost/token",
+            "http://[::1]:9000/userinfo",
+        ] {
+            assert!(local(url, "token").is_ok(), "{}", url);
+        }
+        for url in [
+            "https://127.0.0.1/token",
+            "http://accounts.example.com/token",
+            "http://127.0.0.1:80@evil.example/token",
+            "http://10.0.0.1/token",
+            "127.0.0.1/token",
+        ] {
+            assert!(local(url, "token").is_err(), "{}", url);
+        }
+    }
+}
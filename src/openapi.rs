@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use crate::{headers::header, BeakResult, Handler, Request};
+
+// what a handler that doesn't restrict its methods gets listed under
+const ALL_METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// Types that can describe themselves as a JSON schema, so handlers can declare what they take and return.
+pub trait Schema {
+    fn schema() -> Value;
+}
+
+macro_rules! primitive_schema {
+    ($($ty:ty => $kind:literal $(as $format:literal)?),+ $(,)?) => {
+        $(
+            impl Schema for $ty {
+                fn schema() -> Value {
+                    #[allow(unused_mut)]
+                    let mut schema = object([("type", $kind.into())]);
+                    $(schema["format"] = $format.into();)?
+                    schema
+                }
+            }
+        )+
+    };
+}
+
+primitive_schema! {
+    bool => "boolean",
+    String => "string",
+    str => "string",
+    i8 => "integer" as "int32",
+    i16 => "integer" as "int32",
+    i32 => "integer" as "int32",
+    u8 => "integer" as "int32",
+    u16 => "integer" as "int32",
+    u32 => "integer" as "int64",
+    i64 => "integer" as "int64",
+    u64 => "integer" as "int64",
+    isize => "integer" as "int64",
+    usize => "integer" as "int64",
+    f32 => "number" as "float",
+    f64 => "number" as "double",
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema() -> Value {
+        object([("type", "array".into()), ("items", T::schema())])
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn schema() -> Value {
+        let mut schema = T::schema();
+        schema["nullable"] = true.into();
+        schema
+    }
+}
+
+/// What the document says about one handler. Everything is optional - a handler that declares nothing still shows
+/// up with its path, methods and path parameters.
+#[derive(Default)]
+pub struct Operation {
+    summary: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    query: Vec<Value>,
+    request_body: Option<Value>,
+    responses: Map<String, Value>,
+}
+
+impl Operation {
+    pub fn new() -> Operation {
+        Operation::default()
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn query<T: Schema>(mut self, name: &str, required: bool) -> Self {
+        self.query.push(object([
+            ("name", name.into()),
+            ("in", "query".into()),
+            ("required", required.into()),
+            ("schema", T::schema()),
+        ]));
+        self
+    }
+
+    /// A request body of `content_type`, described by `schema`.
+    pub fn request(mut self, content_type: &str, schema: Value) -> Self {
+        self.request_body = Some(object([
+            ("required", true.into()),
+            ("content", content(content_type, Some(schema))),
+        ]));
+        self
+    }
+
+    pub fn request_json<T: Schema>(self) -> Self {
+        self.request("application/json", T::schema())
+    }
+
+    /// A response without a body worth describing.
+    pub fn response(mut self, status: u16, description: &str) -> Self {
+        self.responses.insert(
+            status.to_string(),
+            object([("description", description.into())]),
+        );
+        self
+    }
+
+    pub fn response_json<T: Schema>(mut self, status: u16, description: &str) -> Self {
+        self.responses.insert(
+            status.to_string(),
+            object([
+                ("description", description.into()),
+                ("content", content("application/json", Some(T::schema()))),
+            ]),
+        );
+        self
+    }
+
+    fn to_json<C: Send + Sync>(&self, handler: &(dyn Handler<C> + Send + Sync)) -> Value {
+        let mut operation = Map::new();
+
+        if let Some(summary) = &self.summary {
+            operation.insert("summary".into(), summary.as_str().into());
+        }
+        if let Some(description) = &self.description {
+            operation.insert("description".into(), description.as_str().into());
+        }
+        if !self.tags.is_empty() {
+            operation.insert(
+                "tags".into(),
+                self.tags.iter().map(String::as_str).collect(),
+            );
+        }
+
+        let parameters: Vec<Value> = path_parameters(handler.path())
+            .map(|name| {
+                object([
+                    ("name", name.into()),
+                    ("in", "path".into()),
+                    ("required", true.into()),
+                    ("schema", String::schema()),
+                ])
+            })
+            .chain(self.query.iter().cloned())
+            .collect();
+        if !parameters.is_empty() {
+            operation.insert("parameters".into(), parameters.into());
+        }
+
+        let request_body = match &self.request_body {
+            Some(body) => Some(body.clone()),
+            None if handler.needs_multipart() => Some(object([
+                ("required", true.into()),
+                ("content", content("multipart/form-data", None)),
+            ])),
+            None => None,
+        };
+        if let Some(body) = request_body {
+            operation.insert("requestBody".into(), body);
+        }
+
+        let responses = if self.responses.is_empty() {
+            object([("default", object([("description", "response".into())]))])
+        } else {
+            self.responses.clone().into()
+        };
+        operation.insert("responses".into(), responses);
+
+        operation.into()
+    }
+}
+
+/// An OpenAPI 3 document for `routes`.
+pub fn document<'r, C: Send + Sync + 'r>(
+    title: &str,
+    version: &str,
+    routes: impl IntoIterator<Item = &'r (dyn Handler<C> + Send + Sync)>,
+) -> Value {
+    let mut paths: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+
+    for handler in routes {
+        let item = paths.entry(openapi_path(handler.path())).or_default();
+
+        let operation = handler.operation().to_json(handler);
+        let methods: Vec<String> = match handler.methods() {
+            [] => ALL_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            methods => methods.iter().map(|m| m.to_ascii_lowercase()).collect(),
+        };
+
+        for method in methods {
+            // the first handler for a method is the one the router picks, so it's the one documented
+            if !item.contains_key(&method) {
+                item.insert(method, operation.clone());
+            }
+        }
+    }
+
+    object([
+        ("openapi", "3.0.3".into()),
+        (
+            "info",
+            object([("title", title.into()), ("version", version.into())]),
+        ),
+        (
+            "paths",
+            paths
+                .into_iter()
+                .map(|(path, item)| (path, Value::Object(item)))
+                .collect::<Map<_, _>>()
+                .into(),
+        ),
+    ])
+}
+
+/// Serves a pre-rendered document at `/openapi.json`, see [`ServerBuilder::openapi`](crate::ServerBuilder::openapi).
+pub struct OpenApiHandler {
+    json: Vec<u8>,
+}
+
+impl OpenApiHandler {
+    pub fn new(document: &Value) -> OpenApiHandler {
+        OpenApiHandler {
+            json: serde_json::to_vec(document).expect("openapi documents are always valid json"),
+        }
+    }
+}
+
+impl<C: Send + Sync> Handler<C> for OpenApiHandler {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        request.respond_with_bytes(
+            200,
+            vec![header("Content-Type", "application/json")],
+            &self.json,
+        )?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        "/openapi.json"
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET"]
+    }
+}
+
+// `/posts/:id/*rest` is `/posts/{id}/{rest}` to openapi
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn path_parameters(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix([':', '*']))
+}
+
+fn content(content_type: &str, schema: Option<Value>) -> Value {
+    let media_type = match schema {
+        Some(schema) => object([("schema", schema)]),
+        None => Value::Object(Map::new()),
+    };
+
+    object([(content_type, media_type)])
+}
+
+fn object<'k>(entries: impl IntoIterator<Item = (&'k str, Value)>) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect(),
+    )
+}
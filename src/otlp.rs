@@ -0,0 +1,442 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::{
+    client::{Client, ClientError},
+    metrics::RouteTimings,
+    path,
+    trace::{SpanRecord, Tracing},
+    ShutdownHandle,
+};
+
+#[derive(Error, Debug)]
+pub enum OtlpError {
+    #[error("{name} is set to {value:?}, which isn't valid for it")]
+    InvalidEnv { name: &'static str, value: String },
+    #[error("only the http/json otlp protocol is supported, not {0}")]
+    UnsupportedProtocol(String),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error("the collector answered {0}")]
+    Status(u16),
+}
+
+/// Where [`Otlp`] sends things, and how often - read from the standard `OTEL_*` environment variables by
+/// [`from_env`](Self::from_env), or set here.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    traces_endpoint: String,
+    metrics_endpoint: String,
+    headers: Vec<(String, String)>,
+    resource: Vec<(String, String)>,
+    span_delay: Duration,
+    metric_interval: Duration,
+    max_queue: usize,
+    timeout: Duration,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> OtlpConfig {
+        OtlpConfig::new("http://localhost:4318")
+    }
+}
+
+impl OtlpConfig {
+    /// Sends to the collector at `endpoint`, on its `/v1/traces` and `/v1/metrics`.
+    pub fn new(endpoint: &str) -> OtlpConfig {
+        let endpoint = endpoint.trim_end_matches('/');
+        OtlpConfig {
+            traces_endpoint: format!("{}/v1/traces", endpoint),
+            metrics_endpoint: format!("{}/v1/metrics", endpoint),
+            headers: Vec::new(),
+            resource: vec![("service.name".to_owned(), "beak".to_owned())],
+            span_delay: Duration::from_secs(5),
+            metric_interval: Duration::from_secs(60),
+            max_queue: 2048,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Reads `OTEL_EXPORTER_OTLP_ENDPOINT` (and the `_TRACES_` and `_METRICS_` ones, which are used as they are),
+    /// `OTEL_EXPORTER_OTLP_HEADERS`, `OTEL_EXPORTER_OTLP_TIMEOUT`, `OTEL_EXPORTER_OTLP_PROTOCOL`,
+    /// `OTEL_SERVICE_NAME`, `OTEL_RESOURCE_ATTRIBUTES`, `OTEL_BSP_SCHEDULE_DELAY`, `OTEL_BSP_MAX_QUEUE_SIZE` and
+    /// `OTEL_METRIC_EXPORT_INTERVAL`, with the spec's defaults for the ones that aren't set.
+    ///
+    /// `None` if `OTEL_SDK_DISABLED` is `true`. The only protocol is `http/json`, and beak's client only speaks
+    /// plain http - a collector on the same host, or a sidecar, is the usual setup.
+    pub fn from_env() -> Result<Option<OtlpConfig>, OtlpError> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+
+        if var("OTEL_SDK_DISABLED")
+            .is_some_and(|disabled| disabled.trim().eq_ignore_ascii_case("true"))
+        {
+            return Ok(None);
+        }
+        if let Some(protocol) = var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+            if protocol.trim() != "http/json" {
+                return Err(OtlpError::UnsupportedProtocol(protocol));
+            }
+        }
+
+        let mut config = match var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            Some(endpoint) => OtlpConfig::new(endpoint.trim()),
+            None => OtlpConfig::default(),
+        };
+        if let Some(endpoint) = var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+            config.traces_endpoint = endpoint.trim().to_owned();
+        }
+        if let Some(endpoint) = var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT") {
+            config.metrics_endpoint = endpoint.trim().to_owned();
+        }
+
+        if let Some(headers) = var("OTEL_EXPORTER_OTLP_HEADERS") {
+            config.headers = pairs("OTEL_EXPORTER_OTLP_HEADERS", &headers)?;
+        }
+        if let Some(attributes) = var("OTEL_RESOURCE_ATTRIBUTES") {
+            for (key, value) in pairs("OTEL_RESOURCE_ATTRIBUTES", &attributes)? {
+                config = config.resource_attribute(key, value);
+            }
+        }
+        // the service name wins over one in the attributes
+        if let Some(name) = var("OTEL_SERVICE_NAME") {
+            config = config.resource_attribute("service.name", name.trim());
+        }
+
+        let millis = |name: &'static str| -> Result<Option<Duration>, OtlpError> {
+            var(name)
+                .map(|value| match value.trim().parse() {
+                    Ok(millis) => Ok(Duration::from_millis(millis)),
+                    Err(_) => Err(OtlpError::InvalidEnv { name, value }),
+                })
+                .transpose()
+        };
+        if let Some(timeout) = millis("OTEL_EXPORTER_OTLP_TIMEOUT")? {
+            config.timeout = timeout;
+        }
+        if let Some(delay) = millis("OTEL_BSP_SCHEDULE_DELAY")? {
+            config.span_delay = delay;
+        }
+        if let Some(interval) = millis("OTEL_METRIC_EXPORT_INTERVAL")? {
+            config.metric_interval = interval;
+        }
+        if let Some(size) = var("OTEL_BSP_MAX_QUEUE_SIZE") {
+            config.max_queue = size.trim().parse().map_err(|_| OtlpError::InvalidEnv {
+                name: "OTEL_BSP_MAX_QUEUE_SIZE",
+                value: size,
+            })?;
+        }
+
+        Ok(Some(config))
+    }
+
+    /// Sent with every export - an api key, usually.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn service_name(self, name: impl Into<String>) -> Self {
+        self.resource_attribute("service.name", name)
+    }
+
+    /// An attribute of the service, like `deployment.environment`, sent with every span and metric.
+    pub fn resource_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        self.resource.retain(|(existing, _)| *existing != key);
+        self.resource.push((key, value));
+        self
+    }
+
+    /// How often finished spans are sent, 5 seconds by default.
+    pub fn span_delay(mut self, delay: Duration) -> Self {
+        self.span_delay = delay;
+        self
+    }
+
+    /// How often metrics are sent, a minute by default.
+    pub fn metric_interval(mut self, interval: Duration) -> Self {
+        self.metric_interval = interval;
+        self
+    }
+
+    /// How many finished spans are kept waiting to be sent at most, 2048 by default - past that, new ones are
+    /// dropped until there's room.
+    pub fn max_queue(mut self, spans: usize) -> Self {
+        self.max_queue = spans;
+        self
+    }
+}
+
+// `key=value,key=value`, with the values percent-encoded
+fn pairs(name: &'static str, text: &str) -> Result<Vec<(String, String)>, OtlpError> {
+    let invalid = || OtlpError::InvalidEnv {
+        name,
+        value: text.to_owned(),
+    };
+    text.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            let value = path::percent_decode(value.trim()).map_err(|_| invalid())?;
+            Ok((key.trim().to_owned(), value))
+        })
+        .collect()
+}
+
+/// Sends the spans [`Tracing`] makes, and the [route timings](crate::ServerBuilder::route_timings), to an
+/// OpenTelemetry collector over OTLP - spans in batches every so often, and the timings as a cumulative
+/// `http.server.request.duration` histogram per route.
+///
+/// Nothing's sent until it's [started](Self::start).
+pub struct Otlp {
+    config: OtlpConfig,
+    client: Client,
+    spans: Mutex<Vec<SpanRecord>>,
+    timings: Option<RouteTimings>,
+    started: SystemTime,
+}
+
+impl Otlp {
+    pub fn new(config: OtlpConfig) -> Otlp {
+        Otlp {
+            client: Client::new().timeout(Some(config.timeout)),
+            config,
+            spans: Mutex::new(Vec::new()),
+            timings: None,
+            started: SystemTime::now(),
+        }
+    }
+
+    /// Sends these timings' histograms as metrics - the same ones the server's recording into.
+    pub fn route_timings(mut self, timings: RouteTimings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    /// A [`Tracing`] middleware that queues its spans up to be sent from here.
+    pub fn tracing(self: &Arc<Self>) -> Tracing<impl Fn(&SpanRecord) + Send + Sync> {
+        let otlp = self.clone();
+        Tracing::new(move |span: &SpanRecord| otlp.record(span))
+    }
+
+    /// Queues a span up to be sent.
+    pub fn record(&self, span: &SpanRecord) {
+        let mut spans = self.spans.lock().unwrap();
+        if spans.len() < self.config.max_queue {
+            spans.push(span.clone());
+        }
+    }
+
+    /// Starts a thread sending spans and metrics until `shutdown` is, with one last send once it has been.
+    /// Exports that fail are dropped - the collector's expected to be back for the next one.
+    pub fn start(self: &Arc<Self>, shutdown: ShutdownHandle) {
+        let otlp = self.clone();
+        thread::spawn(move || {
+            let tick = otlp.config.span_delay.min(otlp.config.metric_interval);
+            let mut metrics_sent = Instant::now();
+
+            while !shutdown.is_shutdown() {
+                thread::sleep(tick);
+                let _ = otlp.send_spans();
+                if metrics_sent.elapsed() >= otlp.config.metric_interval {
+                    let _ = otlp.send_metrics();
+                    metrics_sent = Instant::now();
+                }
+            }
+
+            let _ = otlp.send_spans();
+            let _ = otlp.send_metrics();
+        });
+    }
+
+    /// Sends every queued span now.
+    pub fn send_spans(&self) -> Result<(), OtlpError> {
+        let spans = std::mem::take(&mut *self.spans.lock().unwrap());
+        // the spec's largest batch
+        for batch in spans.chunks(512) {
+            let spans = batch.iter().map(span).collect();
+            let scope = object(vec![("scope", scope()), ("spans", Value::Array(spans))]);
+            let resource_spans = object(vec![
+                ("resource", self.resource()),
+                ("scopeSpans", Value::Array(vec![scope])),
+            ]);
+            let body = object(vec![("resourceSpans", Value::Array(vec![resource_spans]))]);
+            self.send(&self.config.traces_endpoint, &body)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the route timings now, if there are any.
+    pub fn send_metrics(&self) -> Result<(), OtlpError> {
+        let timings = match &self.timings {
+            Some(timings) => timings,
+            None => return Ok(()),
+        };
+
+        let (start, now) = (nanos(self.started), nanos(SystemTime::now()));
+        let points: Vec<Value> = timings
+            .summaries()
+            .into_iter()
+            .map(|summary| {
+                // the summary's buckets count up as they go, otlp's each count their own
+                let mut previous = 0;
+                let counts = summary
+                    .buckets
+                    .iter()
+                    .map(|(_, cumulative)| {
+                        let count = cumulative - previous;
+                        previous = *cumulative;
+                        Value::String(count.to_string())
+                    })
+                    .collect();
+                let bounds = summary
+                    .buckets
+                    .iter()
+                    .filter_map(|(bound, _)| bound.map(|bound| bound.as_secs_f64().into()))
+                    .collect();
+
+                object(vec![
+                    (
+                        "attributes",
+                        Value::Array(vec![attribute("http.route", summary.route)]),
+                    ),
+                    ("startTimeUnixNano", start.clone().into()),
+                    ("timeUnixNano", now.clone().into()),
+                    ("count", summary.count.to_string().into()),
+                    ("sum", summary.total.as_secs_f64().into()),
+                    ("bucketCounts", Value::Array(counts)),
+                    ("explicitBounds", Value::Array(bounds)),
+                ])
+            })
+            .collect();
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let histogram = object(vec![
+            // cumulative
+            ("aggregationTemporality", 2.into()),
+            ("dataPoints", Value::Array(points)),
+        ]);
+        let metric = object(vec![
+            ("name", "http.server.request.duration".into()),
+            ("unit", "s".into()),
+            ("histogram", histogram),
+        ]);
+        let scope = object(vec![
+            ("scope", scope()),
+            ("metrics", Value::Array(vec![metric])),
+        ]);
+        let resource_metrics = object(vec![
+            ("resource", self.resource()),
+            ("scopeMetrics", Value::Array(vec![scope])),
+        ]);
+        let body = object(vec![(
+            "resourceMetrics",
+            Value::Array(vec![resource_metrics]),
+        )]);
+        self.send(&self.config.metrics_endpoint, &body)
+    }
+
+    fn resource(&self) -> Value {
+        let attributes = self
+            .config
+            .resource
+            .iter()
+            .map(|(key, value)| attribute(key, value.as_str()))
+            .collect();
+        object(vec![("attributes", Value::Array(attributes))])
+    }
+
+    fn send(&self, endpoint: &str, body: &Value) -> Result<(), OtlpError> {
+        let body = serde_json::to_vec(body).map_err(|e| ClientError::Io(e.into()))?;
+        let mut request = self
+            .client
+            .post(endpoint)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.body(body).send()?;
+        match response.is_success() {
+            true => Ok(()),
+            false => Err(OtlpError::Status(response.status)),
+        }
+    }
+}
+
+fn span(span: &SpanRecord) -> Value {
+    let mut fields = vec![
+        ("traceId", span.context.trace_id.to_string().into()),
+        ("spanId", span.context.span_id.to_string().into()),
+        ("name", span.name.clone().into()),
+        // server
+        ("kind", 2.into()),
+        ("startTimeUnixNano", nanos(span.start).into()),
+        ("endTimeUnixNano", nanos(span.start + span.duration).into()),
+    ];
+    if let Some(parent) = span.parent {
+        fields.push(("parentSpanId", parent.to_string().into()));
+    }
+    if let Some(state) = &span.context.state {
+        fields.push(("traceState", state.clone().into()));
+    }
+
+    let mut attributes = Vec::new();
+    if let Some((method, route)) = span.name.split_once(' ') {
+        attributes.push(attribute("http.request.method", method));
+        attributes.push(attribute("http.route", route));
+    }
+    if let Some(status) = span.status {
+        let value = object(vec![("intValue", status.to_string().into())]);
+        attributes.push(object(vec![
+            ("key", "http.response.status_code".into()),
+            ("value", value),
+        ]));
+    }
+    fields.push(("attributes", Value::Array(attributes)));
+
+    // servers only count their own failures as errors, not the client's
+    let failed = span.failed || span.status.is_some_and(|status| status >= 500);
+    if failed {
+        fields.push(("status", object(vec![("code", 2.into())])));
+    }
+
+    object(fields)
+}
+
+fn scope() -> Value {
+    object(vec![
+        ("name", "beak".into()),
+        ("version", env!("CARGO_PKG_VERSION").into()),
+    ])
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    let value = object(vec![("stringValue", value.into())]);
+    object(vec![("key", key.into()), ("value", value)])
+}
+
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect::<Map<String, Value>>(),
+    )
+}
+
+// 64-bit integers go in otlp json as strings
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos())
+        .to_string()
+}
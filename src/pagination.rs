@@ -0,0 +1,231 @@
+use thiserror::Error;
+use tiny_http::Header;
+
+use crate::{forms::Form, headers::header, path, Request};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PageError {
+    #[error("{0} has to be a whole number, 1 or more")]
+    Invalid(&'static str),
+}
+
+/// How list endpoints take `page`, `per_page` and `cursor` from the query string.
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    default_per_page: u64,
+    max_per_page: u64,
+}
+
+impl Default for Pagination {
+    fn default() -> Pagination {
+        Pagination {
+            default_per_page: 20,
+            max_per_page: 100,
+        }
+    }
+}
+
+impl Pagination {
+    pub fn new() -> Pagination {
+        Pagination::default()
+    }
+
+    /// For requests that don't say, 20 unless set otherwise.
+    pub fn default_per_page(mut self, per_page: u64) -> Self {
+        self.default_per_page = per_page;
+        self
+    }
+
+    /// Requests asking for more get this many, 100 unless set otherwise.
+    pub fn max_per_page(mut self, per_page: u64) -> Self {
+        self.max_per_page = per_page;
+        self
+    }
+
+    /// The page a request asks for. Numbers that aren't numbers, or are 0, are an error - answer those with a 400.
+    pub fn parse(&self, request: &Request<'_, '_, '_>) -> Result<Page, PageError> {
+        self.parse_query(request.query().unwrap_or(""))
+    }
+
+    fn parse_query(&self, query: &str) -> Result<Page, PageError> {
+        let query = Form::parse(query);
+        let number = |name: &'static str| {
+            query
+                .get(name)
+                .map(|value| match value.trim().parse::<u64>() {
+                    Ok(number) if number > 0 => Ok(number),
+                    _ => Err(PageError::Invalid(name)),
+                })
+                .transpose()
+        };
+
+        let per_page = number("per_page")?.unwrap_or(self.default_per_page);
+        Ok(Page {
+            number: number("page")?.unwrap_or(1),
+            per_page: per_page.min(self.max_per_page).max(1),
+            cursor: query
+                .get("cursor")
+                .filter(|cursor| !cursor.is_empty())
+                .map(str::to_owned),
+        })
+    }
+}
+
+/// The page of a list a request asked for - by number, or by a cursor from the page before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// From 1.
+    pub number: u64,
+    pub per_page: u64,
+    pub cursor: Option<String>,
+}
+
+impl Page {
+    /// How many items come before this page.
+    pub fn offset(&self) -> u64 {
+        (self.number - 1).saturating_mul(self.per_page)
+    }
+
+    /// The number of the last page of `total` items - 1 for none at all, so there's always a first page to be on.
+    pub fn last(&self, total: u64) -> u64 {
+        total.div_ceil(self.per_page).max(1)
+    }
+
+    /// `Link` with the first, previous, next and last pages of `total` items, and `X-Total-Count`. The links are the
+    /// request's own path and query with the page changed, relative so they work behind whatever's proxying.
+    pub fn headers(&self, request: &Request<'_, '_, '_>, total: u64) -> Vec<Header> {
+        let last = self.last(total);
+        let mut links = vec![(1, "first")];
+        if self.number > 1 {
+            links.push((self.number.min(last + 1) - 1, "prev"));
+        }
+        if self.number < last {
+            links.push((self.number + 1, "next"));
+        }
+        links.push((last, "last"));
+
+        let links: Vec<_> = links
+            .into_iter()
+            .map(|(number, rel)| {
+                let params = [
+                    ("page", number.to_string()),
+                    ("per_page", self.per_page.to_string()),
+                ];
+                link(request.path(), request.query(), &params, rel)
+            })
+            .collect();
+
+        vec![
+            header("Link", links.join(", ")),
+            header("X-Total-Count", total.to_string()),
+        ]
+    }
+
+    /// `Link` with the first page, and the next one from `next` - the cursor after this page's last item, or `None`
+    /// if it was the end.
+    pub fn cursor_headers(&self, request: &Request<'_, '_, '_>, next: Option<&str>) -> Vec<Header> {
+        let per_page = self.per_page.to_string();
+        let (path, query) = (request.path(), request.query());
+        let mut links = vec![link(
+            path,
+            query,
+            &[("per_page", per_page.clone())],
+            "first",
+        )];
+        if let Some(next) = next {
+            links.push(link(
+                path,
+                query,
+                &[("cursor", next.to_owned()), ("per_page", per_page)],
+                "next",
+            ));
+        }
+
+        vec![header("Link", links.join(", "))]
+    }
+}
+
+// the request's url with the pagination parameters swapped for `params`, as a link with relation `rel`. anything
+// else in the query goes through as it came
+fn link(path: &str, query: Option<&str>, params: &[(&str, String)], rel: &str) -> String {
+    let mut query: Vec<_> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            !pair.is_empty() && !matches!(name, "page" | "per_page" | "cursor")
+        })
+        .map(str::to_owned)
+        .collect();
+    query.extend(
+        params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, path::percent_encode(value))),
+    );
+
+    format!("<{}?{}>; rel=\"{}\"", path, query.join("&"), rel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pages() {
+        let pagination = Pagination::new();
+        let page = pagination.parse_query("").unwrap();
+        assert_eq!((page.number, page.per_page, page.cursor), (1, 20, None));
+
+        let page = pagination.parse_query("page=3&per_page=50").unwrap();
+        assert_eq!((page.number, page.per_page), (3, 50));
+        assert_eq!(page.offset(), 100);
+
+        let page = pagination.parse_query("cursor=abc%3D&per_page=5").unwrap();
+        assert_eq!(page.cursor.as_deref(), Some("abc="));
+        assert_eq!(pagination.parse_query("cursor=").unwrap().cursor, None);
+    }
+
+    #[test]
+    fn bounds_pages() {
+        let pagination = Pagination::new().default_per_page(10).max_per_page(25);
+        assert_eq!(pagination.parse_query("").unwrap().per_page, 10);
+        assert_eq!(
+            pagination.parse_query("per_page=1000").unwrap().per_page,
+            25
+        );
+
+        for query in ["page=0", "page=-1", "page=abc", "per_page=0", "page=1.5"] {
+            assert!(pagination.parse_query(query).is_err(), "{}", query);
+        }
+        assert_eq!(
+            pagination.parse_query("per_page=0"),
+            Err(PageError::Invalid("per_page"))
+        );
+
+        let page = pagination
+            .parse_query(&format!("page={}", u64::MAX))
+            .unwrap();
+        assert_eq!(page.offset(), u64::MAX);
+    }
+
+    #[test]
+    fn last_pages() {
+        let page = Pagination::new().parse_query("per_page=10").unwrap();
+        assert_eq!(page.last(0), 1);
+        assert_eq!(page.last(10), 1);
+        assert_eq!(page.last(11), 2);
+    }
+
+    #[test]
+    fn links_keep_the_rest_of_the_query() {
+        let params = [("page", "2".to_owned()), ("per_page", "10".to_owned())];
+        assert_eq!(
+            link("/posts", Some("tag=rust&page=1&cursor=x&"), &params, "next"),
+            "</posts?tag=rust&page=2&per_page=10>; rel=\"next\""
+        );
+        assert_eq!(
+            link("/posts", None, &[("cursor", "a b".to_owned())], "next"),
+            "</posts?cursor=a%20b>; rel=\"next\""
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// Why a path from a request can't be used to look anything up.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    #[error("route has no catch-all parameter")]
+    NoWildcard,
+    #[error("path is not valid percent-encoded utf-8")]
+    InvalidEncoding,
+    #[error("path escapes the directory it's relative to")]
+    Traversal,
+}
+
+/// Decodes `%xx` escapes. Fails on malformed escapes, or if the result isn't utf-8.
+pub fn percent_decode(path: &str) -> Result<String, PathError> {
+    let mut decoded = Vec::with_capacity(path.len());
+    percent_decode_into(path, &mut decoded)?;
+    String::from_utf8(decoded).map_err(|_| PathError::InvalidEncoding)
+}
+
+// pushes at most path.len() bytes, without checking they're utf-8
+pub(crate) fn percent_decode_into(path: &str, decoded: &mut Vec<u8>) -> Result<(), PathError> {
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or(PathError::InvalidEncoding)?;
+            // from_str_radix takes a sign, so `%+f` would decode
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return Err(PathError::InvalidEncoding);
+            }
+            let hex = std::str::from_utf8(hex).map_err(|_| PathError::InvalidEncoding)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| PathError::InvalidEncoding)?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Escapes everything but unreserved characters, so `segment` can go in a URL path as a single segment.
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Turns a decoded path into a clean relative one: no leading, trailing or doubled slashes, and no `.` segments.
+/// Anything with `..`, backslashes or NUL bytes is refused outright rather than resolved, since there's no telling
+/// what a filesystem will make of it.
+pub fn normalize(path: &str) -> Result<String, PathError> {
+    if path.contains(['\\', '\0']) {
+        return Err(PathError::Traversal);
+    }
+
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return Err(PathError::Traversal),
+            // "C:" means something to windows
+            segment if cfg!(windows) && segment.ends_with(':') => return Err(PathError::Traversal),
+            segment => segments.push(segment),
+        }
+    }
+
+    Ok(segments.join("/"))
+}
+
+/// `user_path` - percent-encoded, as it came in a URL - joined onto `base` so that it can't end up anywhere outside
+/// it. It's decoded exactly once, so `%2e%2e` is refused like `..` is, while `%252e` names a file called `%2e`, and
+/// then [normalized](normalize), refusing backslashes and NUL bytes - the same checks
+/// [`StaticDir`](crate::files::StaticDir) makes of request paths.
+///
+/// It only looks at the path, not at the filesystem: a symlink inside `base` still goes wherever it points.
+pub fn safe_join(base: impl AsRef<Path>, user_path: &str) -> Result<PathBuf, PathError> {
+    let relative = normalize(&percent_decode(user_path)?)?;
+    Ok(base.as_ref().join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_under_the_base() {
+        assert_eq!(
+            safe_join("/srv", "a/b.txt"),
+            Ok(PathBuf::from("/srv/a/b.txt"))
+        );
+        assert_eq!(
+            safe_join("/srv", "//a/./b//"),
+            Ok(PathBuf::from("/srv/a/b"))
+        );
+        assert_eq!(
+            safe_join("/srv", "a%20b/c%2Fd"),
+            Ok(PathBuf::from("/srv/a b/c/d"))
+        );
+        assert_eq!(safe_join("/srv", ""), Ok(PathBuf::from("/srv/")));
+    }
+
+    #[test]
+    fn refuses_dot_dot() {
+        for path in ["..", "../etc/passwd", "a/../../b", "a/..", "/.."] {
+            assert_eq!(
+                safe_join("/srv", path),
+                Err(PathError::Traversal),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_encoded_dots() {
+        for path in ["%2e%2e", "%2E%2E/etc", "a/.%2e/b", "%2e./b", "a%2f..%2fb"] {
+            assert_eq!(
+                safe_join("/srv", path),
+                Err(PathError::Traversal),
+                "{}",
+                path
+            );
+        }
+        // decoded once, so this is a file called %2e%2e
+        assert_eq!(
+            safe_join("/srv", "%252e%252e"),
+            Ok(PathBuf::from("/srv/%2e%2e"))
+        );
+    }
+
+    #[test]
+    fn refuses_backslashes_and_nul() {
+        for path in ["..\\etc", "a\\b", "a%5cb", "a%5C..%5Cb", "a\0b", "a%00b"] {
+            assert_eq!(
+                safe_join("/srv", path),
+                Err(PathError::Traversal),
+                "{:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_bad_encoding() {
+        for path in ["%", "%2", "%zz", "%ff", "%+f", "%-1"] {
+            assert_eq!(
+                safe_join("/srv", path),
+                Err(PathError::InvalidEncoding),
+                "{}",
+                path
+            );
+        }
+    }
+}
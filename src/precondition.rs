@@ -0,0 +1,163 @@
+use std::{io, time::SystemTime};
+
+use crate::{
+    headers::{self, header},
+    Request,
+};
+
+/// Which version of a resource there is now, to check a request's `If-Match` and `If-Unmodified-Since` against
+/// before changing it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Version {
+    etag: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+impl Version {
+    pub fn new() -> Version {
+        Version::default()
+    }
+
+    /// Quotes and all, `"abc"` - and strong, not `W/`, since weak tags never match for `If-Match`.
+    pub fn etag(mut self, etag: impl Into<String>) -> Self {
+        self.etag = Some(etag.into());
+        self
+    }
+
+    pub fn modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+}
+
+/// Whether the request's preconditions hold for `current`, which is `None` when there's no resource. `If-Match`
+/// wins when both are there, and `If-Unmodified-Since` only counts against resources with a modified time.
+pub fn preconditions_hold(request: &Request<'_, '_, '_>, current: Option<&Version>) -> bool {
+    hold(
+        request.header("If-Match"),
+        request.header("If-Unmodified-Since"),
+        current,
+    )
+}
+
+fn hold(
+    if_match: Option<&str>,
+    if_unmodified_since: Option<&str>,
+    current: Option<&Version>,
+) -> bool {
+    if let Some(tags) = if_match {
+        return tags.split(',').map(str::trim).any(|tag| match current {
+            Some(_) if tag == "*" => true,
+            Some(current) => !tag.starts_with("W/") && current.etag.as_deref() == Some(tag),
+            None => false,
+        });
+    }
+
+    let since = if_unmodified_since.and_then(headers::parse_http_date);
+    match (since, current.and_then(|current| current.modified)) {
+        (Some(since), Some(modified)) => headers::unix_secs(modified) <= headers::unix_secs(since),
+        // the spec says to ignore it for resources without a modified time
+        _ => true,
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Answers with a 412 Precondition Failed if the request's `If-Match` or `If-Unmodified-Since` don't hold for
+    /// `current` - the version there is now, if there's one at all - or hands the request back to go on with the
+    /// update. Check it as close to the write as possible, or two updates can still both pass it.
+    pub fn check_preconditions(self, current: Option<&Version>) -> io::Result<Option<Self>> {
+        if preconditions_hold(&self, current) {
+            return Ok(Some(self));
+        }
+
+        // what's there now, so the client can fetch it and try again
+        let headers = current
+            .and_then(|current| current.etag.as_deref())
+            .map(|etag| header("ETag", etag))
+            .into_iter()
+            .collect();
+        self.respond_with_bytes(412, headers, b"precondition failed")?;
+        Ok(None)
+    }
+
+    /// Answers with a 428 Precondition Required unless the request has an `If-Match` or `If-Unmodified-Since`, for
+    /// updates that shouldn't go ahead blind.
+    pub fn require_precondition(self) -> io::Result<Option<Self>> {
+        if self.header("If-Match").is_some() || self.header("If-Unmodified-Since").is_some() {
+            return Ok(Some(self));
+        }
+
+        self.respond_with_bytes(428, vec![], b"precondition required")?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn date(secs: u64) -> String {
+        headers::http_date(at(secs))
+    }
+
+    #[test]
+    fn star_needs_a_resource() {
+        let current = Version::new().etag("\"abc\"");
+        assert!(hold(Some("*"), None, Some(&current)));
+        assert!(hold(Some("*"), None, Some(&Version::new())));
+        assert!(!hold(Some("*"), None, None));
+    }
+
+    #[test]
+    fn matches_any_listed_tag() {
+        let current = Version::new().etag("\"abc\"");
+        assert!(hold(Some("\"abc\""), None, Some(&current)));
+        assert!(hold(Some("\"xyz\", \"abc\""), None, Some(&current)));
+        assert!(!hold(Some("\"xyz\""), None, Some(&current)));
+        assert!(!hold(Some("\"abc\""), None, None));
+        // a resource without a tag can't match one
+        assert!(!hold(Some("\"abc\""), None, Some(&Version::new())));
+    }
+
+    #[test]
+    fn weak_tags_never_match() {
+        let current = Version::new().etag("\"abc\"");
+        assert!(!hold(Some("W/\"abc\""), None, Some(&current)));
+
+        let weak = Version::new().etag("W/\"abc\"");
+        assert!(!hold(Some("W/\"abc\""), None, Some(&weak)));
+    }
+
+    #[test]
+    fn if_match_beats_if_unmodified_since() {
+        let current = Version::new().etag("\"abc\"").modified(at(2_000));
+        // modified since, but the tag matches
+        assert!(hold(Some("\"abc\""), Some(&date(1_000)), Some(&current)));
+        // not modified since, but the tag doesn't
+        assert!(!hold(Some("\"xyz\""), Some(&date(3_000)), Some(&current)));
+    }
+
+    #[test]
+    fn unmodified_since() {
+        let current = Version::new().modified(at(2_000));
+        assert!(hold(None, Some(&date(2_000)), Some(&current)));
+        assert!(hold(None, Some(&date(3_000)), Some(&current)));
+        assert!(!hold(None, Some(&date(1_000)), Some(&current)));
+        // dates that don't parse are ignored
+        assert!(hold(None, Some("yesterday"), Some(&current)));
+    }
+
+    #[test]
+    fn resources_without_a_modified_time() {
+        let current = Version::new().etag("\"abc\"");
+        assert!(hold(None, Some(&date(1_000)), Some(&current)));
+        assert!(hold(None, Some(&date(1_000)), None));
+        assert!(hold(None, None, None));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use tiny_http::Request as TinyHttpRequest;
+
+use crate::{accounting::Accounting, headers::http_date, random::roll};
+
+// never worth keeping, and not something the admin listener should hand out
+const REDACTED: &[&str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+];
+
+/// Why a request was captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureReason {
+    Sampled,
+    /// It took longer than the profiler's threshold.
+    Slow,
+}
+
+/// Where a request's time went. Everything is measured from a worker picking the request up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    /// Until the middleware and handler got it: rewrites, routing, reading a multipart upload.
+    pub routing: Duration,
+    /// In the middleware and handler.
+    pub handler: Duration,
+    /// Until the response's status line was written, if it was.
+    pub first_byte: Option<Duration>,
+    pub total: Duration,
+}
+
+/// Everything captured about a request.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub reason: CaptureReason,
+    pub method: String,
+    /// As the client sent it, before any rewrites.
+    pub url: String,
+    /// The path pattern of the route that matched, if one did.
+    pub route: Option<&'static str>,
+    pub status: Option<u16>,
+    /// Credentials and cookies are redacted.
+    pub headers: Vec<(String, String)>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub received: SystemTime,
+    pub timings: Timings,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            CaptureReason::Sampled => "sampled",
+            CaptureReason::Slow => "slow",
+        };
+        writeln!(
+            f,
+            "{} {} {} ({}, {})",
+            http_date(self.received),
+            self.method,
+            self.url,
+            self.route.unwrap_or("no route"),
+            reason
+        )?;
+        match self.status {
+            Some(status) => write!(f, "  status {}", status)?,
+            None => write!(f, "  no status")?,
+        }
+        writeln!(
+            f,
+            ", {} bytes in, {} bytes out",
+            self.bytes_read, self.bytes_written
+        )?;
+
+        let Timings {
+            routing,
+            handler,
+            first_byte,
+            total,
+        } = self.timings;
+        write!(
+            f,
+            "  routing {:?}, handler {:?}, total {:?}",
+            routing, handler, total
+        )?;
+        if let Some(first_byte) = first_byte {
+            write!(f, ", first byte {:?}", first_byte)?;
+        }
+        writeln!(f)?;
+
+        for (name, value) in &self.headers {
+            writeln!(f, "  {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Captures everything about a sample of requests, and about every slow one, into a ring buffer holding the most
+/// recent few - for [`ServerBuilder::profiler`](crate::ServerBuilder::profiler), and for the admin listener's
+/// `profiles` command. Clones share the buffer.
+#[derive(Clone)]
+pub struct Profiler {
+    sample: f64,
+    slower_than: Option<Duration>,
+    capacity: usize,
+    captured: Arc<Mutex<VecDeque<Profile>>>,
+}
+
+impl Profiler {
+    /// Keeps the last `capacity` captures. Captures nothing until told what to sample with
+    /// [`sample`](Self::sample) or [`slower_than`](Self::slower_than).
+    pub fn new(capacity: usize) -> Profiler {
+        Profiler {
+            sample: 0.0,
+            slower_than: None,
+            capacity,
+            captured: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Captures about `rate` (between 0 and 1) of requests, picked at random.
+    pub fn sample(mut self, rate: f64) -> Self {
+        self.sample = rate;
+        self
+    }
+
+    /// Captures every request that takes longer than `threshold`.
+    pub fn slower_than(mut self, threshold: Duration) -> Self {
+        self.slower_than = Some(threshold);
+        self
+    }
+
+    /// What's been captured, oldest first.
+    pub fn captured(&self) -> Vec<Profile> {
+        self.captured.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.captured.lock().unwrap().clear();
+    }
+
+    // captures the request if it's slow or sampled - called once it's been answered
+    pub(crate) fn finish(
+        &self,
+        request: &TinyHttpRequest,
+        route: Option<&'static str>,
+        accounting: &Accounting,
+        started: Instant,
+        received: SystemTime,
+    ) {
+        let total = started.elapsed();
+        let reason = if self.slower_than.is_some_and(|threshold| total > threshold) {
+            CaptureReason::Slow
+        } else if self.sample > 0.0 && roll() < self.sample {
+            CaptureReason::Sampled
+        } else {
+            return;
+        };
+        if self.capacity == 0 {
+            return;
+        }
+
+        let handler_started = accounting.handler_started();
+        let routing = handler_started.map_or(total, |at| at.duration_since(started));
+        let timings = Timings {
+            routing,
+            handler: total.saturating_sub(routing),
+            first_byte: accounting.first_byte().map(|at| at.duration_since(started)),
+            total,
+        };
+
+        let headers = request
+            .headers()
+            .iter()
+            .map(|header| {
+                let name = header.field.as_str().as_str();
+                let value = match REDACTED.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+                    true => "[redacted]",
+                    false => header.value.as_str(),
+                };
+                (name.to_owned(), value.to_owned())
+            })
+            .collect();
+
+        let profile = Profile {
+            reason,
+            method: request.method().as_str().to_owned(),
+            url: request.url().to_owned(),
+            route,
+            status: accounting.status(),
+            headers,
+            bytes_read: accounting.bytes_read(),
+            bytes_written: accounting.bytes_written(),
+            received,
+            timings,
+        };
+
+        let mut captured = self.captured.lock().unwrap();
+        if captured.len() == self.capacity {
+            captured.pop_front();
+        }
+        captured.push_back(profile);
+    }
+}
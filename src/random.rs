@@ -0,0 +1,21 @@
+use std::{collections::hash_map::RandomState, hash::BuildHasher};
+
+/// `bytes` random bytes from the OS, hex-encoded.
+#[cfg(any(feature = "csrf", feature = "csp", feature = "tus", feature = "oauth"))]
+pub(crate) fn hex_token(bytes: usize) -> std::io::Result<String> {
+    let mut token = vec![0u8; bytes];
+    getrandom::getrandom(&mut token)?;
+
+    Ok(token.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// a float in [0, 1) - nowhere near good randomness, but plenty for sampling and deciding when to misbehave, and it
+// saves pulling in a dependency. every RandomState gets fresh keys, so hashing nothing with one gives a new number
+// each time.
+pub(crate) fn roll() -> f64 {
+    (bits() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+pub(crate) fn bits() -> u64 {
+    RandomState::new().hash_one(())
+}
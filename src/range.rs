@@ -0,0 +1,168 @@
+use std::{ops::Range, time::SystemTime};
+
+use crate::headers;
+
+/// What a `Range` header asks for, given the length of the representation it applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No range, or one we choose to ignore (multiple ranges, other units) - send the whole thing.
+    Full,
+    Partial(Range<u64>),
+    /// Nothing in the range overlaps the representation, which deserves a 416.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    pub fn parse(header: Option<&str>, len: u64) -> ByteRange {
+        let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRange::Full,
+        };
+
+        let range = match (start.trim(), end.trim()) {
+            // bytes=-500 is the last 500 bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => return ByteRange::Unsatisfiable,
+                Ok(suffix) => len.saturating_sub(suffix)..len,
+                Err(_) => return ByteRange::Full,
+            },
+            (start, "") => match start.parse::<u64>() {
+                Ok(start) => start..len,
+                Err(_) => return ByteRange::Full,
+            },
+            (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(_), Ok(_)) if len == 0 => return ByteRange::Unsatisfiable,
+                // an end past the last byte means the last byte, even bytes=0-18446744073709551615
+                (Ok(start), Ok(end)) if start <= end => start..end.min(len - 1) + 1,
+                _ => return ByteRange::Full,
+            },
+        };
+
+        if range.start >= len {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(range)
+        }
+    }
+}
+
+/// The `Content-Range` value for a partial response - or for a 416, when there isn't a range.
+/// An empty range is as good as none.
+pub fn content_range(range: Option<&Range<u64>>, len: u64) -> String {
+    match range {
+        Some(range) if range.start < range.end => {
+            format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+        }
+        _ => format!("bytes */{}", len),
+    }
+}
+
+/// Whether a `Range` should be honoured given the request's `If-Range`: always without one, otherwise only if the
+/// validator in it (an entity tag or a date) still matches the representation. When it doesn't, the client's partial
+/// copy is stale, and it needs the whole thing again rather than the rest of something that changed.
+///
+/// Tags are compared strongly, so weak ones never match, and dates have to equal `Last-Modified` exactly - a range
+/// stitched onto a different version would just be corrupt.
+pub fn if_range(
+    if_range: Option<&str>,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let validator = match if_range.map(str::trim) {
+        Some(validator) => validator,
+        None => return true,
+    };
+
+    if validator.starts_with('"') {
+        return etag.is_some_and(|etag| etag == validator);
+    }
+
+    match (headers::parse_http_date(validator), last_modified) {
+        // http dates only go down to the second
+        (Some(date), Some(modified)) => headers::unix_secs(date) == headers::unix_secs(modified),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        let parse = |header| ByteRange::parse(Some(header), 1000);
+        assert_eq!(parse("bytes=0-499"), ByteRange::Partial(0..500));
+        assert_eq!(parse("bytes=500-"), ByteRange::Partial(500..1000));
+        assert_eq!(parse("bytes=-200"), ByteRange::Partial(800..1000));
+        assert_eq!(parse("bytes=-5000"), ByteRange::Partial(0..1000));
+        assert_eq!(parse(" bytes= 10 - 20 "), ByteRange::Partial(10..21));
+        assert_eq!(parse("bytes=900-5000"), ByteRange::Partial(900..1000));
+        assert_eq!(parse("bytes=999-999"), ByteRange::Partial(999..1000));
+    }
+
+    #[test]
+    fn ignores_what_it_doesnt_understand() {
+        let parse = |header| ByteRange::parse(Some(header), 1000);
+        assert_eq!(ByteRange::parse(None, 1000), ByteRange::Full);
+        assert_eq!(parse("bytes=0-1,5-6"), ByteRange::Full);
+        assert_eq!(parse("items=0-1"), ByteRange::Full);
+        assert_eq!(parse("bytes=5-1"), ByteRange::Full);
+        assert_eq!(parse("bytes=a-b"), ByteRange::Full);
+        assert_eq!(parse("bytes=12"), ByteRange::Full);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        let parse = |header, len| ByteRange::parse(Some(header), len);
+        assert_eq!(parse("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=1000-2000", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-0", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn huge_ends_dont_overflow() {
+        let header = format!("bytes=0-{}", u64::MAX);
+        assert_eq!(
+            ByteRange::parse(Some(&header), 10),
+            ByteRange::Partial(0..10)
+        );
+        let header = format!("bytes={}-{}", u64::MAX, u64::MAX);
+        assert_eq!(
+            ByteRange::parse(Some(&header), u64::MAX),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn content_ranges() {
+        assert_eq!(content_range(Some(&(0..500)), 1000), "bytes 0-499/1000");
+        assert_eq!(content_range(None, 1000), "bytes */1000");
+        assert_eq!(content_range(Some(&(0..0)), 0), "bytes */0");
+    }
+
+    #[test]
+    fn if_range_validators() {
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        assert!(if_range(None, None, None));
+        assert!(if_range(Some("\"abc\""), Some("\"abc\""), None));
+        assert!(!if_range(Some("\"abc\""), Some("\"def\""), None));
+        assert!(!if_range(Some("W/\"abc\""), Some("W/\"abc\""), None));
+        assert!(if_range(
+            Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+            None,
+            Some(modified)
+        ));
+        assert!(!if_range(
+            Some("Sun, 06 Nov 1994 08:49:38 GMT"),
+            None,
+            Some(modified)
+        ));
+    }
+}
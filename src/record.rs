@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    body::Body,
+    tee::{Captured, Tee},
+    BeakResult, Handler, Middleware, Next, Request,
+};
+
+/// Appends every request and the response it got to a file, one JSON object per line:
+///
+/// `{"method": "POST", "url": "/posts?draft=1", "headers": [["Host", "..."]], "body": "...", "response": "HTTP/1.1 201 Created\r\n..."}`
+///
+/// Bodies that aren't UTF-8 go in `body_hex`/`response_hex` instead. Responses are recorded exactly as they were
+/// written, head and all, so a [`Replay`] can send them back byte for byte.
+///
+/// Request and response bodies are kept up to a limit each (1MiB by default), and exchanges that go over it are
+/// recorded with `"truncated": true`, which replays skip.
+///
+/// Headers carrying credentials - `Authorization`, `Proxy-Authorization`, `Cookie`, `Set-Cookie` and API key
+/// headers like `X-Api-Key` - are written down as `[redacted]`, in the requests and the response heads alike, unless
+/// a [`redact`](Self::redact) filter says otherwise.
+pub struct Recorder {
+    file: Mutex<File>,
+    limit: usize,
+    redact: Box<dyn Fn(&str) -> bool + Send + Sync>,
+}
+
+impl Recorder {
+    /// Appends to `path`, creating it if it isn't there.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Recorder {
+            file: Mutex::new(file),
+            limit: 1024 * 1024,
+            redact: Box::new(is_credential),
+        })
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Which headers to write down as `[redacted]` instead of their value, by name - in place of the credential
+    /// headers redacted by default. Names come as they were sent, so compare them ignoring case.
+    pub fn redact(mut self, redact: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.redact = Box::new(redact);
+        self
+    }
+}
+
+/// The headers a [`Recorder`] redacts unless told otherwise - for building on in a [`redact`](Recorder::redact)
+/// filter of your own.
+pub fn is_credential(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    matches!(
+        name.as_str(),
+        "authorization" | "proxy-authorization" | "cookie" | "set-cookie"
+    ) || name.ends_with("-api-key")
+        || name.ends_with("-auth-token")
+        || name.ends_with("-csrf-token")
+}
+
+// the response with its head's credentials redacted, and the body as it was
+fn redact_head(response: &[u8], redact: &dyn Fn(&str) -> bool) -> Vec<u8> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(response.len(), |end| end + 4);
+    let (head, body) = response.split_at(end);
+
+    let mut redacted = Vec::with_capacity(response.len());
+    for line in head.split_inclusive(|&b| b == b'\n') {
+        let name = line
+            .iter()
+            .position(|&b| b == b':')
+            .and_then(|colon| std::str::from_utf8(&line[..colon]).ok());
+        match name {
+            Some(name) if redact(name) => {
+                redacted.extend_from_slice(name.as_bytes());
+                redacted.extend_from_slice(b": [redacted]");
+                if line.ends_with(b"\n") {
+                    redacted.extend_from_slice(b"\r\n");
+                }
+            }
+            _ => redacted.extend_from_slice(line),
+        }
+    }
+    redacted.extend_from_slice(body);
+    redacted
+}
+
+impl<C: Send + Sync> Middleware<C> for Recorder {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let mut record = Map::new();
+        record.insert("method".to_owned(), request.method().into());
+        record.insert("url".to_owned(), request.url.into());
+        record.insert(
+            "headers".to_owned(),
+            request
+                .headers
+                .iter()
+                .map(|header| {
+                    let name = header.field.as_str().as_str();
+                    let value = match (self.redact)(name) {
+                        true => "[redacted]",
+                        false => header.value.as_str(),
+                    };
+                    Value::Array(vec![name.into(), value.into()])
+                })
+                .collect(),
+        );
+
+        // read what we can keep, then hand the handler all of it
+        let mut body = Vec::new();
+        (&mut request.body)
+            .take(self.limit as u64 + 1)
+            .read_to_end(&mut body)?;
+        let mut truncated = body.len() > self.limit;
+        insert_bytes(&mut record, "body", &body[..body.len().min(self.limit)]);
+        request.body = Body::new(Cursor::new(body).chain(request.body));
+
+        let mut captured = Captured::default();
+        let request =
+            request.wrap_output(|output| Box::new(Tee::new(output, &mut captured, self.limit)));
+        let result = next.run(request, context);
+
+        truncated |= captured.truncated;
+        insert_bytes(
+            &mut record,
+            "response",
+            &redact_head(&captured.bytes, &*self.redact),
+        );
+        if truncated {
+            record.insert("truncated".to_owned(), true.into());
+        }
+
+        let mut line = serde_json::to_vec(&Value::Object(record)).map_err(io::Error::from)?;
+        line.push(b'\n');
+        // one write per line, so records from different workers never interleave
+        self.file.lock().unwrap().write_all(&line)?;
+
+        result
+    }
+}
+
+fn insert_bytes(record: &mut Map<String, Value>, key: &str, bytes: &[u8]) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => record.insert(key.to_owned(), text.into()),
+        Err(_) => record.insert(format!("{}_hex", key), hex(bytes).into()),
+    };
+}
+
+fn read_bytes(record: &Map<String, Value>, key: &str) -> Option<Vec<u8>> {
+    if let Some(text) = record.get(key).and_then(Value::as_str) {
+        return Some(text.as_bytes().to_vec());
+    }
+
+    let hex = record.get(&format!("{}_hex", key))?.as_str()?;
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{:02x}", b);
+        hex
+    })
+}
+
+// a recorded exchange, as the replay needs it
+struct Exchange {
+    body: Vec<u8>,
+    response: Vec<u8>,
+}
+
+/// A stub server playing back what a [`Recorder`] wrote down, for contract tests against a recorded API.
+///
+/// Requests are matched on method and URL, preferring recordings whose body matches too. When the same request was
+/// recorded several times its responses are sent in the order they were recorded, and the last one repeats once
+/// they run out. Anything that wasn't recorded gets a 404.
+pub struct Replay {
+    path: &'static str,
+    exchanges: HashMap<(String, String), Vec<Exchange>>,
+    // how far along each request is in its recorded responses
+    played: Mutex<HashMap<(String, String), usize>>,
+}
+
+impl Replay {
+    /// Loads the recordings in `file`, to answer requests on `path` - usually a catch-all like `/*path`.
+    pub fn load(file: impl AsRef<Path>, path: &'static str) -> io::Result<Replay> {
+        let mut exchanges: HashMap<_, Vec<_>> = HashMap::new();
+
+        for line in BufReader::new(File::open(file)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record = serde_json::from_str(&line).map_err(io::Error::from)?;
+            let record = match &record {
+                Value::Object(record) if record.get("truncated").is_none() => record,
+                _ => continue,
+            };
+
+            let field = |key| record.get(key).and_then(Value::as_str).map(str::to_owned);
+            let exchange = (
+                field("method"),
+                field("url"),
+                read_bytes(record, "body"),
+                read_bytes(record, "response"),
+            );
+
+            match exchange {
+                (Some(method), Some(url), Some(body), Some(response)) => exchanges
+                    .entry((method, url))
+                    .or_default()
+                    .push(Exchange { body, response }),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("incomplete recording: {}", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(Replay {
+            path,
+            exchanges,
+            played: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl<C: Send + Sync> Handler<C> for Replay {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let key = (request.method().to_owned(), request.url.to_owned());
+        let recorded = match self.exchanges.get(&key) {
+            Some(recorded) => recorded,
+            None => {
+                request.respond_with_bytes(404, vec![], b"no recording for this request")?;
+                return Ok(());
+            }
+        };
+
+        let mut body = Vec::new();
+        request.body.read_to_end(&mut body)?;
+
+        let same_body: Vec<&Exchange> = recorded.iter().filter(|e| e.body == body).collect();
+        let candidates = if same_body.is_empty() {
+            recorded.iter().collect()
+        } else {
+            same_body
+        };
+
+        let index = {
+            let mut played = self.played.lock().unwrap();
+            let played = played.entry(key).or_insert(0);
+            let index = (*played).min(candidates.len() - 1);
+            *played += 1;
+            index
+        };
+
+        request.respond_raw(&candidates[index].response)?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_redacted() {
+        for name in [
+            "Authorization",
+            "cookie",
+            "Set-Cookie",
+            "X-Api-Key",
+            "x-auth-token",
+        ] {
+            assert!(is_credential(name), "{}", name);
+        }
+        for name in ["Host", "Content-Type", "Cookie-Policy", "X-Request-Id"] {
+            assert!(!is_credential(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn redacts_response_heads() {
+        let response = b"HTTP/1.1 200 OK\r\nSet-Cookie: session=secret\r\nContent-Length: 16\r\n\r\nSet-Cookie: body";
+        assert_eq!(
+            redact_head(response, &is_credential),
+            b"HTTP/1.1 200 OK\r\nSet-Cookie: [redacted]\r\nContent-Length: 16\r\n\r\nSet-Cookie: body"
+        );
+        assert_eq!(redact_head(response, &|_: &str| false), response.to_vec());
+    }
+}
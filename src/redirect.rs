@@ -0,0 +1,124 @@
+use thiserror::Error;
+
+use crate::{headers, Request, Status};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RedirectError {
+    #[error("redirect target {0:?} isn't a url a browser would read the same way")]
+    Malformed(String),
+    #[error("redirect target has scheme {0}, only http and https are allowed")]
+    Scheme(String),
+    #[error("redirect target goes to {0}, which isn't an allowed host")]
+    Host(String),
+}
+
+/// Where redirects are allowed to go, for targets that came from the client - a `?next=` after logging in, a
+/// `return_to` in a form. Left unchecked, those send people wherever a link someone else crafted says, with your
+/// site's name on the way.
+///
+/// By default only relative targets are allowed, which stay on whatever host the request came to. Anything that
+/// could go elsewhere counts as absolute: `https://...`, but also `//host/path`, and `/\host` and anything else
+/// with a backslash, which browsers read as a slash.
+#[derive(Debug, Clone, Default)]
+pub struct RedirectPolicy {
+    hosts: Vec<String>,
+}
+
+impl RedirectPolicy {
+    pub fn relative_only() -> RedirectPolicy {
+        RedirectPolicy::default()
+    }
+
+    /// Allow absolute http and https targets on `host` as well - an exact hostname, or `*.example.com` for its
+    /// subdomains (but not `example.com` itself).
+    pub fn allow_host(mut self, host: impl Into<String>) -> Self {
+        self.hosts.push(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// `target`, if a redirect can go there.
+    pub fn check<'t>(&self, target: &'t str) -> Result<&'t str, RedirectError> {
+        let host = match target_host(target)? {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return Ok(target),
+        };
+
+        let allowed = self
+            .hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix('*') {
+                Some(suffix) => host.ends_with(suffix) && host.len() > suffix.len(),
+                None => *allowed == host,
+            });
+        match allowed {
+            true => Ok(target),
+            false => Err(RedirectError::Host(host)),
+        }
+    }
+
+    /// `target` if a redirect can go there, `fallback` if not.
+    pub fn or<'t>(&self, target: &'t str, fallback: &'t str) -> &'t str {
+        self.check(target).unwrap_or(fallback)
+    }
+}
+
+// the host an absolute target goes to, None for a relative one
+pub(crate) fn target_host(target: &str) -> Result<Option<&str>, RedirectError> {
+    let malformed = || RedirectError::Malformed(target.to_owned());
+    // browsers drop tabs and line breaks from urls, and take backslashes for slashes, so a target with those in it
+    // isn't going where it looks like it is
+    if target
+        .bytes()
+        .any(|b| b == b'\\' || b.is_ascii_whitespace() || b.is_ascii_control())
+    {
+        return Err(malformed());
+    }
+
+    let rest = match scheme(target) {
+        Some((scheme, rest)) => {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return Err(RedirectError::Scheme(scheme.to_owned()));
+            }
+            rest.strip_prefix("//").ok_or_else(malformed)?
+        }
+        None => match target.strip_prefix("//") {
+            Some(rest) => rest,
+            None => return Ok(None),
+        },
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    // `https://trusted.example@elsewhere.example` goes to elsewhere
+    if authority.contains('@') {
+        return Err(malformed());
+    }
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => authority,
+    };
+
+    match host.is_empty() {
+        true => Err(malformed()),
+        false => Ok(Some(host)),
+    }
+}
+
+// a url's scheme, and what comes after its colon
+fn scheme(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once(':')?;
+    let valid = scheme.bytes().next()?.is_ascii_alphabetic()
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+    valid.then_some((scheme, rest))
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Redirects to `location` as it is - for targets the app chose. For ones from the client, run them through a
+    /// [`RedirectPolicy`] first.
+    pub fn redirect(self, status: impl Into<Status>, location: &str) -> std::io::Result<()> {
+        let location = headers::checked_header("Location", location)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.respond_with_bytes(status, vec![location], &[])
+    }
+}
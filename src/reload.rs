@@ -0,0 +1,82 @@
+use std::{
+    env, io,
+    net::TcpListener,
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::PathBuf,
+    process::{self, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{systemd::LISTEN_FDS_START, ShutdownHandle};
+
+#[derive(Clone)]
+pub struct ReloadHandle {
+    shutdown: ShutdownHandle,
+    requested: Arc<AtomicBool>,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(shutdown: ShutdownHandle) -> ReloadHandle {
+        ReloadHandle {
+            shutdown,
+            requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Starts draining like a normal shutdown, except `run` execs the new binary at the end instead of returning.
+    /// New connections wait in the listener's backlog in the meantime.
+    pub fn reload(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.shutdown.shutdown();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+// exec keeps our pid, so we can fill in LISTEN_PID ourselves and the new binary sees a normal socket activation
+pub(crate) fn reexec(listener: &TcpListener) -> io::Error {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e,
+    };
+
+    // after an upgrade linux reports the replaced binary as "<path> (deleted)" - we want whatever's at <path> now
+    let exe = match exe
+        .to_str()
+        .and_then(|path| path.strip_suffix(" (deleted)"))
+    {
+        Some(path) => PathBuf::from(path),
+        None => exe,
+    };
+
+    let fd = listener.as_raw_fd();
+    let mut command = Command::new(exe);
+    command
+        .args(env::args_os().skip(1))
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_PID", process::id().to_string());
+
+    unsafe {
+        command.pre_exec(move || {
+            // dup2 leaves the copy without CLOEXEC, but doesn't touch the flags if the fd is already in place
+            let result = if fd == LISTEN_FDS_START {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, LISTEN_FDS_START)
+            };
+
+            if result < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    command.exec()
+}
@@ -0,0 +1,508 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, IoSlice, Write},
+};
+
+use tiny_http::StatusCode;
+
+/// A response status - the code plus the reason phrase sent with it.
+/// Non-standard codes (like nginx's 499) and custom reason phrases are fine, handy when mimicking an upstream.
+///
+/// Codes have three digits, 100 to 999, and building a status from anything else panics - it's a bug in the handler,
+/// and sending some other status in its place would hide it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    code: u16,
+    reason: Option<Cow<'static, str>>,
+}
+
+impl Status {
+    pub const fn new(code: u16) -> Status {
+        assert!(
+            code >= 100 && code <= 999,
+            "status codes have three digits, 100 to 999"
+        );
+        Status { code, reason: None }
+    }
+
+    pub fn with_reason(code: u16, reason: impl Into<Cow<'static, str>>) -> Status {
+        Status {
+            reason: Some(reason.into()),
+            ..Status::new(code)
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// The reason phrase that will be sent - the custom one if set, otherwise the standard phrase for the code.
+    pub fn reason(&self) -> &str {
+        match &self.reason {
+            Some(reason) => reason,
+            None => StatusCode(self.code).default_reason_phrase(),
+        }
+    }
+
+    pub(crate) fn custom_reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+impl From<u16> for Status {
+    fn from(code: u16) -> Status {
+        Status::new(code)
+    }
+}
+
+// i32 is the type plain literals like `respond(200, ...)` come as, and the rest are for codes that come from
+// elsewhere in whatever type they're kept in
+macro_rules! status_from_int {
+    ($($int:ty),*) => {
+        $(
+            impl From<$int> for Status {
+                fn from(code: $int) -> Status {
+                    match u16::try_from(code) {
+                        Ok(code) => Status::new(code),
+                        Err(_) => panic!("{} isn't a status code, they have three digits", code),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+status_from_int!(u8, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl From<StatusCode> for Status {
+    fn from(code: StatusCode) -> Status {
+        Status::new(code.0)
+    }
+}
+
+impl From<(u16, &'static str)> for Status {
+    fn from((code, reason): (u16, &'static str)) -> Status {
+        Status::with_reason(code, reason)
+    }
+}
+
+impl From<Status> for StatusCode {
+    fn from(status: Status) -> StatusCode {
+        StatusCode(status.code)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.reason())
+    }
+}
+
+// tiny_http always writes the default reason phrase for a code, so to send a custom one we sit between it and the
+// client and swap the phrase out of the status line as it passes through.
+pub(crate) struct ReasonPhraseWriter<'w, W: Write + ?Sized> {
+    inner: &'w mut W,
+    reason: &'w str,
+    status_line: Option<SmallBuf<64>>,
+}
+
+impl<'w, W: Write + ?Sized> ReasonPhraseWriter<'w, W> {
+    pub(crate) fn new(inner: &'w mut W, reason: &'w str) -> ReasonPhraseWriter<'w, W> {
+        ReasonPhraseWriter {
+            inner,
+            reason,
+            status_line: Some(SmallBuf::new()),
+        }
+    }
+
+    fn write_status_line(&mut self, line: &[u8]) -> io::Result<()> {
+        // "HTTP/1.1 499 Unknown\r\n" - keep everything up to and including the space after the code
+        let code_end = line
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b' ')
+            .map(|(i, _)| i)
+            .nth(1);
+
+        match code_end {
+            Some(code_end) => {
+                self.inner.write_all(&line[..=code_end])?;
+                // a reason phrase can't contain line breaks, or it'd let whoever picked it inject headers
+                for chunk in self.reason.as_bytes().split(|b| *b == b'\r' || *b == b'\n') {
+                    self.inner.write_all(chunk)?;
+                }
+                self.inner.write_all(b"\r\n")
+            }
+            None => self.inner.write_all(line),
+        }
+    }
+}
+
+impl<'w, W: Write + ?Sized> Write for ReasonPhraseWriter<'w, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = match self.status_line.as_mut() {
+            Some(line) => line,
+            None => return self.inner.write(buf),
+        };
+
+        match buf.iter().position(|b| *b == b'\n') {
+            Some(end) => {
+                line.extend_from_slice(&buf[..=end]);
+                let line = self.status_line.take().unwrap();
+                self.write_status_line(line.as_slice())?;
+                Ok(end + 1)
+            }
+            None => {
+                line.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// a byte buffer that lives inline until it outgrows N bytes, and only then moves to the heap - what's written per
+// request is usually small enough to never need an allocation of its own
+pub(crate) struct SmallBuf<const N: usize> {
+    inline: [u8; N],
+    len: usize,
+    spilled: Option<Vec<u8>>,
+}
+
+impl<const N: usize> SmallBuf<N> {
+    pub(crate) fn new() -> SmallBuf<N> {
+        SmallBuf {
+            inline: [0; N],
+            len: 0,
+            spilled: None,
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        if let Some(spilled) = &mut self.spilled {
+            return spilled.extend_from_slice(data);
+        }
+
+        match self.len + data.len() {
+            len if len <= N => {
+                self.inline[self.len..len].copy_from_slice(data);
+                self.len = len;
+            }
+            len => {
+                let mut spilled = Vec::with_capacity(len.max(2 * N));
+                spilled.extend_from_slice(&self.inline[..self.len]);
+                spilled.extend_from_slice(data);
+                self.spilled = Some(spilled);
+            }
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        match &self.spilled {
+            Some(spilled) => spilled,
+            None => &self.inline[..self.len],
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+}
+
+// tiny_http writes a response head a piece at a time - the status line, then every header on its own - and each of
+// those would be a syscall of its own. this holds on to the head until the body starts, and sends the two together
+// in one vectored write, which for small responses is the whole thing. nothing of the head reaches the connection
+// until it's whole, so a handler panicking halfway through one leaves nothing half-sent behind it.
+//
+// the head goes into a buffer the worker reuses from one request to the next. with small responses on (see
+// ServerBuilder::small_responses) a body that fits alongside it is gathered up there too, however many writes it
+// comes in, and goes out in one piece as soon as it's all there.
+pub(crate) struct HeadBatcher<'b, W> {
+    inner: W,
+    head: &'b mut Vec<u8>,
+    // whether we've seen the blank line ending the head
+    head_done: bool,
+    batching: bool,
+    small: bool,
+    // how long the whole response will be, once the head says and it's small enough to gather
+    complete_at: Option<usize>,
+}
+
+// heads bigger than this go out as they are
+const MAX_HEAD: usize = 16 * 1024;
+
+// the most of a response small responses gather
+const SMALL_RESPONSE: usize = 2048;
+
+impl<'b, W: Write> HeadBatcher<'b, W> {
+    // whatever's left in `head` from the last request is thrown away
+    pub(crate) fn new(inner: W, head: &'b mut Vec<u8>, small: bool) -> HeadBatcher<'b, W> {
+        head.clear();
+        HeadBatcher {
+            inner,
+            head,
+            head_done: false,
+            batching: true,
+            small,
+            complete_at: None,
+        }
+    }
+
+    fn write_head(&mut self) -> io::Result<()> {
+        self.batching = false;
+        self.inner.write_all(self.head.as_slice())?;
+        self.head.clear();
+        Ok(())
+    }
+
+    fn finish_head(&mut self) {
+        self.head_done = true;
+        if !self.small {
+            return;
+        }
+
+        // HEAD responses say how long a body they're not sending, so they wait for the flush like they always did
+        self.complete_at = content_length(self.head.as_slice())
+            .and_then(|length| length.checked_add(self.head.len()))
+            .filter(|total| *total <= SMALL_RESPONSE);
+    }
+}
+
+impl<'b, W: Write> Write for HeadBatcher<'b, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.batching {
+            return self.inner.write(buf);
+        }
+
+        if self.head_done {
+            if let Some(complete_at) = self.complete_at {
+                if self.head.len() + buf.len() <= complete_at {
+                    self.head.extend_from_slice(buf);
+                    if self.head.len() == complete_at {
+                        self.write_head()?;
+                    }
+                    return Ok(buf.len());
+                }
+            }
+
+            self.batching = false;
+            write_all_vectored(
+                &mut self.inner,
+                &mut [IoSlice::new(self.head.as_slice()), IoSlice::new(buf)],
+            )?;
+            self.head.clear();
+            return Ok(buf.len());
+        }
+
+        // the terminator might straddle two writes
+        let search_from = self.head.len().saturating_sub(3);
+        self.head.extend_from_slice(buf);
+        match find(&self.head.as_slice()[search_from..], b"\r\n\r\n") {
+            Some(at) if search_from + at + 4 == self.head.len() => self.finish_head(),
+            // head and body came in the same write, which is as batched as it gets
+            Some(_) => self.write_head()?,
+            None if self.head.len() > MAX_HEAD => self.write_head()?,
+            None => {}
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.batching && !self.head.is_empty() {
+            // an interim head (early hints) goes out on its own, and the real one after it still gets batched
+            let interim = self.head_done && self.head.as_slice().get(9) == Some(&b'1');
+            self.write_head()?;
+            if interim {
+                self.batching = true;
+                self.head_done = false;
+                self.complete_at = None;
+            }
+        }
+        self.inner.flush()
+    }
+}
+
+fn content_length(head: &[u8]) -> Option<usize> {
+    head.split(|b| *b == b'\n').find_map(|line| {
+        let (name, value) = std::str::from_utf8(line).ok()?.split_once(':')?;
+        match name.trim().eq_ignore_ascii_case("content-length") {
+            true => value.trim().parse().ok(),
+            false => None,
+        }
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn write_all_vectored(writer: &mut impl Write, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// flushes after every write, see Request::unbuffered
+pub(crate) struct AutoFlush<W> {
+    inner: W,
+}
+
+impl<W: Write> AutoFlush<W> {
+    pub(crate) fn new(inner: W) -> AutoFlush<W> {
+        AutoFlush { inner }
+    }
+}
+
+impl<W: Write> Write for AutoFlush<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.inner.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// for a HEAD answered by a GET handler: heads go through, and the body after the final one doesn't
+pub(crate) struct HeadOnly<W> {
+    inner: W,
+    // the head being written, up to its blank line
+    head: Vec<u8>,
+    done: bool,
+}
+
+impl<W: Write> HeadOnly<W> {
+    pub(crate) fn new(inner: W) -> HeadOnly<W> {
+        HeadOnly {
+            inner,
+            head: Vec::new(),
+            done: false,
+        }
+    }
+}
+
+impl<W: Write> Write for HeadOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(buf.len());
+        }
+
+        // the blank line could have started in the last write
+        let start = self.head.len().saturating_sub(3);
+        self.head.extend_from_slice(buf);
+        let Some(end) = find(&self.head[start..], b"\r\n\r\n").map(|i| start + i + 4) else {
+            self.inner.write_all(buf)?;
+            return Ok(buf.len());
+        };
+
+        let in_buf = end - (self.head.len() - buf.len());
+        self.inner.write_all(&buf[..in_buf])?;
+        // `HTTP/1.1 1xx` - interim responses don't have a body, and the final one comes after
+        let interim = self.head.get(9) == Some(&b'1');
+        self.head.clear();
+        match interim {
+            true => self.write_all(&buf[in_buf..])?,
+            false => self.done = true,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        assert_eq!(Status::from(200).code(), 200);
+        assert_eq!(Status::from(499u16).code(), 499);
+        assert_eq!(Status::from((599, "Custom")).to_string(), "599 Custom");
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrapping_codes_panic() {
+        // would be 200 if it were truncated to a u16
+        let _ = Status::from(65736);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_codes_panic() {
+        let _ = Status::from(-1);
+    }
+
+    #[test]
+    fn other_integer_types() {
+        assert_eq!(Status::from(200u8).code(), 200);
+        assert_eq!(Status::from(404u32).code(), 404);
+        assert_eq!(Status::from(503u64).code(), 503);
+        assert_eq!(Status::from(301usize).code(), 301);
+        assert_eq!(Status::from(204i16).code(), 204);
+        assert_eq!(Status::from(418i64).code(), 418);
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrapping_u64_codes_panic() {
+        let _ = Status::from(65736u64);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_i8_codes_panic() {
+        let _ = Status::from(-56i8);
+    }
+
+    #[test]
+    #[should_panic]
+    fn small_u8_codes_panic() {
+        let _ = Status::from(42u8);
+    }
+
+    #[test]
+    #[should_panic]
+    fn four_digit_codes_panic() {
+        let _ = Status::new(1000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn two_digit_codes_panic() {
+        let _ = Status::from(99);
+    }
+
+    #[test]
+    fn head_only_drops_the_body() {
+        let mut out = Vec::new();
+        let mut writer = HeadOnly::new(&mut out);
+        writer.write_all(b"HTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\nHTTP/1.1 200 OK\r").unwrap();
+        writer.write_all(b"\nContent-Length: 5\r\n\r").unwrap();
+        writer.write_all(b"\nhello").unwrap();
+        writer.write_all(b"more").unwrap();
+
+        assert_eq!(
+            out,
+            b"HTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    blob::{BlobHash, BlobStore},
+    files,
+    headers::header,
+    Request, ShutdownHandle,
+};
+
+/// How long a blob is kept around, and how often it can be downloaded before it's gone. No limits at all by
+/// default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub ttl: Option<Duration>,
+    pub max_downloads: Option<u32>,
+}
+
+impl Policy {
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn max_downloads(mut self, downloads: u32) -> Self {
+        self.max_downloads = Some(downloads);
+        self
+    }
+}
+
+/// Why a blob went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Expired,
+    DownloadsUsedUp,
+    /// By [`Retention::remove`].
+    Removed,
+}
+
+type RemoveHook = Box<dyn Fn(&BlobHash, Reason) + Send + Sync>;
+
+// what's kept for each blob on disk
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    expires: Option<u64>,
+    downloads_left: Option<u32>,
+}
+
+impl Record {
+    fn used_up(&self, now: u64) -> Option<Reason> {
+        if self.expires.is_some_and(|expires| expires <= now) {
+            Some(Reason::Expired)
+        } else if self.downloads_left == Some(0) {
+            Some(Reason::DownloadsUsedUp)
+        } else {
+            None
+        }
+    }
+}
+
+/// Blobs in a [`BlobStore`] that are only around for a while - until a TTL runs out, or they've been downloaded so
+/// many times. Blobs that are [kept](Self::keep) get a record in a directory of their own, and expired ones are
+/// deleted, record and all, when someone asks for them or when they're [swept](Self::sweep), whichever's first.
+///
+/// Blobs are named for what's in them, so two uploads of the same file are the same blob. Keeping one that's kept
+/// already replaces its policy.
+pub struct Retention {
+    store: Arc<BlobStore>,
+    dir: PathBuf,
+    on_remove: Vec<RemoveHook>,
+    // records are read, changed and written back whole
+    lock: Mutex<()>,
+}
+
+impl Retention {
+    /// Keeps its records in `dir`, creating it if it isn't there yet.
+    pub fn new(store: Arc<BlobStore>, dir: impl Into<PathBuf>) -> io::Result<Retention> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Retention {
+            store,
+            dir,
+            on_remove: Vec::new(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Runs for every blob that's removed, whichever way it went - for an audit log, or to clear up whatever else
+    /// referred to it.
+    pub fn on_remove(mut self, hook: impl Fn(&BlobHash, Reason) + Send + Sync + 'static) -> Self {
+        self.on_remove.push(Box::new(hook));
+        self
+    }
+
+    pub fn store(&self) -> &BlobStore {
+        &self.store
+    }
+
+    pub fn keep(&self, hash: &BlobHash, policy: Policy) -> io::Result<()> {
+        let record = Record {
+            expires: policy
+                .ttl
+                .map(|ttl| unix_now().saturating_add(ttl.as_secs())),
+            downloads_left: policy.max_downloads,
+        };
+
+        let _lock = self.lock.lock().unwrap();
+        self.write_record(hash, &record)
+    }
+
+    /// When the blob expires, and how many downloads it has left - `None` for a blob that isn't kept, or has gone.
+    pub fn remaining(&self, hash: &BlobHash) -> Option<(Option<SystemTime>, Option<u32>)> {
+        let record = self.record(hash)?;
+        if record.used_up(unix_now()).is_some() {
+            return None;
+        }
+        let expires = record
+            .expires
+            .map(|expires| UNIX_EPOCH + Duration::from_secs(expires));
+        Some((expires, record.downloads_left))
+    }
+
+    /// Deletes a kept blob before its time. Whether there was one.
+    pub fn remove(&self, hash: &BlobHash) -> io::Result<bool> {
+        let _lock = self.lock.lock().unwrap();
+        self.remove_locked(hash, Reason::Removed)
+    }
+
+    /// Deletes every blob that's expired or used up, returning how many there were.
+    pub fn sweep(&self) -> io::Result<usize> {
+        let now = unix_now();
+        let mut swept = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            // anything that isn't a record is one on its way in
+            let hash = match name.to_str().and_then(parse_hex) {
+                Some(hash) => hash,
+                None => continue,
+            };
+
+            let _lock = self.lock.lock().unwrap();
+            let reason = match self.record(&hash).and_then(|record| record.used_up(now)) {
+                Some(reason) => reason,
+                None => continue,
+            };
+            if self.remove_locked(&hash, reason)? {
+                swept += 1;
+            }
+        }
+
+        Ok(swept)
+    }
+
+    /// Starts a thread that [sweeps](Self::sweep) every `interval`, until `shutdown` is.
+    pub fn sweep_every(self: &Arc<Self>, interval: Duration, shutdown: ShutdownHandle) {
+        let retention = self.clone();
+        thread::spawn(move || {
+            while !shutdown.is_shutdown() {
+                thread::sleep(interval);
+                let _ = retention.sweep();
+            }
+        });
+    }
+
+    /// Serves the kept blob named by the route's `hash` parameter, counting it as a download - 404 for one that
+    /// isn't kept, or has expired or been downloaded as often as it could be.
+    pub fn serve_request(&self, request: Request<'_, '_, '_>) -> io::Result<()> {
+        let hash = request
+            .params
+            .get("hash")
+            .and_then(|hash| hash.parse::<BlobHash>().ok());
+        match hash {
+            Some(hash) => self.serve(request, &hash),
+            None => request.respond_with_bytes(404, vec![], b"not found"),
+        }
+    }
+
+    pub fn serve(&self, request: Request<'_, '_, '_>, hash: &BlobHash) -> io::Result<()> {
+        self.serve_as(request, hash, "application/octet-stream")
+    }
+
+    /// [`serve`](Self::serve), with a `Content-Type`.
+    pub fn serve_as(
+        &self,
+        mut request: Request<'_, '_, '_>,
+        hash: &BlobHash,
+        content_type: &str,
+    ) -> io::Result<()> {
+        // HEADs and resumed downloads don't count - a download's only counted where it starts
+        let counts = request.method() == "GET"
+            && request
+                .header("Range")
+                .filter(|range| !range.trim().starts_with("bytes=0-"))
+                .is_none();
+
+        let record = {
+            let _lock = self.lock.lock().unwrap();
+            let mut record = match self.record(hash) {
+                Some(record) => record,
+                None => return request.respond_with_bytes(404, vec![], b"not found"),
+            };
+
+            if let Some(reason) = record.used_up(unix_now()) {
+                self.remove_locked(hash, reason)?;
+                return request.respond_with_bytes(404, vec![], b"not found");
+            }
+
+            if let Some(left) = record.downloads_left.as_mut().filter(|_| counts) {
+                *left -= 1;
+                self.write_record(hash, &record)?;
+            }
+            record
+        };
+
+        // a cached copy would outlive the blob, so the cache only gets it for as long as it's got
+        let cache_control = match (record.downloads_left, record.expires) {
+            (Some(_), _) => "no-store".to_owned(),
+            (None, Some(expires)) => {
+                format!("private, max-age={}", expires.saturating_sub(unix_now()))
+            }
+            (None, None) => "private, no-cache".to_owned(),
+        };
+        request.add_response_header(header("Cache-Control", cache_control));
+
+        let served = files::serve_file_as(request, &self.store.path(hash), content_type);
+
+        if counts && record.downloads_left == Some(0) {
+            let _lock = self.lock.lock().unwrap();
+            self.remove_locked(hash, Reason::DownloadsUsedUp)?;
+        }
+        served
+    }
+
+    fn record_path(&self, hash: &BlobHash) -> PathBuf {
+        self.dir.join(hash.to_hex())
+    }
+
+    fn record(&self, hash: &BlobHash) -> Option<Record> {
+        let text = fs::read_to_string(self.record_path(hash)).ok()?;
+        let mut record = Record {
+            expires: None,
+            downloads_left: None,
+        };
+
+        for line in text.lines() {
+            match line.split_once(' ')? {
+                ("expires", expires) => record.expires = Some(expires.parse().ok()?),
+                ("downloads", downloads) => record.downloads_left = Some(downloads.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(record)
+    }
+
+    fn write_record(&self, hash: &BlobHash, record: &Record) -> io::Result<()> {
+        let mut text = String::new();
+        if let Some(expires) = record.expires {
+            text.push_str(&format!("expires {}\n", expires));
+        }
+        if let Some(downloads) = record.downloads_left {
+            text.push_str(&format!("downloads {}\n", downloads));
+        }
+
+        // written aside and renamed, so a half-written record is never read
+        let path = self.record_path(hash);
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, text)?;
+        fs::rename(temp, path)
+    }
+
+    // with the lock held, so a blob's only removed once, and its hooks only run once
+    fn remove_locked(&self, hash: &BlobHash, reason: Reason) -> io::Result<bool> {
+        match fs::remove_file(self.record_path(hash)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        self.store.delete(hash)?;
+
+        for hook in &self.on_remove {
+            hook(hash, reason);
+        }
+        Ok(true)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn parse_hex(text: &str) -> Option<BlobHash> {
+    if text.len() != 64 {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (byte, pair) in hash.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(BlobHash(hash))
+}
@@ -0,0 +1,134 @@
+use crate::{
+    redirect::{self, RedirectPolicy},
+    BeakError, BeakResult,
+};
+
+/// Rewrites a request's path before it's routed - stripping an `/api/v1` prefix, mapping legacy URLs onto new ones,
+/// that sort of thing. Handlers only ever see the rewritten path.
+///
+/// Patterns use the same syntax as routes, and any `:param`/`*param` they capture can be used in the replacement:
+/// `Rewrite::pattern("/posts/:id/view", "/p/:id")`. Marking a rewrite as a [redirect](Rewrite::redirect) makes beak
+/// answer with a 301 to the new path instead - unless what it captured would send the client to another host than
+/// the replacement names, like `/old//elsewhere.example` stripped of its `/old`, which gets a 400.
+pub struct Rewrite {
+    kind: Kind,
+    redirect: bool,
+}
+
+enum Kind {
+    StripPrefix(&'static str),
+    Pattern {
+        from: matchit::Router<()>,
+        to: &'static str,
+    },
+}
+
+impl Rewrite {
+    pub fn strip_prefix(prefix: &'static str) -> Rewrite {
+        Rewrite {
+            kind: Kind::StripPrefix(prefix.trim_end_matches('/')),
+            redirect: false,
+        }
+    }
+
+    pub fn pattern(from: &'static str, to: &'static str) -> BeakResult<Rewrite> {
+        let mut router = matchit::Router::new();
+        router
+            .insert(from, ())
+            .map_err(|source| BeakError::Route { path: from, source })?;
+
+        Ok(Rewrite {
+            kind: Kind::Pattern { from: router, to },
+            redirect: false,
+        })
+    }
+
+    /// Send clients a 301 to the rewritten path rather than rewriting internally.
+    pub fn redirect(mut self) -> Rewrite {
+        self.redirect = true;
+        self
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.redirect
+    }
+
+    // relative redirects, and absolute ones to the host the replacement has, if it has one
+    fn policy(&self) -> RedirectPolicy {
+        let host = match &self.kind {
+            Kind::Pattern { to, .. } => redirect::target_host(to).ok().flatten(),
+            Kind::StripPrefix(_) => None,
+        };
+
+        match host {
+            Some(host) => RedirectPolicy::relative_only().allow_host(host),
+            None => RedirectPolicy::relative_only(),
+        }
+    }
+
+    /// The rewritten path, if this rewrite applies to `path`.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        match &self.kind {
+            Kind::StripPrefix(prefix) => {
+                let rest = path.strip_prefix(prefix)?;
+                match rest.as_bytes().first() {
+                    None => Some("/".to_owned()),
+                    Some(b'/') => Some(rest.to_owned()),
+                    // "/apix" doesn't start with the "/api" prefix
+                    Some(_) => None,
+                }
+            }
+            Kind::Pattern { from, to } => {
+                let matched = from.at(path).ok()?;
+                let mut rewritten = String::with_capacity(to.len());
+
+                for (i, segment) in to.split('/').enumerate() {
+                    if i > 0 {
+                        rewritten.push('/');
+                    }
+
+                    let param = segment
+                        .strip_prefix(':')
+                        .or_else(|| segment.strip_prefix('*'));
+                    match param.and_then(|name| matched.params.get(name)) {
+                        // the template already has the slash a catch-all might capture
+                        Some(value) => rewritten.push_str(value.trim_start_matches('/')),
+                        None => rewritten.push_str(segment),
+                    }
+                }
+
+                Some(rewritten)
+            }
+        }
+    }
+}
+
+pub(crate) struct Rewritten {
+    pub(crate) url: String,
+    pub(crate) redirect: bool,
+    // whether a redirect stays where the rewrite meant it to
+    pub(crate) allowed: bool,
+}
+
+/// Runs the first rewrite that applies to `url`'s path, keeping the query string as it was.
+pub(crate) fn rewrite(rewrites: &[Rewrite], url: &str) -> Option<Rewritten> {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+
+    rewrites.iter().find_map(|rewrite| {
+        let mut url = rewrite.apply(path)?;
+        if let Some(query) = query {
+            url.push('?');
+            url.push_str(query);
+        }
+
+        let allowed = !rewrite.redirect || rewrite.policy().check(&url).is_ok();
+        Some(Rewritten {
+            url,
+            redirect: rewrite.redirect,
+            allowed,
+        })
+    })
+}
@@ -0,0 +1,361 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use matchit::Match;
+use thiserror::Error;
+
+use crate::{
+    headers::header,
+    intern::{intern_static, Symbol},
+    BeakError, BeakResult, Handler, Request,
+};
+
+pub type Routes<C> = &'static [&'static (dyn Handler<C> + Send + Sync)];
+pub(crate) type HandlerRef<C> = &'static (dyn Handler<C> + Send + Sync);
+
+// several handlers can share a path, as long as they answer different methods - each kept with its path interned,
+// so what matched can be compared and hashed without looking at the string
+type Tier<C> = matchit::Router<Vec<(HandlerRef<C>, Symbol)>>;
+
+// one matchit router per route priority, highest first - overlapping routes that matchit would reject as conflicts
+// can live side by side as long as they're on different tiers
+struct Table<C: 'static> {
+    tiers: Vec<(i32, Tier<C>)>,
+    // every handler, highest priority first, for CONNECTs - whose authority isn't a path any tier could match
+    handlers: Vec<HandlerRef<C>>,
+}
+
+/// Why a request couldn't be routed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    #[error("no route matches")]
+    NotFound,
+    /// Something lives at the path, just not for this method - `allowed` is what belongs in the `Allow` header.
+    #[error("method not allowed, expected one of {}", allowed.join(", "))]
+    MethodNotAllowed { allowed: Vec<&'static str> },
+}
+
+/// One registered route, as [`Router::routes`] lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The virtual host the route belongs to, `None` for the default routes.
+    pub host: Option<String>,
+    pub path: &'static str,
+    /// Empty when the handler answers every method.
+    pub methods: &'static [&'static str],
+    pub needs_multipart: bool,
+    pub priority: i32,
+    pub handler: &'static str,
+}
+
+/// Every route table the server knows about: the default one, plus one per virtual host.
+///
+/// Hosts are either exact (`example.com`) or wildcards covering every subdomain (`*.example.com`, which doesn't
+/// match `example.com` itself). Requests whose `Host` matches nothing use the default routes.
+pub struct Router<C: 'static> {
+    default: Table<C>,
+    exact_hosts: HashMap<String, Table<C>>,
+    // kept longest suffix first, so the most specific wildcard wins
+    wildcard_hosts: Vec<(String, Table<C>)>,
+    // matchit can't list what's in a router, so we keep track ourselves
+    listing: Vec<RouteInfo>,
+}
+
+impl<C: Send + Sync + 'static> Router<C> {
+    pub fn new(routes: Routes<C>) -> BeakResult<Router<C>> {
+        Ok(Router {
+            default: table(routes)?,
+            exact_hosts: HashMap::new(),
+            wildcard_hosts: Vec::new(),
+            listing: routes
+                .iter()
+                .map(|route| route_info(None, *route))
+                .collect(),
+        })
+    }
+
+    pub fn add_host(&mut self, host: &str, routes: Routes<C>) -> BeakResult<()> {
+        let host = host.to_ascii_lowercase();
+        let table = table(routes)?;
+
+        self.listing.extend(
+            routes
+                .iter()
+                .map(|route| route_info(Some(host.clone()), *route)),
+        );
+
+        match host.strip_prefix('*') {
+            Some(suffix) => {
+                self.wildcard_hosts.push((suffix.to_owned(), table));
+                self.wildcard_hosts
+                    .sort_by_key(|(suffix, _)| Reverse(suffix.len()));
+            }
+            None => {
+                self.exact_hosts.insert(host, table);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn at<'r, 'p>(
+        &'r self,
+        host: Option<&str>,
+        method: &str,
+        path: &'p str,
+    ) -> Result<Match<'r, 'p, &'r HandlerRef<C>>, RouteError> {
+        self.at_interned(host, method, path)
+            .map(|(matched, _)| matched)
+    }
+
+    // like at, with the matched route's path as a symbol. a HEAD goes to a handler that answers it if there's one on
+    // the path, and to one that answers GET otherwise - the server keeps the body from going out
+    pub(crate) fn at_interned<'r, 'p>(
+        &'r self,
+        host: Option<&str>,
+        method: &str,
+        path: &'p str,
+    ) -> Result<(Match<'r, 'p, &'r HandlerRef<C>>, Symbol), RouteError> {
+        let mut allowed: Option<Vec<&'static str>> = None;
+
+        // a path that matches at a higher priority but not for this method falls through to lower ones
+        for (_, tier) in &self.table_for(host).tiers {
+            let matched = match tier.at(path) {
+                Ok(matched) => matched,
+                Err(_) => continue,
+            };
+
+            let find = |method: &str| {
+                matched
+                    .value
+                    .iter()
+                    .find(|(handler, _)| answers(*handler, method))
+            };
+            let handler = find(method).or_else(|| match method.eq_ignore_ascii_case("HEAD") {
+                true => find("GET"),
+                false => None,
+            });
+
+            match handler {
+                Some((handler, route)) => {
+                    return Ok((
+                        Match {
+                            value: handler,
+                            params: matched.params,
+                        },
+                        *route,
+                    ))
+                }
+                None => allowed.get_or_insert_with(Vec::new).extend(
+                    matched
+                        .value
+                        .iter()
+                        .flat_map(|(handler, _)| handler.methods().iter().copied()),
+                ),
+            }
+        }
+
+        Err(match allowed {
+            Some(mut allowed) => {
+                if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+                    allowed.push("HEAD");
+                }
+                RouteError::MethodNotAllowed { allowed }
+            }
+            None => RouteError::NotFound,
+        })
+    }
+
+    // the handler a CONNECT for `authority` gets tunneled to, if any takes it
+    pub(crate) fn tunnel_for(&self, host: Option<&str>, authority: &str) -> Option<HandlerRef<C>> {
+        self.table_for(host)
+            .handlers
+            .iter()
+            .find(|handler| handler.tunnels(authority))
+            .copied()
+    }
+
+    /// Adds a route to the default table, next to the ones it was built with.
+    pub(crate) fn insert(&mut self, handler: HandlerRef<C>) -> BeakResult<()> {
+        insert(&mut self.default, handler)?;
+        self.listing.push(route_info(None, handler));
+        Ok(())
+    }
+
+    /// Every route, default ones first and then each virtual host's in the order they were added.
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.listing
+    }
+
+    fn table_for(&self, host: Option<&str>) -> &Table<C> {
+        let host = match host.map(strip_port) {
+            Some(host) if !self.exact_hosts.is_empty() || !self.wildcard_hosts.is_empty() => {
+                host.to_ascii_lowercase()
+            }
+            _ => return &self.default,
+        };
+
+        if let Some(table) = self.exact_hosts.get(&host) {
+            return table;
+        }
+
+        self.wildcard_hosts
+            .iter()
+            .find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+            .map_or(&self.default, |(_, table)| table)
+    }
+}
+
+// whether `handler` takes requests with `method`, not counting HEAD going to GET
+pub(crate) fn answers<C: Send + Sync>(handler: HandlerRef<C>, method: &str) -> bool {
+    let methods = handler.methods();
+    methods.is_empty() || methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+}
+
+fn table<C: Send + Sync + 'static>(routes: Routes<C>) -> BeakResult<Table<C>> {
+    let mut table = Table {
+        tiers: Vec::new(),
+        handlers: Vec::new(),
+    };
+    for route in routes {
+        insert(&mut table, *route)?;
+    }
+
+    Ok(table)
+}
+
+fn insert<C: Send + Sync + 'static>(
+    table: &mut Table<C>,
+    handler: HandlerRef<C>,
+) -> BeakResult<()> {
+    let priority = handler.priority();
+    let i = match table.tiers.iter().position(|(p, _)| *p <= priority) {
+        Some(i) if table.tiers[i].0 == priority => i,
+        position => {
+            let i = position.unwrap_or(table.tiers.len());
+            table.tiers.insert(i, (priority, Tier::new()));
+            i
+        }
+    };
+    let tier = &mut table.tiers[i].1;
+    let after = table
+        .handlers
+        .iter()
+        .position(|other| other.priority() < priority)
+        .unwrap_or(table.handlers.len());
+    table.handlers.insert(after, handler);
+
+    let path = handler.path();
+
+    // matchit hands back whatever route a path matches, so make sure it's this exact pattern before sharing it
+    if let Ok(existing) = tier.at_mut(path) {
+        if existing
+            .value
+            .first()
+            .is_some_and(|(other, _)| other.path() == path)
+        {
+            existing.value.push((handler, intern_static(path)));
+            return Ok(());
+        }
+    }
+
+    tier.insert(path, vec![(handler, intern_static(path))])
+        .map_err(|source| BeakError::Route { path, source })
+}
+
+fn route_info<C: Send + Sync>(host: Option<String>, handler: HandlerRef<C>) -> RouteInfo {
+    RouteInfo {
+        host,
+        path: handler.path(),
+        methods: handler.methods(),
+        needs_multipart: handler.needs_multipart(),
+        priority: handler.priority(),
+        handler: handler.name(),
+    }
+}
+
+/// Renders the route table as plain text, for [`ServerBuilder::route_table`](crate::ServerBuilder::route_table).
+pub(crate) struct RouteTable {
+    path: &'static str,
+    text: String,
+}
+
+impl RouteTable {
+    pub(crate) fn new(path: &'static str, routes: &[RouteInfo]) -> RouteTable {
+        let rows: Vec<[String; 5]> = routes
+            .iter()
+            .map(|route| {
+                [
+                    match route.methods {
+                        [] => "*".to_owned(),
+                        methods => methods.join(","),
+                    },
+                    route.path.to_owned(),
+                    route.host.clone().unwrap_or_else(|| "-".to_owned()),
+                    if route.needs_multipart { "yes" } else { "no" }.to_owned(),
+                    route.handler.to_owned(),
+                ]
+            })
+            .collect();
+
+        let header = ["METHODS", "PATH", "HOST", "MULTIPART", "HANDLER"].map(str::to_owned);
+        let mut widths = [0; 5];
+        for row in rows.iter().chain([&header]) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut text = String::new();
+        for row in [&header].into_iter().chain(&rows) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            text.push_str(line.join("  ").trim_end());
+            text.push('\n');
+        }
+
+        RouteTable { path, text }
+    }
+
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<C: Send + Sync> Handler<C> for RouteTable {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        request.respond_with_bytes(
+            200,
+            vec![header("Content-Type", "text/plain; charset=utf-8")],
+            self.text.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET"]
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    // [::1]:8000 style hosts have colons of their own
+    if let Some(end) = host.strip_prefix('[').and_then(|_| host.find(']')) {
+        return &host[..=end];
+    }
+
+    host.rsplit_once(':').map_or(host, |(host, _)| host)
+}
@@ -0,0 +1,583 @@
+use std::{
+    io::{self, Read},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client::{Client, ClientError, ClientResponse},
+    headers,
+    tus::{UploadInfo, UploadStore},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// S3's smallest part, for every part but the last
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// An [`UploadStore`] in a bucket on S3, or anything that speaks its API - MinIO, Ceph, SeaweedFS, Garage.
+///
+/// Each upload is an S3 multipart upload, finished into the object at [`object_key`](Self::object_key) once the last
+/// byte is in. Parts have to be at least 5MiB, so whatever's arrived since the last full one is kept in an object of
+/// its own until there's enough for another - a client that's cut off halfway through a part can still resume from
+/// where it got to. Past that part, nothing's held in memory: a `PATCH` goes to S3 as it comes in. Alongside each
+/// upload is a `<key>.info` object, with what [`UploadInfo`] says and the parts so far.
+///
+/// Requests go through beak's [`Client`], which is plain http only - that's fine for a MinIO next to the server, but
+/// AWS itself wants https, so go through a proxy on the same machine that makes the TLS connection. Objects are
+/// addressed path-style, `<endpoint>/<bucket>/<key>`.
+pub struct S3Store {
+    client: Client,
+    endpoint: String,
+    // the endpoint without its scheme, as it goes in the Host header
+    host: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    part_size: usize,
+}
+
+impl S3Store {
+    /// `endpoint` is like `http://minio:9000`.
+    pub fn new(
+        endpoint: impl Into<String>,
+        bucket: impl Into<String>,
+        access_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> S3Store {
+        let endpoint = endpoint.into().trim_end_matches('/').to_owned();
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint.as_str(), |(_, host)| host)
+            .to_owned();
+
+        S3Store {
+            client: Client::new(),
+            endpoint,
+            host,
+            bucket: bucket.into(),
+            prefix: String::new(),
+            region: "us-east-1".to_owned(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into(),
+            part_size: 0,
+        }
+        .part_size(8 * 1024 * 1024)
+    }
+
+    /// What requests are signed for, `us-east-1` by default - which is also what MinIO expects unless it's been
+    /// told otherwise.
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.region = region.into();
+        self
+    }
+
+    /// Put every object under `prefix`, like `uploads/`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// In bytes, 8MiB by default, and never under S3's minimum of 5MiB. An upload can have 10000 parts, so this is
+    /// also what decides how big one can get. Uploads keep the part size they were created with.
+    pub fn part_size(mut self, bytes: usize) -> Self {
+        self.part_size = bytes.max(MIN_PART_SIZE);
+        // the part held back between requests is read back in whole
+        self.client = Client::new().max_response_size(self.part_size + 64 * 1024);
+        self
+    }
+
+    /// Where an upload ends up once it's complete.
+    pub fn object_key(&self, id: &str) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+
+    fn info_key(&self, id: &str) -> String {
+        format!("{}{}.info", self.prefix, id)
+    }
+
+    // the bytes past the last full part, for a number of parts - never rewritten with different contents, so the
+    // info object can't be left pointing at the wrong ones
+    fn tail_key(&self, id: &str, parts: usize) -> String {
+        format!("{}{}.tail.{}", self.prefix, id, parts)
+    }
+
+    fn state(&self, id: &str) -> io::Result<Option<State>> {
+        let response = self.send("GET", &self.info_key(id), &[], Vec::new())?;
+        match response.status {
+            404 => Ok(None),
+            _ => State::parse(&checked(response)?.text()).map(Some),
+        }
+    }
+
+    fn save(&self, id: &str, state: &State) -> io::Result<()> {
+        checked(self.send(
+            "PUT",
+            &self.info_key(id),
+            &[],
+            state.to_string().into_bytes(),
+        )?)?;
+        Ok(())
+    }
+
+    fn tail(&self, id: &str, state: &State) -> io::Result<Vec<u8>> {
+        if state.tail == 0 {
+            return Ok(Vec::new());
+        }
+
+        let response = self.send(
+            "GET",
+            &self.tail_key(id, state.etags.len()),
+            &[],
+            Vec::new(),
+        )?;
+        let mut tail = checked(response)?.body;
+        if tail.len() < state.tail {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "upload's held back part is shorter than its info says",
+            ));
+        }
+        // anything past what the info says came from a request that didn't get as far as saving it
+        tail.truncate(state.tail);
+        Ok(tail)
+    }
+
+    fn upload_part(&self, id: &str, state: &mut State, data: Vec<u8>) -> io::Result<()> {
+        let number = (state.etags.len() + 1).to_string();
+        let query = [
+            ("partNumber", number.as_str()),
+            ("uploadId", state.upload_id.as_str()),
+        ];
+        let response = checked(self.send("PUT", &self.object_key(id), &query, data)?)?;
+
+        let etag = response
+            .header("ETag")
+            .ok_or_else(|| io::Error::other("s3 didn't say what the uploaded part's etag is"))?;
+        state.etags.push(etag.to_owned());
+        Ok(())
+    }
+
+    fn complete(&self, id: &str, state: &mut State, last: Vec<u8>) -> io::Result<()> {
+        // the last part can be as small as it likes, but there has to be one
+        if !last.is_empty() || state.etags.is_empty() {
+            self.upload_part(id, state, last)?;
+        }
+
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in state.etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                escape(etag)
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+
+        let query = [("uploadId", state.upload_id.as_str())];
+        let response =
+            checked(self.send("POST", &self.object_key(id), &query, body.into_bytes())?)?;
+        // errors after a 200 come in the body, since s3 starts answering before it's done
+        if response.text().contains("<Error>") {
+            return Err(s3_error(&response));
+        }
+
+        state.upload_id.clear();
+        state.tail = 0;
+        self.save(id, state)
+    }
+
+    // once the info's stopped pointing at it, so failing to is only a leftover object
+    fn delete_tail(&self, id: &str, parts: usize) {
+        let _ = self.send("DELETE", &self.tail_key(id, parts), &[], Vec::new());
+    }
+
+    // the ids of every upload under the prefix
+    fn ids(&self) -> io::Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", self.prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let listing = checked(self.send("GET", "", &query, Vec::new())?)?.text();
+
+            ids.extend(elements(&listing, "Key").filter_map(|key| {
+                let key = unescape(key);
+                let id = key.strip_prefix(&self.prefix)?.strip_suffix(".info")?;
+                Some(id.to_owned())
+            }));
+
+            continuation = match elements(&listing, "IsTruncated").next() == Some("true") {
+                true => elements(&listing, "NextContinuationToken")
+                    .next()
+                    .map(unescape),
+                false => None,
+            };
+            if continuation.is_none() {
+                return Ok(ids);
+            }
+        }
+    }
+
+    // a request signed with aws signature version 4, to `key` in the bucket - or the bucket itself, for an empty key
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> io::Result<ClientResponse> {
+        let path = match key {
+            "" => format!("/{}", encode(&self.bucket, false)),
+            key => format!("/{}/{}", encode(&self.bucket, false), encode(key, false)),
+        };
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (encode(name, true), encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let (date, time) = timestamp(SystemTime::now());
+        let payload = hex(&Sha256::digest(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload, time, signed_headers, payload
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            hex(&Sha256::digest(canonical.as_bytes()))
+        );
+        let mut key = hmac(
+            format!("AWS4{}", self.secret_key).as_bytes(),
+            date.as_bytes(),
+        );
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            signed_headers,
+            hex(&hmac(&key, to_sign.as_bytes()))
+        );
+
+        let url = match query.as_str() {
+            "" => format!("{}{}", self.endpoint, path),
+            query => format!("{}{}?{}", self.endpoint, path, query),
+        };
+        self.client
+            .request(method, &url)
+            .header("x-amz-content-sha256", &payload)
+            .header("x-amz-date", &time)
+            .header("Authorization", authorization)
+            .body(body)
+            .send()
+            .map_err(|e| match e {
+                ClientError::Io(e) => e,
+                e => io::Error::other(e),
+            })
+    }
+}
+
+impl UploadStore for S3Store {
+    fn create(&self, id: &str, length: u64, metadata: Option<&str>) -> io::Result<()> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let mut state = State {
+            length,
+            created,
+            part_size: self.part_size,
+            upload_id: String::new(),
+            tail: 0,
+            etags: Vec::new(),
+            metadata: metadata.unwrap_or_default().to_owned(),
+        };
+
+        // already complete, and there's no need for parts to get there
+        if length == 0 {
+            checked(self.send("PUT", &self.object_key(id), &[], Vec::new())?)?;
+            return self.save(id, &state);
+        }
+
+        let response =
+            checked(self.send("POST", &self.object_key(id), &[("uploads", "")], Vec::new())?)?;
+        state.upload_id = elements(&response.text(), "UploadId")
+            .next()
+            .map(unescape)
+            .ok_or_else(|| io::Error::other("s3 didn't say what the multipart upload's id is"))?;
+        self.save(id, &state)
+    }
+
+    fn info(&self, id: &str) -> io::Result<Option<UploadInfo>> {
+        Ok(self.state(id)?.map(|state| UploadInfo {
+            offset: state.offset(state.tail),
+            length: state.length,
+            metadata: Some(state.metadata).filter(|metadata| !metadata.is_empty()),
+            created: UNIX_EPOCH + Duration::from_secs(state.created),
+        }))
+    }
+
+    fn append(&self, id: &str, data: &mut dyn Read) -> io::Result<()> {
+        let mut state = self
+            .state(id)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such upload"))?;
+        if state.is_complete() {
+            return Ok(());
+        }
+
+        // where what was held back is, which won't be once there's another part
+        let held = (state.tail > 0).then_some(state.etags.len());
+        let mut pending = self.tail(id, &state)?;
+        let read = loop {
+            let room = (state.part_size - pending.len()) as u64;
+            let left = state.length - state.offset(pending.len());
+            let read = (&mut *data)
+                .take(room.min(left))
+                .read_to_end(&mut pending)
+                .map(|_| ());
+
+            if state.offset(pending.len()) >= state.length {
+                self.complete(id, &mut state, pending)?;
+                if let Some(parts) = held {
+                    self.delete_tail(id, parts);
+                }
+                return read;
+            }
+            if read.is_err() || pending.len() < state.part_size {
+                break read;
+            }
+
+            self.upload_part(id, &mut state, std::mem::take(&mut pending))?;
+        };
+
+        // whatever didn't make up a whole part waits for the next request, failed read or not
+        state.tail = pending.len();
+        if !pending.is_empty() {
+            let key = self.tail_key(id, state.etags.len());
+            checked(self.send("PUT", &key, &[], pending)?)?;
+        }
+        self.save(id, &state)?;
+        if let Some(parts) = held.filter(|&parts| parts != state.etags.len()) {
+            self.delete_tail(id, parts);
+        }
+
+        read
+    }
+
+    fn delete(&self, id: &str) -> io::Result<()> {
+        let state = match self.state(id)? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        if !state.is_complete() {
+            let query = [("uploadId", state.upload_id.as_str())];
+            let response = self.send("DELETE", &self.object_key(id), &query, Vec::new())?;
+            // already gone is as good as aborted
+            if response.status != 404 {
+                checked(response)?;
+            }
+            self.delete_tail(id, state.etags.len());
+        }
+
+        checked(self.send("DELETE", &self.object_key(id), &[], Vec::new())?)?;
+        checked(self.send("DELETE", &self.info_key(id), &[], Vec::new())?)?;
+        Ok(())
+    }
+
+    fn purge(&self, created_before: SystemTime) -> io::Result<usize> {
+        let mut purged = 0;
+
+        for id in self.ids()? {
+            if let Some(info) = self.info(&id)? {
+                if !info.is_complete() && info.created < created_before {
+                    self.delete(&id)?;
+                    purged += 1;
+                }
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+// what's in an upload's info object: a line each for its length, when it was created, the part size, the multipart
+// upload's id (empty once it's complete) and how much is held back, then the parts' etags on one line, and the
+// metadata on the last
+struct State {
+    length: u64,
+    created: u64,
+    part_size: usize,
+    upload_id: String,
+    tail: usize,
+    etags: Vec<String>,
+    metadata: String,
+}
+
+impl State {
+    fn parse(info: &str) -> io::Result<State> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt upload info");
+        let mut lines = info.splitn(7, '\n');
+        let mut number = || {
+            lines
+                .next()
+                .and_then(|l| l.parse::<u64>().ok())
+                .ok_or_else(invalid)
+        };
+        let (length, created, part_size) = (number()?, number()?, number()?);
+
+        let upload_id = lines.next().ok_or_else(invalid)?.to_owned();
+        let tail = lines
+            .next()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(invalid)?;
+        let etags = lines
+            .next()
+            .ok_or_else(invalid)?
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect();
+        let metadata = lines.next().unwrap_or_default().to_owned();
+
+        Ok(State {
+            length,
+            created,
+            part_size: part_size as usize,
+            upload_id,
+            tail,
+            etags,
+            metadata,
+        })
+    }
+
+    fn is_complete(&self) -> bool {
+        self.upload_id.is_empty()
+    }
+
+    // how much has arrived, with `held` bytes past the full parts
+    fn offset(&self, held: usize) -> u64 {
+        if self.is_complete() {
+            return self.length;
+        }
+        (self.etags.len() * self.part_size) as u64 + held as u64
+    }
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            self.length,
+            self.created,
+            self.part_size,
+            self.upload_id,
+            self.tail,
+            self.etags.join(" "),
+            self.metadata
+        )
+    }
+}
+
+fn checked(response: ClientResponse) -> io::Result<ClientResponse> {
+    match response.is_success() {
+        true => Ok(response),
+        false => Err(s3_error(&response)),
+    }
+}
+
+fn s3_error(response: &ClientResponse) -> io::Error {
+    let text = response.text();
+    let code = elements(&text, "Code").next().unwrap_or("no error code");
+    io::Error::other(format!("s3 answered with {}: {}", response.status, code))
+}
+
+// the contents of every `<tag>` in `xml`, which is all there is to s3's answers worth reading
+fn elements<'x>(xml: &'x str, tag: &str) -> impl Iterator<Item = &'x str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let (_, after) = rest.split_once(open.as_str())?;
+        let (contents, after) = after.split_once(close.as_str())?;
+        rest = after;
+        Some(contents)
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// the way signature version 4 wants it: everything but unreserved characters percent-encoded, and slashes too
+// outside of paths
+fn encode(text: &str, slashes: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !slashes => encoded.push('/'),
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// the date and the time, as `20260314` and `20260314T092653Z`
+fn timestamp(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = headers::civil_from_days(days as i64);
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    (date, time)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,2294 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    io::{self, BufWriter, Cursor, Read, Write},
+    mem,
+    net::{IpAddr, SocketAddr, TcpListener},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant, SystemTime},
+};
+
+use std::path::PathBuf;
+
+use multipart::server::Multipart;
+use tiny_http::{Header, Request as TinyHttpRequest, Response};
+
+use crate::{
+    access_log::AccessLog,
+    metrics::{MetricsEndpoint, MetricsSource, RouteTimings},
+    profiler::Profiler,
+    accounting::{Accounting, CountingReader, CountingWriter},
+    admin::{self, Admin, AdminAddr, LogLevelHook},
+    body::{self, Body, LengthCheck, Progress},
+    config::{ConfigLoader, ReloadHook, Reloader},
+    connection::Connections,
+    dispatch::Dispatch,
+    find_header, headers,
+    intern::{intern_static, Symbol},
+    maintenance::MaintenanceHandle,
+    middleware::MiddlewareList,
+    response::{HeadBatcher, HeadOnly},
+    rewrite::{self, Rewrite},
+    router::{self, HandlerRef, RouteTable},
+    tcp::{self, TcpOptions},
+    transport::Transport,
+    upload::{self, ReceiveError},
+    urls::{Origins, Urls},
+    well_known::{Fixed, FixedEndpoint, WellKnown},
+    Arena, BeakConfig, ClientConnection, BeakError, BeakResult, ConfigError, ConfigReload, ErrorContext, Extensions, Middleware, MultipartEntry, Next, QueueClass,
+    Request, RouteError, Router, Routes,
+};
+
+type ContextHook<C> = Box<dyn Fn(&C) + Send + Sync>;
+type PanicHook = Box<dyn Fn(&WorkerPanic) + Send + Sync>;
+type RequestHook = Box<dyn Fn(&CompletedRequest) + Send + Sync>;
+type ProgressHook = Box<dyn Fn(&UploadProgress<'_>) + Send + Sync>;
+type ConnectionHook = Box<dyn Fn(&ClientConnection) + Send + Sync>;
+type DrainHook = Box<dyn Fn(&DrainReport) + Send + Sync>;
+type ErrorHook = Box<dyn Fn(&BeakError) + Send + Sync>;
+type ReportHook = Box<dyn Fn(&BeakError, &RequestMeta) + Send + Sync>;
+
+/// What we know about a handler that panicked, passed to `on_worker_panic` hooks.
+#[derive(Debug)]
+pub struct WorkerPanic {
+    pub worker: usize,
+    pub url: String,
+    pub message: String,
+}
+
+/// A request that's been answered, passed to `on_request_complete` hooks.
+#[derive(Debug)]
+pub struct CompletedRequest {
+    pub method: String,
+    /// As the client sent it, before any rewrites.
+    pub url: String,
+    /// The path pattern of the route that matched, if one did.
+    pub route: Option<&'static str>,
+    pub status: Option<u16>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub duration: Duration,
+    /// When a worker picked the request up.
+    pub received: SystemTime,
+    pub remote_addr: Option<SocketAddr>,
+    /// Like `1.1`.
+    pub http_version: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// The request an error happened in, passed to `on_error` hooks along with the error.
+#[derive(Debug, Clone)]
+pub struct RequestMeta {
+    pub method: String,
+    /// As the client sent it, before any rewrites.
+    pub url: String,
+    /// The path pattern of the route that matched, if one did.
+    pub route: Option<&'static str>,
+    /// The status that was sent, if one was - the 500 beak sends for a handler that failed before responding, and
+    /// usually nothing for one that panicked.
+    pub status: Option<u16>,
+    /// From a worker picking the request up to the error.
+    pub duration: Duration,
+    /// The request's `X-Request-Id`, when it came with one.
+    pub request_id: Option<String>,
+}
+
+/// How much of a request body has come in, passed to `on_upload_progress` hooks.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadProgress<'r> {
+    pub method: &'r str,
+    /// As the client sent it, before any rewrites - the place for an upload id to tie this to a progress bar.
+    pub url: &'r str,
+    /// In bytes as they came over the wire, before any decompression.
+    pub received: u64,
+    /// The `Content-Length`, when there is one.
+    pub total: Option<u64>,
+    /// Whether the body has been read to the end.
+    pub done: bool,
+}
+
+/// A request a worker is busy with, as [`ShutdownHandle::in_flight`] lists it.
+#[derive(Debug, Clone)]
+pub struct InFlightRequest {
+    pub method: String,
+    /// As the client sent it, before any rewrites.
+    pub url: String,
+    /// The path pattern of the route that matched - `None` until routing is done, or if nothing matched.
+    pub route: Option<&'static str>,
+    pub started: Instant,
+}
+
+/// How shutting down went, passed to `on_drain` hooks once the workers are done or the drain timeout ran out.
+#[derive(Debug, Clone)]
+pub struct DrainReport {
+    /// From shutdown starting to the drain being over, one way or the other.
+    pub duration: Duration,
+    /// What the workers were busy with when shutdown started.
+    pub in_flight: Vec<InFlightRequest>,
+    /// What they were still busy with when the drain timeout ran out - empty if every worker finished in time.
+    pub abandoned: Vec<InFlightRequest>,
+}
+
+impl DrainReport {
+    /// Whether the drain timeout cut anything off.
+    pub fn timed_out(&self) -> bool {
+        !self.abandoned.is_empty()
+    }
+
+    /// How many of the requests abandoned were on each route, busiest first - the ones to look at when tuning the
+    /// drain timeout.
+    pub fn abandoned_by_route(&self) -> Vec<(Option<&'static str>, usize)> {
+        by_route(&self.abandoned)
+    }
+}
+
+fn by_route(requests: &[InFlightRequest]) -> Vec<(Option<&'static str>, usize)> {
+    let mut counts: HashMap<Option<&'static str>, usize> = HashMap::new();
+    for request in requests {
+        *counts.entry(request.route).or_default() += 1;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+// everything the workers share, built once in `run`
+struct Shared<C: 'static> {
+    router: Router<C>,
+    rewrites: Vec<Rewrite>,
+    middleware: MiddlewareList<C>,
+    shadows: HashMap<Symbol, Vec<HandlerRef<C>>>,
+    shadow_body_limit: usize,
+    upload_limits: upload::ReceiveLimits,
+    upload_dir: PathBuf,
+    output_buffer: usize,
+    small_responses: bool,
+    ask_to_close: bool,
+    // moved here from the builder's hooks, since they run deep inside request handling
+    progress_hooks: Vec<ProgressHook>,
+    progress_interval: u64,
+    request_timeout: Option<Duration>,
+    connections: Connections,
+    maintenance: MaintenanceHandle,
+    method_override: bool,
+    profiler: Option<Profiler>,
+    route_timings: Option<RouteTimings>,
+    urls: &'static Urls,
+    shutdown: ShutdownHandle,
+    #[cfg(feature = "decompression")]
+    inflate_limit: usize,
+}
+
+struct Hooks<C> {
+    on_start: Vec<ContextHook<C>>,
+    on_shutdown: Vec<ContextHook<C>>,
+    on_worker_panic: Vec<PanicHook>,
+    on_request_complete: Vec<RequestHook>,
+    on_upload_progress: Vec<ProgressHook>,
+    on_drain: Vec<DrainHook>,
+    on_handler_error: Vec<ErrorHook>,
+    on_error: Vec<ReportHook>,
+    on_config_reload: Vec<ReloadHook>,
+    on_connection_open: Vec<ConnectionHook>,
+    on_connection_close: Vec<ConnectionHook>,
+}
+
+/// Stops a running server: workers finish the request they're on, `on_shutdown` hooks run, and `run` returns.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownState>,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    // each server, with how many workers are blocked on it
+    servers: Mutex<Vec<(Arc<dyn Transport>, usize)>>,
+    requested_signal: Condvar,
+    // what each worker thread is serving right now
+    in_flight: Mutex<HashMap<ThreadId, InFlightRequest>>,
+    // when shutdown was asked for, and what was in flight then
+    drain_started: Mutex<Option<(Instant, Vec<InFlightRequest>)>>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        {
+            let mut drain_started = self.inner.drain_started.lock().unwrap();
+            if drain_started.is_none() {
+                *drain_started = Some((Instant::now(), self.in_flight()));
+            }
+        }
+        self.inner.requested.store(true, Ordering::SeqCst);
+
+        let servers = self.inner.servers.lock().unwrap();
+
+        // every blocked worker needs its own wake-up
+        for (server, workers) in &*servers {
+            for _ in 0..*workers {
+                server.unblock();
+            }
+        }
+
+        self.inner.requested_signal.notify_all();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    /// What every worker is busy with right now, oldest first. While draining, this is what's left to finish.
+    pub fn in_flight(&self) -> Vec<InFlightRequest> {
+        let mut requests: Vec<_> = self
+            .inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        requests.sort_by_key(|request| request.started);
+        requests
+    }
+
+    /// How many requests are in flight on each route, busiest first. Requests still being routed, and ones that
+    /// didn't match anything, are counted under `None`.
+    pub fn in_flight_by_route(&self) -> Vec<(Option<&'static str>, usize)> {
+        by_route(&self.in_flight())
+    }
+
+    fn track(&self, method: &str, url: &str) {
+        let request = InFlightRequest {
+            method: method.to_owned(),
+            url: url.to_owned(),
+            route: None,
+            started: Instant::now(),
+        };
+        self.inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .insert(thread::current().id(), request);
+    }
+
+    fn track_route(&self, route: &'static str) {
+        if let Some(request) = self
+            .inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .get_mut(&thread::current().id())
+        {
+            request.route = Some(route);
+        }
+    }
+
+    fn untrack(&self) -> Option<InFlightRequest> {
+        self.inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .remove(&thread::current().id())
+    }
+
+    fn drain_report(&self) -> DrainReport {
+        let abandoned = self.in_flight();
+        let (started, in_flight) = self
+            .inner
+            .drain_started
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_else(|| (Instant::now(), Vec::new()));
+
+        DrainReport {
+            duration: started.elapsed(),
+            in_flight,
+            abandoned,
+        }
+    }
+
+    fn attach(&self, server: Arc<dyn Transport>, workers: usize) {
+        self.inner.servers.lock().unwrap().push((server, workers));
+    }
+
+    fn wait(&self) {
+        let mut servers = self.inner.servers.lock().unwrap();
+        while !self.is_shutdown() {
+            servers = self.inner.requested_signal.wait(servers).unwrap();
+        }
+    }
+}
+
+pub struct ServerBuilder<C: 'static> {
+    addr: String,
+    listener: Option<TcpListener>,
+    transports: Vec<Arc<dyn Transport>>,
+    #[cfg(unix)]
+    unix_sockets: Vec<PathBuf>,
+    #[cfg(unix)]
+    socket_activation: bool,
+    #[cfg(unix)]
+    per_worker_accept: bool,
+    tcp: TcpOptions,
+    #[cfg(feature = "tls")]
+    tls: Option<(Vec<u8>, Vec<u8>)>,
+    workers: usize,
+    priority_queue: Option<usize>,
+    multipart_upload_limit: usize,
+    upload_disk_limit: u64,
+    upload_part_limit: usize,
+    upload_dir: PathBuf,
+    output_buffer: usize,
+    small_responses: bool,
+    ask_to_close: bool,
+    progress_interval: u64,
+    routes: Routes<C>,
+    virtual_hosts: Vec<(String, Routes<C>)>,
+    rewrites: Vec<Rewrite>,
+    middleware: MiddlewareList<C>,
+    shadows: HashMap<Symbol, Vec<HandlerRef<C>>>,
+    shadow_body_limit: usize,
+    #[cfg(feature = "decompression")]
+    inflate_limit: usize,
+    hooks: Hooks<C>,
+    shutdown: ShutdownHandle,
+    drain_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    connection_idle: Duration,
+    busy_connections_per_ip: Option<usize>,
+    maintenance: MaintenanceHandle,
+    method_override: bool,
+    origins: Origins,
+    profiler: Option<Profiler>,
+    route_timings: Option<RouteTimings>,
+    metrics_endpoint: Option<&'static str>,
+    metrics_sources: Vec<MetricsSource>,
+    #[cfg(feature = "batch")]
+    batch_endpoint: Option<&'static str>,
+    #[cfg(feature = "openapi")]
+    openapi: Option<(String, String)>,
+    route_table: Option<&'static str>,
+    robots_txt: Option<Fixed>,
+    favicon: Option<Fixed>,
+    well_known: WellKnown,
+    #[cfg(feature = "acme-challenges")]
+    acme_challenges: Option<crate::acme::Challenges>,
+    #[cfg(all(unix, feature = "acme-challenges", feature = "reload"))]
+    certificate_watch: Option<(PathBuf, PathBuf, Duration)>,
+    admin: Option<AdminAddr>,
+    on_log_level: Option<LogLevelHook>,
+    // the config as given to `config`, which reloads are compared against
+    pub(crate) loaded_config: BeakConfig,
+    config_loader: Option<ConfigLoader>,
+    #[cfg(feature = "config")]
+    config_watch: Option<(std::path::PathBuf, Duration)>,
+    #[cfg(feature = "signals")]
+    handle_signals: bool,
+    #[cfg(all(unix, feature = "reload"))]
+    reload: crate::reload::ReloadHandle,
+}
+
+impl<C: Clone + Send + Sync + 'static> ServerBuilder<C> {
+    pub fn new(addr: impl Into<String>, routes: Routes<C>) -> ServerBuilder<C> {
+        let shutdown = ShutdownHandle::default();
+
+        ServerBuilder {
+            addr: addr.into(),
+            listener: None,
+            transports: Vec::new(),
+            #[cfg(unix)]
+            unix_sockets: Vec::new(),
+            #[cfg(unix)]
+            socket_activation: false,
+            #[cfg(unix)]
+            per_worker_accept: false,
+            tcp: TcpOptions::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            workers: 4,
+            priority_queue: None,
+            multipart_upload_limit: 200000,
+            upload_disk_limit: 100 * 1024 * 1024,
+            upload_part_limit: 100,
+            upload_dir: std::env::temp_dir(),
+            output_buffer: 0,
+            small_responses: true,
+            ask_to_close: false,
+            progress_interval: 64 * 1024,
+            routes,
+            virtual_hosts: Vec::new(),
+            rewrites: Vec::new(),
+            middleware: Vec::new(),
+            shadows: HashMap::new(),
+            shadow_body_limit: 1024 * 1024,
+            #[cfg(feature = "decompression")]
+            inflate_limit: 16 * 1024 * 1024,
+            hooks: Hooks {
+                on_start: Vec::new(),
+                on_shutdown: Vec::new(),
+                on_worker_panic: Vec::new(),
+                on_request_complete: Vec::new(),
+                on_upload_progress: Vec::new(),
+                on_drain: Vec::new(),
+                on_handler_error: Vec::new(),
+                on_error: Vec::new(),
+                on_config_reload: Vec::new(),
+                on_connection_open: Vec::new(),
+                on_connection_close: Vec::new(),
+            },
+            shutdown: shutdown.clone(),
+            drain_timeout: None,
+            request_timeout: None,
+            connection_idle: Duration::from_secs(60),
+            busy_connections_per_ip: None,
+            maintenance: MaintenanceHandle::new(),
+            method_override: false,
+            origins: Origins::default(),
+            profiler: None,
+            route_timings: None,
+            metrics_endpoint: None,
+            metrics_sources: Vec::new(),
+            #[cfg(feature = "batch")]
+            batch_endpoint: None,
+            #[cfg(feature = "openapi")]
+            openapi: None,
+            route_table: None,
+            robots_txt: None,
+            favicon: None,
+            well_known: WellKnown::default(),
+            #[cfg(feature = "acme-challenges")]
+            acme_challenges: None,
+            #[cfg(all(unix, feature = "acme-challenges", feature = "reload"))]
+            certificate_watch: None,
+            admin: None,
+            on_log_level: None,
+            loaded_config: BeakConfig::default(),
+            config_loader: None,
+            #[cfg(feature = "config")]
+            config_watch: None,
+            #[cfg(feature = "signals")]
+            handle_signals: false,
+            #[cfg(all(unix, feature = "reload"))]
+            reload: crate::reload::ReloadHandle::new(shutdown.clone()),
+        }
+    }
+
+    /// Bind `addr` instead of the address the builder was made with.
+    pub fn addr(mut self, addr: impl Into<String>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    /// Serve HTTPS, with a PEM certificate chain and its PEM private key.
+    ///
+    /// beak only speaks HTTP/1.x, over TLS or not: clients offered HTTP/2 through ALPN settle for HTTP/1.1, and so do
+    /// the ones asking to `Upgrade: h2c`, which the spec allows. Clients aren't asked for certificates - for that,
+    /// terminate TLS at a proxy that does, and use [`ClientCert`](crate::mtls::ClientCert).
+    ///
+    /// There's no SNI certificate selection: one certificate serves however many [virtual hosts](Self::virtual_host)
+    /// there are, since tiny_http's listener only takes the one. So for several domains in one process, it has to
+    /// name them all, or have a wildcard that does - `run` refuses to start when it doesn't cover one of the virtual
+    /// hosts. For a certificate per domain, put a proxy that picks by SNI in front.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, certificate: Vec<u8>, private_key: Vec<u8>) -> Self {
+        self.tls = Some((certificate, private_key));
+        self
+    }
+
+    /// Answers ACME HTTP-01 challenges from `challenges`, at `/.well-known/acme-challenge/:token`.
+    #[cfg(feature = "acme-challenges")]
+    pub fn acme_challenges(mut self, challenges: crate::acme::Challenges) -> Self {
+        self.acme_challenges = Some(challenges);
+        self
+    }
+
+    /// Reloads the server (see [`reload_handle`](Self::reload_handle)) once the certificate and key at these paths
+    /// have been renewed, checking every `interval` - so the new process starts with them, and nothing's dropped
+    /// on the way. It waits until both have stopped changing and look like a certificate and a key, since renewal
+    /// writes them one at a time. The new process has to read them from the same paths, through
+    /// [`config`](Self::config) or however it read them the first time.
+    #[cfg(all(unix, feature = "acme-challenges", feature = "reload"))]
+    pub fn reload_on_renewal(
+        mut self,
+        certificate: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> Self {
+        self.certificate_watch = Some((certificate.into(), key.into(), interval));
+        self
+    }
+
+    /// Serve on an already-bound listener instead of binding `addr`.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Serve requests from `transport` instead of binding `addr` - every transport given, once more than one is.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transports.push(Arc::new(transport));
+        self
+    }
+
+    /// Serve on a unix socket at `path` instead of binding `addr`, like a [`transport`](Self::transport). Whatever's
+    /// already at `path` is replaced, and the socket is removed once the server's done.
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix_sockets.push(path.into());
+        self
+    }
+
+    /// Use the first socket passed in by systemd (`LISTEN_FDS`) if there is one, falling back to binding `addr`
+    /// when we weren't socket-activated.
+    #[cfg(unix)]
+    pub fn socket_activation(mut self) -> Self {
+        self.socket_activation = true;
+        self
+    }
+
+    /// Set `TCP_NODELAY`, so small responses go out right away instead of waiting on Nagle's algorithm.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp.nodelay = nodelay;
+        self
+    }
+
+    /// Turn on TCP keepalive, probing connections that have been idle this long.
+    pub fn tcp_keepalive(mut self, idle: Duration) -> Self {
+        self.tcp.keepalive = Some(idle);
+        self
+    }
+
+    /// `SO_REUSEADDR`, so a restarted server can bind while old connections are still in `TIME_WAIT`. On by default
+    /// on unix, like std does it.
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.tcp.reuse_address = reuse;
+        self
+    }
+
+    /// `SO_REUSEPORT`, letting several processes bind the same address and have the kernel spread connections
+    /// between them.
+    #[cfg(unix)]
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.tcp.reuse_port = reuse;
+        self
+    }
+
+    /// Give every worker a listener of its own, bound to the same address with `SO_REUSEPORT`, instead of having
+    /// them all take turns on one. The kernel spreads new connections across the listeners, and workers stop
+    /// contending over a shared accept queue, which helps under high connection rates.
+    ///
+    /// A listener passed in (through [`listener`](Self::listener) or socket activation) needs `SO_REUSEPORT` set
+    /// already for the others to bind next to it.
+    #[cfg(unix)]
+    pub fn per_worker_accept(mut self) -> Self {
+        self.per_worker_accept = true;
+        self.tcp.reuse_port = true;
+        self
+    }
+
+    /// How many connections the kernel queues up waiting to be accepted (128 by default).
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.tcp.backlog = backlog.min(i32::MAX as u32) as i32;
+        self
+    }
+
+    /// Serve `routes` instead of the default routes for requests whose `Host` is `host` - either an exact hostname or
+    /// a `*.example.com` wildcard.
+    pub fn virtual_host(mut self, host: impl Into<String>, routes: Routes<C>) -> Self {
+        self.virtual_hosts.push((host.into(), routes));
+        self
+    }
+
+    /// Rewrites are tried in the order they were added, and only the first one that applies is used.
+    pub fn rewrite(mut self, rewrite: Rewrite) -> Self {
+        self.rewrites.push(rewrite);
+        self
+    }
+
+    /// Middleware wrap every routed request, the first one added outermost.
+    pub fn middleware(mut self, middleware: impl Middleware<C> + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Sends a copy of every request matching `route` (a path pattern, exactly as the handler that answers it
+    /// declares it) to `handler` as well, for trying a new implementation on live traffic. The shadow runs after the
+    /// real response has been sent, without middleware, and whatever it writes, returns or panics with is thrown
+    /// away.
+    pub fn shadow(
+        mut self,
+        route: &'static str,
+        handler: &'static (dyn crate::Handler<C> + Send + Sync),
+    ) -> Self {
+        self.shadows
+            .entry(intern_static(route))
+            .or_default()
+            .push(handler);
+        self
+    }
+
+    /// Requests with bodies bigger than this (1MiB by default) aren't shadowed, so we don't hold onto huge uploads.
+    pub fn shadow_body_limit(mut self, limit: usize) -> Self {
+        self.shadow_body_limit = limit;
+        self
+    }
+
+    /// gzip and deflate request bodies are decompressed before anything parses them, up to this many bytes
+    /// (16MiB by default). Bodies that inflate past it fail to read, rather than exhausting memory.
+    #[cfg(feature = "decompression")]
+    pub fn inflate_limit(mut self, limit: usize) -> Self {
+        self.inflate_limit = limit;
+        self
+    }
+
+    /// Serve an OpenAPI document describing the default routes at `/openapi.json`.
+    #[cfg(feature = "openapi")]
+    pub fn openapi(mut self, title: impl Into<String>, version: impl Into<String>) -> Self {
+        self.openapi = Some((title.into(), version.into()));
+        self
+    }
+
+    /// Serve a plain-text listing of every route at `path`, for debugging. Best kept off public-facing servers.
+    pub fn route_table(mut self, path: &'static str) -> Self {
+        self.route_table = Some(path);
+        self
+    }
+
+    /// Serve `robots.txt` at `/robots.txt`.
+    pub fn robots_txt(mut self, robots_txt: impl Into<String>) -> Self {
+        self.robots_txt = Some(Fixed::new(
+            "text/plain; charset=utf-8",
+            robots_txt.into().into_bytes(),
+        ));
+        self
+    }
+
+    /// Serve `icon` at `/favicon.ico`, as `content_type` - `image/x-icon` for an actual .ico, though browsers take
+    /// a png there too.
+    pub fn favicon(mut self, content_type: &str, icon: impl Into<Vec<u8>>) -> Self {
+        self.favicon = Some(Fixed::new(content_type, icon.into()));
+        self
+    }
+
+    /// Serve `body` at `/.well-known/<name>` - `security.txt`, `openid-configuration`, `matrix/server`. Other names
+    /// under there get a 404, except for `acme-challenge/`, which is [`acme_challenges`](Self::acme_challenges)'
+    /// to answer. These, the robots.txt and the favicon come ahead of the app's own routes, catch-alls included.
+    pub fn well_known(
+        mut self,
+        name: impl Into<String>,
+        content_type: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> Self {
+        let name = name.into().trim_matches('/').to_owned();
+        self.well_known
+            .files
+            .insert(name, Fixed::new(content_type, body.into()));
+        self
+    }
+
+    /// Workers take the listeners and transports in turn, so there have to be at least as many of them - unless
+    /// there's a [priority queue](Self::priority_queue), which they all take from.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Accept connections on threads of their own and queue requests by their handler's
+    /// [`queue_class`](crate::Handler::queue_class), so when every worker is busy the most important ones are served
+    /// first. Lower classes only get a worker once nothing higher is waiting, so a steady stream of high class
+    /// requests can hold them off indefinitely.
+    ///
+    /// At most `limit` requests wait at once. Past that, the oldest one of a lower class than the newcomer is
+    /// answered with a 503 to make room - or the newcomer is, if there's nothing lower.
+    pub fn priority_queue(mut self, limit: usize) -> Self {
+        self.priority_queue = Some(limit);
+        self
+    }
+
+    /// Let POSTs ask to be routed as PUT, PATCH or DELETE, with an `X-HTTP-Method-Override` header or a `_method`
+    /// field in a urlencoded form - for html forms, which can't send those methods themselves. Handlers (and
+    /// middleware) only ever see the method asked for.
+    pub fn method_override(mut self, enabled: bool) -> Self {
+        self.method_override = enabled;
+        self
+    }
+
+    /// Believe `X-Forwarded-Proto` and `X-Forwarded-Host` on requests from `proxy`, for
+    /// [`Request::base_url`](crate::Request::base_url) and the urls built on it - which take the connection's own
+    /// scheme and the `Host` header otherwise. Call it once for each proxy in front of the server.
+    pub fn trust_proxy(mut self, proxy: IpAddr) -> Self {
+        self.origins.proxies.push(proxy);
+        self
+    }
+
+    /// Believe the same headers on requests without a client address, which come over a unix socket - for a proxy
+    /// on the same machine that only it can write to.
+    pub fn trust_local_sockets(mut self, trust: bool) -> Self {
+        self.origins.local = trust;
+        self
+    }
+
+    /// A host - with its port, if it isn't the default - that [`Request::base_url`](crate::Request::base_url) may
+    /// use. Once there's one, requests naming any other get the first one added in their urls instead, so links and
+    /// redirects can't be pointed somewhere else with a made-up `Host`.
+    pub fn allowed_host(mut self, host: impl Into<String>) -> Self {
+        self.origins.hosts.push(host.into());
+        self
+    }
+
+    pub fn multipart_upload_limit(mut self, limit: usize) -> Self {
+        self.multipart_upload_limit = limit;
+        self
+    }
+
+    /// How many bytes of files one request can put on disk, for handlers that take multipart bodies
+    /// [to disk](crate::Handler::multipart_to_disk) - 100MiB unless set otherwise. Requests sending more are answered
+    /// with a 413 and their files deleted.
+    pub fn upload_disk_limit(mut self, limit: u64) -> Self {
+        self.upload_disk_limit = limit;
+        self
+    }
+
+    /// How many parts - files and fields together - one multipart body taken to disk can have, 100 unless set
+    /// otherwise. Requests with more are answered with a 413.
+    pub fn upload_part_limit(mut self, limit: usize) -> Self {
+        self.upload_part_limit = limit;
+        self
+    }
+
+    /// Where handlers that take multipart bodies [to disk](crate::Handler::multipart_to_disk) have them put, each
+    /// request in a directory of its own - the system's temporary directory by default. Somewhere on the same
+    /// filesystem as where they're persisted to saves copying them there.
+    pub fn upload_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.upload_dir = dir.into();
+        self
+    }
+
+    /// Buffer up to `capacity` bytes of every response before writing to the connection. Off by default, when only
+    /// the response head is held back, to go out together with the start of the body.
+    ///
+    /// Buffered output goes out when the buffer fills, when the handler flushes the writer it's responding with, and
+    /// once the handler is done. Streaming handlers that need every write sent right away can opt out with
+    /// [`Request::unbuffered`](crate::Request::unbuffered).
+    pub fn output_buffer(mut self, capacity: usize) -> Self {
+        self.output_buffer = capacity;
+        self
+    }
+
+    /// Whether responses of a couple of KiB or less are put together in a buffer each worker reuses, head and body
+    /// both, rather than one allocated for each request - and sent in one write once the body's all there, however
+    /// many writes the handler makes. On by default; it doesn't apply with an
+    /// [`output_buffer`](Self::output_buffer), which does its own buffering.
+    ///
+    /// `cargo bench --features bench --bench respond` counts the allocations per request both ways.
+    pub fn small_responses(mut self, enabled: bool) -> Self {
+        self.small_responses = enabled;
+        self
+    }
+
+    /// Whether every response says `Connection: close`, asking clients to open a new connection for their next
+    /// request. Off by default.
+    ///
+    /// It's a hint to clients and no more: tiny_http goes by the request for whether to read another one, so requests
+    /// already pipelined behind the one being answered still get read and answered, and a client that ignores the
+    /// header can carry on sending them. Pipelined requests are always answered in the order they came in, whichever
+    /// worker handles each - one that's ready first waits for the ones ahead of it on its connection. A handler that
+    /// panics before responding is answered for with a `500`, so nothing later gets taken for its response.
+    pub fn ask_clients_to_close(mut self, enabled: bool) -> Self {
+        self.ask_to_close = enabled;
+        self
+    }
+
+    /// Runs when a connection sees its first request, before that request is handled - see
+    /// [`ClientConnection`](crate::ClientConnection) for how beak tells connections apart.
+    pub fn on_connection_open(mut self, hook: impl Fn(&ClientConnection) + Send + Sync + 'static) -> Self {
+        self.hooks.on_connection_open.push(Box::new(hook));
+        self
+    }
+
+    /// Runs once a connection's closed, or gone quiet for long enough to count as closed, and when the server shuts
+    /// down for every connection that's still open.
+    pub fn on_connection_close(mut self, hook: impl Fn(&ClientConnection) + Send + Sync + 'static) -> Self {
+        self.hooks.on_connection_close.push(Box::new(hook));
+        self
+    }
+
+    /// Runs once the address is bound, before any worker picks up a request.
+    pub fn on_start(mut self, hook: impl Fn(&C) + Send + Sync + 'static) -> Self {
+        self.hooks.on_start.push(Box::new(hook));
+        self
+    }
+
+    /// Runs after every worker has stopped, so it's safe to tear down whatever the context holds.
+    pub fn on_shutdown(mut self, hook: impl Fn(&C) + Send + Sync + 'static) -> Self {
+        self.hooks.on_shutdown.push(Box::new(hook));
+        self
+    }
+
+    /// Runs on the worker's thread whenever a handler panics. The worker keeps serving afterwards.
+    pub fn on_worker_panic(mut self, hook: impl Fn(&WorkerPanic) + Send + Sync + 'static) -> Self {
+        self.hooks.on_worker_panic.push(Box::new(hook));
+        self
+    }
+
+    /// Runs on the worker's thread after every request has been answered - whether by a handler or by beak itself
+    /// (404s, redirects), but not when a handler panics. The place for access logs and bandwidth accounting.
+    pub fn on_request_complete(
+        mut self,
+        hook: impl Fn(&CompletedRequest) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_request_complete.push(Box::new(hook));
+        self
+    }
+
+    /// Writes a line for every completed request to `log`, as an
+    /// [`on_request_complete`](Self::on_request_complete) hook.
+    pub fn access_log(self, log: AccessLog) -> Self {
+        self.on_request_complete(move |request| log.log(request))
+    }
+
+    /// Captures the requests `profiler` asks for. Keep a clone of it to look at them, or use the
+    /// [admin listener](Self::admin_listener)'s `profiles` command.
+    pub fn profiler(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Records how long every request takes in a histogram for its route. Requests that didn't match a route
+    /// aren't counted.
+    pub fn route_timings(mut self, timings: RouteTimings) -> Self {
+        self.route_timings = Some(timings);
+        self
+    }
+
+    /// Serves the [route timings](Self::route_timings) at `path` for Prometheus to scrape, recording them with the
+    /// default buckets if they weren't set up already.
+    pub fn metrics_endpoint(mut self, path: &'static str) -> Self {
+        self.metrics_endpoint = Some(path);
+        self
+    }
+
+    /// More metrics for the [metrics endpoint](Self::metrics_endpoint), in the Prometheus text format - called on
+    /// every scrape, and served after the route timings.
+    pub fn metrics_source(mut self, source: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        self.metrics_sources.push(Box::new(source));
+        self
+    }
+
+    /// Answers `POST`s to `path` carrying a JSON array of requests - each with a `method`, `path`, `headers` and
+    /// `body` - with an array of their `status`, `headers` and `body`, for clients too far away to make them one at a
+    /// time. A header a response has more than once is an array of its values. Bodies are text, and a `body` that's
+    /// JSON but not a string is sent as JSON.
+    ///
+    /// Sub-requests go back into the server over a [loopback transport](crate::loopback::LoopbackTransport), through
+    /// rewrites, middleware and routing like any other, one after another. They get the batch's `Host`, `Cookie` and
+    /// `Authorization` unless they set their own, but come from the transport rather than the client's address, and
+    /// any `X-Forwarded-*` headers they're sent with are dropped. That's a unix socket, or `127.0.0.1` where there
+    /// aren't any - so there, don't [trust](Self::trust_proxy) `127.0.0.1` as a proxy. The transport has as many
+    /// [workers](Self::workers) again of its own, so batches never wait on each other. Sub-requests can't be batches
+    /// themselves, and a batch can have at most 20 requests, in a 1MiB body.
+    #[cfg(feature = "batch")]
+    pub fn batch_endpoint(mut self, path: &'static str) -> Self {
+        self.batch_endpoint = Some(path);
+        self
+    }
+
+    /// Runs on the worker's thread whenever a handler (or middleware) returns an error, with the method, url, route
+    /// and `X-Request-Id` of the request it failed on attached as its [`context`](BeakError::context). If nothing
+    /// had been sent yet, the client gets a 500. Without any of these hooks or `on_error` ones, errors are written
+    /// to stderr.
+    pub fn on_handler_error(mut self, hook: impl Fn(&BeakError) + Send + Sync + 'static) -> Self {
+        self.hooks.on_handler_error.push(Box::new(hook));
+        self
+    }
+
+    /// Runs on the worker's thread for every handler error and panic, with the route, status and timing of the
+    /// request - the hook for crash reporting. Panics come through as [`BeakError::Panic`], after any
+    /// `on_worker_panic` hooks.
+    pub fn on_error(
+        mut self,
+        hook: impl Fn(&BeakError, &RequestMeta) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_error.push(Box::new(hook));
+        self
+    }
+
+    /// Runs on the worker's thread as request bodies are read - whenever another
+    /// [`upload_progress_interval`](Self::upload_progress_interval) worth of bytes has come in, and once more at the
+    /// end. The body is read as the handler reads it (or as beak does for multipart routes), so a handler that never
+    /// touches its body never reports any progress.
+    pub fn on_upload_progress(
+        mut self,
+        hook: impl Fn(&UploadProgress<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_upload_progress.push(Box::new(hook));
+        self
+    }
+
+    /// How many bytes come in between `on_upload_progress` calls, 64KiB by default.
+    pub fn upload_progress_interval(mut self, bytes: u64) -> Self {
+        self.progress_interval = bytes.max(1);
+        self
+    }
+
+    /// Runs once draining is over - every worker finished, or the drain timeout ran out - before the `on_shutdown`
+    /// hooks, with a report of what was in flight and what got abandoned.
+    pub fn on_drain(mut self, hook: impl Fn(&DrainReport) + Send + Sync + 'static) -> Self {
+        self.hooks.on_drain.push(Box::new(hook));
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Whether to start out in maintenance mode. Either way, it can be switched on and off with
+    /// [`maintenance_handle`](Self::maintenance_handle) once the server runs.
+    pub fn maintenance(self, enabled: bool) -> Self {
+        if enabled {
+            self.maintenance.enable();
+        } else {
+            self.maintenance.disable();
+        }
+        self
+    }
+
+    pub fn maintenance_handle(&self) -> MaintenanceHandle {
+        self.maintenance.clone()
+    }
+
+    /// How long to wait for in-flight requests once shutdown starts. Workers still busy after that are abandoned,
+    /// `on_shutdown` hooks run anyway, and `run` returns. Without one, shutdown waits for every worker.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives every request a [deadline](crate::Request::with_deadline) this long after it arrives. For deadlines
+    /// that depend on the route or on what the client asks for, there's the [`Deadline`](crate::deadline::Deadline)
+    /// middleware.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a connection can go without a request before it's taken to have closed, running the
+    /// `on_connection_close` hooks - a minute by default. There's no hearing from tiny_http when a client hangs up,
+    /// so this is as soon as beak can know unless a request said it was the last.
+    pub fn connection_idle(mut self, idle: Duration) -> Self {
+        self.connection_idle = idle;
+        self
+    }
+
+    /// How many connections one client ip can have requests being handled on at once. A request that would make it
+    /// one more gets a 429 before any routing or middleware, and its connection is closed. Clients behind a shared
+    /// NAT or proxy all count as one, so leave room for that. Unlimited by default.
+    ///
+    /// This isn't a limit on open connections - that would mean counting them as they're accepted, and tiny_http does
+    /// the accepting. A connection only counts once a request comes in on it, and one of the ip's connections that's
+    /// gone quiet makes way for a new one rather than locking the client out, so only the busy ones are sure to count.
+    /// Clients that open connections and never send a request get past it entirely; a limit on those needs a proxy
+    /// or firewall in front.
+    pub fn max_busy_connections_per_ip(mut self, limit: usize) -> Self {
+        self.busy_connections_per_ip = Some(limit);
+        self
+    }
+
+    /// Listens on `addr` for admin commands, one per line: `routes`, `maintenance on|off`, `log-level <level>`,
+    /// `reload`, `profiles`, `memory`, `shutdown` and `help`. There's no authentication, so keep it on loopback or behind a firewall - or use
+    /// [`admin_socket`](Self::admin_socket), where file permissions decide who gets in.
+    pub fn admin_listener(mut self, addr: impl Into<String>) -> Self {
+        self.admin = Some(AdminAddr::Tcp(addr.into()));
+        self
+    }
+
+    /// The [admin listener](Self::admin_listener), on a unix socket at `path` instead. Whatever's already at
+    /// `path` is replaced.
+    #[cfg(unix)]
+    pub fn admin_socket(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.admin = Some(AdminAddr::Unix(path.into()));
+        self
+    }
+
+    /// Called with whatever the admin listener's `log-level` command asks for - beak doesn't log by itself, so it
+    /// hands the level to the app's logger. Errors go back to the admin client.
+    pub fn on_log_level(
+        mut self,
+        hook: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_log_level = Some(Arc::new(hook));
+        self
+    }
+
+    /// How to load the config again while the server runs - on SIGHUP with
+    /// [`handle_signals`](Self::handle_signals), on the [admin listener](Self::admin_listener)'s `reload`, or whenever
+    /// [`watch_config`](Self::watch_config) sees the file change. Changes to `log_level` (through [`on_log_level`](Self::on_log_level)) and `maintenance` are applied,
+    /// `rate_limit` and `static_dir` go to [`on_config_reload`](Self::on_config_reload) hooks for the app to apply,
+    /// and changes to anything else are rejected, since they'd need a restart.
+    pub fn reload_config(
+        mut self,
+        loader: impl Fn() -> Result<BeakConfig, ConfigError> + Send + Sync + 'static,
+    ) -> Self {
+        self.config_loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Reloads the config from the TOML file at `path` whenever it changes, checking every `interval`. Loads it
+    /// with [`BeakConfig::from_toml`], unless [`reload_config`](Self::reload_config) says otherwise.
+    #[cfg(feature = "config")]
+    pub fn watch_config(mut self, path: impl Into<std::path::PathBuf>, interval: Duration) -> Self {
+        let path = path.into();
+        if self.config_loader.is_none() {
+            let file = path.clone();
+            self.config_loader = Some(Box::new(move || BeakConfig::from_toml(file.clone())));
+        }
+        self.config_watch = Some((path, interval));
+        self
+    }
+
+    /// Called after every config reload with what changed and what was rejected, or why the config couldn't be
+    /// loaded. Without any of these, that goes to stderr.
+    pub fn on_config_reload(
+        mut self,
+        hook: impl Fn(Result<&ConfigReload, &ConfigError>) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_config_reload.push(Box::new(hook));
+        self
+    }
+
+    /// Drains this server and then re-executes the binary on the same listener, for upgrades that don't drop
+    /// connections. The new binary picks the listener up through [`ServerBuilder::socket_activation`].
+    #[cfg(all(unix, feature = "reload"))]
+    pub fn reload_handle(&self) -> crate::reload::ReloadHandle {
+        self.reload.clone()
+    }
+
+    /// Shut down gracefully on SIGTERM/SIGINT. A second signal while draining exits the process immediately.
+    /// With the `reload` feature, SIGUSR2 triggers a reload.
+    #[cfg(feature = "signals")]
+    pub fn handle_signals(mut self) -> Self {
+        self.handle_signals = true;
+        self
+    }
+
+    fn listen(&mut self) -> std::io::Result<TcpListener> {
+        #[cfg(unix)]
+        if self.socket_activation && self.listener.is_none() {
+            self.listener = crate::systemd::listen_fds()?.into_iter().next();
+        }
+
+        // listeners we didn't bind ourselves only get the options that can still be changed
+        match self.listener.take() {
+            Some(listener) => {
+                tcp::apply(&listener, &self.tcp)?;
+                Ok(listener)
+            }
+            None => tcp::bind(&self.addr, &self.tcp),
+        }
+    }
+
+    // a tiny_http server on the listener, and with per-worker accept, one on a listener of its own for every other
+    // worker - TLS and all, when there is any
+    fn tcp_servers(&self, listener: TcpListener) -> BeakResult<Vec<tiny_http::Server>> {
+        let mut listeners = vec![listener];
+        #[cfg(unix)]
+        if self.per_worker_accept {
+            // the first listener's address, in case we were asked for port 0
+            let addr = listeners[0].local_addr()?.to_string();
+            for _ in 1..self.workers {
+                let listener = tcp::bind(&addr, &self.tcp).map_err(|source| BeakError::Bind {
+                    addr: addr.clone(),
+                    source: Box::new(source),
+                })?;
+                listeners.push(listener);
+            }
+        }
+
+        listeners
+            .into_iter()
+            .map(|listener| {
+                #[cfg(feature = "tls")]
+                let tls = self
+                    .tls
+                    .clone()
+                    .map(|(certificate, private_key)| tiny_http::SslConfig {
+                        certificate,
+                        private_key,
+                    });
+                #[cfg(not(feature = "tls"))]
+                let tls = None;
+
+                tiny_http::Server::from_listener(listener, tls).map_err(|source| {
+                    BeakError::Bind {
+                        addr: self.addr.clone(),
+                        source,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    pub fn run(mut self, context: C) -> BeakResult<()> {
+        // a certificate that doesn't parse could still be good for them, and tiny_http is the judge of that
+        #[cfg(feature = "tls")]
+        if let Some(names) = self
+            .tls
+            .as_ref()
+            .and_then(|(certificate, _)| crate::tls::certificate_names(certificate))
+        {
+            let uncovered = self
+                .virtual_hosts
+                .iter()
+                .find(|(host, _)| !crate::tls::covers(&names, host));
+            if let Some((host, _)) = uncovered {
+                return Err(BeakError::UncoveredHost(host.clone()));
+            }
+        }
+
+        let mut router = Router::new(self.routes)?;
+        for (host, routes) in &self.virtual_hosts {
+            router.add_host(host, routes)?;
+        }
+
+        #[cfg(feature = "openapi")]
+        if let Some((title, version)) = &self.openapi {
+            let document = crate::openapi::document(title, version, self.routes.iter().copied());
+            // routes are 'static, and this one is around for as long as the others are
+            router.insert(Box::leak(Box::new(crate::openapi::OpenApiHandler::new(
+                &document,
+            ))))?;
+        }
+
+        if let Some(path) = self.metrics_endpoint {
+            let timings = self
+                .route_timings
+                .get_or_insert_with(RouteTimings::new)
+                .clone();
+            let sources = std::mem::take(&mut self.metrics_sources);
+            router.insert(Box::leak(Box::new(MetricsEndpoint {
+                path,
+                timings,
+                sources,
+            })))?;
+        }
+
+        #[cfg(feature = "batch")]
+        let batch_transport = match self.batch_endpoint {
+            Some(path) => {
+                let transport = crate::loopback::LoopbackTransport::new()?;
+                router.insert(Box::leak(Box::new(crate::batch::BatchEndpoint {
+                    path,
+                    connector: transport.connector(),
+                })))?;
+                Some(transport)
+            }
+            None => None,
+        };
+
+        for (path, file) in [
+            ("/robots.txt", self.robots_txt.take()),
+            ("/favicon.ico", self.favicon.take()),
+        ] {
+            if let Some(file) = file {
+                router.insert(Box::leak(Box::new(FixedEndpoint { path, file })))?;
+            }
+        }
+        if !self.well_known.files.is_empty() {
+            router.insert(Box::leak(Box::new(mem::take(&mut self.well_known))))?;
+        }
+
+        #[cfg(feature = "acme-challenges")]
+        if let Some(challenges) = self.acme_challenges.take() {
+            router.insert(Box::leak(Box::new(crate::acme::ChallengeEndpoint { challenges })))?;
+        }
+
+        if let Some(path) = self.route_table {
+            let table = RouteTable::new(path, router.routes());
+            router.insert(Box::leak(Box::new(table)))?;
+        }
+
+        // the router is about to be shared, and the admin listener only starts once everything else is bound
+        let admin_routes = self
+            .admin
+            .as_ref()
+            .map(|_| RouteTable::new("", router.routes()).text().to_owned());
+
+        // leaked like the routes above, so requests can hold onto it without another lifetime
+        let urls: &'static Urls = Box::leak(Box::new(Urls::new(
+            router.routes(),
+            mem::take(&mut self.origins),
+        )));
+
+        let shared = Arc::new(Shared {
+            router,
+            rewrites: mem::take(&mut self.rewrites),
+            middleware: mem::take(&mut self.middleware),
+            shadows: mem::take(&mut self.shadows),
+            shadow_body_limit: self.shadow_body_limit,
+            upload_limits: upload::ReceiveLimits {
+                fields: self.multipart_upload_limit,
+                disk: self.upload_disk_limit,
+                parts: self.upload_part_limit,
+            },
+            upload_dir: mem::take(&mut self.upload_dir),
+            output_buffer: self.output_buffer,
+            small_responses: self.small_responses,
+            ask_to_close: self.ask_to_close,
+            progress_hooks: mem::take(&mut self.hooks.on_upload_progress),
+            progress_interval: self.progress_interval,
+            request_timeout: self.request_timeout,
+            connections: Connections::new(self.connection_idle, self.busy_connections_per_ip),
+            maintenance: self.maintenance.clone(),
+            method_override: self.method_override,
+            profiler: self.profiler.clone(),
+            route_timings: self.route_timings.clone(),
+            urls,
+            shutdown: self.shutdown.clone(),
+            #[cfg(feature = "decompression")]
+            inflate_limit: self.inflate_limit,
+        });
+
+        let bind_error = |addr: &str, source: std::io::Error| BeakError::Bind {
+            addr: addr.to_owned(),
+            source: Box::new(source),
+        };
+
+        let mut servers = mem::take(&mut self.transports);
+        #[cfg(unix)]
+        for path in &self.unix_sockets {
+            let server =
+                crate::transport::unix(path).map_err(|e| bind_error(&path.display().to_string(), e))?;
+            servers.push(Arc::new(server));
+        }
+
+        #[cfg(all(unix, feature = "reload"))]
+        let mut handoff = None;
+        if servers.is_empty() {
+            let listener = self.listen().map_err(|e| bind_error(&self.addr, e))?;
+
+            // tiny_http closes its copy of the listener when it's dropped, this one stays open for the next process
+            #[cfg(all(unix, feature = "reload"))]
+            {
+                handoff = Some(listener.try_clone()?);
+            }
+
+            for server in self.tcp_servers(listener)? {
+                servers.push(Arc::new(server));
+            }
+        }
+
+        // workers take the servers in turn, so with a count that doesn't divide evenly the first few get one more -
+        // and each has to be unblocked as many times as it has workers waiting on it. with a priority queue, the only
+        // thread blocked on each server is the one feeding the queue
+        if self.priority_queue.is_none() && self.workers < servers.len() {
+            return Err(BeakError::TooFewWorkers {
+                workers: self.workers,
+                transports: servers.len(),
+            });
+        }
+        for (i, server) in servers.iter().enumerate() {
+            let blocked = match self.priority_queue {
+                Some(_) => 1,
+                None => (0..self.workers).filter(|worker| worker % servers.len() == i).count(),
+            };
+            self.shutdown.attach(server.clone(), blocked);
+        }
+
+        // sub-requests get workers of their own, one for each worker that could be running a batch, so a batch never
+        // waits on a worker that's busy with a batch of its own
+        #[cfg(feature = "batch")]
+        let batch_transport = batch_transport.map(|transport| {
+            let transport: Arc<dyn Transport> = Arc::new(transport);
+            self.shutdown.attach(transport.clone(), self.workers);
+            transport
+        });
+
+        let dispatch = self
+            .priority_queue
+            .map(|limit| Arc::new(Dispatch::new(limit)));
+        let mut feeders = Vec::new();
+        if let Some(dispatch) = &dispatch {
+            for server in &servers {
+                let (server, dispatch, shared) = (server.clone(), dispatch.clone(), shared.clone());
+                let shutdown = self.shutdown.clone();
+
+                feeders.push(thread::spawn(move || {
+                    while !shutdown.is_shutdown() {
+                        if let Ok(request) = server.recv() {
+                            dispatch.push(queue_class(&shared, &request), request);
+                        }
+                    }
+                }));
+            }
+        }
+
+        let reloader = self.config_loader.take().map(|loader| {
+            Arc::new(Reloader {
+                loader,
+                current: Mutex::new(mem::take(&mut self.loaded_config)),
+                maintenance: self.maintenance.clone(),
+                log_level: self.on_log_level.clone(),
+                hooks: mem::take(&mut self.hooks.on_config_reload),
+            })
+        });
+
+        #[cfg(feature = "config")]
+        if let (Some(reloader), Some((path, interval))) = (&reloader, self.config_watch.take()) {
+            let (reloader, shutdown) = (reloader.clone(), self.shutdown.clone());
+            thread::spawn(move || crate::config::watch(path, interval, reloader, shutdown));
+        }
+
+        #[cfg(all(unix, feature = "acme-challenges", feature = "reload"))]
+        if let Some((certificate, key, interval)) = self.certificate_watch.take() {
+            let (reload, shutdown) = (self.reload.clone(), self.shutdown.clone());
+            thread::spawn(move || crate::acme::watch(certificate, key, interval, reload, shutdown));
+        }
+
+        #[cfg(feature = "signals")]
+        let signals = if self.handle_signals {
+            Some(crate::signals::listen(
+                self.shutdown.clone(),
+                reloader.clone(),
+                #[cfg(all(unix, feature = "reload"))]
+                self.reload.clone(),
+            )?)
+        } else {
+            None
+        };
+
+        let admin = match (&self.admin, admin_routes) {
+            (Some(addr), Some(routes)) => {
+                let admin = Admin {
+                    routes,
+                    maintenance: self.maintenance.clone(),
+                    shutdown: self.shutdown.clone(),
+                    log_level: self.on_log_level.clone(),
+                    config: reloader.clone(),
+                    profiler: self.profiler.clone(),
+                };
+                let bind_error = |source: std::io::Error| BeakError::Bind {
+                    addr: match addr {
+                        AdminAddr::Tcp(addr) => addr.clone(),
+                        #[cfg(unix)]
+                        AdminAddr::Unix(path) => path.display().to_string(),
+                    },
+                    source: Box::new(source),
+                };
+                Some(admin::listen(addr, admin).map_err(bind_error)?)
+            }
+            _ => None,
+        };
+
+        for hook in &self.hooks.on_start {
+            hook(&context);
+        }
+
+        let hooks = Arc::new(self.hooks);
+        let mut guards = Vec::with_capacity(self.workers);
+        let (done_sender, done) = mpsc::channel::<()>();
+
+        #[allow(unused_mut)]
+        let mut sources: Vec<_> = (0..self.workers)
+            .map(|worker| match &dispatch {
+                Some(dispatch) => (Source::Queue(dispatch.clone()), false),
+                None => (Source::Server(servers[worker % servers.len()].clone()), false),
+            })
+            .collect();
+        #[cfg(feature = "batch")]
+        if let Some(transport) = &batch_transport {
+            sources.extend((0..self.workers).map(|_| (Source::Server(transport.clone()), true)));
+        }
+
+        for (worker, (source, sub_requests)) in sources.into_iter().enumerate() {
+            let context = context.clone();
+            let hooks = hooks.clone();
+            let shutdown = self.shutdown.clone();
+            let done_sender = done_sender.clone();
+            let shared = shared.clone();
+
+            let mut scratch = Scratch {
+                buffer: Vec::with_capacity(self.multipart_upload_limit),
+                arena: Arena::new(),
+                head: Vec::with_capacity(512),
+                sub_requests,
+            };
+
+            let guard = thread::spawn(move || {
+                // dropped when this worker exits, which is how `run` counts who's still draining
+                let _done_sender = done_sender;
+
+                loop {
+                    let request = match &source {
+                        Source::Server(server) => {
+                            if shutdown.is_shutdown() {
+                                break;
+                            }
+                            match server.recv() {
+                                Ok(request) => request,
+                                // either we got unblocked for shutdown, or accepting failed - the next go round sorts
+                                // out which
+                                Err(_) => continue,
+                            }
+                        }
+                        Source::Queue(dispatch) => match dispatch.pop(&shutdown) {
+                            Some(request) => request,
+                            None => break,
+                        },
+                    };
+
+                    let url = request.url().to_owned();
+                    // only needed if the handler panics, when the request itself is long gone
+                    let id = request_id(&request);
+                    shutdown.track(request.method().as_str(), &url);
+                    let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                        serve(request, &shared, &hooks, &mut scratch, context.clone())
+                    }));
+                    scratch.arena.reset();
+
+                    let tracked = shutdown.untrack();
+
+                    if let Err(payload) = served {
+                        let report = WorkerPanic {
+                            worker,
+                            url,
+                            message: panic_message(&*payload),
+                        };
+
+                        for hook in &hooks.on_worker_panic {
+                            hook(&report);
+                        }
+
+                        // the panic hook has already told stderr about it
+                        if let (Some(tracked), false) = (tracked, hooks.on_error.is_empty()) {
+                            let meta = RequestMeta {
+                                method: tracked.method,
+                                url: tracked.url,
+                                route: tracked.route,
+                                status: None,
+                                duration: tracked.started.elapsed(),
+                                request_id: id,
+                            };
+                            let context = ErrorContext::new(
+                                meta.method.clone(),
+                                meta.url.clone(),
+                                meta.route,
+                                meta.request_id.clone(),
+                            );
+                            let error = BeakError::Panic {
+                                message: report.message,
+                            }
+                            .in_request(context);
+                            for hook in &hooks.on_error {
+                                hook(&error, &meta);
+                            }
+                        }
+                    }
+                }
+            });
+
+            guards.push(guard);
+        }
+
+        drop(done_sender);
+        self.shutdown.wait();
+
+        for feeder in feeders {
+            feeder.join().unwrap();
+        }
+
+        // nothing is ever sent on `done` - recv returns once every worker has dropped its sender, or we give up waiting
+        match self.drain_timeout {
+            Some(timeout) => drop(done.recv_timeout(timeout)),
+            None => drop(done.recv()),
+        }
+
+        for guard in guards {
+            if guard.is_finished() {
+                guard.join().unwrap();
+            }
+        }
+
+        if !hooks.on_drain.is_empty() {
+            let report = self.shutdown.drain_report();
+            for hook in &hooks.on_drain {
+                hook(&report);
+            }
+        }
+
+        #[cfg(feature = "signals")]
+        if let Some(signals) = signals {
+            signals.close();
+        }
+
+        if let Some(admin) = admin {
+            admin.close();
+        }
+
+        for connection in shared.connections.close_all() {
+            for hook in &hooks.on_connection_close {
+                hook(&connection);
+            }
+        }
+
+        for hook in &hooks.on_shutdown {
+            hook(&context);
+        }
+
+        #[cfg(unix)]
+        for path in &self.unix_sockets {
+            let _ = std::fs::remove_file(path);
+        }
+
+        // only a listener of our own can be handed on
+        #[cfg(all(unix, feature = "reload"))]
+        if let (true, Some(handoff)) = (self.reload.is_requested(), handoff) {
+            return Err(crate::reload::reexec(&handoff).into());
+        }
+
+        Ok(())
+    }
+}
+
+// what a worker keeps around from one request to the next: the multipart upload buffer, the arena, and the buffer
+// response heads are put together in
+struct Scratch {
+    buffer: Vec<u8>,
+    arena: Arena,
+    head: Vec<u8>,
+    // whether the worker serves a batch endpoint's sub-requests, which are marked with Batched
+    sub_requests: bool,
+}
+
+// in the extensions of requests sent by a batch endpoint, so they can't run batches of their own, and nothing
+// trusts what they say about where they came from
+pub(crate) struct Batched;
+
+// where a worker gets its requests
+enum Source {
+    Server(Arc<dyn Transport>),
+    Queue(Arc<Dispatch>),
+}
+
+// the class of the handler a request is going to end up with - unmatched requests are cheap to answer, so they can
+// wait with the rest
+fn queue_class<C: Send + Sync>(shared: &Shared<C>, request: &TinyHttpRequest) -> QueueClass {
+    let rewritten = rewrite::rewrite(&shared.rewrites, request.url());
+    let url = rewritten
+        .as_ref()
+        .map_or(request.url(), |rewritten| rewritten.url.as_str());
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    let host = find_header(request.headers(), "Host");
+
+    shared
+        .router
+        .at(host, request.method().as_str(), path)
+        .map_or(QueueClass::Normal, |matched| matched.value.queue_class())
+}
+
+fn serve<C: Clone + Send + Sync>(
+    mut mutable_req: TinyHttpRequest,
+    shared: &Shared<C>,
+    hooks: &Hooks<C>,
+    scratch: &mut Scratch,
+    context: C,
+) {
+    // we're going to have to borrow the request both mutably and immutably - we need it's data immutably, and it's output pipe mutably
+    // as these don't interact, this is safe to do, but violates borrow rules
+    let immutable_req_ptr: *const TinyHttpRequest = &mutable_req;
+    let immutable_req = unsafe { immutable_req_ptr.as_ref().unwrap_unchecked() };
+
+    if let Some(status) = unreadable_after(immutable_req) {
+        respond_and_close(mutable_req, Response::empty(status));
+        return;
+    }
+
+    let started = Instant::now();
+    let received = SystemTime::now();
+    let accounting = Accounting::default();
+    let started_on = shared
+        .connections
+        .started(immutable_req.remote_addr().copied(), immutable_req.secure());
+    let Some((connection, opened)) = started_on else {
+        // one busy connection over the ip's limit, so it's closed without going any further
+        respond_and_close(mutable_req, Response::empty(429));
+        return;
+    };
+    if opened {
+        for hook in &hooks.on_connection_open {
+            hook(&connection);
+        }
+    }
+    for closed in shared.connections.sweep() {
+        for hook in &hooks.on_connection_close {
+            hook(&closed);
+        }
+    }
+
+    if immutable_req.method().as_str() == "CONNECT" {
+        let host = find_header(immutable_req.headers(), "Host");
+        if let Some(handler) = shared.router.tunnel_for(host, immutable_req.url()) {
+            let tunneled = panic::catch_unwind(AssertUnwindSafe(|| {
+                tunnel(mutable_req, handler, hooks, context, started)
+            }));
+            if let Some(closed) = shared.connections.finished(&connection, true) {
+                for hook in &hooks.on_connection_close {
+                    hook(&closed);
+                }
+            }
+            if let Err(payload) = tunneled {
+                panic::resume_unwind(payload);
+            }
+            return;
+        }
+    }
+
+    // every response goes through here, even the ones beak sends without a handler, so they all get counted
+    let raw_writer = mutable_req.extract_writer_impl();
+    // the worker's head buffer is taken for the request and put back after - if a handler panics it's lost, and the
+    // next request starts another
+    let mut head = match shared.small_responses {
+        true => mem::take(&mut scratch.head),
+        false => Vec::with_capacity(512),
+    };
+    let output: Box<dyn Write + Send + '_> = match shared.output_buffer {
+        0 => Box::new(HeadBatcher::new(
+            raw_writer,
+            &mut head,
+            shared.small_responses,
+        )),
+        capacity => Box::new(BufWriter::with_capacity(capacity, raw_writer)),
+    };
+    let mut resp_writer = CountingWriter::new(output, accounting.clone());
+
+    let routed = panic::catch_unwind(AssertUnwindSafe(|| {
+        route_and_handle(
+            &mut mutable_req,
+            immutable_req,
+            shared,
+            scratch,
+            context,
+            &connection,
+            &mut resp_writer,
+            &accounting,
+        )
+    }));
+    let (symbol, handled) = match routed {
+        Ok(routed) => routed,
+        Err(payload) => {
+            // the next response on the connection can't go out until this one has, and a client reading pipelined
+            // responses would take it for this one's - so this one gets one, and the worker still hears of the panic
+            if accounting.status().is_none() {
+                respond_early(
+                    &mut resp_writer,
+                    immutable_req,
+                    shared.ask_to_close,
+                    Response::empty(500),
+                );
+            }
+            let _ = resp_writer.flush();
+            shared.connections.finished(&connection, false);
+            panic::resume_unwind(payload)
+        }
+    };
+
+    TinyHttpRequest::ignore_client_closing_errors(resp_writer.flush()).unwrap();
+
+    // drop our output pipe
+    drop(resp_writer);
+    if shared.small_responses {
+        scratch.head = head;
+    }
+    let route = symbol.map(Symbol::as_str);
+
+    if let Some(sender) = mutable_req.notify_when_responded.take() {
+        sender.send(()).unwrap();
+    }
+
+    if let Err(error) = handled {
+        let meta = RequestMeta {
+            method: immutable_req.method().as_str().to_owned(),
+            url: immutable_req.url().to_owned(),
+            route,
+            status: accounting.status(),
+            duration: started.elapsed(),
+            request_id: request_id(immutable_req),
+        };
+        report_error(hooks, &error, &meta);
+    }
+
+    if !hooks.on_request_complete.is_empty() {
+        let completed = CompletedRequest {
+            method: immutable_req.method().as_str().to_owned(),
+            url: immutable_req.url().to_owned(),
+            route,
+            status: accounting.status(),
+            bytes_read: accounting.bytes_read(),
+            bytes_written: accounting.bytes_written(),
+            duration: started.elapsed(),
+            received,
+            remote_addr: immutable_req.remote_addr().copied(),
+            http_version: format!(
+                "{}.{}",
+                immutable_req.http_version().0,
+                immutable_req.http_version().1
+            ),
+            referer: find_header(immutable_req.headers(), "Referer").map(str::to_owned),
+            user_agent: find_header(immutable_req.headers(), "User-Agent").map(str::to_owned),
+        };
+
+        for hook in &hooks.on_request_complete {
+            hook(&completed);
+        }
+    }
+
+    if let (Some(timings), Some(symbol)) = (&shared.route_timings, symbol) {
+        timings.record_interned(symbol, started.elapsed());
+    }
+
+    if let Some(profiler) = &shared.profiler {
+        profiler.finish(immutable_req, route, &accounting, started, received);
+    }
+
+    let last = last_on_connection(immutable_req, shared.ask_to_close);
+    if let Some(closed) = shared.connections.finished(&connection, last) {
+        for hook in &hooks.on_connection_close {
+            hook(&closed);
+        }
+    }
+
+    // drop our request, running it's destructor
+    drop(mutable_req);
+}
+
+// answers a CONNECT with a 200 and hands the connection to the handler tunneling it, which has it until it's done
+// - nothing on it is http after that, so it's closed then
+fn tunnel<C: Clone + Send + Sync>(
+    request: TinyHttpRequest,
+    handler: HandlerRef<C>,
+    hooks: &Hooks<C>,
+    context: C,
+    started: Instant,
+) {
+    let authority = request.url().to_owned();
+    let request_id = request_id(&request);
+    // the upgrade only takes the stream off tiny_http, like for closing - the 200 is what says the tunnel's open
+    let stream = request.upgrade("HTTP/1.1", Response::empty(200));
+
+    if let Err(error) = handler.tunnel(&authority, stream, context) {
+        let meta = RequestMeta {
+            method: "CONNECT".to_owned(),
+            url: authority,
+            route: None,
+            status: Some(200),
+            duration: started.elapsed(),
+            request_id,
+        };
+        report_error(hooks, &error, &meta);
+    }
+}
+
+// routes the request and runs whatever should answer it, returning the matched route's path and how the handler did
+#[allow(clippy::too_many_arguments)]
+fn route_and_handle<'r, C: Clone + Send + Sync>(
+    mutable_req: &'r mut TinyHttpRequest,
+    immutable_req: &'r TinyHttpRequest,
+    shared: &Shared<C>,
+    scratch: &mut Scratch,
+    context: C,
+    connection: &Arc<ClientConnection>,
+    resp_writer: &mut (dyn Write + Send),
+    accounting: &Accounting,
+) -> (Option<Symbol>, BeakResult<()>) {
+    let arrived = Instant::now();
+    let (buffer, arena) = (&mut scratch.buffer, &scratch.arena);
+    let mut multipart_entry: Option<MultipartEntry<'_>> = None;
+
+    // http/1.1 requests need exactly one Host, and there's no knowing which virtual host one without it is for
+    let hosts = immutable_req
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("Host"))
+        .count();
+    let version = immutable_req.http_version();
+    if hosts > 1 || (hosts == 0 && (version.0, version.1) >= (1, 1)) {
+        respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(400));
+        return (None, Ok(()));
+    }
+
+    let rewritten = rewrite::rewrite(&shared.rewrites, immutable_req.url());
+    let url = match &rewritten {
+        Some(rewritten) if rewritten.redirect && !rewritten.allowed => {
+            respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(400));
+            return (None, Ok(()));
+        }
+        Some(rewritten) if rewritten.redirect => {
+            let location = Header::from_bytes(&b"Location"[..], rewritten.url.as_bytes()).unwrap();
+            respond_early(
+                resp_writer,
+                immutable_req,
+                shared.ask_to_close,
+                Response::empty(301).with_header(location),
+            );
+            return (None, Ok(()));
+        }
+        Some(rewritten) => rewritten.url.as_str(),
+        None => immutable_req.url(),
+    };
+
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    let host = find_header(immutable_req.headers(), "Host");
+
+    // whatever of the body had to be read to find an overriding method, which still belongs to the handler
+    let mut peeked = Vec::new();
+    let method = match shared.method_override {
+        true => method_override(immutable_req, mutable_req.as_reader(), &mut peeked),
+        false => None,
+    }
+    .unwrap_or(immutable_req.method().as_str());
+
+    let routed = shared.router.at_interned(host, method, path);
+
+    // before anything else, so there's no telling what's routable while we're down
+    let routed_symbol = routed.as_ref().ok().map(|(_, symbol)| *symbol);
+    if let Some(response) = shared
+        .maintenance
+        .response_for(routed_symbol.map(Symbol::as_str))
+    {
+        respond_early(resp_writer, immutable_req, shared.ask_to_close, response);
+        return (routed_symbol, Ok(()));
+    }
+
+    let (matched, symbol) = match routed {
+        Ok(routed) => routed,
+        Err(RouteError::NotFound) => {
+            respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(404));
+            return (None, Ok(()));
+        }
+        Err(RouteError::MethodNotAllowed { allowed }) => {
+            let allow = headers::header("Allow", allowed.join(", "));
+            respond_early(
+                resp_writer,
+                immutable_req,
+                shared.ask_to_close,
+                Response::empty(405).with_header(allow),
+            );
+            return (None, Ok(()));
+        }
+    };
+    let route = symbol.as_str();
+    shared.shutdown.track_route(route);
+
+    let headers = immutable_req.headers();
+    let content_encoding = find_header(headers, "Content-Encoding");
+
+    #[cfg(feature = "decompression")]
+    let undecodable = !body::can_decode(content_encoding);
+    // without decompression, encoded bodies reach the handler as they came
+    #[cfg(not(feature = "decompression"))]
+    let undecodable = false;
+
+    if undecodable {
+        respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(415));
+        return (Some(symbol), Ok(()));
+    }
+
+    let content_length = find_header(headers, "Content-Length");
+    let counted = CountingReader::new(
+        Cursor::new(peeked).chain(mutable_req.as_reader()),
+        accounting.clone(),
+    );
+    let raw_body: Box<dyn Read> = if shared.progress_hooks.is_empty() {
+        Box::new(LengthCheck::new(counted, content_length))
+    } else {
+        let mut progress = UploadProgress {
+            method: immutable_req.method().as_str(),
+            url: immutable_req.url(),
+            received: 0,
+            total: content_length.and_then(|length| length.trim().parse().ok()),
+            done: false,
+        };
+        let reported = Progress::new(counted, shared.progress_interval, move |received, done| {
+            progress.received = received;
+            progress.done = done;
+            for hook in &shared.progress_hooks {
+                hook(&progress);
+            }
+        });
+        Box::new(LengthCheck::new(reported, content_length))
+    };
+
+    #[cfg(feature = "decompression")]
+    let mut body = Body::decoded(raw_body, content_encoding, shared.inflate_limit);
+    #[cfg(not(feature = "decompression"))]
+    let mut body = {
+        let _ = content_encoding;
+        Body::new(raw_body)
+    };
+
+    let shadows = shared.shadows.get(&symbol).map_or(&[][..], Vec::as_slice);
+
+    // the primary handler consumes the body, so the shadows get a copy of it - unless it's too big to hold onto
+    let mut shadow_body = None;
+    if !shadows.is_empty() {
+        let limit = shared.shadow_body_limit;
+        let mut copied = Vec::new();
+        let read = (&mut body).take(limit as u64 + 1).read_to_end(&mut copied);
+        if read.is_ok() && copied.len() <= limit {
+            shadow_body = Some(copied.clone());
+        }
+        body = Body::new(Cursor::new(copied).chain(body));
+    }
+
+    let boundary = find_header(headers, "Content-Type").and_then(body::multipart_boundary);
+    let mut uploaded = None;
+    if let (true, Some(boundary)) = (matched.value.multipart_to_disk(), boundary) {
+        let multipart = Multipart::with_body(&mut body, boundary);
+        match upload::receive(multipart, &shared.upload_dir, &shared.upload_limits) {
+            Ok(files) => uploaded = Some(files),
+            Err(ReceiveError::Malformed) => {
+                respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(400));
+                return (Some(symbol), Ok(()));
+            }
+            Err(ReceiveError::TooLarge) => {
+                respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(413));
+                return (Some(symbol), Ok(()));
+            }
+            Err(ReceiveError::Disk(e)) => {
+                respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(500));
+                return (Some(symbol), Err(e.into()));
+            }
+        }
+    } else if let (true, Some(boundary)) = (matched.value.needs_multipart(), boundary) {
+        if let Some(mut multipart) = Multipart::with_body(&mut body, boundary)
+            .into_entry()
+            .into_result()
+            .ok()
+            .flatten()
+        {
+            buffer.clear();
+            if multipart.data.read_to_end(buffer).is_err() {
+                // cut short, or past the inflation limit - either way not something to hand over as the upload
+                respond_early(resp_writer, immutable_req, shared.ask_to_close, Response::empty(400));
+                return (Some(symbol), Ok(()));
+            }
+            multipart_entry = Some(MultipartEntry {
+                name: multipart.headers.name.clone(),
+                file_name: multipart.headers.filename,
+                content_type: multipart.headers.content_type,
+                data: buffer,
+            });
+        }
+    }
+
+    let shadow_params = shadow_body.as_ref().map(|_| matched.params.clone());
+
+    let mut extensions = Extensions::new();
+    if let Some(uploaded) = uploaded {
+        extensions.insert(uploaded);
+    }
+    if scratch.sub_requests {
+        extensions.insert(Batched);
+    }
+
+    // a HEAD that went to a GET handler, which won't know to leave its body out
+    let head_from_get = method.eq_ignore_ascii_case("HEAD") && !router::answers(*matched.value, method);
+    let output: Box<dyn Write + Send + '_> = match head_from_get {
+        true => Box::new(HeadOnly::new(&mut *resp_writer)),
+        false => Box::new(&mut *resp_writer),
+    };
+
+    let processed_req = Request {
+        url,
+        params: matched.params,
+        multipart_entry: multipart_entry.clone(),
+        headers,
+        body,
+        extensions,
+        method,
+        route,
+        http_version: immutable_req.http_version().clone(),
+        output,
+        response_headers: match shared.ask_to_close {
+            true => vec![connection_close()],
+            false => Vec::new(),
+        },
+        accounting: accounting.clone(),
+        deadline: None,
+        secure: immutable_req.secure(),
+        urls: shared.urls,
+        arena,
+        connection: connection.clone(),
+    };
+    let processed_req = match shared.request_timeout {
+        Some(timeout) => processed_req.with_deadline(arrived + timeout),
+        None => processed_req,
+    };
+    accounting.mark_handler_started();
+
+    let (shadow_body, shadow_params) = match (shadow_body, shadow_params) {
+        (Some(body), Some(params)) => (body, params),
+        _ => {
+            let handled = Next::new(&shared.middleware, *matched.value).run(processed_req, context);
+            return (
+                Some(symbol),
+                handler_failed(handled, immutable_req, route, resp_writer, accounting, shared.ask_to_close),
+            );
+        }
+    };
+
+    let handled = Next::new(&shared.middleware, *matched.value).run(processed_req, context.clone());
+    let handled = handler_failed(handled, immutable_req, route, resp_writer, accounting, shared.ask_to_close);
+
+    // the client shouldn't wait on the shadows
+    TinyHttpRequest::ignore_client_closing_errors(resp_writer.flush()).unwrap();
+
+    for shadow in shadows {
+        let shadow_req = Request {
+            url,
+            params: shadow_params.clone(),
+            multipart_entry: multipart_entry.clone(),
+            headers,
+            body: Body::new(Cursor::new(shadow_body.clone())),
+            extensions: Extensions::new(),
+            method,
+            route,
+            http_version: immutable_req.http_version().clone(),
+            output: Box::new(io::sink()),
+            response_headers: Vec::new(),
+            accounting: Accounting::default(),
+            deadline: None,
+            secure: immutable_req.secure(),
+            urls: shared.urls,
+            arena,
+            connection: connection.clone(),
+        };
+
+        // a broken shadow is exactly what we're trying to find out about, and mustn't take the real request down
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+            shadow.handle(shadow_req, context.clone())
+        }));
+    }
+
+    (Some(symbol), handled)
+}
+
+// methods a POST can be turned into with method_override - the ones html forms can't send themselves
+const OVERRIDABLE: &[&str] = &["PUT", "PATCH", "DELETE"];
+
+// how much of a form gets read looking for `_method`, which forms tend to put first anyway
+const OVERRIDE_PEEK: u64 = 16 * 1024;
+
+// the method a POST asks to be treated as, from X-HTTP-Method-Override or a urlencoded form's `_method` field
+fn method_override(
+    request: &TinyHttpRequest,
+    body: &mut dyn Read,
+    peeked: &mut Vec<u8>,
+) -> Option<&'static str> {
+    let overridden = |name: &str| {
+        OVERRIDABLE
+            .iter()
+            .copied()
+            .find(|method| method.eq_ignore_ascii_case(name.trim()))
+    };
+
+    if !request.method().as_str().eq_ignore_ascii_case("POST") {
+        return None;
+    }
+
+    let headers = request.headers();
+    if let Some(method) = find_header(headers, "X-HTTP-Method-Override") {
+        return overridden(method);
+    }
+
+    let is_form = find_header(headers, "Content-Type").is_some_and(|content_type| {
+        content_type
+            .trim()
+            .to_ascii_lowercase()
+            .starts_with("application/x-www-form-urlencoded")
+    });
+    // compressed forms are left alone, they aren't worth inflating twice
+    if !is_form || find_header(headers, "Content-Encoding").is_some() {
+        return None;
+    }
+
+    // a failed read keeps what it got, and the handler runs into the same failure
+    let _ = body.take(OVERRIDE_PEEK).read_to_end(peeked);
+    let form = String::from_utf8_lossy(peeked);
+    // if there might be more, the last pair could be cut short
+    let complete = match peeked.len() as u64 >= OVERRIDE_PEEK {
+        true => form.rsplit_once('&').map_or("", |(complete, _)| complete),
+        false => &form,
+    };
+
+    complete
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("_method="))
+        .and_then(overridden)
+}
+
+// if the handler gave up, answers for it when it hadn't started to, and attaches what we know about the request
+fn handler_failed(
+    handled: BeakResult<()>,
+    request: &TinyHttpRequest,
+    route: &'static str,
+    writer: &mut dyn Write,
+    accounting: &Accounting,
+    close: bool,
+) -> BeakResult<()> {
+    let error = match handled {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+
+    if accounting.status().is_none() {
+        respond_early(writer, request, close, Response::empty(500));
+    }
+
+    let context = ErrorContext::new(
+        request.method().as_str(),
+        request.url(),
+        Some(route),
+        request_id(request),
+    );
+    Err(error.in_request(context))
+}
+
+// the status for a request nothing after which on the connection can be read as a request, if it's one
+fn unreadable_after(request: &TinyHttpRequest) -> Option<u16> {
+    // the HTTP/2 connection preface, from a client assuming prior knowledge of h2c - which we don't have, and a 404
+    // for the path `*` would only confuse it. whatever comes next is http/2 frames
+    if request.method().as_str() == "PRI" && request.url() == "*" {
+        return Some(505);
+    }
+    // a body whose length depends on who's asked is how requests get smuggled past a proxy, so it's refused without
+    // being read - and whatever's after it is no telling where
+    if ambiguous_framing(request) {
+        return Some(400);
+    }
+    None
+}
+
+// whether the body's length could be read more than one way (rfc 7230 3.3.3): both Content-Length and
+// Transfer-Encoding, lengths that disagree or aren't numbers, or codings that don't end in chunked
+fn ambiguous_framing(request: &TinyHttpRequest) -> bool {
+    let values = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .filter(move |header| header.field.equiv(name))
+            .flat_map(|header| header.value.as_str().split(','))
+            .map(str::trim)
+    };
+
+    let mut lengths = values("Content-Length").peekable();
+    let length = lengths.peek().copied();
+    let lengths_agree = lengths.all(|value| {
+        !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) && Some(value) == length
+    });
+    let codings: Vec<&str> = values("Transfer-Encoding").collect();
+
+    if codings.is_empty() {
+        return !lengths_agree;
+    }
+
+    let version = request.http_version();
+    let chunked_last = codings
+        .last()
+        .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"));
+    let chunked_once = codings
+        .iter()
+        .filter(|coding| coding.eq_ignore_ascii_case("chunked"))
+        .count()
+        == 1;
+
+    // http/1.0 doesn't have transfer codings, so one there is somebody up to something
+    length.is_some() || !chunked_last || !chunked_once || (version.0, version.1) < (1, 1)
+}
+
+// whether the connection closes once this request's answered, by what the client asked for or what we answered
+fn last_on_connection(request: &TinyHttpRequest, ask_to_close: bool) -> bool {
+    let connection = find_header(request.headers(), "Connection").unwrap_or("");
+    let has = |token: &str| {
+        connection
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case(token))
+    };
+    let version = request.http_version();
+    // nothing after an http/2 request is one we could read
+    if version.0 >= 2 {
+        return true;
+    }
+
+    match (version.0, version.1) >= (1, 1) {
+        true => has("close") || ask_to_close,
+        false => !has("keep-alive") || ask_to_close,
+    }
+}
+
+fn request_id(request: &TinyHttpRequest) -> Option<String> {
+    find_header(request.headers(), "X-Request-Id").map(str::to_owned)
+}
+
+// hands a handler's error to the hooks that want it, or to stderr if nobody does
+fn report_error<C>(hooks: &Hooks<C>, error: &BeakError, meta: &RequestMeta) {
+    for hook in &hooks.on_handler_error {
+        hook(error);
+    }
+    for hook in &hooks.on_error {
+        hook(error, meta);
+    }
+
+    if hooks.on_handler_error.is_empty() && hooks.on_error.is_empty() {
+        eprintln!("{}", error);
+    }
+}
+
+// for the responses beak sends itself, without involving a handler. `close` is for when clients are asked to close
+fn respond_early(
+    writer: &mut dyn Write,
+    request: &TinyHttpRequest,
+    close: bool,
+    mut response: Response<impl Read>,
+) {
+    if close {
+        response.add_header(connection_close());
+    }
+    TinyHttpRequest::ignore_client_closing_errors(response.raw_print(
+        writer,
+        request.http_version().clone(),
+        request.headers(),
+        false,
+        None,
+    ))
+    .unwrap();
+}
+
+// answers and closes the connection for good. tiny_http goes by the request's own Connection header for whether
+// to read another one, whatever the response says, so the stream's taken off it and dropped. the upgrade is to
+// http/1.1, which is no protocol switch at all - anything but a 101 leaves the client where it was anyway
+fn respond_and_close(request: TinyHttpRequest, mut response: Response<impl Read>) {
+    response.add_header(connection_close());
+    drop(request.upgrade("HTTP/1.1", response));
+}
+
+fn connection_close() -> Header {
+    headers::header("Connection", "close")
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
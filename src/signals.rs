@@ -0,0 +1,50 @@
+use std::{io, process, sync::Arc, thread};
+
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::{Handle, Signals},
+};
+
+use crate::{config::Reloader, ShutdownHandle};
+
+pub(crate) fn listen(
+    shutdown: ShutdownHandle,
+    config: Option<Arc<Reloader>>,
+    #[cfg(all(unix, feature = "reload"))] reload: crate::reload::ReloadHandle,
+) -> io::Result<Handle> {
+    let mut signals = Signals::new([
+        SIGTERM,
+        SIGINT,
+        #[cfg(all(unix, feature = "reload"))]
+        signal_hook::consts::SIGUSR2,
+    ])?;
+    // only taken over when there's something to reload, otherwise it still hangs up the process
+    if config.is_some() {
+        signals.add_signal(SIGHUP)?;
+    }
+    let handle = signals.handle();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if shutdown.is_shutdown() {
+                // someone's impatient - skip the drain
+                process::exit(128 + signal);
+            }
+
+            if let (SIGHUP, Some(config)) = (signal, &config) {
+                let _ = config.reload();
+                continue;
+            }
+
+            #[cfg(all(unix, feature = "reload"))]
+            if signal == signal_hook::consts::SIGUSR2 {
+                reload.reload();
+                continue;
+            }
+
+            shutdown.shutdown();
+        }
+    });
+
+    Ok(handle)
+}
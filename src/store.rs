@@ -0,0 +1,221 @@
+use std::{
+    borrow::Borrow,
+    collections::{
+        hash_map::{Entry, RandomState},
+        HashMap,
+    },
+    hash::{BuildHasher, Hash},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+struct Slot<V> {
+    value: V,
+    expires: Option<Instant>,
+}
+
+impl<V> Slot<V> {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+type Shard<K, V> = RwLock<HashMap<K, Slot<V>>>;
+
+struct Shards<K, V> {
+    shards: Box<[Shard<K, V>]>,
+    hasher: RandomState,
+}
+
+/// A concurrent hash map split into independently locked shards, for the counters, caches and sessions that would
+/// otherwise all fight over one `Mutex<HashMap>`.
+///
+/// Clones share the same map, so it can live in the context and be cloned into every worker. Entries can expire:
+/// expired ones are never returned, and are dropped when overwritten or when [`purge_expired`](Self::purge_expired)
+/// runs.
+pub struct ShardedMap<K, V> {
+    inner: Arc<Shards<K, V>>,
+    default_ttl: Option<Duration>,
+}
+
+impl<K, V> Clone for ShardedMap<K, V> {
+    fn clone(&self) -> Self {
+        ShardedMap {
+            inner: self.inner.clone(),
+            default_ttl: self.default_ttl,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        ShardedMap::new()
+    }
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    /// A map with a few shards per CPU and no expiry.
+    pub fn new() -> ShardedMap<K, V> {
+        let cpus = thread::available_parallelism().map_or(4, |cpus| cpus.get());
+        ShardedMap::with_shards(cpus * 4)
+    }
+
+    /// Rounded up to a power of two.
+    pub fn with_shards(shards: usize) -> ShardedMap<K, V> {
+        let shards = shards.max(1).next_power_of_two();
+
+        ShardedMap {
+            inner: Arc::new(Shards {
+                shards: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+                hasher: RandomState::new(),
+            }),
+            default_ttl: None,
+        }
+    }
+
+    /// How long entries inserted through this handle (and its clones) with [`insert`](Self::insert) or
+    /// [`update`](Self::update) live.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Shard<K, V> {
+        // the shard count is a power of two, so masking picks one
+        let hash = self.inner.hasher.hash_one(key) as usize;
+        &self.inner.shards[hash & (self.inner.shards.len() - 1)]
+    }
+
+    /// Inserts with the default TTL, if there is one, handing back the live value it replaced.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.insert_slot(key, value, self.default_ttl)
+    }
+
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.insert_slot(key, value, Some(ttl))
+    }
+
+    fn insert_slot(&self, key: K, value: V, ttl: Option<Duration>) -> Option<V> {
+        let now = Instant::now();
+        let slot = Slot {
+            value,
+            expires: ttl.map(|ttl| now + ttl),
+        };
+
+        self.shard(&key)
+            .write()
+            .unwrap()
+            .insert(key, slot)
+            .filter(|old| old.is_live(now))
+            .map(|old| old.value)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.with(key, V::clone)
+    }
+
+    /// Runs `f` on the value under `key` without cloning it.
+    pub fn with<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let shard = self.shard(key).read().unwrap();
+        shard
+            .get(key)
+            .filter(|slot| slot.is_live(Instant::now()))
+            .map(|slot| f(&slot.value))
+    }
+
+    /// Changes the value under `key` in place, inserting `init()` (with the default TTL) first if there isn't one.
+    /// Handy for counters: `hits.update(path, || 0, |hits| *hits += 1)`.
+    pub fn update<R>(&self, key: K, init: impl FnOnce() -> V, f: impl FnOnce(&mut V) -> R) -> R {
+        let now = Instant::now();
+        let mut shard = self.shard(&key).write().unwrap();
+
+        let slot = match shard.entry(key) {
+            Entry::Occupied(entry) if entry.get().is_live(now) => entry.into_mut(),
+            entry => {
+                let slot = Slot {
+                    value: init(),
+                    expires: self.default_ttl.map(|ttl| now + ttl),
+                };
+                match entry {
+                    Entry::Occupied(mut entry) => {
+                        entry.insert(slot);
+                        entry.into_mut()
+                    }
+                    Entry::Vacant(entry) => entry.insert(slot),
+                }
+            }
+        };
+
+        f(&mut slot.value)
+    }
+
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key)
+            .write()
+            .unwrap()
+            .remove(key)
+            .filter(|slot| slot.is_live(Instant::now()))
+            .map(|slot| slot.value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.with(key, |_| ()).is_some()
+    }
+
+    /// How many live entries there are. Locks every shard in turn, so the answer may be stale by the time it's back.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.read().unwrap();
+                shard.values().filter(|slot| slot.is_live(now)).count()
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every expired entry, returning how many there were. Worth calling now and then (from a background
+    /// thread, say) for maps whose keys don't get overwritten.
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard.write().unwrap();
+                let before = shard.len();
+                shard.retain(|_, slot| slot.is_live(now));
+                before - shard.len()
+            })
+            .sum()
+    }
+
+    pub fn clear(&self) {
+        for shard in self.inner.shards.iter() {
+            shard.write().unwrap().clear();
+        }
+    }
+}
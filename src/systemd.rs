@@ -0,0 +1,40 @@
+use std::{
+    env, io,
+    net::TcpListener,
+    os::unix::io::{FromRawFd, RawFd},
+    process,
+};
+
+/// The first file descriptor systemd hands over, as in `sd_listen_fds(3)`.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets passed in by systemd socket activation.
+///
+/// Returns nothing if we weren't socket-activated (or the sockets were meant for another process). Like
+/// `sd_listen_fds(1)`, the `LISTEN_*` variables are cleared so children don't try to claim the same sockets.
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let (pid, fds) = match (pid, fds) {
+        (Some(pid), Some(fds)) => (pid, fds),
+        _ => return Ok(Vec::new()),
+    };
+
+    if pid.parse::<u32>().ok() != Some(process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let fds: RawFd = fds
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "LISTEN_FDS isn't a number"))?;
+
+    Ok((LISTEN_FDS_START..LISTEN_FDS_START + fds)
+        // systemd guarantees these are ours and open for as long as we are
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect())
+}
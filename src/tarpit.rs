@@ -0,0 +1,110 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{headers::header, throttle, BeakResult, Handler, Request};
+
+/// Answers a path only scanners ask for - `/wp-login.php`, `/.env`, `/phpmyadmin/` on a site without any of them -
+/// with a response that never seems to finish: a byte at a time, for minutes, so the scanner waits on it instead of
+/// moving on to the next thing to try.
+///
+/// Writes are paced the way a [`Throttle`](crate::throttle::Throttle) paces them, by sleeping, so every scanner
+/// that's caught holds a worker for as long as it's kept. That's only cheap while there are only a few of them at
+/// once, which is what [`max_held`](Self::max_held) is for - past it, requests get a plain 404 straight away.
+///
+/// One path per tarpit, like any handler.
+pub struct Tarpit {
+    path: &'static str,
+    rate: u64,
+    duration: Duration,
+    max_held: usize,
+    held: AtomicUsize,
+}
+
+impl Tarpit {
+    /// Drips a byte a second for five minutes, to up to 4 clients at once.
+    pub const fn new(path: &'static str) -> Tarpit {
+        Tarpit {
+            path,
+            rate: 1,
+            duration: Duration::from_secs(5 * 60),
+            max_held: 4,
+            held: AtomicUsize::new(0),
+        }
+    }
+
+    /// In bytes per second. That's the response's chunked framing as well as what's in it, so it takes a few
+    /// seconds per byte of the body.
+    pub const fn rate(mut self, bytes_per_second: u64) -> Self {
+        self.rate = bytes_per_second;
+        self
+    }
+
+    /// About how long a client's kept before the response finishes.
+    pub const fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// How many clients can be held at once, each of them taking up a worker.
+    pub const fn max_held(mut self, max_held: usize) -> Self {
+        self.max_held = max_held;
+        self
+    }
+}
+
+struct Held<'t>(&'t AtomicUsize);
+
+impl Drop for Held<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<C: Send + Sync> Handler<C> for Tarpit {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let _held = match self.held.fetch_add(1, Ordering::Relaxed) < self.max_held {
+            true => Held(&self.held),
+            false => {
+                self.held.fetch_sub(1, Ordering::Relaxed);
+                request.respond_with_bytes(404, vec![], b"not found")?;
+                return Ok(());
+            }
+        };
+
+        let until = Instant::now() + self.duration;
+        let request = request.wrap_output(|output| throttle::paced(output, self.rate));
+        request.respond(
+            200,
+            vec![header("Content-Type", "text/html; charset=utf-8")],
+            |writer, _| {
+                writer.write_all(b"<!doctype html>")?;
+                // flushed every time, or it'd all sit in one chunk until the end. a write failing means the client
+                // gave up, which is as good as it running out
+                while Instant::now() < until {
+                    writer.write_all(b" ")?;
+                    writer.flush()?;
+                }
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn name(&self) -> &'static str {
+        "tarpit"
+    }
+}
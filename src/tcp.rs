@@ -0,0 +1,83 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+// socket options for the listener, set through the ServerBuilder
+#[derive(Debug, Clone)]
+pub(crate) struct TcpOptions {
+    pub(crate) nodelay: bool,
+    pub(crate) reuse_address: bool,
+    pub(crate) reuse_port: bool,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) backlog: i32,
+}
+
+impl Default for TcpOptions {
+    fn default() -> TcpOptions {
+        TcpOptions {
+            nodelay: false,
+            // what std's TcpListener::bind does
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            keepalive: None,
+            backlog: 128,
+        }
+    }
+}
+
+/// Binds the first address `addr` resolves to that works, like `TcpListener::bind`, but with the options applied
+/// before binding where they have to be.
+pub(crate) fn bind(addr: &str, options: &TcpOptions) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match bind_one(addr, options) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+fn bind_one(addr: SocketAddr, options: &TcpOptions) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    socket.set_reuse_address(options.reuse_address)?;
+    #[cfg(unix)]
+    if options.reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+
+    let listener = socket.into();
+    apply(&listener, options)?;
+    Ok(listener)
+}
+
+/// The options that still mean something on a listener that's already bound, like one handed to us by systemd.
+/// tiny_http does the accepting, so these go on the listener, and connections inherit them from it (on Linux and
+/// the BSDs, at least).
+pub(crate) fn apply(listener: &TcpListener, options: &TcpOptions) -> io::Result<()> {
+    let socket = SockRef::from(listener);
+
+    if options.nodelay {
+        socket.set_nodelay(true)?;
+    }
+
+    if let Some(idle) = options.keepalive {
+        socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+
+    Ok(())
+}
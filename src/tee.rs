@@ -0,0 +1,44 @@
+use std::io::{self, Write};
+
+/// A copy of the bytes that went through a [`Tee`], up to its limit.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Captured {
+    pub(crate) bytes: Vec<u8>,
+    /// Whether more was written than fit under the limit.
+    pub(crate) truncated: bool,
+}
+
+/// Passes writes through to `inner`, keeping a copy of the first `limit` bytes.
+pub(crate) struct Tee<'t> {
+    inner: Box<dyn Write + Send + 't>,
+    copy: &'t mut Captured,
+    limit: usize,
+}
+
+impl<'t> Tee<'t> {
+    pub(crate) fn new(
+        inner: Box<dyn Write + Send + 't>,
+        copy: &'t mut Captured,
+        limit: usize,
+    ) -> Tee<'t> {
+        Tee { inner, copy, limit }
+    }
+}
+
+impl<'t> Write for Tee<'t> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        let room = self.limit.saturating_sub(self.copy.bytes.len());
+        if written > room {
+            self.copy.truncated = true;
+        }
+        self.copy.bytes.extend_from_slice(&buf[..written.min(room)]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
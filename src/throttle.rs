@@ -0,0 +1,203 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{BeakResult, Middleware, Next, Request};
+
+// how much goes out per write at most, so throttled responses trickle out smoothly instead of in bursts
+const CHUNK: usize = 16 * 1024;
+
+// a token bucket holding up to a second's worth of bytes
+struct Bucket {
+    rate: u64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Bucket {
+        Bucket {
+            rate: rate.max(1),
+            tokens: rate.max(1) as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        self.rate = rate.max(1);
+        self.tokens = self.tokens.min(self.rate as f64);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled = now;
+    }
+
+    // takes up to `wanted` bytes' worth, or says how long until there's some
+    fn take(&mut self, wanted: usize) -> Result<usize, Duration> {
+        self.refill(Instant::now());
+
+        let granted = (self.tokens as usize).min(wanted);
+        if granted == 0 {
+            let missing = wanted.min(self.rate as usize) as f64 - self.tokens;
+            return Err(Duration::from_secs_f64(missing / self.rate as f64));
+        }
+
+        self.tokens -= granted as f64;
+        Ok(granted)
+    }
+
+    fn refund(&mut self, bytes: usize) {
+        self.tokens = (self.tokens + bytes as f64).min(self.rate as f64);
+    }
+}
+
+/// Caps how fast responses go out, per response and across all of them, so a file server can limit its egress
+/// without a traffic shaper in front. Writes are paced by sleeping on the worker's thread, so a throttled download
+/// keeps its worker busy for as long as it takes.
+///
+/// Clones share the global limit, so keeping one around is how to change it while the server runs.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    per_response: Option<u64>,
+    global: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl Throttle {
+    pub fn new() -> Throttle {
+        Throttle::default()
+    }
+
+    /// In bytes per second, for each response on its own.
+    pub fn per_response(mut self, rate: u64) -> Self {
+        self.per_response = Some(rate);
+        self
+    }
+
+    /// In bytes per second, shared by every response going through this middleware.
+    pub fn global(mut self, rate: u64) -> Self {
+        self.global = Some(Arc::new(Mutex::new(Bucket::new(rate))));
+        self
+    }
+
+    /// Changes the global rate, for responses already going out too - say from a reloaded
+    /// [`rate_limit`](crate::BeakConfig::rate_limit). Does nothing for a throttle without a global limit.
+    pub fn set_global(&self, rate: u64) {
+        if let Some(global) = &self.global {
+            global.lock().unwrap().set_rate(rate);
+        }
+    }
+}
+
+impl<C: Send + Sync> Middleware<C> for Throttle {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        if self.per_response.is_none() && self.global.is_none() {
+            return next.run(request, context);
+        }
+
+        let local = self.per_response.map(Bucket::new);
+        let global = self.global.clone();
+        let request = request.wrap_output(|inner| {
+            Box::new(Paced {
+                inner,
+                local,
+                global,
+            })
+        });
+
+        next.run(request, context)
+    }
+}
+
+// `inner`, let out at `rate` bytes a second
+pub(crate) fn paced<'p>(
+    inner: Box<dyn Write + Send + 'p>,
+    rate: u64,
+) -> Box<dyn Write + Send + 'p> {
+    Box::new(Paced {
+        inner,
+        local: Some(Bucket::new(rate)),
+        global: None,
+    })
+}
+
+struct Paced<'p> {
+    inner: Box<dyn Write + Send + 'p>,
+    local: Option<Bucket>,
+    global: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl<'p> Paced<'p> {
+    // how many of `wanted` bytes both buckets let through right now
+    fn reserve(&mut self, wanted: usize) -> usize {
+        loop {
+            let local = match &mut self.local {
+                Some(local) => local.take(wanted),
+                None => Ok(wanted),
+            };
+            let allowed = match local {
+                Ok(allowed) => allowed,
+                Err(wait) => {
+                    thread::sleep(wait);
+                    continue;
+                }
+            };
+
+            let global = match &self.global {
+                Some(global) => global.lock().unwrap().take(allowed),
+                None => Ok(allowed),
+            };
+            match global {
+                Ok(granted) => {
+                    if let Some(local) = &mut self.local {
+                        local.refund(allowed - granted);
+                    }
+                    return granted;
+                }
+                Err(wait) => {
+                    if let Some(local) = &mut self.local {
+                        local.refund(allowed);
+                    }
+                    thread::sleep(wait);
+                }
+            }
+        }
+    }
+
+    fn refund(&mut self, bytes: usize) {
+        if let Some(local) = &mut self.local {
+            local.refund(bytes);
+        }
+        if let Some(global) = &self.global {
+            global.lock().unwrap().refund(bytes);
+        }
+    }
+}
+
+impl<'p> Write for Paced<'p> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        let granted = self.reserve(buf.len().min(CHUNK));
+        let written = self.inner.write(&buf[..granted]);
+
+        // whatever didn't go out is still there for the next write, here or in another response
+        self.refund(granted - *written.as_ref().unwrap_or(&0));
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,99 @@
+use crate::base64;
+
+// the subjectAltName extension, 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+// the dns names the first certificate in a pem chain is for, from its subject alternative names - or None if it
+// doesn't parse, when there's no telling what it covers
+pub(crate) fn certificate_names(pem: &[u8]) -> Option<Vec<String>> {
+    let pem = std::str::from_utf8(pem).ok()?;
+    let (_, rest) = pem.split_once("-----BEGIN CERTIFICATE-----")?;
+    let (encoded, _) = rest.split_once("-----END CERTIFICATE-----")?;
+    let der = base64::decode(encoded.trim().as_bytes())?;
+
+    let (_, certificate, _) = tlv(&der)?;
+    let (_, mut tbs, _) = tlv(certificate)?;
+
+    // version, serial number, signature algorithm, issuer, validity, subject and public key come before the
+    // optional unique ids and then the extensions, which are the only thing tagged [3]
+    let extensions = loop {
+        let (tag, content, rest) = tlv(tbs)?;
+        if tag == 0xa3 {
+            break content;
+        }
+        tbs = rest;
+    };
+
+    let (_, mut extensions, _) = tlv(extensions)?;
+    while !extensions.is_empty() {
+        let (_, extension, rest) = tlv(extensions)?;
+        extensions = rest;
+
+        let (_, id, mut fields) = tlv(extension)?;
+        if id != SUBJECT_ALT_NAME {
+            continue;
+        }
+        // skipping over `critical`, if it's there
+        let value = loop {
+            let (tag, content, rest) = tlv(fields)?;
+            if tag == 0x04 {
+                break content;
+            }
+            fields = rest;
+        };
+
+        let (_, mut general_names, _) = tlv(value)?;
+        let mut names = Vec::new();
+        while !general_names.is_empty() {
+            let (tag, name, rest) = tlv(general_names)?;
+            general_names = rest;
+            // dNSName, [2] IA5String
+            if tag == 0x82 {
+                names.push(String::from_utf8_lossy(name).to_ascii_lowercase());
+            }
+        }
+        return Some(names);
+    }
+
+    Some(Vec::new())
+}
+
+// whether a certificate for `names` is good for the virtual host `host` - exactly, or through a wildcard standing in
+// for its first label. a wildcard host is only covered by the same wildcard
+pub(crate) fn covers(names: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    names.iter().any(|name| {
+        if *name == host {
+            return true;
+        }
+        match (name.strip_prefix("*."), host.split_once('.')) {
+            (Some(suffix), Some((label, rest))) => {
+                !label.is_empty() && label != "*" && rest == suffix
+            }
+            _ => false,
+        }
+    })
+}
+
+// one der tag-length-value: the tag, the value, and whatever comes after it
+fn tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+
+    let length = match first {
+        0..=0x7f => first as usize,
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let (bytes, rest) = input.split_at_checked(count)?;
+            input = rest;
+            bytes
+                .iter()
+                .fold(0, |length, &b| (length << 8) | b as usize)
+        }
+        // indefinite lengths aren't der, and nothing in a certificate is four gigabytes
+        _ => return None,
+    };
+
+    let (value, rest) = input.split_at_checked(length)?;
+    Some((tag, value, rest))
+}
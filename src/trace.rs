@@ -0,0 +1,298 @@
+use std::{
+    fmt,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    audit, random,
+    tee::{Captured, Tee},
+    BeakResult, Middleware, Next, Request,
+};
+
+/// Which trace a span is part of - the same for every span in it, across every service it went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(pub [u8; 16]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanId(pub [u8; 8]);
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// Where a span sits in a trace, as a [W3C trace context](https://www.w3.org/TR/trace-context/) carries it from
+/// one service to the next in `traceparent` and `tracestate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+    /// Whether whoever started the trace is recording it - spans that aren't sampled are still passed on, just
+    /// not reported.
+    pub sampled: bool,
+    /// `tracestate` as it came in, vendor-specific and passed on untouched.
+    pub state: Option<String>,
+}
+
+impl SpanContext {
+    /// The first span of a new trace, sampled.
+    pub fn root() -> SpanContext {
+        let mut trace_id = [0; 16];
+        trace_id[..8].copy_from_slice(&nonzero_bits().to_be_bytes());
+        trace_id[8..].copy_from_slice(&random::bits().to_be_bytes());
+
+        SpanContext {
+            trace_id: TraceId(trace_id),
+            span_id: SpanId(nonzero_bits().to_be_bytes()),
+            sampled: true,
+            state: None,
+        }
+    }
+
+    /// A span under this one, in the same trace.
+    pub fn child(&self) -> SpanContext {
+        SpanContext {
+            span_id: SpanId(nonzero_bits().to_be_bytes()),
+            ..self.clone()
+        }
+    }
+
+    /// `None` for a `traceparent` that isn't one - which means starting a new trace, the spec says, rather than
+    /// failing the request.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<SpanContext> {
+        let traceparent = traceparent.trim();
+        let version = traceparent.get(..2)?;
+        // later versions can add fields after ours, but have to keep these where they are
+        let valid_length = match version {
+            "00" => traceparent.len() == 55,
+            "ff" => false,
+            _ => traceparent.len() == 55 || traceparent.as_bytes().get(55) == Some(&b'-'),
+        };
+        if !valid_length {
+            return None;
+        }
+
+        let mut fields = traceparent[..55].split('-');
+        let (_, trace_id, span_id, flags) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        if !is_hex(version) || !is_hex(flags) || flags.len() != 2 {
+            return None;
+        }
+
+        let trace_id = TraceId(parse_hex(trace_id)?);
+        let span_id = SpanId(parse_hex(span_id)?);
+        // all zeroes is how an unset id looks, so it's never a real one
+        if trace_id.0 == [0; 16] || span_id.0 == [0; 8] {
+            return None;
+        }
+
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+            state: tracestate
+                .map(str::trim)
+                .filter(|state| !state.is_empty())
+                .map(str::to_owned),
+        })
+    }
+
+    /// What goes in `traceparent` for a request made as part of this span.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+}
+
+// none of the ids can be all zeroes
+fn nonzero_bits() -> u64 {
+    loop {
+        let bits = random::bits();
+        if bits != 0 {
+            return bits;
+        }
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    text.bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+// lowercase only, as the spec has it
+fn parse_hex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 || !is_hex(text) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// A finished span, for a [`Tracing`] hook to report.
+#[derive(Debug, Clone)]
+pub struct SpanRecord {
+    /// The method and route, like `GET /posts/:id`.
+    pub name: String,
+    pub context: SpanContext,
+    /// The span in the service that called this one, if it said.
+    pub parent: Option<SpanId>,
+    pub start: SystemTime,
+    pub duration: Duration,
+    /// The status code from the response's status line, if there was a response.
+    pub status: Option<u16>,
+    /// Whether the handler failed.
+    pub failed: bool,
+}
+
+/// Makes every request a span: a child of the one in its `traceparent` if it came with one, or the root of a new
+/// trace if not. Handlers find it with [`Request::span`], to pass on to whatever they call - see
+/// [`ClientRequest::traced`](crate::client::ClientRequest::traced) - and the hook gets each sampled span once its
+/// request's done.
+pub struct Tracing<F> {
+    hook: F,
+}
+
+impl<F: Fn(&SpanRecord) + Send + Sync> Tracing<F> {
+    pub fn new(hook: F) -> Tracing<F> {
+        Tracing { hook }
+    }
+}
+
+impl<C: Send + Sync, F: Fn(&SpanRecord) + Send + Sync> Middleware<C> for Tracing<F> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        let parent = request
+            .header("traceparent")
+            .and_then(|traceparent| SpanContext::parse(traceparent, request.header("tracestate")));
+        let span = match &parent {
+            Some(parent) => parent.child(),
+            None => SpanContext::root(),
+        };
+
+        let name = format!("{} {}", request.method(), next.handler().path());
+        let (start, started) = (SystemTime::now(), Instant::now());
+        request.extensions.insert(span.clone());
+
+        let mut captured = Captured::default();
+        // the status line's all that's needed
+        let request = request.wrap_output(|output| Box::new(Tee::new(output, &mut captured, 32)));
+        let result = next.run(request, context);
+
+        if span.sampled {
+            (self.hook)(&SpanRecord {
+                name,
+                context: span,
+                parent: parent.map(|parent| parent.span_id),
+                start,
+                duration: started.elapsed(),
+                status: audit::status_code(&captured.bytes),
+                failed: result.is_err(),
+            });
+        }
+
+        result
+    }
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// The request's span, with [`Tracing`] in front of the handler.
+    pub fn span(&self) -> Option<&SpanContext> {
+        self.extensions.get::<SpanContext>()
+    }
+}
+
+#[cfg(feature = "client")]
+impl<'c> crate::client::ClientRequest<'c> {
+    /// Sends `traceparent` and `tracestate`, so the request shows up in the trace as made from `span`.
+    pub fn traced(self, span: &SpanContext) -> Self {
+        let request = self.header("traceparent", span.traceparent());
+        match &span.state {
+            Some(state) => request.header("tracestate", state),
+            None => request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parses_traceparent() {
+        let context = SpanContext::parse(TRACEPARENT, Some(" congo=t61rcWkgMzE ")).unwrap();
+        assert_eq!(
+            context.trace_id.to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(context.span_id.to_string(), "00f067aa0ba902b7");
+        assert!(context.sampled);
+        assert_eq!(context.state.as_deref(), Some("congo=t61rcWkgMzE"));
+        assert_eq!(context.traceparent(), TRACEPARENT);
+
+        let unsampled = SpanContext::parse(&TRACEPARENT.replace("-01", "-00"), Some("")).unwrap();
+        assert!(!unsampled.sampled);
+        assert_eq!(unsampled.state, None);
+    }
+
+    #[test]
+    fn later_versions_can_add_fields() {
+        let future = "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-what-comes-next";
+        assert!(SpanContext::parse(future, None).is_some());
+        // but version 00 can't
+        assert!(SpanContext::parse(&format!("{}-extra", TRACEPARENT), None).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_traceparents() {
+        for traceparent in [
+            "",
+            "00",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-600f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-0g",
+            "zz-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00_4bf92f3577b34da6a3ce929d0e0e4736_00f067aa0ba902b7_01",
+            "cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01x",
+        ] {
+            assert!(
+                SpanContext::parse(traceparent, None).is_none(),
+                "{}",
+                traceparent
+            );
+        }
+    }
+
+    #[test]
+    fn children_stay_in_the_trace() {
+        let root = SpanContext::root();
+        let child = root.child();
+        assert_eq!(child.trace_id, root.trace_id);
+        assert_ne!(child.span_id.0, [0; 8]);
+        assert_eq!(SpanContext::parse(&child.traceparent(), None), Some(child));
+    }
+}
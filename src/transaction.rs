@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{
+    audit,
+    tee::{Captured, Tee},
+    BeakError, BeakResult, Middleware, Next, Request,
+};
+
+/// A database pool, or whatever else hands out transactions.
+pub trait TransactionPool: Send + Sync {
+    type Transaction: Send + 'static;
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn begin(&self) -> Result<Self::Transaction, Self::Error>;
+    fn commit(&self, transaction: Self::Transaction) -> Result<(), Self::Error>;
+    fn rollback(&self, transaction: Self::Transaction) -> Result<(), Self::Error>;
+}
+
+/// A context with a [`TransactionPool`] in it, for [`WithTransaction`].
+pub trait HasPool {
+    type Pool: TransactionPool;
+
+    fn pool(&self) -> &Self::Pool;
+}
+
+/// The request's transaction, in its [`extensions`](Request::extensions).
+pub struct Transaction<T> {
+    inner: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> Transaction<T> {
+    /// `None` in there once it's been [taken](Self::take).
+    pub fn get(&self) -> MutexGuard<'_, Option<T>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Takes the transaction out, for a handler that commits it itself - before it responds, say, so it can still
+    /// answer with an error if that fails. [`WithTransaction`] leaves it alone after that.
+    pub fn take(&self) -> Option<T> {
+        self.inner.lock().unwrap().take()
+    }
+}
+
+/// Begins a transaction from the context's [pool](HasPool) for every request, and hands it to the handler as a
+/// [`Transaction`] in the request's extensions. It's committed once the handler's done if the response was a 2xx or
+/// 3xx, and rolled back otherwise - when the handler fails, panics or answers with an error.
+///
+/// The response has gone out by the time the transaction's committed, so a failed commit can only be reported as
+/// the request's error, not to the client. Handlers that need to tell them can [take](Transaction::take) the
+/// transaction and commit it themselves.
+pub struct WithTransaction;
+
+// rolls back whatever's left in the transaction when the request's done with it, however it ended
+struct Rollback<'r, P: TransactionPool> {
+    pool: &'r P,
+    transaction: Arc<Mutex<Option<P::Transaction>>>,
+}
+
+impl<'r, P: TransactionPool> Drop for Rollback<'r, P> {
+    fn drop(&mut self) {
+        let transaction = match self.transaction.lock() {
+            Ok(mut transaction) => transaction.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(transaction) = transaction {
+            let _ = self.pool.rollback(transaction);
+        }
+    }
+}
+
+impl<C: HasPool + Clone + Send + Sync> Middleware<C> for WithTransaction {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        mut request: Request<'url, 'sender, 'mv>,
+        context: C,
+        next: Next<'_, C>,
+    ) -> BeakResult<()> {
+        // the handler gets the context, and the transaction's finished with the pool after it's done
+        let ours = context.clone();
+        let pool = ours.pool();
+
+        let transaction = Arc::new(Mutex::new(Some(pool.begin().map_err(BeakError::handler)?)));
+        let rollback = Rollback {
+            pool,
+            transaction: transaction.clone(),
+        };
+        request.extensions.insert(Transaction {
+            inner: transaction.clone(),
+        });
+
+        let mut captured = Captured::default();
+        // the status line's all that's needed
+        let request = request.wrap_output(|output| Box::new(Tee::new(output, &mut captured, 32)));
+        let result = next.run(request, context);
+
+        let succeeded = result.is_ok()
+            && audit::status_code(&captured.bytes).is_some_and(|status| status < 400);
+        if succeeded {
+            let committing = transaction.lock().unwrap().take();
+            if let Some(committing) = committing {
+                pool.commit(committing).map_err(BeakError::handler)?;
+            }
+        }
+
+        drop(rollback);
+        result
+    }
+}
@@ -0,0 +1,42 @@
+use std::io;
+
+#[cfg(unix)]
+use std::{fs, path::Path};
+
+use tiny_http::Request as TinyHttpRequest;
+
+/// Somewhere requests come from, for [`ServerBuilder::transport`](crate::ServerBuilder::transport). Whatever a
+/// transport hands over goes through exactly what a request off a TCP listener would - rewrites, routing,
+/// middleware, accounting and all - and its response goes back through the request's own writer.
+///
+/// TCP and TLS listeners, and unix sockets, are tiny_http servers, which are transports already.
+pub trait Transport: Send + Sync {
+    /// Blocks until there's a request. Once [`unblock`](Self::unblock)ed, it should give up with an error instead.
+    fn recv(&self) -> io::Result<TinyHttpRequest>;
+
+    /// Wakes one worker blocked in [`recv`](Self::recv), for shutting down - it's called once for every worker
+    /// that might be.
+    fn unblock(&self);
+}
+
+impl Transport for tiny_http::Server {
+    fn recv(&self) -> io::Result<TinyHttpRequest> {
+        tiny_http::Server::recv(self)
+    }
+
+    fn unblock(&self) {
+        tiny_http::Server::unblock(self)
+    }
+}
+
+// a server on a unix socket at `path`, clearing away whatever a server that didn't get to clean up left there
+#[cfg(unix)]
+pub(crate) fn unix(path: &Path) -> io::Result<tiny_http::Server> {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+
+    tiny_http::Server::http_unix(path).map_err(io::Error::other)
+}
@@ -0,0 +1,566 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tiny_http::Header;
+
+use crate::{
+    headers::{self, header},
+    random, BeakResult, Handler, Request,
+};
+
+const VERSION: &str = "1.0.0";
+const ID_BYTES: usize = 16;
+
+/// Where an upload stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadInfo {
+    /// How many bytes have arrived so far.
+    pub offset: u64,
+    pub length: u64,
+    /// `Upload-Metadata` as the client sent it: comma-separated keys with base64 values.
+    pub metadata: Option<String>,
+    pub created: SystemTime,
+}
+
+impl UploadInfo {
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+}
+
+/// Where [`Tus`] keeps uploads. Ids are always lowercase hex, so they're safe to use in file names and keys.
+pub trait UploadStore: Send + Sync {
+    fn create(&self, id: &str, length: u64, metadata: Option<&str>) -> io::Result<()>;
+
+    /// `None` if there's no upload with this id.
+    fn info(&self, id: &str) -> io::Result<Option<UploadInfo>>;
+
+    /// Appends to the end of the upload. Whatever was read before `data` failed has to stay appended, so clients can
+    /// resume from there.
+    fn append(&self, id: &str, data: &mut dyn Read) -> io::Result<()>;
+
+    fn delete(&self, id: &str) -> io::Result<()>;
+
+    /// Deletes unfinished uploads created before `created_before`, returning how many there were.
+    fn purge(&self, created_before: SystemTime) -> io::Result<usize>;
+}
+
+/// An [`UploadStore`] keeping each upload in a directory as two files: `<id>` with the data received so far, and
+/// `<id>.info` with its length, creation time and metadata.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates `dir` if it isn't there yet.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    /// Where an upload's data ends up, for moving it somewhere permanent once it's done.
+    pub fn data_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.info", id))
+    }
+}
+
+impl UploadStore for FileStore {
+    fn create(&self, id: &str, length: u64, metadata: Option<&str>) -> io::Result<()> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        File::create(self.data_path(id))?;
+        fs::write(
+            self.info_path(id),
+            format!("{}\n{}\n{}", length, created, metadata.unwrap_or_default()),
+        )
+    }
+
+    fn info(&self, id: &str) -> io::Result<Option<UploadInfo>> {
+        let info = match fs::read_to_string(self.info_path(id)) {
+            Ok(info) => info,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt upload info");
+        let mut lines = info.splitn(3, '\n');
+        let length = lines
+            .next()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(invalid)?;
+        let created = lines
+            .next()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(invalid)?;
+        let metadata = lines.next().filter(|m| !m.is_empty()).map(str::to_owned);
+
+        Ok(Some(UploadInfo {
+            offset: fs::metadata(self.data_path(id))?.len(),
+            length,
+            metadata,
+            created: UNIX_EPOCH + Duration::from_secs(created),
+        }))
+    }
+
+    fn append(&self, id: &str, data: &mut dyn Read) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(self.data_path(id))?;
+        io::copy(data, &mut file)?;
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.info_path(id))?;
+        match fs::remove_file(self.data_path(id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge(&self, created_before: SystemTime) -> io::Result<usize> {
+        let mut purged = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let id = match name.to_str().and_then(|name| name.strip_suffix(".info")) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            if let Some(info) = self.info(id)? {
+                if !info.is_complete() && info.created < created_before {
+                    self.delete(id)?;
+                    purged += 1;
+                }
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+/// An [`UploadStore`] keeping the data received so far in files in a directory, like [`FileStore`], but each
+/// upload's info in a [`MetadataStore`](crate::metadata::MetadataStore), under `tus:<id>`.
+#[cfg(feature = "metadata")]
+pub struct SidecarStore<M> {
+    dir: PathBuf,
+    metadata: M,
+}
+
+#[cfg(feature = "metadata")]
+impl<M: crate::metadata::MetadataStore> SidecarStore<M> {
+    /// Creates `dir` if it isn't there yet.
+    pub fn new(dir: impl Into<PathBuf>, metadata: M) -> io::Result<SidecarStore<M>> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(SidecarStore { dir, metadata })
+    }
+
+    /// Where an upload's data ends up, for moving it somewhere permanent once it's done.
+    pub fn data_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+}
+
+#[cfg(feature = "metadata")]
+impl<M: crate::metadata::MetadataStore> UploadStore for SidecarStore<M> {
+    fn create(&self, id: &str, length: u64, metadata: Option<&str>) -> io::Result<()> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        let mut info = serde_json::Map::new();
+        info.insert("length".to_owned(), length.into());
+        info.insert("created".to_owned(), created.into());
+        info.insert("metadata".to_owned(), metadata.into());
+
+        File::create(self.data_path(id))?;
+        self.metadata
+            .put(&format!("tus:{}", id), &info.into(), None)
+    }
+
+    fn info(&self, id: &str) -> io::Result<Option<UploadInfo>> {
+        let info = match self.metadata.get(&format!("tus:{}", id))? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt upload info");
+        let length = info["length"].as_u64().ok_or_else(invalid)?;
+        let created = info["created"].as_u64().ok_or_else(invalid)?;
+        let metadata = info["metadata"].as_str().map(str::to_owned);
+
+        Ok(Some(UploadInfo {
+            offset: fs::metadata(self.data_path(id))?.len(),
+            length,
+            metadata,
+            created: UNIX_EPOCH + Duration::from_secs(created),
+        }))
+    }
+
+    fn append(&self, id: &str, data: &mut dyn Read) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(self.data_path(id))?;
+        io::copy(data, &mut file)?;
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> io::Result<()> {
+        self.metadata.delete(&format!("tus:{}", id))?;
+        match fs::remove_file(self.data_path(id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge(&self, created_before: SystemTime) -> io::Result<usize> {
+        let mut purged = 0;
+
+        for key in self.metadata.keys("tus:")? {
+            let id = &key["tus:".len()..];
+            if let Some(info) = self.info(id)? {
+                if !info.is_complete() && info.created < created_before {
+                    self.delete(id)?;
+                    purged += 1;
+                }
+            }
+        }
+
+        Ok(purged)
+    }
+}
+
+type CompleteHook = Box<dyn Fn(&str, &UploadInfo) + Send + Sync>;
+
+/// The [tus](https://tus.io) resumable upload protocol, with the creation, expiration and termination extensions.
+///
+/// Clients `POST` to the base path to start an upload, then `PATCH` chunks to the upload's own URL, asking with
+/// `HEAD` how far they got whenever the connection drops. [`Tus::handlers`] hands out the two routes that takes.
+pub struct Tus<S> {
+    base: &'static str,
+    store: S,
+    max_size: Option<u64>,
+    expire_after: Option<Duration>,
+    on_complete: Vec<CompleteHook>,
+    // uploads with a PATCH in flight, which another PATCH mustn't interleave with
+    busy: Mutex<HashSet<String>>,
+}
+
+impl<S: UploadStore + 'static> Tus<S> {
+    /// Serves uploads at `base`, like `/files`, and each one at `/files/<id>`.
+    pub fn new(base: &'static str, store: S) -> Tus<S> {
+        Tus {
+            base: base.trim_end_matches('/'),
+            store,
+            max_size: None,
+            expire_after: None,
+            on_complete: Vec::new(),
+            busy: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Unfinished uploads older than this stop being resumable. They're deleted when someone tries to resume them,
+    /// or by [`purge_expired`](Self::purge_expired).
+    pub fn expire_after(mut self, expiry: Duration) -> Self {
+        self.expire_after = Some(expiry);
+        self
+    }
+
+    /// Runs once the last byte of an upload has arrived, before the final `PATCH` is answered.
+    pub fn on_complete(mut self, hook: impl Fn(&str, &UploadInfo) + Send + Sync + 'static) -> Self {
+        self.on_complete.push(Box::new(hook));
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        match self.expire_after {
+            Some(expiry) => self.store.purge(SystemTime::now() - expiry),
+            None => Ok(0),
+        }
+    }
+
+    /// The creation route at the base path, and the route for each upload. Routes are `'static`, so the `Tus` has
+    /// to be too - `Box::leak` it, and keep the reference around for [`purge_expired`](Self::purge_expired).
+    pub fn handlers<C: Send + Sync>(&'static self) -> [&'static (dyn Handler<C> + Send + Sync); 2] {
+        let tus = self;
+        let upload_path: &'static str = Box::leak(format!("{}/:id", tus.base).into_boxed_str());
+
+        [
+            Box::leak(Box::new(Creation(tus))),
+            Box::leak(Box::new(Upload {
+                tus,
+                path: upload_path,
+            })),
+        ]
+    }
+
+    fn expires(&self, info: &UploadInfo) -> Option<SystemTime> {
+        self.expire_after
+            .filter(|_| !info.is_complete())
+            .map(|expiry| info.created + expiry)
+    }
+
+    // an upload that exists and hasn't expired
+    fn find(&self, id: &str) -> io::Result<Option<UploadInfo>> {
+        let valid =
+            id.len() == ID_BYTES * 2 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if !valid {
+            return Ok(None);
+        }
+
+        let info = match self.store.info(id)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        if self
+            .expires(&info)
+            .is_some_and(|expires| expires <= SystemTime::now())
+        {
+            self.store.delete(id)?;
+            return Ok(None);
+        }
+
+        Ok(Some(info))
+    }
+
+    fn options(&self) -> Vec<Header> {
+        let mut headers = vec![
+            header("Tus-Resumable", VERSION),
+            header("Tus-Version", VERSION),
+            header("Tus-Extension", "creation,expiration,termination"),
+        ];
+        if let Some(max) = self.max_size {
+            headers.push(header("Tus-Max-Size", max.to_string()));
+        }
+        headers
+    }
+}
+
+// every request but OPTIONS has to say which version of the protocol it speaks
+fn check_version<'url, 'sender, 'mv>(
+    request: Request<'url, 'sender, 'mv>,
+) -> BeakResult<Option<Request<'url, 'sender, 'mv>>> {
+    if request.method().eq_ignore_ascii_case("OPTIONS")
+        || request.header("Tus-Resumable").map(str::trim) == Some(VERSION)
+    {
+        return Ok(Some(request));
+    }
+
+    request.respond_with_bytes(
+        412,
+        vec![header("Tus-Version", VERSION)],
+        b"unsupported tus version",
+    )?;
+    Ok(None)
+}
+
+fn respond(request: Request<'_, '_, '_>, status: u16, mut headers: Vec<Header>) -> BeakResult<()> {
+    headers.push(header("Tus-Resumable", VERSION));
+    request.respond_with_bytes(status, headers, &[])?;
+    Ok(())
+}
+
+struct Creation<S: 'static>(&'static Tus<S>);
+
+impl<C: Send + Sync, S: UploadStore + 'static> Handler<C> for Creation<S> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let tus = self.0;
+        let request = match check_version(request)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        if request.method().eq_ignore_ascii_case("OPTIONS") {
+            request.respond_with_bytes(204, tus.options(), &[])?;
+            return Ok(());
+        }
+
+        // deferred lengths (the creation-defer-length extension) aren't supported
+        let length = match request
+            .header("Upload-Length")
+            .map(|l| l.trim().parse::<u64>())
+        {
+            Some(Ok(length)) => length,
+            _ => return respond(request, 400, vec![]),
+        };
+        if tus.max_size.is_some_and(|max| length > max) {
+            return respond(request, 413, vec![]);
+        }
+
+        let id = random::hex_token(ID_BYTES)?;
+        tus.store
+            .create(&id, length, request.header("Upload-Metadata"))?;
+
+        let mut response_headers = vec![header("Location", format!("{}/{}", tus.base, id))];
+        let info = UploadInfo {
+            offset: 0,
+            length,
+            metadata: None,
+            created: SystemTime::now(),
+        };
+        if let Some(expires) = tus.expires(&info) {
+            response_headers.push(header("Upload-Expires", headers::http_date(expires)));
+        }
+
+        respond(request, 201, response_headers)
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.0.base
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["POST", "OPTIONS"]
+    }
+}
+
+struct Upload<S: 'static> {
+    tus: &'static Tus<S>,
+    path: &'static str,
+}
+
+// takes an upload off the busy list once its PATCH is done, however it ends
+struct BusyGuard<'g> {
+    busy: &'g Mutex<HashSet<String>>,
+    id: &'g str,
+}
+
+impl<'g> Drop for BusyGuard<'g> {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(self.id);
+    }
+}
+
+impl<C: Send + Sync, S: UploadStore + 'static> Handler<C> for Upload<S> {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let tus = self.tus;
+        let mut request = match check_version(request)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        if request.method().eq_ignore_ascii_case("OPTIONS") {
+            request.respond_with_bytes(204, tus.options(), &[])?;
+            return Ok(());
+        }
+
+        let id = request.params.get("id").unwrap_or_default().to_owned();
+        let info = match tus.find(&id)? {
+            Some(info) => info,
+            None => return respond(request, 404, vec![]),
+        };
+
+        let mut response_headers = vec![header("Cache-Control", "no-store")];
+        if let Some(expires) = tus.expires(&info) {
+            response_headers.push(header("Upload-Expires", headers::http_date(expires)));
+        }
+
+        let method = request.method().to_ascii_uppercase();
+        match method.as_str() {
+            "HEAD" => {
+                response_headers.push(header("Upload-Offset", info.offset.to_string()));
+                response_headers.push(header("Upload-Length", info.length.to_string()));
+                if let Some(metadata) = &info.metadata {
+                    response_headers.push(header("Upload-Metadata", metadata));
+                }
+                respond(request, 200, response_headers)
+            }
+            "DELETE" => {
+                tus.store.delete(&id)?;
+                respond(request, 204, vec![])
+            }
+            _ => {
+                let content_type = request.header("Content-Type").map(str::trim);
+                if content_type != Some("application/offset+octet-stream") {
+                    return respond(request, 415, vec![]);
+                }
+
+                if !tus.busy.lock().unwrap().insert(id.clone()) {
+                    return respond(request, 423, vec![]);
+                }
+                let _guard = BusyGuard {
+                    busy: &tus.busy,
+                    id: &id,
+                };
+
+                // read again now nobody else can be appending, since a PATCH that finished after we looked has
+                // moved the offset on - or a DELETE has taken the upload away
+                let info = match tus.find(&id)? {
+                    Some(info) => info,
+                    None => return respond(request, 404, vec![]),
+                };
+                let offset = request
+                    .header("Upload-Offset")
+                    .map(|o| o.trim().parse::<u64>());
+                if offset != Some(Ok(info.offset)) {
+                    return respond(request, 409, response_headers);
+                }
+
+                // never more than the upload said it'd be
+                let mut chunk = (&mut request.body).take(info.length - info.offset);
+                let appended = tus.store.append(&id, &mut chunk);
+
+                // even if the client went away halfway through, whatever made it in is there to resume from
+                let info = match tus.store.info(&id)? {
+                    Some(info) => info,
+                    None => return respond(request, 404, vec![]),
+                };
+                appended?;
+
+                if info.is_complete() {
+                    for hook in &tus.on_complete {
+                        hook(&id, &info);
+                    }
+                }
+
+                response_headers.push(header("Upload-Offset", info.offset.to_string()));
+                respond(request, 204, response_headers)
+            }
+        }
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["HEAD", "PATCH", "DELETE", "OPTIONS"]
+    }
+}
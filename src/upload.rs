@@ -0,0 +1,438 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use mime::Mime;
+use multipart::server::Multipart;
+use thiserror::Error;
+
+use crate::{random, MultipartEntry};
+
+/// What an uploaded file has to look like, for [`MultipartEntry::validate`].
+#[derive(Debug, Clone, Default)]
+pub struct FileRules {
+    /// In bytes.
+    pub max_size: Option<usize>,
+    /// Content types the file may have, which can be wildcards like `image/*`. Empty allows anything.
+    pub allowed_mimes: Vec<Mime>,
+    /// Check the start of the file against the magic numbers of the formats we know, so a file can't just claim to
+    /// be a PNG.
+    pub magic_check: bool,
+}
+
+/// One way an upload broke its [`FileRules`].
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Violation {
+    #[error("file is {size} bytes, more than the {max} allowed")]
+    TooLarge { size: usize, max: usize },
+    #[error("file has no content type")]
+    MissingContentType,
+    #[error("files of type {0} are not allowed")]
+    MimeNotAllowed(Mime),
+    #[error("file claims to be {declared}, but its contents look like {}", .detected.unwrap_or("something else"))]
+    MagicMismatch {
+        declared: Mime,
+        detected: Option<&'static str>,
+    },
+}
+
+// enough of the common upload formats to catch the usual lies
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"II*\0", "image/tiff"),
+    (b"MM\0*", "image/tiff"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"\x1aE\xdf\xa3", "video/webm"),
+];
+
+/// Guesses a file's content type from its first few bytes, for the formats we know the magic numbers of.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    // RIFF containers and ISO media keep the interesting part a few bytes in
+    if data.len() >= 12 && &data[..4] == b"RIFF" {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(match &data[8..12] {
+            b"avif" => "image/avif",
+            b"heic" | b"heix" | b"mif1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            _ => "video/mp4",
+        });
+    }
+
+    MAGIC
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
+
+// other names for what `sniff` calls these, and formats that are a zip or an ISO media file inside
+const ALIASES: &[(&str, &str)] = &[
+    ("image/jpg", "image/jpeg"),
+    ("image/pjpeg", "image/jpeg"),
+    ("image/x-ms-bmp", "image/bmp"),
+    ("image/heif", "image/heic"),
+    ("application/x-pdf", "application/pdf"),
+    ("application/x-gzip", "application/gzip"),
+    ("application/x-zip-compressed", "application/zip"),
+    ("application/epub+zip", "application/zip"),
+    ("application/java-archive", "application/zip"),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "application/zip",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "application/zip",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "application/zip",
+    ),
+    ("application/vnd.oasis.opendocument.text", "application/zip"),
+    (
+        "application/vnd.oasis.opendocument.spreadsheet",
+        "application/zip",
+    ),
+    (
+        "application/vnd.oasis.opendocument.presentation",
+        "application/zip",
+    ),
+    ("application/ogg", "audio/ogg"),
+    ("video/ogg", "audio/ogg"),
+    ("audio/x-flac", "audio/flac"),
+    ("audio/x-wav", "audio/wav"),
+    ("audio/wave", "audio/wav"),
+    ("audio/vnd.wave", "audio/wav"),
+    ("audio/webm", "video/webm"),
+    ("video/avi", "video/x-msvideo"),
+    ("audio/mp4", "video/mp4"),
+    ("audio/x-m4a", "video/mp4"),
+    ("video/x-m4v", "video/mp4"),
+];
+
+// what `sniff` says about files of the declared type, or None for types it can't tell anything about
+fn sniffed_as(declared: &Mime) -> Option<&'static str> {
+    let essence = declared.essence_str();
+    let known = [
+        "image/webp",
+        "audio/wav",
+        "video/x-msvideo",
+        "image/avif",
+        "image/heic",
+        "video/quicktime",
+        "video/mp4",
+    ];
+    MAGIC
+        .iter()
+        .map(|(_, mime)| *mime)
+        .chain(known)
+        .find(|mime| *mime == essence)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == essence)
+                .map(|(_, mime)| *mime)
+        })
+}
+
+fn allows(pattern: &Mime, mime: &Mime) -> bool {
+    pattern.type_() == mime::STAR
+        || (pattern.type_() == mime.type_()
+            && (pattern.subtype() == mime::STAR || pattern.subtype() == mime.subtype()))
+}
+
+impl<'v> MultipartEntry<'v> {
+    /// Checks the entry against `rules`, collecting every violation rather than stopping at the first.
+    pub fn validate(&self, rules: &FileRules) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        if let Some(max) = rules.max_size {
+            if self.data.len() > max {
+                violations.push(Violation::TooLarge {
+                    size: self.data.len(),
+                    max,
+                });
+            }
+        }
+
+        match &self.content_type {
+            Some(mime) => {
+                let allowed = rules.allowed_mimes.is_empty()
+                    || rules
+                        .allowed_mimes
+                        .iter()
+                        .any(|pattern| allows(pattern, mime));
+                if !allowed {
+                    violations.push(Violation::MimeNotAllowed(mime.clone()));
+                }
+
+                // we can only tell for the formats we know
+                if let (true, Some(expected)) = (rules.magic_check, sniffed_as(mime)) {
+                    let detected = sniff(self.data);
+                    if detected != Some(expected) {
+                        violations.push(Violation::MagicMismatch {
+                            declared: mime.clone(),
+                            detected,
+                        });
+                    }
+                }
+            }
+            None if !rules.allowed_mimes.is_empty() || rules.magic_check => {
+                violations.push(Violation::MissingContentType)
+            }
+            None => {}
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// Every part of a multipart body, for a handler that takes them [to disk](crate::Handler::multipart_to_disk) - in
+/// [`Request::extensions`](crate::Request::extensions). The files are in a directory of their own, which is deleted
+/// with whatever's still in it once the request's been handled, so the ones worth keeping have to be
+/// [persisted](UploadedFile::persist) before then.
+pub struct UploadedFiles {
+    dir: PathBuf,
+    pub files: Vec<UploadedFile>,
+    /// The parts that aren't files, which are kept in memory - up to the
+    /// [`multipart_upload_limit`](crate::ServerBuilder::multipart_upload_limit) all together. The files have a
+    /// limit of their own, [`upload_disk_limit`](crate::ServerBuilder::upload_disk_limit).
+    pub fields: Vec<(Arc<str>, String)>,
+}
+
+pub struct UploadedFile {
+    pub name: Arc<str>,
+    pub file_name: Option<String>,
+    pub content_type: Option<Mime>,
+    /// Where the file is until it's persisted or cleaned up.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl UploadedFiles {
+    /// The first file uploaded as `name`.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| &*file.name == name)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| &**field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Drop for UploadedFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl UploadedFile {
+    /// Moves the file to `to`, where it's left alone by the cleanup. Its `file_name` is whatever the client said, so
+    /// it's no good as part of `to` without checking.
+    pub fn persist(&self, to: impl AsRef<Path>) -> io::Result<()> {
+        let to = to.as_ref();
+        // renaming doesn't work across filesystems, and the temporary directory is often on one of its own
+        if fs::rename(&self.path, to).is_err() {
+            fs::copy(&self.path, to)?;
+            let _ = fs::remove_file(&self.path);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) enum ReceiveError {
+    Malformed,
+    TooLarge,
+    Disk(io::Error),
+}
+
+// how much one request gets to send, all its parts together
+pub(crate) struct ReceiveLimits {
+    pub(crate) fields: usize,
+    pub(crate) disk: u64,
+    pub(crate) parts: usize,
+}
+
+// streams the files in `multipart` into a new directory under `parent`, keeping the other parts in memory
+pub(crate) fn receive<R: Read>(
+    mut multipart: Multipart<R>,
+    parent: &Path,
+    limits: &ReceiveLimits,
+) -> Result<UploadedFiles, ReceiveError> {
+    // cleaned up by its Drop from here on, however this goes
+    let mut uploaded = UploadedFiles {
+        dir: private_dir(parent, "beak-upload").map_err(ReceiveError::Disk)?,
+        files: Vec::new(),
+        fields: Vec::new(),
+    };
+    let (mut field_bytes, mut disk_bytes) = (0, 0);
+
+    while let Some(field) = multipart
+        .read_entry()
+        .map_err(|_| ReceiveError::Malformed)?
+    {
+        // empty parts cost nothing to send, but a file and a file handle each to keep
+        if uploaded.files.len() + uploaded.fields.len() >= limits.parts {
+            return Err(ReceiveError::TooLarge);
+        }
+
+        let (headers, mut data) = (field.headers, field.data);
+        match headers.filename {
+            Some(file_name) => {
+                // numbered rather than by the client's name for it, which could be anything
+                let path = uploaded.dir.join(uploaded.files.len().to_string());
+                let mut file = File::options()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .map_err(ReceiveError::Disk)?;
+                let size = copy_to(&mut data, &mut file, limits.disk - disk_bytes)?;
+                disk_bytes += size;
+
+                uploaded.files.push(UploadedFile {
+                    name: headers.name,
+                    file_name: Some(file_name),
+                    content_type: headers.content_type,
+                    path,
+                    size,
+                });
+            }
+            None => {
+                let mut value = Vec::new();
+                (&mut data)
+                    .take((limits.fields - field_bytes) as u64 + 1)
+                    .read_to_end(&mut value)
+                    .map_err(|_| ReceiveError::Malformed)?;
+                field_bytes += value.len();
+                if field_bytes > limits.fields {
+                    return Err(ReceiveError::TooLarge);
+                }
+
+                let value = String::from_utf8(value).map_err(|_| ReceiveError::Malformed)?;
+                uploaded.fields.push((headers.name, value));
+            }
+        }
+    }
+
+    Ok(uploaded)
+}
+
+// like io::copy, but telling the body going wrong apart from the disk, and stopping past `limit` bytes
+fn copy_to(
+    data: &mut impl BufRead,
+    file: &mut impl Write,
+    limit: u64,
+) -> Result<u64, ReceiveError> {
+    let mut size = 0;
+    loop {
+        let chunk = data.fill_buf().map_err(|_| ReceiveError::Malformed)?;
+        if chunk.is_empty() {
+            return Ok(size);
+        }
+        let read = chunk.len();
+        if size + read as u64 > limit {
+            return Err(ReceiveError::TooLarge);
+        }
+        file.write_all(chunk).map_err(ReceiveError::Disk)?;
+
+        data.consume(read);
+        size += read as u64;
+    }
+}
+
+// a new directory under `parent`, named `prefix` and something random
+pub(crate) fn private_dir(parent: &Path, prefix: &str) -> io::Result<PathBuf> {
+    let mut builder = fs::DirBuilder::new();
+    // only ours to look into
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    loop {
+        let dir = parent.join(format!("{}-{:016x}", prefix, random::bits()));
+        match builder.create(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            created => return created.map(|_| dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mismatch(declared: &str, data: &[u8]) -> bool {
+        let rules = FileRules {
+            magic_check: true,
+            ..FileRules::default()
+        };
+        let entry = MultipartEntry {
+            name: "file".into(),
+            file_name: None,
+            content_type: Some(declared.parse().unwrap()),
+            data,
+        };
+        entry.validate(&rules).is_err()
+    }
+
+    #[test]
+    fn checks_magic_numbers() {
+        assert!(!mismatch("image/png", b"\x89PNG\r\n\x1a\n...."));
+        assert!(mismatch("image/png", b"\xff\xd8\xff...."));
+        assert!(mismatch("image/png", b"just text"));
+        assert!(!mismatch("video/mp4", b"\0\0\0\x18ftypisom...."));
+    }
+
+    #[test]
+    fn accepts_aliases() {
+        assert!(!mismatch("image/jpg", b"\xff\xd8\xff...."));
+        assert!(!mismatch("application/x-gzip", b"\x1f\x8b...."));
+        assert!(!mismatch("audio/mp4", b"\0\0\0\x18ftypM4A ...."));
+        let docx = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+        assert!(!mismatch(docx, b"PK\x03\x04...."));
+        assert!(!mismatch("application/epub+zip", b"PK\x03\x04...."));
+        assert!(mismatch(docx, b"%PDF-1.7"));
+    }
+
+    #[test]
+    fn leaves_other_types_alone() {
+        // a csv that happens to start like a bitmap, or anything we don't know the magic of
+        assert!(!mismatch("text/csv", b"BM,total\n1,2"));
+        assert!(!mismatch("application/octet-stream", b"PK\x03\x04...."));
+    }
+
+    #[test]
+    fn copies_up_to_the_limit() {
+        let mut file = Vec::new();
+        let size = copy_to(&mut &b"hello"[..], &mut file, 5).ok();
+        assert_eq!((size, file.as_slice()), (Some(5), &b"hello"[..]));
+
+        let mut file = Vec::new();
+        let copied = copy_to(&mut &b"hello"[..], &mut file, 4);
+        assert!(matches!(copied, Err(ReceiveError::TooLarge)));
+        assert!(file.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use thiserror::Error;
+
+use crate::{path::percent_encode, Request, RouteInfo};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UrlError {
+    #[error("no route is named {0}")]
+    UnknownRoute(String),
+    #[error("route {route} needs a value for {param}")]
+    MissingParam { route: &'static str, param: String },
+}
+
+// what url_for looks names up in - the route's path pattern under its handler's name - and what base_url believes
+#[derive(Debug, Default)]
+pub(crate) struct Urls {
+    patterns: HashMap<&'static str, &'static str>,
+    origins: Origins,
+}
+
+// set through ServerBuilder::trust_proxy, trust_local_sockets and allowed_host
+#[derive(Debug, Default)]
+pub(crate) struct Origins {
+    pub(crate) proxies: Vec<IpAddr>,
+    pub(crate) local: bool,
+    pub(crate) hosts: Vec<String>,
+}
+
+impl Urls {
+    // a name more than one route shares goes to the first of them, same as in the route listing
+    pub(crate) fn new(routes: &[RouteInfo], origins: Origins) -> Urls {
+        let mut patterns = HashMap::new();
+        for route in routes {
+            patterns.entry(route.handler).or_insert(route.path);
+        }
+        Urls { patterns, origins }
+    }
+}
+
+// a host and maybe a port, and nothing that would take a url built from it somewhere else
+fn is_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.bytes().all(|b| {
+            b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b':' | b'[' | b']')
+        })
+}
+
+/// Fills in a path pattern like `/posts/:id/*rest`, percent-encoding each value. A catch-all's value keeps its
+/// slashes, so the path it captured comes back out the same.
+pub fn build_path(pattern: &'static str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+    let value = |name: &str| {
+        params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| UrlError::MissingParam {
+                route: pattern,
+                param: name.to_owned(),
+            })
+    };
+
+    let mut path = String::with_capacity(pattern.len());
+    for (i, segment) in pattern.split('/').enumerate() {
+        if i > 0 {
+            path.push('/');
+        }
+
+        if let Some(name) = segment.strip_prefix(':') {
+            path.push_str(&percent_encode(value(name)?));
+        } else if let Some(name) = segment.strip_prefix('*') {
+            let rest: Vec<String> = value(name)?.split('/').map(percent_encode).collect();
+            path.push_str(&rest.join("/"));
+        } else {
+            path.push_str(segment);
+        }
+    }
+
+    Ok(path)
+}
+
+/// Whether `names` are exactly the parameters in `pattern`, for [`url_for!`](crate::url_for) to check at compile
+/// time.
+#[doc(hidden)]
+pub const fn params_match(pattern: &str, names: &[&str]) -> bool {
+    let pattern = pattern.as_bytes();
+
+    let mut i = 0;
+    while i < names.len() {
+        if !has_param(pattern, names[i].as_bytes()) {
+            return false;
+        }
+        let mut j = 0;
+        while j < i {
+            if bytes_eq(names[i].as_bytes(), names[j].as_bytes()) {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+
+    names.len() == param_count(pattern)
+}
+
+// const fns can't slice yet, hence all the index juggling
+
+// whether `pattern` has a `:name` or `*name` segment
+const fn has_param(pattern: &[u8], name: &[u8]) -> bool {
+    let mut i = 0;
+    while i < pattern.len() {
+        if is_param_start(pattern, i) {
+            let mut j = 0;
+            while j < name.len() && i + 1 + j < pattern.len() && pattern[i + 1 + j] == name[j] {
+                j += 1;
+            }
+            let end = i + 1 + j;
+            if j == name.len() && (end == pattern.len() || pattern[end] == b'/') {
+                return true;
+            }
+        }
+        i += 1;
+    }
+    false
+}
+
+const fn param_count(pattern: &[u8]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < pattern.len() {
+        if is_param_start(pattern, i) {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+}
+
+const fn is_param_start(pattern: &[u8], i: usize) -> bool {
+    (pattern[i] == b':' || pattern[i] == b'*') && (i == 0 || pattern[i - 1] == b'/')
+}
+
+const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+impl<'url, 'sender, 'mv> Request<'url, 'sender, 'mv> {
+    /// Where the client reached us, like `https://example.com`.
+    ///
+    /// The scheme is the connection's own, and the host is from `Host` - unless the request came from a
+    /// [trusted proxy](crate::ServerBuilder::trust_proxy), when it's `X-Forwarded-Proto` and `X-Forwarded-Host`
+    /// that count, as long as it sets them. `Host` is still whatever the client sent, so with
+    /// [allowed hosts](crate::ServerBuilder::allowed_host) set, a request for any other host gets the first of them
+    /// instead - and without, it's only as honest as the client is.
+    pub fn base_url(&self) -> String {
+        let origins = &self.urls.origins;
+        let trusted = match self.connection().peer_addr() {
+            Some(peer) => origins.proxies.contains(&peer.ip()),
+            None => origins.local,
+        };
+        let forwarded = |name| {
+            self.header(name)
+                .filter(|_| trusted)
+                .and_then(|value| value.split(',').next())
+                .map(str::trim)
+        };
+
+        let proto = forwarded("X-Forwarded-Proto").filter(|proto| {
+            proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https")
+        });
+        let scheme = match proto {
+            Some(proto) => proto.to_ascii_lowercase(),
+            None if self.secure => "https".to_owned(),
+            None => "http".to_owned(),
+        };
+
+        let host = forwarded("X-Forwarded-Host")
+            .or_else(|| self.header("Host").map(str::trim))
+            .filter(|host| is_host(host))
+            .filter(|host| {
+                origins.hosts.is_empty()
+                    || origins
+                        .hosts
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+            })
+            .or(origins.hosts.first().map(String::as_str))
+            .unwrap_or("localhost");
+
+        format!("{}://{}", scheme, host)
+    }
+
+    /// An absolute URL for the route whose handler is called `name` (see [`Handler::name`](crate::Handler::name)),
+    /// with `params` filling in its pattern. Names are only known once the server runs - [`url_for!`](crate::url_for)
+    /// is checked at compile time instead.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let pattern = self
+            .urls
+            .patterns
+            .get(name)
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_owned()))?;
+
+        Ok(format!(
+            "{}{}",
+            self.base_url(),
+            build_path(pattern, params)?
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        for host in [
+            "example.com",
+            "example.com:8080",
+            "[::1]:80",
+            "127.0.0.1",
+            "my_host",
+        ] {
+            assert!(is_host(host), "{}", host);
+        }
+        for host in [
+            "",
+            "evil.com/path",
+            "user@evil.com",
+            "a b",
+            "evil.com#",
+            "evil.com?x",
+        ] {
+            assert!(!is_host(host), "{}", host);
+        }
+    }
+}
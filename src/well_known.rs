@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use tiny_http::Header;
+
+use crate::{headers::header, BeakResult, Handler, Request};
+
+// a file served from memory, for the little ones every site has
+pub(crate) struct Fixed {
+    content_type: Header,
+    body: Vec<u8>,
+}
+
+impl Fixed {
+    pub(crate) fn new(content_type: &str, body: Vec<u8>) -> Fixed {
+        Fixed {
+            content_type: header("Content-Type", content_type),
+            body,
+        }
+    }
+
+    fn serve(&self, request: Request<'_, '_, '_>) -> std::io::Result<()> {
+        let headers = vec![
+            self.content_type.clone(),
+            header("Cache-Control", "public, max-age=86400"),
+            header("Content-Length", self.body.len().to_string()),
+        ];
+        let head = request.method() == "HEAD";
+        request.respond(200, headers, |writer, _| match head {
+            true => Ok(()),
+            false => writer.write_all(&self.body),
+        })
+    }
+}
+
+// /robots.txt and /favicon.ico, from ServerBuilder::robots_txt and ServerBuilder::favicon
+pub(crate) struct FixedEndpoint {
+    pub(crate) path: &'static str,
+    pub(crate) file: Fixed,
+}
+
+impl<C: Send + Sync> Handler<C> for FixedEndpoint {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        self.file.serve(request)?;
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        self.path
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET", "HEAD"]
+    }
+
+    // ahead of a catch-all the app might have
+    fn priority(&self) -> i32 {
+        i32::MAX
+    }
+}
+
+// the files under /.well-known/ from ServerBuilder::well_known, by their name in there
+#[derive(Default)]
+pub(crate) struct WellKnown {
+    pub(crate) files: HashMap<String, Fixed>,
+}
+
+impl<C: Send + Sync> Handler<C> for WellKnown {
+    fn handle<'url, 'sender, 'mv>(
+        &self,
+        request: Request<'url, 'sender, 'mv>,
+        _context: C,
+    ) -> BeakResult<()> {
+        let file = request
+            .wildcard()
+            .ok()
+            .and_then(|name| self.files.get(name.trim_matches('/')));
+
+        match file {
+            Some(file) => file.serve(request)?,
+            None => request.respond_with_bytes(404, vec![], b"not found")?,
+        }
+        Ok(())
+    }
+
+    fn needs_multipart(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &'static str {
+        "/.well-known/*name"
+    }
+
+    fn methods(&self) -> &'static [&'static str] {
+        &["GET", "HEAD"]
+    }
+
+    // under the acme challenges, whose route is inside this one's
+    fn priority(&self) -> i32 {
+        i32::MAX - 1
+    }
+}
@@ -0,0 +1,42 @@
+// the conformance matrix, run against a server over a loopback transport
+
+use std::thread;
+
+use beak::{conformance, loopback::LoopbackTransport, *};
+
+fn echo(mut request: Request<'_, '_, '_>, _context: ()) -> BeakResult<()> {
+    let body = request.body.read_to_vec(1024 * 1024)?;
+    request.respond_with_bytes(200, vec![], &body)?;
+    Ok(())
+}
+
+fn_to_handler!(Echo with context (); POST "/" => echo);
+
+#[test]
+fn every_case_passes() {
+    let transport = LoopbackTransport::new().unwrap();
+    let connector = transport.connector();
+
+    let builder = ServerBuilder::new("conformance", &[&Echo])
+        .transport(transport)
+        .workers(2);
+    let shutdown = builder.shutdown_handle();
+    let server = thread::spawn(move || builder.run(()).unwrap());
+
+    let outcomes = conformance::run(&connector);
+    shutdown.shutdown();
+    server.join().unwrap();
+
+    let failed: Vec<String> = outcomes
+        .iter()
+        .filter(|outcome| !outcome.passed)
+        .map(ToString::to_string)
+        .collect();
+    assert!(
+        failed.is_empty(),
+        "{} of {} cases failed:\n{}",
+        failed.len(),
+        outcomes.len(),
+        failed.join("\n")
+    );
+}